@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{conf::Protocol, locale::Locale, theme::Theme, units, RequestStats};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+// what we've suppressed during the current window, printed as one line instead
+#[derive(Default)]
+struct Aggregate {
+    requests: u64,
+    packets: u64,
+    bytes: u64,
+    per_protocol: HashMap<Protocol, u64>,
+}
+
+// switches the console to aggregated per-second output when the event rate exceeds a threshold,
+// and back to per-request output once it has calmed down
+pub struct RateGovernor {
+    threshold: u64,
+    window_start: Instant,
+    window_events: u64,
+    degraded: bool,
+    aggregate: Aggregate,
+    raw_bytes: bool,
+    theme: Theme,
+    locale: Option<Locale>,
+}
+
+impl RateGovernor {
+    pub fn new(threshold: u64, raw_bytes: bool, theme: Theme, locale: Option<Locale>) -> RateGovernor {
+        RateGovernor {
+            threshold,
+            window_start: Instant::now(),
+            window_events: 0,
+            degraded: false,
+            aggregate: Aggregate::default(),
+            raw_bytes,
+            theme,
+            locale,
+        }
+    }
+
+    // count a request that is about to be printed, returning whether it should be printed in full
+    pub fn record(&mut self, stats: &RequestStats) -> bool {
+        self.tick();
+
+        self.window_events += 1;
+
+        // switch as soon as the threshold is crossed, rather than waiting out the window
+        if !self.degraded && self.window_events > self.threshold {
+            self.degraded = true;
+            outln!(
+                "{}",
+                self.theme.paint(
+                    self.theme.warning,
+                    &format!(
+                        "*** output degraded: more than {} requests/s, printing per-second totals until the rate drops ***",
+                        self.threshold
+                    )
+                )
+            );
+        }
+
+        if !self.degraded {
+            return true;
+        }
+
+        self.aggregate.requests += 1;
+        self.aggregate.packets += stats.packets;
+        self.aggregate.bytes += stats.bytes;
+        *self.aggregate.per_protocol.entry(stats.protocol).or_insert(0) += 1;
+
+        false
+    }
+
+    // close the window if it's over, printing the aggregate and restoring detail if the rate has dropped
+    // called for every request, and periodically from the capture loop so a quiet period still restores
+    pub fn tick(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < WINDOW {
+            return;
+        }
+
+        if self.degraded {
+            let aggregate = std::mem::take(&mut self.aggregate);
+
+            let mut protocols: Vec<_> = aggregate.per_protocol.into_iter().collect();
+            protocols.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+            outln!(
+                "{} {:.2}s: {} requests, {} packets, {} ({})",
+                self.theme.paint(self.theme.warning, "[degraded]"),
+                elapsed.as_secs_f32(),
+                aggregate.requests,
+                aggregate.packets,
+                units::format_bytes(
+                    aggregate.bytes,
+                    Some(aggregate.bytes as f64 / elapsed.as_secs_f64()),
+                    self.raw_bytes,
+                    self.locale.as_ref(),
+                ),
+                protocols
+                    .iter()
+                    .map(|(protocol, count)| format!("{} {}", protocol, count))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+
+            // only restore once comfortably below the threshold, so we don't flap around it
+            if self.window_events < self.threshold / 2 {
+                self.degraded = false;
+                outln!("{}", self.theme.paint(self.theme.warning, "*** output restored ***"));
+            }
+        }
+
+        self.window_start = Instant::now();
+        self.window_events = 0;
+    }
+}
@@ -0,0 +1,246 @@
+// --backend afpacket (Linux only): capture on several AF_PACKET sockets joined into one PACKET_FANOUT group, each read
+// by a thread of its own, so the kernel spreads frames over them instead of everything queueing on pnet's one socket
+// (and being dropped once its buffer's full). Frames are shared out by flow, so each connection's packets stay in
+// order; each socket gets a big receive buffer, and what the kernel still had to drop is counted from
+// PACKET_STATISTICS
+
+use std::{
+    io::{Error, ErrorKind},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    capture::{Ring, MAX_READ_ERRORS},
+    error,
+    metrics::METRICS,
+    sampling::Sampler,
+};
+
+// every socket gets this much kernel buffer, if we're allowed it
+const RCVBUF: libc::c_int = 32 * 1024 * 1024;
+// the biggest frame we read, which covers GRO/TSO super-packets
+const SNAPLEN: usize = 65536;
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+// how often a busy worker checks how many frames the kernel's received and dropped (a quiet one checks whenever a read times out)
+const STATISTICS_EVERY: u64 = 4096;
+
+// open `workers` sockets on the interface and start a thread reading each into the ring, until `running` is cleared
+pub fn spawn(
+    interface: &str,
+    workers: usize,
+    promiscuous: bool,
+    sample: u32,
+    ring: Arc<Ring>,
+    running: &'static AtomicBool,
+) -> std::io::Result<Vec<JoinHandle<()>>> {
+    let name = std::ffi::CString::new(interface).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    // one group per capture, so two sniffs on the same interface each see everything
+    let group = std::process::id() as u16;
+    let fanout = group as u32 | (libc::PACKET_FANOUT_HASH | libc::PACKET_FANOUT_FLAG_DEFRAG) << 16;
+
+    let mut sockets = Vec::new();
+    for _ in 0..workers.max(1) {
+        sockets.push(Socket::open(index as libc::c_int, promiscuous, fanout)?);
+    }
+
+    let consumer = std::thread::current();
+
+    Ok(sockets
+        .into_iter()
+        .enumerate()
+        .map(|(worker, socket)| {
+            let (ring, consumer) = (ring.clone(), consumer.clone());
+
+            std::thread::spawn(move || {
+                let mut buf = vec![0u8; SNAPLEN];
+                let mut failures = 0; // in a row
+                let mut received: u64 = 0;
+                let mut sampler = Sampler::new(sample, worker as u64);
+
+                while running.load(Ordering::SeqCst) {
+                    match socket.recv(&mut buf) {
+                        Ok(len) => {
+                            failures = 0;
+                            METRICS.packets.fetch_add(1, Ordering::Relaxed);
+                            METRICS.bytes.fetch_add(len as u64, Ordering::Relaxed);
+
+                            if sampler.keep() {
+                                METRICS.sampled.fetch_add(1, Ordering::Relaxed);
+                                if ring.push((SystemTime::now(), Instant::now(), buf[..len].to_vec())).is_err() {
+                                    METRICS.dropped.fetch_add(1, Ordering::Relaxed);
+                                }
+                                consumer.unpark();
+                            }
+
+                            received += 1;
+                            if received.is_multiple_of(STATISTICS_EVERY) {
+                                count_statistics(socket.fd);
+                            }
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::Interrupted => {
+                            count_statistics(socket.fd);
+                        }
+                        Err(e) => {
+                            METRICS.read_errors.fetch_add(1, Ordering::Relaxed);
+                            failures += 1;
+                            if error::is_fatal(&e) || failures >= MAX_READ_ERRORS {
+                                eprintln!("Failed to receive packet, stopping: {}", e);
+                                running.store(false, Ordering::SeqCst);
+                            } else {
+                                eprintln!("Failed to receive packet: {}", e);
+                            }
+                        }
+                    }
+                }
+
+                count_statistics(socket.fd);
+            })
+        })
+        .collect())
+}
+
+struct Socket {
+    fd: libc::c_int,
+}
+
+impl Socket {
+    fn open(index: libc::c_int, promiscuous: bool, fanout: u32) -> std::io::Result<Socket> {
+        let protocol = (libc::ETH_P_ALL as u16).to_be() as libc::c_int;
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol) };
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+        // closes the socket if anything below fails
+        let socket = Socket { fd };
+
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol as u16;
+        addr.sll_ifindex = index;
+        if unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        } < 0
+        {
+            return Err(Error::last_os_error());
+        }
+
+        let timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: READ_TIMEOUT.as_micros() as libc::suseconds_t,
+        };
+        socket.set(libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout)?;
+        // a smaller buffer than asked for is still better than the default, so this one isn't fatal
+        let _ = socket.set(libc::SOL_SOCKET, libc::SO_RCVBUF, &RCVBUF);
+
+        if promiscuous {
+            let mut membership: libc::packet_mreq = unsafe { std::mem::zeroed() };
+            membership.mr_ifindex = index;
+            membership.mr_type = libc::PACKET_MR_PROMISC as u16;
+            socket.set(libc::SOL_PACKET, libc::PACKET_ADD_MEMBERSHIP, &membership)?;
+        }
+
+        socket.set(libc::SOL_PACKET, libc::PACKET_FANOUT, &fanout)?;
+
+        // reading the statistics resets them, so this clears anything counted before we were ready
+        statistics(socket.fd);
+
+        Ok(socket)
+    }
+
+    fn set<T>(&self, level: libc::c_int, option: libc::c_int, value: &T) -> std::io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                self.fd,
+                level,
+                option,
+                value as *const T as *const libc::c_void,
+                std::mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), libc::MSG_TRUNC) };
+        if len < 0 {
+            return Err(Error::last_os_error());
+        }
+        // MSG_TRUNC gives the frame's real length, which can be more than we had room for
+        Ok((len as usize).min(buf.len()))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+// what the kernel's counted on a packet socket since we last asked: the frames it received (those it dropped
+// included) and those it dropped because we weren't reading fast enough
+pub fn statistics(fd: libc::c_int) -> Option<(u64, u64)> {
+    let mut stats: libc::tpacket_stats = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tpacket_stats>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_PACKET,
+            libc::PACKET_STATISTICS,
+            &mut stats as *mut libc::tpacket_stats as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return None;
+    }
+    Some((stats.tp_packets as u64, stats.tp_drops as u64))
+}
+
+pub fn count_statistics(fd: libc::c_int) {
+    if let Some((received, dropped)) = statistics(fd) {
+        METRICS.kernel_received.fetch_add(received, Ordering::Relaxed);
+        METRICS.kernel_dropped.fetch_add(dropped, Ordering::Relaxed);
+    }
+}
+
+// the packet sockets we have open; pnet doesn't hand over its own, but its statistics can be read all the same
+pub fn packet_sockets() -> Vec<libc::c_int> {
+    let fds = std::fs::read_dir("/proc/self/fd")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<libc::c_int>().ok());
+
+    fds.filter(|fd| {
+        let mut domain: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                *fd,
+                libc::SOL_SOCKET,
+                libc::SO_DOMAIN,
+                &mut domain as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        result == 0 && domain == libc::AF_PACKET
+    })
+    .collect()
+}
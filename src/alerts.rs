@@ -0,0 +1,544 @@
+// alert rules loaded from a TOML file, evaluated against every request, e.g.
+//
+// [[rule]]
+// name = "telnet"
+// filter = "dst net 0.0.0.0/0 and dst port 23"
+//
+// [[rule]]
+// name = "big talker"
+// bytes_per_minute = 10_000_000
+// actions = [{ type = "webhook", url = "http://127.0.0.1:8080/alerts" }]
+//
+// [[rule]]
+// name = "evil dns"
+// dns_query = "*.evil.com"
+// actions = [{ type = "console" }, { type = "command", command = "logger -t sniff \"$SNIFF_ALERT\"" }]
+//
+// [[rule]]
+// name = "backup server"
+// rate_alert = "10.0.0.12=5MBps"
+// actions = [{ type = "webhook", url = "http://127.0.0.1:8080/alerts" }]
+//
+// [[rule]]
+// name = "ssh from outside"
+// filter = "dst port 22 and not src net 10.0.0.0/8"
+// actions = [
+//     { type = "desktop" },
+//     { type = "email", server = "smtp.example.com", from = "sniff@example.com", to = ["me@example.com"],
+//       username = "sniff@example.com" },
+//     { type = "exec", program = "/usr/local/bin/page", args = ["--summary", "{rule}: {orig_ip} -> {dest_ip}"] },
+// ]
+//
+// an email action's password is its `password`, or SNIFF_SMTP_PASSWORD in the environment so it needn't be in the file
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    aliases::Aliases,
+    conf::{IpAddr, RateAlert},
+    dns, filter, ip,
+    rules::RuleStats,
+    smtp::{self, Mailer, Security},
+    theme::Theme,
+    units, RequestStats,
+};
+
+const VOLUME_WINDOW: Duration = Duration::from_secs(60);
+// a host's rate is its traffic over this long, so one burst doesn't set it off
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(rename = "rule", default)]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    name: String,
+    filter: Option<String>,
+    dns_query: Option<String>,
+    bytes_per_minute: Option<u64>,
+    rate_alert: Option<String>, // HOST=RATE, as for --rate-alert
+    #[serde(default = "default_cooldown")]
+    cooldown: u64,
+    #[serde(default)]
+    actions: Vec<Action>,
+}
+
+fn default_cooldown() -> u64 {
+    60
+}
+
+fn default_security() -> Security {
+    Security::StartTls
+}
+
+// what an exec action's arguments can have filled in
+const PLACEHOLDERS: [&str; 7] = ["rule", "message", "protocol", "orig_ip", "dest_ip", "bytes", "timestamp"];
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum Action {
+    Console,
+    Webhook {
+        url: String,
+    },
+    Command {
+        command: String,
+    },
+    // a desktop notification, with the notify feature
+    Desktop,
+    Email {
+        server: String,
+        port: Option<u16>, // 465 for tls, 587 for starttls and 25 for none
+        #[serde(default = "default_security")]
+        security: Security,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    // run directly rather than through a shell, with {placeholders} in its arguments filled in
+    Exec {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+// a rule's conditions are all optional, and all of the ones given have to hold
+struct Rule {
+    name: String,
+    filter: Option<filter::Expr>,
+    dns_query: Option<String>,
+    bytes_per_minute: Option<u64>,
+    rate_alert: Option<RateAlert>,
+    cooldown: Duration,
+    actions: Vec<Action>,
+}
+
+pub struct AlertEngine {
+    rules: Vec<Rule>,
+    theme: Theme,
+    // keyed by (rule, origin), or the host for rate alerts
+    last_fired: HashMap<(usize, IpAddr), SystemTime>,               // -> when it last alerted
+    volume: HashMap<(usize, IpAddr), VecDeque<(SystemTime, u64)>>, // -> recent request sizes
+}
+
+impl AlertEngine {
+    pub fn load(path: &str, theme: Theme) -> AlertEngine {
+        AlertEngine {
+            rules: load_rules(path).unwrap_or_else(|e| panic!("{}", e)),
+            ..AlertEngine::new(theme)
+        }
+    }
+
+    // whether the rules file can be loaded, so a mistake in it is reported along with the rest of the options
+    pub fn check(path: &str) -> Result<(), String> {
+        load_rules(path).map(|_| ())
+    }
+
+    pub fn new(theme: Theme) -> AlertEngine {
+        AlertEngine {
+            rules: Vec::new(),
+            theme,
+            last_fired: HashMap::new(),
+            volume: HashMap::new(),
+        }
+    }
+
+    // --rate-alert, as a rule that only prints
+    pub fn add_rate_alert(&mut self, alert: &RateAlert) {
+        self.rules.push(Rule {
+            name: alert.to_string(),
+            filter: None,
+            dns_query: None,
+            bytes_per_minute: None,
+            rate_alert: Some(alert.clone()),
+            cooldown: Duration::from_secs(default_cooldown()),
+            actions: vec![Action::Console],
+        });
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.rules.iter().map(|x| x.name.clone()).collect()
+    }
+
+    // rules' filters see addresses, not the names they're shown as
+    pub fn expand_aliases(&mut self, aliases: &Aliases) {
+        for rule in self.rules.iter_mut() {
+            rule.filter = rule.filter.take().map(|x| aliases.expand(x));
+        }
+    }
+
+    // whether any rule alerted
+    pub fn evaluate(&mut self, stats: &RequestStats, rule_stats: &mut RuleStats) -> bool {
+        let mut alerted = false;
+        let query = ip::transport(&stats.raw)
+            .filter(|(_, dst_port, _)| *dst_port == 53)
+            .and_then(|(_, _, payload)| dns::query_name(payload))
+            .map(|x| x.to_ascii_lowercase());
+
+        for i in 0..self.rules.len() {
+            let rule = &self.rules[i];
+            let mut details = Vec::new();
+
+            // what the rule is counting for
+            let subject = match rule.rate_alert {
+                Some(ref alert) if alert.host == stats.orig_ip || alert.host == stats.dest_ip => alert.host.clone(),
+                Some(_) => continue,
+                None => stats.orig_ip.clone(),
+            };
+
+            if let Some(ref filter) = rule.filter {
+                let (orig, dest) = (stats.orig_ip.to_string(), stats.dest_ip.to_string());
+                if !filter.matches(stats, &orig, &dest) {
+                    continue;
+                }
+            }
+
+            if let Some(ref pattern) = rule.dns_query {
+                match query {
+                    Some(ref name) if glob(pattern, name) => details.push(format!("DNS query for {}", name)),
+                    _ => continue,
+                }
+            }
+
+            if let Some(threshold) = rule.bytes_per_minute {
+                let total = volume(&mut self.volume, (i, subject.clone()), stats, VOLUME_WINDOW);
+                if total <= threshold {
+                    continue;
+                }
+                details.push(format!("{} bytes from {} in the last minute", total, subject));
+            }
+
+            if let Some(ref alert) = rule.rate_alert {
+                let total = volume(&mut self.volume, (i, subject.clone()), stats, RATE_WINDOW);
+                let rate = total as f64 / RATE_WINDOW.as_secs_f64();
+                if rate <= alert.limit {
+                    continue;
+                }
+                details.push(format!(
+                    "{} at {} over the last {}s, limit {}",
+                    subject,
+                    units::human_rate(rate, None),
+                    RATE_WINDOW.as_secs(),
+                    units::human_rate(alert.limit, None)
+                ));
+            }
+
+            rule_stats.hit("alert", &rule.name);
+
+            // one alert per rule and origin (or host) per cooldown period, so a noisy host can't flood us
+            let key = (i, subject);
+            if let Some(last) = self.last_fired.get(&key) {
+                if stats.timestamp.duration_since(*last).unwrap_or_default() < rule.cooldown {
+                    continue;
+                }
+            }
+            self.last_fired.insert(key, stats.timestamp);
+            alerted = true;
+
+            let message = format!(
+                "ALERT [{}]: {} {} -> {}{}",
+                rule.name,
+                stats.protocol,
+                stats.orig_ip,
+                stats.dest_ip,
+                if details.is_empty() { String::new() } else { format!(" ({})", details.join(", ")) },
+            );
+
+            for action in rule.actions.iter() {
+                match action {
+                    Action::Console => outln!("{}", self.theme.paint(self.theme.warning, &message)),
+                    Action::Webhook { url } => post_webhook(url.clone(), &rule.name, stats, &message),
+                    Action::Command { command } => run_command(command.clone(), &rule.name, stats, &message),
+                    Action::Desktop => notify_desktop(&rule.name, &message),
+                    Action::Email {
+                        server,
+                        port,
+                        security,
+                        username,
+                        password,
+                        from,
+                        to,
+                    } => {
+                        let mailer = Mailer {
+                            server: server.clone(),
+                            port: port.unwrap_or(security.default_port()),
+                            security: *security,
+                            login: username.clone().zip(password.clone()),
+                            from: from.clone(),
+                            to: to.clone(),
+                        };
+                        send_email(mailer, &rule.name, stats, &message);
+                    }
+                    Action::Exec { program, args } => run_exec(program, args, &rule.name, stats, &message),
+                }
+            }
+        }
+
+        alerted
+    }
+}
+
+// the bytes counted towards a rule for this origin or host within the window, this request included
+fn volume(
+    windows: &mut HashMap<(usize, IpAddr), VecDeque<(SystemTime, u64)>>,
+    key: (usize, IpAddr),
+    stats: &RequestStats,
+    span: Duration,
+) -> u64 {
+    let window = windows.entry(key).or_default();
+    window.push_back((stats.timestamp, stats.bytes));
+    while let Some((timestamp, _)) = window.front() {
+        if stats.timestamp.duration_since(*timestamp).unwrap_or_default() <= span {
+            break;
+        }
+        window.pop_front();
+    }
+
+    window.iter().map(|(_, bytes)| bytes).sum()
+}
+
+// '*' matches any run of characters, everything else matches itself
+fn glob(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            let Some(text) = text.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=text.len())
+                .filter(|i| text.is_char_boundary(*i))
+                .any(|i| glob(rest, &text[i..]))
+        }
+    }
+}
+
+fn load_rules(path: &str) -> Result<Vec<Rule>, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("Failed to read alert rules {}: {}", path, e))?;
+    let file: RulesFile = toml::from_str(&data).map_err(|e| format!("Failed to parse alert rules {}: {}", path, e))?;
+
+    let mut rules = Vec::new();
+    for mut rule in file.rules {
+        for action in rule.actions.iter_mut() {
+            match action {
+                Action::Webhook { url } => match ureq::post(url).request_url() {
+                    Ok(x) if x.scheme() == "http" || x.scheme() == "https" => {}
+                    _ => {
+                        return Err(format!(
+                            "Alert rule \"{}\": invalid webhook URL {}, expected http:// or https://",
+                            rule.name, url
+                        ))
+                    }
+                },
+                #[cfg(not(feature = "notify"))]
+                Action::Desktop => {
+                    return Err(format!(
+                        "Alert rule \"{}\": sniff was built without desktop notifications (enable the `notify` \
+                         feature)",
+                        rule.name
+                    ))
+                }
+                Action::Email {
+                    security,
+                    username,
+                    password,
+                    to,
+                    ..
+                } => {
+                    if to.is_empty() {
+                        return Err(format!("Alert rule \"{}\": an email action needs someone to send it to", rule.name));
+                    }
+                    if username.is_some() && *security == Security::None {
+                        return Err(format!("Alert rule \"{}\": won't log in to an SMTP server without TLS", rule.name));
+                    }
+                    if username.is_some() && password.is_none() {
+                        *password = Some(std::env::var("SNIFF_SMTP_PASSWORD").map_err(|_| {
+                            format!(
+                                "Alert rule \"{}\": an email action with a username needs a password, or \
+                                 SNIFF_SMTP_PASSWORD set",
+                                rule.name
+                            )
+                        })?);
+                    }
+                }
+                Action::Exec { args, .. } => {
+                    if let Some(name) = args.iter().flat_map(|x| placeholders(x)).find(|x| !PLACEHOLDERS.contains(x)) {
+                        return Err(format!(
+                            "Alert rule \"{}\": unknown placeholder {{{}}}, expected one of {}",
+                            rule.name,
+                            name,
+                            PLACEHOLDERS.map(|x| format!("{{{}}}", x)).join(", ")
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // they'd be counting the same bytes over different windows
+        if rule.bytes_per_minute.is_some() && rule.rate_alert.is_some() {
+            return Err(format!("Alert rule \"{}\": use one of bytes_per_minute and rate_alert", rule.name));
+        }
+
+        rules.push(Rule {
+            filter: rule
+                .filter
+                .map(|x| x.parse())
+                .transpose()
+                .map_err(|e| format!("Alert rule \"{}\": {}", rule.name, e))?,
+            dns_query: rule.dns_query.map(|x| x.to_ascii_lowercase()),
+            bytes_per_minute: rule.bytes_per_minute,
+            rate_alert: rule
+                .rate_alert
+                .map(|x| x.parse())
+                .transpose()
+                .map_err(|e| format!("Alert rule \"{}\": {}", rule.name, e))?,
+            cooldown: Duration::from_secs(rule.cooldown),
+            actions: if rule.actions.is_empty() {
+                vec![Action::Console]
+            } else {
+                rule.actions
+            },
+            name: rule.name,
+        });
+    }
+    Ok(rules)
+}
+
+// fire and forget, so a slow or dead endpoint doesn't hold up the capture
+fn post_webhook(url: String, rule: &str, stats: &RequestStats, message: &str) {
+    let body = serde_json::json!({
+        "rule": rule,
+        "message": message,
+        "protocol": stats.protocol.to_string(),
+        "orig_ip": stats.orig_ip.to_string(),
+        "dest_ip": stats.dest_ip.to_string(),
+        "bytes": stats.bytes,
+        "timestamp": stats.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+    })
+    .to_string();
+
+    std::thread::spawn(move || {
+        let result = ureq::post(&url)
+            .timeout(Duration::from_secs(5))
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+
+        if let Err(e) = result {
+            eprintln!("Failed to post alert to {}: {}", url, e);
+        }
+    });
+}
+
+// the alert is passed in the environment, so commands don't have to parse anything
+fn alert_env(child: &mut std::process::Command, rule: &str, stats: &RequestStats, message: &str) {
+    child
+        .env("SNIFF_ALERT", message)
+        .env("SNIFF_RULE", rule)
+        .env("SNIFF_PROTOCOL", stats.protocol.to_string())
+        .env("SNIFF_ORIG_IP", stats.orig_ip.to_string())
+        .env("SNIFF_DEST_IP", stats.dest_ip.to_string());
+}
+
+// reap it in the background
+fn spawn(mut child: std::process::Command, name: &str) {
+    match child.spawn() {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => eprintln!("Failed to run alert command {}: {}", name, e),
+    }
+}
+
+fn run_command(command: String, rule: &str, stats: &RequestStats, message: &str) {
+    let mut child = if cfg!(windows) {
+        let mut child = std::process::Command::new("cmd");
+        child.arg("/C").arg(&command);
+        child
+    } else {
+        let mut child = std::process::Command::new("sh");
+        child.arg("-c").arg(&command);
+        child
+    };
+
+    alert_env(&mut child, rule, stats, message);
+    spawn(child, &command);
+}
+
+// no shell, so nothing from the network (a DNS name in the message, say) can be taken as anything but an argument
+fn run_exec(program: &str, args: &[String], rule: &str, stats: &RequestStats, message: &str) {
+    let values = [
+        rule.to_string(),
+        message.to_string(),
+        stats.protocol.to_string(),
+        stats.orig_ip.to_string(),
+        stats.dest_ip.to_string(),
+        stats.bytes.to_string(),
+        stats.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs().to_string(),
+    ];
+
+    let mut child = std::process::Command::new(program);
+    for arg in args.iter() {
+        let mut filled = arg.clone();
+        for (name, value) in PLACEHOLDERS.iter().zip(values.iter()) {
+            filled = filled.replace(&format!("{{{}}}", name), value);
+        }
+        child.arg(filled);
+    }
+    alert_env(&mut child, rule, stats, message);
+    spawn(child, program);
+}
+
+// the names between braces in an exec action's argument
+fn placeholders(arg: &str) -> impl Iterator<Item = &str> {
+    arg.split('{').skip(1).filter_map(|x| x.split_once('}')).map(|(name, _)| name)
+}
+
+#[cfg(feature = "notify")]
+fn notify_desktop(rule: &str, message: &str) {
+    let summary = format!("sniff: {}", rule);
+    let body = message.to_string();
+
+    // showing one can wait on the notification daemon
+    std::thread::spawn(move || {
+        if let Err(e) = notify_rust::Notification::new().appname("sniff").summary(&summary).body(&body).show() {
+            eprintln!("Failed to show alert notification: {}", e);
+        }
+    });
+}
+
+// rejected when the rules are loaded
+#[cfg(not(feature = "notify"))]
+fn notify_desktop(_rule: &str, _message: &str) {}
+
+// sent from a thread of its own, like webhooks, so a slow server doesn't hold up the capture
+fn send_email(mailer: Mailer, rule: &str, stats: &RequestStats, message: &str) {
+    // a subject is a header, so nothing in it can be allowed to start another
+    let subject: String = format!("sniff alert: {}", rule).chars().filter(|x| !x.is_control()).collect();
+    let body = format!(
+        "{}\n\nRule: {}\nProtocol: {}\nFrom: {}\nTo: {}\nBytes: {}\nSeen: {}\n",
+        message,
+        rule,
+        stats.protocol,
+        stats.orig_ip,
+        stats.dest_ip,
+        stats.bytes,
+        smtp::date(stats.timestamp)
+    );
+
+    std::thread::spawn(move || {
+        if let Err(e) = mailer.send(&subject, &body) {
+            eprintln!("Failed to email alert to {}: {}", mailer.to.join(", "), e);
+        }
+    });
+}
@@ -0,0 +1,192 @@
+// --aliases hosts.toml: friendly names for the hosts on a network, shown in place of their addresses and usable
+// wherever a filter takes a host, so DHCP-assigned addresses don't have to be remembered. Each name gets an address
+// or a list of them, IPs and MACs alike:
+//
+// nas = "192.168.1.10"
+// thermostat = "3c:71:bf:12:34:56"
+// laptop-anna = ["192.168.1.23", "fd00::23", "a4:83:e7:00:11:22"]
+//
+// a MAC only names the IP it's seen with when that IP's a private one, since anything from the internet arrives from
+// the router's MAC
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    conf::{Config, IpAddr, IpAddrOrHostname, MacAddr},
+    filter::{Direction, Expr},
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Addresses {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Clone, Copy)]
+enum Address {
+    Ip(std::net::IpAddr),
+    Mac(MacAddr),
+}
+
+#[derive(Clone, Default)]
+pub struct Aliases {
+    ips: HashMap<std::net::IpAddr, String>,
+    macs: HashMap<MacAddr, String>,
+    addresses: HashMap<String, Vec<Address>>, // lowercased name ->
+}
+
+impl Aliases {
+    pub fn load(path: &str) -> Aliases {
+        let data = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read aliases {}: {}", path, e));
+        let file: HashMap<String, Addresses> =
+            toml::from_str(&data).unwrap_or_else(|e| panic!("Failed to parse aliases {}: {}", path, e));
+
+        let mut aliases = Aliases::default();
+        for (name, addresses) in file {
+            // shown between colons and ports, and parsed back out of filters
+            if name.is_empty() || name.contains(|x: char| x == ':' || x == '(' || x == ')' || x.is_whitespace()) {
+                panic!(
+                    "Alias \"{}\" can't be used as a name: no spaces, colons or parentheses",
+                    name
+                );
+            }
+
+            let addresses = match addresses {
+                Addresses::One(address) => vec![address],
+                Addresses::Many(addresses) => addresses,
+            };
+            if addresses.is_empty() {
+                panic!("Alias \"{}\" needs at least one address", name);
+            }
+
+            let mut parsed = Vec::new();
+            for address in addresses {
+                let (parsed_address, taken) = if let Ok(ip) = address.parse::<std::net::IpAddr>() {
+                    (Address::Ip(ip), aliases.ips.insert(ip, name.clone()))
+                } else if let Ok(mac) = address.parse::<MacAddr>() {
+                    (Address::Mac(mac), aliases.macs.insert(mac, name.clone()))
+                } else {
+                    panic!(
+                        "Alias \"{}\": invalid address {}, expected an IP or a MAC",
+                        name, address
+                    );
+                };
+                if let Some(other) = taken.filter(|x| *x != name) {
+                    panic!("Alias \"{}\": {} is already \"{}\"", name, address, other);
+                }
+                parsed.push(parsed_address);
+            }
+            aliases.addresses.insert(name.to_lowercase(), parsed);
+        }
+
+        aliases
+    }
+
+    // the name to show for one end of a request, if it has one
+    pub fn name(&self, ip: &IpAddr, mac: MacAddr, by_mac: bool) -> Option<&str> {
+        let by_ip = if by_mac { None } else { self.ips.get(&ip.to_std()) };
+        by_ip
+            .or_else(|| {
+                if by_mac || ip.is_private() {
+                    self.macs.get(&mac)
+                } else {
+                    None
+                }
+            })
+            .map(|x| x.as_str())
+    }
+
+    // the MACs a name stands for, if it's one of ours
+    pub fn macs(&self, name: &str) -> Option<Vec<MacAddr>> {
+        let addresses = self.addresses.get(&name.to_lowercase())?;
+        Some(
+            addresses
+                .iter()
+                .filter_map(|x| match x {
+                    Address::Mac(mac) => Some(*mac),
+                    Address::Ip(_) => None,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.addresses.contains_key(&name.to_lowercase())
+    }
+
+    // the names in the filters given on the command line, as the addresses they stand for
+    pub fn expand_config(&self, config: &mut Config) {
+        for hosts in [
+            &mut config.filter_ips,
+            &mut config.exclude_ips,
+            &mut config.highlight_ips,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *hosts = self.expand_hosts(std::mem::take(hosts));
+        }
+
+        for expr in [&mut config.filter, &mut config.trigger].into_iter().flatten() {
+            *expr = self.expand(expr.clone());
+        }
+        for output in config.outputs.iter_mut() {
+            if let Some(ref mut expr) = output.filter {
+                *expr = self.expand(expr.clone());
+            }
+        }
+    }
+
+    // an alias's IPs go in its place; one with MACs is also still matched by name, which is what it's shown as
+    pub fn expand_hosts(&self, hosts: Vec<IpAddrOrHostname>) -> Vec<IpAddrOrHostname> {
+        let mut expanded = Vec::new();
+        for host in hosts {
+            let addresses = match host {
+                IpAddrOrHostname::Hostname(ref name) => self.addresses.get(&name.to_lowercase()),
+                _ => None,
+            };
+            let Some(addresses) = addresses else {
+                expanded.push(host);
+                continue;
+            };
+
+            for address in addresses.iter() {
+                if let Address::Ip(ip) = address {
+                    expanded.push(IpAddrOrHostname::Ip((*ip).into()));
+                }
+            }
+            if addresses.iter().any(|x| matches!(x, Address::Mac(_))) {
+                expanded.push(host);
+            }
+        }
+        expanded
+    }
+
+    // "host nas" becomes "host 192.168.1.10 or ether host ...", for every address nas has
+    pub fn expand(&self, expr: Expr) -> Expr {
+        match expr {
+            Expr::And(a, b) => Expr::And(Box::new(self.expand(*a)), Box::new(self.expand(*b))),
+            Expr::Or(a, b) => Expr::Or(Box::new(self.expand(*a)), Box::new(self.expand(*b))),
+            Expr::Not(a) => Expr::Not(Box::new(self.expand(*a))),
+            Expr::Hostname(direction, name) => match self.addresses.get(&name.to_lowercase()) {
+                Some(addresses) => addresses
+                    .iter()
+                    .map(|x| host(direction, *x))
+                    .reduce(|a, b| Expr::Or(Box::new(a), Box::new(b)))
+                    .unwrap(),
+                None => Expr::Hostname(direction, name),
+            },
+            expr => expr,
+        }
+    }
+}
+
+fn host(direction: Direction, address: Address) -> Expr {
+    match address {
+        Address::Ip(ip) => Expr::Host(direction, ip),
+        Address::Mac(mac) => Expr::Ether(direction, mac),
+    }
+}
@@ -0,0 +1,335 @@
+// --tcp-anomalies: TCP connections going wrong, which a stream of requests doesn't make obvious: handshakes that fail
+// (refused, never answered, or never completed), connections reset by one end, and storms of retransmissions
+//
+// each is called out as it happens, and totalled per connection at exit
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, theme::Theme};
+
+// how long a handshake gets before it's failed; clients give up on a SYN after a few retries, 3s apart or more
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// this many retransmissions within STORM_WINDOW is a storm, which lasts until the retransmissions stop
+const STORM_RETRANSMITS: usize = 10;
+const STORM_WINDOW: Duration = Duration::from_secs(1);
+// a copy of the segment just before it this soon is the same packet seen twice (e.g. on loopback), not a resend
+const DUPLICATE_WINDOW: Duration = Duration::from_millis(1);
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+const REPORT_FLOWS: usize = 20;
+
+enum State {
+    SynSent { since: SystemTime, syns: u32 },
+    SynReceived { since: SystemTime },
+    Established,
+}
+
+// what one end has sent
+#[derive(Default)]
+struct Side {
+    seq_end: Option<u32>,
+    last_segment: Option<(u32, u32, SystemTime)>, // seq, length, when
+    retransmits: VecDeque<SystemTime>,            // within the last STORM_WINDOW
+    storm: bool,
+    fin: bool,
+}
+
+struct Connection {
+    ends: [SocketAddr; 2], // client first, if we saw the handshake; otherwise the lower
+    state: State,
+    sides: [Side; 2],
+    last: SystemTime,
+}
+
+#[derive(Default)]
+struct Counts {
+    refused: u64,
+    timed_out: u64,
+    incomplete: u64,
+    resets: u64,
+    storms: u64,
+}
+
+impl Counts {
+    fn total(&self) -> u64 {
+        self.refused + self.timed_out + self.incomplete + self.resets + self.storms
+    }
+}
+
+pub struct AnomalyTracker {
+    theme: Theme,
+    connections: HashMap<(SocketAddr, SocketAddr), Connection>, // the ends, lower first
+    counts: BTreeMap<(SocketAddr, SocketAddr), Counts>,         // by the connection's ends, client first
+    checked: Option<SystemTime>,
+    finished: bool, // once the capture's over, anomalies are only counted, for the report
+}
+
+impl AnomalyTracker {
+    pub fn new(theme: Theme) -> AnomalyTracker {
+        AnomalyTracker {
+            theme,
+            connections: HashMap::new(),
+            counts: BTreeMap::new(),
+            checked: None,
+            finished: false,
+        }
+    }
+
+    // look at one IP packet, following the state of the TCP connection it's part of
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet).filter(|x| x.protocol == 6) else {
+            return;
+        };
+        // short frames are padded out, and the padding isn't part of the segment
+        let end = ip::total_len(packet).unwrap_or(packet.len()).min(packet.len());
+        let Some(segment) = packet.get(header.header_len..end).filter(|x| x.len() >= 20) else {
+            return;
+        };
+
+        let src = SocketAddr::new(header.src, u16::from_be_bytes([segment[0], segment[1]]));
+        let dst = SocketAddr::new(header.dst, u16::from_be_bytes([segment[2], segment[3]]));
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let header_len = (segment[12] >> 4) as usize * 4;
+        let flags = segment[13];
+        let (fin, syn, rst, ack) = (
+            flags & 0x01 != 0,
+            flags & 0x02 != 0,
+            flags & 0x04 != 0,
+            flags & 0x10 != 0,
+        );
+        let payload_len = segment.len().saturating_sub(header_len) as u32;
+
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.check(timestamp);
+        }
+
+        let key = if src < dst { (src, dst) } else { (dst, src) };
+
+        // a reset of something we know nothing about (or have just seen reset) isn't news
+        if rst && !self.connections.contains_key(&key) {
+            return;
+        }
+
+        if syn && !ack {
+            match self.connections.get_mut(&key) {
+                Some(Connection {
+                    state: State::SynSent { syns, .. },
+                    sides,
+                    ..
+                }) => {
+                    if !is_duplicate(&sides[0], seq, payload_len, timestamp) {
+                        *syns += 1;
+                    }
+                }
+                _ => {
+                    self.connections.insert(
+                        key,
+                        Connection {
+                            ends: [src, dst],
+                            state: State::SynSent {
+                                since: timestamp,
+                                syns: 1,
+                            },
+                            sides: Default::default(),
+                            last: timestamp,
+                        },
+                    );
+                }
+            }
+        }
+
+        // a connection already open when the capture started
+        let connection = self.connections.entry(key).or_insert_with(|| Connection {
+            ends: [key.0, key.1],
+            state: State::Established,
+            sides: Default::default(),
+            last: timestamp,
+        });
+        connection.last = timestamp;
+        let this = if connection.ends[0] == src { 0 } else { 1 };
+
+        if rst {
+            let connection = self.connections.remove(&key).unwrap();
+            let closing = connection.sides.iter().any(|x| x.fin);
+
+            match connection.state {
+                State::SynSent { .. } if this == 1 => {
+                    self.anomaly(connection.ends, "connection refused", |x| x.refused += 1);
+                }
+                // plenty of applications reset a connection instead of finishing closing it
+                State::Established | State::SynReceived { .. } if !closing => {
+                    let message = format!("connection reset by {}", src);
+                    self.anomaly(connection.ends, &message, |x| x.resets += 1);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match connection.state {
+            State::SynSent { .. } if syn && ack && this == 1 => {
+                connection.state = State::SynReceived { since: timestamp };
+            }
+            State::SynReceived { .. } if ack && !syn && this == 0 => connection.state = State::Established,
+            _ => {}
+        }
+
+        let side = &mut connection.sides[this];
+        side.fin |= fin;
+
+        // SYN and FIN each take up a sequence number
+        let seq_end = seq.wrapping_add(payload_len + syn as u32 + fin as u32);
+        let duplicate = is_duplicate(side, seq, payload_len, timestamp);
+        side.last_segment = Some((seq, payload_len, timestamp));
+
+        let retransmit = payload_len > 0 && !duplicate && side.seq_end.is_some_and(|x| !after(seq_end, x));
+        if side.seq_end.is_none_or(|x| after(seq_end, x)) {
+            side.seq_end = Some(seq_end);
+        }
+
+        while side
+            .retransmits
+            .front()
+            .is_some_and(|x| timestamp.duration_since(*x).unwrap_or_default() >= STORM_WINDOW)
+        {
+            side.retransmits.pop_front();
+        }
+        if side.retransmits.is_empty() {
+            side.storm = false;
+        }
+
+        if retransmit {
+            side.retransmits.push_back(timestamp);
+            if side.retransmits.len() >= STORM_RETRANSMITS && !side.storm {
+                side.storm = true;
+                let ends = connection.ends;
+                let message = format!(
+                    "retransmission storm from {}, {} resent in {}s",
+                    src,
+                    STORM_RETRANSMITS,
+                    STORM_WINDOW.as_secs()
+                );
+                self.anomaly(ends, &message, |x| x.storms += 1);
+            }
+        }
+
+        if self
+            .connections
+            .get(&key)
+            .is_some_and(|x| x.sides.iter().all(|x| x.fin))
+        {
+            self.connections.remove(&key);
+        }
+    }
+
+    // fail handshakes that have gone unanswered too long, and forget idle connections
+    fn check(&mut self, now: SystemTime) {
+        let age = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+        let mut failed = Vec::new();
+
+        self.connections.retain(|_, connection| match connection.state {
+            State::SynSent { since, syns } if age(since) >= HANDSHAKE_TIMEOUT => {
+                failed.push((connection.ends, Some(syns)));
+                false
+            }
+            State::SynReceived { since } if age(since) >= HANDSHAKE_TIMEOUT => {
+                failed.push((connection.ends, None));
+                false
+            }
+            _ => age(connection.last) < IDLE_TIMEOUT,
+        });
+
+        for (ends, syns) in failed {
+            match syns {
+                Some(syns) => {
+                    let message = format!(
+                        "handshake timed out, {} SYN{} unanswered",
+                        syns,
+                        if syns == 1 { "" } else { "s" }
+                    );
+                    self.anomaly(ends, &message, |x| x.timed_out += 1);
+                }
+                None => self.anomaly(ends, "handshake never completed", |x| x.incomplete += 1),
+            }
+        }
+    }
+
+    fn anomaly(&mut self, ends: [SocketAddr; 2], message: &str, count: impl FnOnce(&mut Counts)) {
+        count(self.counts.entry((ends[0], ends[1])).or_default());
+
+        if self.finished {
+            return;
+        }
+        let message = format!("*** TCP {}: {} -> {} ***", message, ends[0], ends[1]);
+        outln!("{}", self.theme.paint(self.theme.anomaly, &message));
+    }
+
+    pub fn print_report(mut self) {
+        // handshakes still waiting at the end are only failed if they've had long enough
+        self.finished = true;
+        self.check(SystemTime::now());
+
+        if self.counts.is_empty() {
+            return;
+        }
+
+        let sum = |f: fn(&Counts) -> u64| self.counts.values().map(f).sum::<u64>();
+        let (refused, timed_out, incomplete) = (sum(|x| x.refused), sum(|x| x.timed_out), sum(|x| x.incomplete));
+
+        println!("TCP anomalies:");
+        println!(
+            "    {} failed handshake{} ({} refused, {} timed out, {} never completed), {} reset{}, {} retransmission storm{}",
+            refused + timed_out + incomplete,
+            if refused + timed_out + incomplete == 1 { "" } else { "s" },
+            refused,
+            timed_out,
+            incomplete,
+            sum(|x| x.resets),
+            if sum(|x| x.resets) == 1 { "" } else { "s" },
+            sum(|x| x.storms),
+            if sum(|x| x.storms) == 1 { "" } else { "s" },
+        );
+
+        let mut flows: Vec<_> = self.counts.iter().collect();
+        flows.sort_by_key(|(_, x)| std::cmp::Reverse(x.total()));
+
+        for ((client, server), counts) in flows.iter().take(REPORT_FLOWS) {
+            let kinds: Vec<String> = [
+                (counts.refused, "refused"),
+                (counts.timed_out, "timed out"),
+                (counts.incomplete, "never completed"),
+                (counts.resets, "reset"),
+                (counts.storms, "retransmission storm"),
+            ]
+            .iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, kind)| format!("{} {}", kind, n))
+            .collect();
+            println!("    {} -> {}: {}", client, server, kinds.join(", "));
+        }
+        if flows.len() > REPORT_FLOWS {
+            println!("    ... and {} more", flows.len() - REPORT_FLOWS);
+        }
+    }
+}
+
+// whether a segment is another copy of the one just before it from the same side
+fn is_duplicate(side: &Side, seq: u32, len: u32, timestamp: SystemTime) -> bool {
+    side.last_segment.is_some_and(|(last_seq, last_len, when)| {
+        last_seq == seq && last_len == len && timestamp.duration_since(when).unwrap_or_default() < DUPLICATE_WINDOW
+    })
+}
+
+// whether sequence number a comes after b, allowing for wraparound
+fn after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
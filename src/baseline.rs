@@ -0,0 +1,355 @@
+// --baseline FILE: a lightweight passive IDS. For the first --baseline-window of capture (1h by default, carried over
+// between captures until it's done) it learns what's normal for each host on the local network: how many bytes a
+// minute it moves over each protocol, the ports it uses and the countries it talks to. From then on it warns about
+// whatever doesn't fit:
+//
+//     a host that wasn't there while learning
+//     a host's first use of a port (or protocol) it never used before
+//     a host talking to a country it never did before (with --geoip)
+//     a minute of traffic 10x a host's usual for that protocol, and well outside its usual spread
+//
+// each new host, port and country is only called out once; the baseline itself isn't changed by what's seen after it
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::Protocol, ip, roles, theme::Theme, units, RequestStats};
+
+// how far over a host's usual minute it has to be, both as a multiple and in standard deviations
+const VOLUME_FACTOR: f64 = 10.0;
+const VOLUME_DEVIATIONS: f64 = 3.0;
+// too little to bother with, however unusual
+const MIN_VOLUME: u64 = 64 * 1024;
+const REPORT_HOSTS: usize = 20;
+// where ephemeral ports start on Linux (and later still elsewhere); a connection between two of them is counted as one
+// service, rather than each being a port never used before
+const EPHEMERAL_PORTS: u16 = 32768;
+
+#[derive(Serialize, Deserialize, Default)]
+struct Baseline {
+    minutes: u64, // learned so far
+    hosts: BTreeMap<IpAddr, Host>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Host {
+    volume: BTreeMap<String, Volume>, // bytes a minute, by protocol
+    services: BTreeSet<String>,       // e.g. TCP/443, or ICMP
+    countries: BTreeSet<String>,      // of the hosts it's talked to
+}
+
+// over every minute learned, including the ones it was quiet in
+#[derive(Serialize, Deserialize, Default)]
+struct Volume {
+    sum: f64,
+    sum_squares: f64,
+}
+
+#[derive(Default)]
+struct Deviations {
+    new_host: bool,
+    services: u64,
+    countries: u64,
+    volume: u64,
+}
+
+pub struct BaselineTracker {
+    path: String,
+    window: u64, // minutes to learn for
+    baseline: Baseline,
+    theme: Theme,
+    minute: Option<u64>,                     // the one being counted, since the epoch
+    current: HashMap<(IpAddr, String), u64>, // bytes this minute, by host and protocol
+    flagged: HashMap<(IpAddr, String), u64>, // the minute each was last called out for its volume
+    deviations: BTreeMap<IpAddr, Deviations>,
+}
+
+impl BaselineTracker {
+    pub fn new(path: &str, window: Duration, theme: Theme) -> BaselineTracker {
+        let baseline = match std::fs::read(path) {
+            Ok(data) => {
+                serde_json::from_slice(&data).unwrap_or_else(|e| panic!("Failed to read baseline {}: {}", path, e))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Baseline::default(),
+            Err(e) => panic!("Failed to read baseline {}: {}", path, e),
+        };
+
+        let tracker = BaselineTracker {
+            path: path.to_string(),
+            window: window.as_secs().div_ceil(60).max(1),
+            baseline,
+            theme,
+            minute: None,
+            current: HashMap::new(),
+            flagged: HashMap::new(),
+            deviations: BTreeMap::new(),
+        };
+
+        if tracker.learning() {
+            println!(
+                "[baseline] learning for {} more minute{}",
+                tracker.window - tracker.baseline.minutes,
+                if tracker.window - tracker.baseline.minutes == 1 {
+                    ""
+                } else {
+                    "s"
+                }
+            );
+        }
+
+        tracker
+    }
+
+    fn learning(&self) -> bool {
+        self.baseline.minutes < self.window
+    }
+
+    pub fn observe(&mut self, stats: &RequestStats) {
+        if matches!(stats.protocol, Protocol::Ether(_) | Protocol::Unknown) {
+            return;
+        }
+
+        let minute = stats
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 60;
+        self.roll(minute);
+
+        let protocol = stats.protocol.to_string();
+        let service = match (stats.protocol, ip::transport(&stats.raw)) {
+            (Protocol::Tcp | Protocol::Udp, Some((src_port, dst_port, _))) => match src_port.min(dst_port) {
+                port if port >= EPHEMERAL_PORTS => format!("{}/ephemeral", protocol),
+                port => format!("{}/{}", protocol, port),
+            },
+            _ => protocol.clone(),
+        };
+        let country = |geo: &Option<crate::geoip::GeoInfo>| geo.as_ref().and_then(|x| x.country.clone());
+
+        let ends = [
+            (&stats.orig_ip, &stats.dest_ip, country(&stats.dest_geo)),
+            (&stats.dest_ip, &stats.orig_ip, country(&stats.orig_geo)),
+        ];
+
+        // only hosts on the local network have a baseline; everything out on the internet is who they talk to
+        for (host, peer, peer_country) in ends {
+            if roles::is_internet(host) {
+                continue;
+            }
+            let (host, peer) = (host.to_std(), peer.to_std());
+            if !host.is_multicast() && !host.is_unspecified() {
+                self.observe_host(host, peer, &protocol, &service, peer_country, stats.bytes);
+            }
+        }
+    }
+
+    fn observe_host(
+        &mut self,
+        host: IpAddr,
+        peer: IpAddr,
+        protocol: &str,
+        service: &str,
+        country: Option<String>,
+        bytes: u64,
+    ) {
+        let minute_bytes = self.current.entry((host, protocol.to_string())).or_default();
+        *minute_bytes += bytes;
+        let minute_bytes = *minute_bytes;
+
+        if self.learning() {
+            let learned = self.baseline.hosts.entry(host).or_default();
+            learned.services.insert(service.to_string());
+            learned.countries.extend(country);
+            return;
+        }
+
+        let minutes = self.baseline.minutes as f64;
+        let Some(learned) = self.baseline.hosts.get_mut(&host) else {
+            // called out once, then learned, so the rest of its traffic isn't every kind of deviation at once
+            let message = format!(
+                "[baseline] new host {} (talking to {}), not seen while learning",
+                host, peer
+            );
+            outln!("{}", self.theme.paint(self.theme.anomaly, &message));
+            self.deviations.entry(host).or_default().new_host = true;
+
+            let learned = self.baseline.hosts.entry(host).or_default();
+            learned.services.insert(service.to_string());
+            learned.countries.extend(country);
+            return;
+        };
+
+        let mut messages = Vec::new();
+        let deviations = self.deviations.entry(host).or_default();
+
+        if learned.services.insert(service.to_string()) {
+            messages.push(format!(
+                "[baseline] {} used {} (with {}) for the first time",
+                host, service, peer
+            ));
+            deviations.services += 1;
+        }
+
+        if let Some(country) = country {
+            if learned.countries.insert(country.clone()) {
+                messages.push(format!(
+                    "[baseline] {} talked to {} in {}, a new country for it",
+                    host, peer, country
+                ));
+                deviations.countries += 1;
+            }
+        }
+
+        // a protocol it's never used has no usual volume, and was called out as a new service already
+        if let Some(volume) = learned.volume.get(protocol) {
+            let mean = volume.sum / minutes;
+            let deviation = (volume.sum_squares / minutes - mean * mean).max(0.0).sqrt();
+            let bytes = minute_bytes as f64;
+            let minute = self.minute.unwrap_or_default();
+
+            if minute_bytes >= MIN_VOLUME
+                && bytes >= mean * VOLUME_FACTOR
+                && bytes >= mean + deviation * VOLUME_DEVIATIONS
+                && self.flagged.insert((host, protocol.to_string()), minute) != Some(minute)
+            {
+                messages.push(format!(
+                    "[baseline] {} has moved {} of {} this minute, {:.0}x its usual {}",
+                    host,
+                    units::human_bytes(minute_bytes, None),
+                    protocol,
+                    bytes / mean.max(1.0),
+                    units::human_bytes(mean as u64, None)
+                ));
+                deviations.volume += 1;
+            }
+        }
+
+        for message in messages {
+            outln!("{}", self.theme.paint(self.theme.anomaly, &message));
+        }
+    }
+
+    // start counting a new minute, adding the last one (and any quiet ones since) to what's been learned
+    fn roll(&mut self, minute: u64) {
+        let Some(current) = self.minute else {
+            self.minute = Some(minute);
+            return;
+        };
+        if minute <= current {
+            return;
+        }
+        self.minute = Some(minute);
+
+        if !self.learning() {
+            self.current.clear();
+            return;
+        }
+
+        self.learn_minute();
+        // the quiet minutes in between count as nothing sent
+        self.baseline.minutes = (self.baseline.minutes + minute - current - 1).min(self.window);
+
+        if !self.learning() {
+            match self.save() {
+                Ok(()) => println!(
+                    "[baseline] learned {} host{} over {} minutes, saved to {}; watching for deviations",
+                    self.baseline.hosts.len(),
+                    if self.baseline.hosts.len() == 1 { "" } else { "s" },
+                    self.baseline.minutes,
+                    self.path
+                ),
+                Err(e) => eprintln!("Failed to save baseline {}: {}", self.path, e),
+            }
+        }
+    }
+
+    fn learn_minute(&mut self) {
+        for ((host, protocol), bytes) in self.current.drain() {
+            let volume = self
+                .baseline
+                .hosts
+                .entry(host)
+                .or_default()
+                .volume
+                .entry(protocol)
+                .or_default();
+            volume.sum += bytes as f64;
+            volume.sum_squares += (bytes as f64) * (bytes as f64);
+        }
+        self.baseline.minutes += 1;
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&self.baseline)?)
+    }
+
+    pub fn print_report(mut self) {
+        if self.learning() {
+            // what's been learned so far is kept, and the next capture carries on from there
+            if self.minute.is_some() {
+                self.learn_minute();
+            }
+            match self.save() {
+                Ok(()) => println!(
+                    "Baseline: learned {} of {} minutes so far, saved to {} (the next capture carries on learning)",
+                    self.baseline.minutes.min(self.window),
+                    self.window,
+                    self.path
+                ),
+                Err(e) => eprintln!("Failed to save baseline {}: {}", self.path, e),
+            }
+            return;
+        }
+
+        if self.deviations.is_empty() {
+            println!("Baseline: no deviations");
+            return;
+        }
+
+        let sum = |f: fn(&Deviations) -> u64| self.deviations.values().map(f).sum::<u64>();
+        println!("Baseline deviations:");
+        println!(
+            "    {} new host{}, {} new port{}, {} new countr{}, {} volume spike{}",
+            sum(|x| x.new_host as u64),
+            if sum(|x| x.new_host as u64) == 1 { "" } else { "s" },
+            sum(|x| x.services),
+            if sum(|x| x.services) == 1 { "" } else { "s" },
+            sum(|x| x.countries),
+            if sum(|x| x.countries) == 1 { "y" } else { "ies" },
+            sum(|x| x.volume),
+            if sum(|x| x.volume) == 1 { "" } else { "s" },
+        );
+
+        let mut hosts: Vec<_> = self.deviations.iter().collect();
+        hosts.sort_by_key(|(_, x)| std::cmp::Reverse(x.services + x.countries + x.volume));
+
+        for (host, deviations) in hosts.iter().take(REPORT_HOSTS) {
+            let kinds: Vec<String> = [
+                (deviations.services, "new ports"),
+                (deviations.countries, "new countries"),
+                (deviations.volume, "volume spikes"),
+            ]
+            .iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, kind)| format!("{} {}", kind, n))
+            .collect();
+
+            println!(
+                "    {}{}{}",
+                host,
+                if deviations.new_host { " (new host)" } else { "" },
+                if kinds.is_empty() {
+                    String::new()
+                } else {
+                    format!(": {}", kinds.join(", "))
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// Bluetooth LE advertisement scanning over a raw HCI socket (Linux only, behind the `ble` feature)
+// advertisers are fed into the shared device inventory alongside Wi-Fi/Ethernet devices
+
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{
+    conf::MacAddr,
+    inventory::{DeviceKind, Inventory},
+};
+
+const AF_BLUETOOTH: libc::c_int = 31;
+const BTPROTO_HCI: libc::c_int = 1;
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+const HCI_CHANNEL_RAW: u16 = 0;
+
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+const EVT_LE_META_EVENT: u8 = 0x3e;
+const EVT_LE_ADVERTISING_REPORT: u8 = 0x02;
+
+const OGF_LE_CTL: u16 = 0x08;
+const OCF_LE_SET_SCAN_PARAMETERS: u16 = 0x000b;
+const OCF_LE_SET_SCAN_ENABLE: u16 = 0x000c;
+
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+// one advertisement, as pulled out of an LE advertising report
+pub struct Advertisement {
+    pub addr: MacAddr,
+    pub name: Option<String>,
+    pub details: Option<String>,
+    pub rssi: i8,
+}
+
+// open hci<dev>, start a passive scan, and feed every advertisement into the inventory from a background thread
+pub fn spawn_scanner(dev: u16, inventory: Arc<Mutex<Inventory>>) -> std::io::Result<()> {
+    let fd = unsafe { libc::socket(AF_BLUETOOTH, libc::SOCK_RAW | libc::SOCK_CLOEXEC, BTPROTO_HCI) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let addr = SockaddrHci {
+        hci_family: AF_BLUETOOTH as libc::sa_family_t,
+        hci_dev: dev,
+        hci_channel: HCI_CHANNEL_RAW,
+    };
+
+    let filter = HciFilter {
+        type_mask: 1 << HCI_EVENT_PKT,
+        event_mask: [0, 1 << (EVT_LE_META_EVENT - 32)],
+        opcode: 0,
+    };
+
+    unsafe {
+        if libc::bind(
+            fd,
+            &addr as *const SockaddrHci as *const libc::sockaddr,
+            std::mem::size_of::<SockaddrHci>() as libc::socklen_t,
+        ) < 0
+            || libc::setsockopt(
+                fd,
+                SOL_HCI,
+                HCI_FILTER,
+                &filter as *const HciFilter as *const libc::c_void,
+                std::mem::size_of::<HciFilter>() as libc::socklen_t,
+            ) < 0
+        {
+            let err = std::io::Error::last_os_error();
+            libc::close(fd);
+            return Err(err);
+        }
+    }
+
+    // passive scan, 10ms interval and window, public address, accept all advertisements
+    send_command(fd, OCF_LE_SET_SCAN_PARAMETERS, &[0x00, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00])?;
+    // enable, without duplicate filtering so we keep getting RSSI updates
+    send_command(fd, OCF_LE_SET_SCAN_ENABLE, &[0x01, 0x00])?;
+
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 258];
+
+        loop {
+            let len = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if len <= 0 {
+                break;
+            }
+
+            for advertisement in parse_advertising_report(&buf[..len as usize]) {
+                let mut inventory = inventory.lock().unwrap();
+                let device = inventory.observe(advertisement.addr, DeviceKind::Ble, SystemTime::now());
+
+                device.signal = Some(advertisement.rssi);
+                if advertisement.name.is_some() {
+                    device.name = advertisement.name;
+                }
+                if advertisement.details.is_some() {
+                    device.details = advertisement.details;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn send_command(fd: libc::c_int, ocf: u16, params: &[u8]) -> std::io::Result<()> {
+    let opcode = (OGF_LE_CTL << 10) | ocf;
+
+    let mut packet = vec![HCI_COMMAND_PKT];
+    packet.extend_from_slice(&opcode.to_le_bytes());
+    packet.push(params.len() as u8);
+    packet.extend_from_slice(params);
+
+    let written = unsafe { libc::write(fd, packet.as_ptr() as *const libc::c_void, packet.len()) };
+    if written < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn parse_advertising_report(data: &[u8]) -> Vec<Advertisement> {
+    let mut advertisements = Vec::new();
+
+    // packet type, event code, parameter length, subevent, number of reports
+    if data.len() < 5
+        || data[0] != HCI_EVENT_PKT
+        || data[1] != EVT_LE_META_EVENT
+        || data[3] != EVT_LE_ADVERTISING_REPORT
+    {
+        return advertisements;
+    }
+
+    let mut reports = &data[5..];
+
+    for _ in 0..data[4] {
+        // event type, address type, address (little endian), data length, data, rssi
+        if reports.len() < 9 {
+            break;
+        }
+
+        let mut octets: [u8; 6] = reports[2..8].try_into().unwrap();
+        octets.reverse();
+
+        let data_len = reports[8] as usize;
+        let (Some(ad), Some(rssi)) = (reports.get(9..9 + data_len), reports.get(9 + data_len)) else {
+            break;
+        };
+
+        let (name, details) = parse_ad_structures(ad);
+
+        advertisements.push(Advertisement {
+            addr: MacAddr::from(octets),
+            name,
+            details,
+            rssi: *rssi as i8,
+        });
+
+        reports = &reports[10 + data_len..];
+    }
+
+    advertisements
+}
+
+// pull the local name, and anything that identifies the kind of beacon/tracker, out of the advertising data
+fn parse_ad_structures(mut ad: &[u8]) -> (Option<String>, Option<String>) {
+    let mut name = None;
+    let mut details = None;
+
+    while ad.len() >= 2 {
+        let len = ad[0] as usize;
+        if len == 0 || ad.len() < len + 1 {
+            break;
+        }
+
+        let (ad_type, value) = (ad[1], &ad[2..len + 1]);
+
+        match ad_type {
+            // shortened/complete local name
+            0x08 | 0x09 => name = Some(String::from_utf8_lossy(value).to_string()),
+            // 16-bit service UUIDs
+            0x02 | 0x03 | 0x16 if value.len() >= 2 => {
+                let uuid = u16::from_le_bytes([value[0], value[1]]);
+                if let Some(tracker) = match uuid {
+                    0xfeed | 0xfeec => Some("Tile tracker"),
+                    0xfd5a => Some("Samsung SmartTag"),
+                    0xfe2c => Some("Google Fast Pair"),
+                    0xfeaa => Some("Eddystone beacon"),
+                    _ => None,
+                } {
+                    details = Some(tracker.to_string());
+                }
+            }
+            // manufacturer specific data, starting with the company identifier
+            0xff if value.len() >= 2 => {
+                let company = u16::from_le_bytes([value[0], value[1]]);
+                let description = match (company, value.get(2)) {
+                    (0x004c, Some(0x02)) => "iBeacon".to_string(),
+                    (0x004c, Some(0x12)) => "Apple Find My".to_string(),
+                    (0x004c, _) => "Apple".to_string(),
+                    (0x0006, _) => "Microsoft".to_string(),
+                    (0x0075, _) => "Samsung".to_string(),
+                    (0x00e0, _) => "Google".to_string(),
+                    (company, _) => format!("manufacturer 0x{:04x}", company),
+                };
+                // a more specific service-based description wins
+                if details.is_none() {
+                    details = Some(description);
+                }
+            }
+            _ => {}
+        }
+
+        ad = &ad[len + 1..];
+    }
+
+    (name, details)
+}
@@ -0,0 +1,313 @@
+// the capture layer: finding the interface, opening it, and the threads that read frames off it into the ring, with
+// whichever backend --backend picks:
+//
+//     pnet        pnet's datalink channel: AF_PACKET on Linux, BPF on macOS and the BSDs, and Npcap's Packet API on
+//                 Windows (Npcap installed in WinPcap-compatible mode, or its directory on PATH)
+//     afpacket    several AF_PACKET sockets in a fanout group, each with a thread of its own (Linux only)
+//
+// or, with --read, a thread reading a pcap file's frames in instead, with the file's timestamps; it waits for room in
+// the ring rather than dropping anything, and stops the capture at the end of the file
+//
+// every backend counts what it reads in METRICS and pushes (wall clock, monotonic clock, frame) onto the ring, waking
+// the capture loop as it goes; with --sample, only the frames its sampler keeps
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam_queue::ArrayQueue;
+use pnet::datalink::{self, DataLinkReceiver, DataLinkSender, NetworkInterface};
+
+use crate::{conf::Backend, error, gro, metrics::METRICS, pcap, sampling::Sampler};
+
+pub type Ring = ArrayQueue<(SystemTime, Instant, Vec<u8>)>;
+
+// failed reads in a row before we give up on the interface
+pub const MAX_READ_ERRORS: u32 = 100;
+
+// time out reads periodically, so we notice ctrl-c even on a quiet interface
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+// how often pnet's socket statistics are read
+const STATISTICS_EVERY: Duration = Duration::from_secs(1);
+
+// an interface by its name (e.g. eth0, or \Device\NPF_{...} on Windows) or, since Windows' names mean nothing to
+// anyone, its description (e.g. "Intel(R) Ethernet Connection"); without one, the first that's up and not loopback
+pub fn find_interface(name: Option<&str>) -> NetworkInterface {
+    let interfaces = datalink::interfaces();
+
+    match name {
+        Some(name) => interfaces
+            .iter()
+            .find(|iface| iface.name == name)
+            .or_else(|| {
+                interfaces
+                    .iter()
+                    .find(|iface| !iface.description.is_empty() && iface.description.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+            .unwrap_or_else(|| {
+                panic!("Failed to find the requested network interface {} (`sniff interfaces` lists them)", name)
+            }),
+        None => interfaces
+            .into_iter()
+            .find(|iface| iface.is_up() && !iface.is_loopback())
+            .expect("Failed to find a suitable network interface"),
+    }
+}
+
+// what --read's frames are taken to have been captured on, without -n: an interface with no addresses (so nothing's
+// inbound or outbound) and, with no MTU of its own, the default one
+pub fn no_interface() -> NetworkInterface {
+    NetworkInterface {
+        name: String::new(),
+        description: String::new(),
+        index: 0,
+        mac: None,
+        ips: Vec::new(),
+        flags: 0,
+    }
+}
+
+// open pnet's channel on the interface; it's what replays are sent with, whichever backend's capturing
+pub fn open(interface: &NetworkInterface) -> (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>) {
+    let channel_config = datalink::Config {
+        read_timeout: Some(READ_TIMEOUT),
+        ..Default::default()
+    };
+
+    match datalink::channel(interface, channel_config) {
+        Ok(datalink::Channel::Ethernet(tx, rx)) => (tx, rx),
+        Ok(_) => panic!("Unsupported channel type"),
+        Err(e) if !elevated() => panic!("Failed to create channel: {} ({})", e, PRIVILEGES),
+        Err(e) => panic!("Failed to create channel: {}", e),
+    }
+}
+
+// what capturing takes, for when it fails without it
+#[cfg(windows)]
+const PRIVILEGES: &str = "capturing needs Npcap, and an Administrator prompt unless Npcap was installed without \
+                          the admin-only option";
+#[cfg(not(windows))]
+const PRIVILEGES: &str = "capturing needs root, or the CAP_NET_RAW capability on Linux";
+
+#[cfg(windows)]
+fn elevated() -> bool {
+    #[link(name = "shell32")]
+    extern "system" {
+        fn IsUserAnAdmin() -> i32;
+    }
+
+    unsafe { IsUserAnAdmin() != 0 }
+}
+
+#[cfg(unix)]
+fn elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn elevated() -> bool {
+    true
+}
+
+// start reading frames into the ring, until `running` is cleared
+pub fn start(
+    backend: Backend,
+    interface: &NetworkInterface,
+    threads: usize,
+    sample: u32,
+    channel: (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>),
+    ring: Arc<Ring>,
+    running: &'static AtomicBool,
+) -> Vec<JoinHandle<()>> {
+    match backend {
+        Backend::AfPacket => {
+            // pnet's socket would only be reading everything a second time
+            drop(channel);
+
+            #[cfg(target_os = "linux")]
+            {
+                crate::afpacket::spawn(&interface.name, threads, true, sample, ring, running)
+                    .unwrap_or_else(|e| panic!("Failed to open AF_PACKET sockets: {}", e))
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = (interface, threads, sample, ring, running);
+                panic!("The afpacket capture backend is only supported on Linux");
+            }
+        }
+        Backend::Pnet => {
+            // pnet's channel is the only packet socket open by now
+            #[cfg(target_os = "linux")]
+            let sockets = crate::afpacket::packet_sockets();
+            #[cfg(not(target_os = "linux"))]
+            let sockets = Vec::new();
+
+            vec![spawn_pnet(channel.1, sockets, sample, ring, running)]
+        }
+    }
+}
+
+// sockets are pnet's packet sockets, whose statistics are read as it goes (Linux only)
+fn spawn_pnet(
+    mut rx: Box<dyn DataLinkReceiver>,
+    sockets: Vec<i32>,
+    sample: u32,
+    ring: Arc<Ring>,
+    running: &'static AtomicBool,
+) -> JoinHandle<()> {
+    let consumer = std::thread::current();
+
+    std::thread::spawn(move || {
+        let mut failures = 0; // in a row
+        let mut sampler = Sampler::new(sample, 0);
+        let mut counted = Instant::now();
+
+        while running.load(Ordering::SeqCst) {
+            if counted.elapsed() >= STATISTICS_EVERY {
+                count_statistics(&sockets);
+                counted = Instant::now();
+            }
+
+            match rx.next() {
+                Ok(packet) => {
+                    failures = 0;
+                    METRICS.packets.fetch_add(1, Ordering::Relaxed);
+                    METRICS.bytes.fetch_add(packet.len() as u64, Ordering::Relaxed);
+
+                    if !sampler.keep() {
+                        continue;
+                    }
+                    METRICS.sampled.fetch_add(1, Ordering::Relaxed);
+
+                    if ring.push((SystemTime::now(), Instant::now(), packet.to_vec())).is_err() {
+                        METRICS.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    consumer.unpark();
+                }
+                // timeouts let us check for ctrl-c, which itself interrupts the read
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut || e.kind() == std::io::ErrorKind::Interrupted => {
+                    continue
+                }
+                // one bad read shouldn't end the session, but a dead interface (or a flood of errors) should
+                Err(e) => {
+                    METRICS.read_errors.fetch_add(1, Ordering::Relaxed);
+                    failures += 1;
+                    if error::is_fatal(&e) || failures >= MAX_READ_ERRORS {
+                        eprintln!("Failed to receive packet, stopping: {}", e);
+                        running.store(false, Ordering::SeqCst);
+                    } else {
+                        eprintln!("Failed to receive packet: {}", e);
+                    }
+                }
+            }
+        }
+
+        count_statistics(&sockets);
+    })
+}
+
+// --read's file: its frames, with their timestamps, read as they're wanted, and whether they're 802.11 (radiotap)
+// rather than Ethernet
+pub fn read_file(path: &str) -> (std::iter::Peekable<pcap::PcapReader>, bool) {
+    let reader = pcap::PcapReader::open(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+
+    let monitor = match reader.linktype {
+        pcap::LINKTYPE_ETHERNET => false,
+        pcap::LINKTYPE_IEEE802_11_RADIOTAP => true,
+        linktype => panic!("Can only read Ethernet or radiotap captures, {} has link type {}", path, linktype),
+    };
+
+    (reader.peekable(), monitor)
+}
+
+// a file's frames can't be dropped and read again later, so a full ring is waited on rather than counted as drops
+pub fn spawn_file(
+    frames: impl Iterator<Item = (SystemTime, Vec<u8>)> + Send + 'static,
+    sample: u32,
+    ring: Arc<Ring>,
+    running: &'static AtomicBool,
+) -> JoinHandle<()> {
+    let consumer = std::thread::current();
+
+    std::thread::spawn(move || {
+        let mut sampler = Sampler::new(sample, 0);
+
+        for (timestamp, frame) in frames {
+            if !running.load(Ordering::SeqCst) {
+                return;
+            }
+
+            METRICS.packets.fetch_add(1, Ordering::Relaxed);
+            METRICS.bytes.fetch_add(frame.len() as u64, Ordering::Relaxed);
+
+            if !sampler.keep() {
+                continue;
+            }
+            METRICS.sampled.fetch_add(1, Ordering::Relaxed);
+
+            let mut frame = (timestamp, Instant::now(), frame);
+            while let Err(rejected) = ring.push(frame) {
+                // stopped with the ring full, so nothing's going to make room
+                if !running.load(Ordering::SeqCst) {
+                    return;
+                }
+                frame = rejected;
+                consumer.unpark();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            consumer.unpark();
+        }
+
+        // the end of the file is the end of the capture
+        running.store(false, Ordering::SeqCst);
+        consumer.unpark();
+    })
+}
+
+fn count_statistics(sockets: &[i32]) {
+    #[cfg(target_os = "linux")]
+    for fd in sockets {
+        crate::afpacket::count_statistics(*fd);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = sockets;
+}
+
+// `sniff interfaces`: what -n accepts, marking the one we'd pick without it
+pub fn print_interfaces() {
+    let interfaces = datalink::interfaces();
+    let default = interfaces.iter().find(|iface| iface.is_up() && !iface.is_loopback()).map(|x| x.name.clone());
+
+    for iface in interfaces.iter() {
+        let mut details = vec![if iface.is_up() { "up" } else { "down" }.to_string()];
+        if iface.is_loopback() {
+            details.push("loopback".to_string());
+        }
+        if let Some(mac) = iface.mac {
+            details.push(mac.to_string());
+        }
+        details.push(format!("mtu {}", gro::interface_mtu(&iface.name)));
+
+        println!(
+            "{}{} ({})",
+            iface.name,
+            if Some(&iface.name) == default.as_ref() { " *" } else { "" },
+            details.join(", ")
+        );
+        // the adapter's friendly name, which -n takes too (Windows only)
+        if !iface.description.is_empty() {
+            println!("    {}", iface.description);
+        }
+        for ip in iface.ips.iter() {
+            println!("    {}", ip);
+        }
+    }
+}
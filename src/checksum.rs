@@ -0,0 +1,89 @@
+// --verify-checksums: check each packet's IPv4 header checksum and its TCP, UDP, ICMP or ICMPv6 checksum, and mark
+// requests with a packet that fails. Corruption on the wire is rare; checksum offload is the usual cause, since a
+// packet we send is captured before the NIC fills its checksum in, so outgoing packets failing and nothing else is
+// a sign of offload rather than trouble
+//
+// transport checksums are only checked when we have the whole segment, and not for fragments, whose checksum covers
+// the whole datagram
+
+use serde::{Deserialize, Serialize};
+
+use crate::ip;
+
+const ICMP: u8 = 1;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMPV6: u8 = 58;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Ip,
+    Tcp,
+    Udp,
+    Icmp,
+    Icmpv6,
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Layer::Ip => "IP",
+            Layer::Tcp => "TCP",
+            Layer::Udp => "UDP",
+            Layer::Icmp => "ICMP",
+            Layer::Icmpv6 => "ICMPv6",
+        })
+    }
+}
+
+// the first checksum in the packet that's wrong, if any is
+pub fn verify(packet: &[u8]) -> Option<Layer> {
+    let header = ip::parse(packet)?;
+    let ipv6 = packet[0] >> 4 == 6;
+
+    if !ipv6 {
+        if internet(&packet[..header.header_len], 0) != 0 {
+            return Some(Layer::Ip);
+        }
+        // more fragments, or a fragment offset
+        if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+            return None;
+        }
+    }
+
+    let end = ip::total_len(packet)?;
+    let segment = packet.get(header.header_len..end)?;
+
+    let layer = match header.protocol {
+        TCP if segment.len() >= 20 => Layer::Tcp,
+        // an IPv4 UDP checksum of zero means there isn't one
+        UDP if segment.len() >= 8 && (ipv6 || segment[6..8] != [0, 0]) => Layer::Udp,
+        ICMP if !ipv6 && segment.len() >= 4 => Layer::Icmp,
+        ICMPV6 if ipv6 && segment.len() >= 4 => Layer::Icmpv6,
+        _ => return None,
+    };
+
+    // ICMP's checksum is of its message alone, the rest cover the addresses too
+    let initial = if layer == Layer::Icmp {
+        0
+    } else {
+        let addresses = if ipv6 { &packet[8..40] } else { &packet[12..20] };
+        !internet(addresses, header.protocol as u32 + segment.len() as u32) as u32
+    };
+
+    (internet(segment, initial) != 0).then_some(layer)
+}
+
+// the internet checksum (RFC 1071): the ones' complement of the ones' complement sum of 16-bit words, starting from
+// `initial`, e.g. a pseudo-header's sum. It comes to zero over data with a correct checksum in it
+pub fn internet(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for chunk in data.chunks(2) {
+        sum += u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,419 @@
+// --ciphers: the protocol versions and ciphers servers negotiate, from the parts of TLS and SSH handshakes that are
+// sent in the clear, so endpoints still on SSL 3.0, TLS 1.0/1.1 or SSH 1, or settling on ciphers like RC4 and 3DES,
+// stand out. TLS's come from the ServerHello (the version and cipher suite the server picked), with the name the
+// client asked for from its ClientHello; SSH's from the two banners and the KEXINITs, the cipher being the first of
+// the client's the server also has (RFC 4253 7.1)
+//
+// outdated and weak handshakes are called out as they're seen, and every server's are totalled at exit
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, sni, theme::Theme};
+
+const TCP: u8 = 6;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+const REPORT_HANDSHAKES: usize = 20;
+
+const HANDSHAKE: u8 = 0x16;
+const SERVER_HELLO: u8 = 0x02;
+const SUPPORTED_VERSIONS: u16 = 43;
+const TLS_1_2: u16 = 0x0303;
+
+const SSH_PORT: u16 = 22;
+const SSH_MSG_KEXINIT: u8 = 20;
+
+// SSH ciphers and key exchanges that are broken, or close enough
+const WEAK_SSH: [&str; 9] = [
+    "none",
+    "arcfour",
+    "arcfour128",
+    "arcfour256",
+    "des-cbc",
+    "3des-cbc",
+    "blowfish-cbc",
+    "cast128-cbc",
+    "diffie-hellman-group1-sha1",
+];
+
+// client, server
+type Key = (SocketAddr, SocketAddr);
+
+#[derive(Default)]
+struct Connection {
+    server_name: Option<String>,    // from the ClientHello
+    banners: [Option<String>; 2],   // SSH's, the client's then the server's
+    kexinits: [Option<KexInit>; 2], // likewise
+    reported: bool,
+    last: Option<SystemTime>,
+}
+
+// the parts of an SSH KEXINIT that matter here
+struct KexInit {
+    kex: Vec<String>,
+    ciphers: Vec<String>, // client to server
+}
+
+struct Handshake {
+    description: String, // e.g. "TLS 1.2 with TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"
+    outdated: bool,
+    weak: bool,
+}
+
+pub struct CipherTracker {
+    theme: Theme,
+    connections: HashMap<Key, Connection>,
+    // (server, name, description) -> whether it's outdated or weak, how many
+    handshakes: BTreeMap<(SocketAddr, Option<String>, String), (bool, u64)>,
+    checked: Option<SystemTime>,
+}
+
+impl CipherTracker {
+    pub fn new(theme: Theme) -> CipherTracker {
+        CipherTracker {
+            theme,
+            connections: HashMap::new(),
+            handshakes: BTreeMap::new(),
+            checked: None,
+        }
+    }
+
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet).filter(|x| x.protocol == TCP) else {
+            return;
+        };
+        let Some((src_port, dst_port, payload)) = ip::transport(packet) else {
+            return;
+        };
+        if payload.is_empty() {
+            return;
+        }
+
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.connections.retain(|_, connection| {
+                connection
+                    .last
+                    .is_some_and(|x| timestamp.duration_since(x).unwrap_or_default() < IDLE_TIMEOUT)
+            });
+        }
+
+        let (src, dst) = (
+            SocketAddr::new(header.src, src_port),
+            SocketAddr::new(header.dst, dst_port),
+        );
+        let handshake = if payload[0] == HANDSHAKE {
+            self.tls(src, dst, payload, timestamp)
+        } else {
+            self.ssh(src, dst, payload, timestamp)
+        };
+        let Some((key, handshake)) = handshake else {
+            return;
+        };
+
+        let connection = self.connections.entry(key).or_default();
+        if connection.reported {
+            return;
+        }
+        connection.reported = true;
+        let server_name = connection.server_name.clone();
+
+        let flagged = handshake.outdated || handshake.weak;
+        self.handshakes
+            .entry((key.1, server_name.clone(), handshake.description.clone()))
+            .or_insert((flagged, 0))
+            .1 += 1;
+
+        if !flagged {
+            return;
+        }
+        let what = match (handshake.outdated, handshake.weak) {
+            (true, true) => "an outdated protocol version and a weak cipher",
+            (true, false) => "an outdated protocol version",
+            _ => "a weak cipher",
+        };
+        let message = format!(
+            "*** {} negotiated: {} -> {}{}, {} ***",
+            what,
+            key.0,
+            key.1,
+            server_name.map(|x| format!(" ({})", x)).unwrap_or_default(),
+            handshake.description
+        );
+        outln!("{}", self.theme.paint(self.theme.warning, &message));
+    }
+
+    // a ClientHello is kept for its server name; a ServerHello says what was picked
+    fn tls(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+        timestamp: SystemTime,
+    ) -> Option<(Key, Handshake)> {
+        if let Some(name) = sni::client_hello_server_name(payload) {
+            let connection = self.connections.entry((src, dst)).or_default();
+            connection.server_name = Some(name);
+            connection.last = Some(timestamp);
+            return None;
+        }
+
+        let (version, cipher_suite) = parse_server_hello(payload.get(5..)?)?;
+        self.connections.entry((dst, src)).or_default().last = Some(timestamp);
+
+        let name = cipher_suite_name(cipher_suite);
+        Some((
+            (dst, src),
+            Handshake {
+                description: format!("{} with {}", tls_version_name(version), name),
+                outdated: version < TLS_1_2,
+                weak: ["RC4", "DES", "NULL", "EXPORT", "anon", "MD5"]
+                    .iter()
+                    .any(|x| name.contains(x)),
+            },
+        ))
+    }
+
+    fn ssh(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+        timestamp: SystemTime,
+    ) -> Option<(Key, Handshake)> {
+        // which end's the server isn't in the banners, so it's the one on 22, or failing that the lower port
+        let from_client = match (src.port(), dst.port()) {
+            (_, SSH_PORT) => true,
+            (SSH_PORT, _) => false,
+            (src_port, dst_port) => src_port > dst_port,
+        };
+        let key = if from_client { (src, dst) } else { (dst, src) };
+        let side = if from_client { 0 } else { 1 };
+
+        let mut rest = payload;
+        if payload.starts_with(b"SSH-") {
+            let end = payload.iter().position(|x| *x == b'\n').unwrap_or(payload.len());
+            let banner = String::from_utf8_lossy(&payload[..end]).trim_end().to_string();
+            let connection = self.connections.entry(key).or_default();
+            connection.banners[side] = Some(banner);
+            connection.last = Some(timestamp);
+            rest = &payload[(end + 1).min(payload.len())..];
+        }
+
+        // a KEXINIT follows the banner, in the same segment or the next
+        let connection = self.connections.get_mut(&key)?;
+        connection.banners[side].as_ref()?;
+        if let Some(kexinit) = parse_kexinit(rest) {
+            connection.kexinits[side] = Some(kexinit);
+            connection.last = Some(timestamp);
+        }
+
+        let [Some(client), Some(server)] = &connection.banners else {
+            return None;
+        };
+        let (client_version, server_version) = (ssh_version(client), ssh_version(server));
+        let software = server
+            .splitn(3, '-')
+            .nth(2)
+            .and_then(|x| x.split_whitespace().next())
+            .unwrap_or("?");
+
+        // SSH 1 has no KEXINIT, and 1.99 is a server that speaks either
+        for version in [client_version, server_version] {
+            if version.starts_with("1.") && version != "1.99" {
+                return Some((
+                    key,
+                    Handshake {
+                        description: format!("SSH {} ({})", version, software),
+                        outdated: true,
+                        weak: false,
+                    },
+                ));
+            }
+        }
+
+        let [Some(client), Some(server)] = &connection.kexinits else {
+            return None;
+        };
+        let negotiated = |client: &[String], server: &[String]| {
+            client
+                .iter()
+                .find(|x| server.contains(x))
+                .cloned()
+                .unwrap_or_else(|| "none in common".to_string())
+        };
+        let (cipher, kex) = (
+            negotiated(&client.ciphers, &server.ciphers),
+            negotiated(&client.kex, &server.kex),
+        );
+
+        Some((
+            key,
+            Handshake {
+                weak: WEAK_SSH.contains(&cipher.as_str()) || WEAK_SSH.contains(&kex.as_str()),
+                description: format!("SSH 2.0 ({}) with {}, {} key exchange", software, cipher, kex),
+                outdated: false,
+            },
+        ))
+    }
+
+    pub fn print_report(self) {
+        if self.handshakes.is_empty() {
+            return;
+        }
+
+        // the flagged ones first
+        let mut handshakes: Vec<_> = self.handshakes.iter().collect();
+        handshakes.sort_by_key(|(_, (flagged, count))| (std::cmp::Reverse(*flagged), std::cmp::Reverse(*count)));
+
+        println!("TLS and SSH handshakes:");
+        for ((server, name, description), (flagged, count)) in handshakes.iter().take(REPORT_HANDSHAKES) {
+            println!(
+                "    {}{}: {}, {} connection{}{}",
+                server,
+                name.as_ref().map(|x| format!(" ({})", x)).unwrap_or_default(),
+                description,
+                count,
+                if *count == 1 { "" } else { "s" },
+                if *flagged { " (outdated or weak)" } else { "" }
+            );
+        }
+        if handshakes.len() > REPORT_HANDSHAKES {
+            println!("    ... and {} more", handshakes.len() - REPORT_HANDSHAKES);
+        }
+    }
+}
+
+// the version and cipher suite in a ServerHello handshake message; TLS 1.3 puts its version in supported_versions,
+// leaving 1.2 in the old field
+fn parse_server_hello(data: &[u8]) -> Option<(u16, u16)> {
+    if *data.first()? != SERVER_HELLO {
+        return None;
+    }
+
+    let u8_at = |at: usize| data.get(at).map(|x| *x as usize);
+    let u16_at = |at: usize| data.get(at..at + 2).map(|x| u16::from_be_bytes([x[0], x[1]]));
+
+    let mut version = u16_at(4)?;
+    // handshake header (4), server version (2) and random (32), then the session id
+    let mut at = 38;
+    at += 1 + u8_at(at)?;
+    let cipher_suite = u16_at(at)?;
+    at += 3; // and the compression method
+
+    // extensions are optional before TLS 1.3
+    if let Some(len) = u16_at(at) {
+        let end = (at + 2 + len as usize).min(data.len());
+        at += 2;
+        while at + 4 <= end {
+            let (kind, len) = (u16_at(at)?, u16_at(at + 2)? as usize);
+            if kind == SUPPORTED_VERSIONS && len == 2 {
+                version = u16_at(at + 4)?;
+            }
+            at += 4 + len;
+        }
+    }
+
+    Some((version, cipher_suite))
+}
+
+fn tls_version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSL 3.0".to_string(),
+        0x0301 => "TLS 1.0".to_string(),
+        0x0302 => "TLS 1.1".to_string(),
+        0x0303 => "TLS 1.2".to_string(),
+        0x0304 => "TLS 1.3".to_string(),
+        0x7f00..=0x7fff => format!("TLS 1.3 (draft {})", version & 0xff),
+        _ => format!("TLS version 0x{:04x}", version),
+    }
+}
+
+fn cipher_suite_name(suite: u16) -> String {
+    let name = match suite {
+        0x0001 => "TLS_RSA_WITH_NULL_MD5",
+        0x0002 => "TLS_RSA_WITH_NULL_SHA",
+        0x0003 => "TLS_RSA_EXPORT_WITH_RC4_40_MD5",
+        0x0004 => "TLS_RSA_WITH_RC4_128_MD5",
+        0x0005 => "TLS_RSA_WITH_RC4_128_SHA",
+        0x0008 => "TLS_RSA_EXPORT_WITH_DES40_CBC_SHA",
+        0x0009 => "TLS_RSA_WITH_DES_CBC_SHA",
+        0x000a => "TLS_RSA_WITH_3DES_EDE_CBC_SHA",
+        0x0016 => "TLS_DHE_RSA_WITH_3DES_EDE_CBC_SHA",
+        0x0018 => "TLS_DH_anon_WITH_RC4_128_MD5",
+        0x002f => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0033 => "TLS_DHE_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        0x0039 => "TLS_DHE_RSA_WITH_AES_256_CBC_SHA",
+        0x003c => "TLS_RSA_WITH_AES_128_CBC_SHA256",
+        0x003d => "TLS_RSA_WITH_AES_256_CBC_SHA256",
+        0x0067 => "TLS_DHE_RSA_WITH_AES_128_CBC_SHA256",
+        0x006b => "TLS_DHE_RSA_WITH_AES_256_CBC_SHA256",
+        0x009c => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009d => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x009e => "TLS_DHE_RSA_WITH_AES_128_GCM_SHA256",
+        0x009f => "TLS_DHE_RSA_WITH_AES_256_GCM_SHA384",
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0x1304 => "TLS_AES_128_CCM_SHA256",
+        0x1305 => "TLS_AES_128_CCM_8_SHA256",
+        0xc007 => "TLS_ECDHE_ECDSA_WITH_RC4_128_SHA",
+        0xc008 => "TLS_ECDHE_ECDSA_WITH_3DES_EDE_CBC_SHA",
+        0xc009 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA",
+        0xc00a => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA",
+        0xc011 => "TLS_ECDHE_RSA_WITH_RC4_128_SHA",
+        0xc012 => "TLS_ECDHE_RSA_WITH_3DES_EDE_CBC_SHA",
+        0xc013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xc014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0xc023 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256",
+        0xc024 => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384",
+        0xc027 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256",
+        0xc028 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384",
+        0xc02b => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xc02c => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xc02f => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xc030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xcca8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xcca9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xccaa => "TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        _ => return format!("cipher suite 0x{:04x}", suite),
+    };
+    name.to_string()
+}
+
+// e.g. "2.0" from "SSH-2.0-OpenSSH_9.6"
+fn ssh_version(banner: &str) -> &str {
+    banner.split('-').nth(1).unwrap_or("?")
+}
+
+// an SSH binary packet (RFC 4253 6) holding a KEXINIT: its length (4), the padding's (1), the message number (1) and a
+// cookie (16), then name-lists of key exchanges, host keys and ciphers each way
+fn parse_kexinit(data: &[u8]) -> Option<KexInit> {
+    if *data.get(5)? != SSH_MSG_KEXINIT {
+        return None;
+    }
+
+    let mut at = 22;
+    let mut lists = Vec::new();
+    for _ in 0..3 {
+        let len = u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize;
+        let list = std::str::from_utf8(data.get(at + 4..at + 4 + len)?).ok()?;
+        lists.push(list.split(',').map(|x| x.to_string()).collect::<Vec<String>>());
+        at += 4 + len;
+    }
+
+    let ciphers = lists.pop()?;
+    Some(KexInit {
+        kex: lists.swap_remove(0),
+        ciphers,
+    })
+}
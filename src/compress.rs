@@ -0,0 +1,54 @@
+// log files, compressed or not: reading goes by the magic bytes at the start, so a compressed log can be loaded
+// whatever it's called, and writing streams through --log-compress's encoder
+
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::conf::LogCompression;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// the file's contents, decompressed if they need to be
+pub fn read(path: impl AsRef<Path>) -> std::io::Result<Vec<u8>> {
+    let data = std::fs::read(path)?;
+
+    if data.starts_with(&ZSTD_MAGIC) {
+        zstd::decode_all(&data[..])
+    } else if data.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        // rotated logs may have been gzipped again, and gzip files can be several members back to back
+        flate2::read::MultiGzDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    } else {
+        Ok(data)
+    }
+}
+
+// write the whole file through `write`, into a new file that replaces the old one once it's complete, so a capture
+// that dies mid-write still leaves the last good log behind
+pub fn write<F>(path: &str, compression: LogCompression, write: F) -> Result<(), crate::error::Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), crate::error::Error>,
+{
+    let partial = format!("{}.partial", path);
+    let file = BufWriter::new(File::create(&partial)?);
+
+    match compression {
+        LogCompression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            write(&mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+        LogCompression::Zstd => {
+            let mut encoder = zstd::Encoder::new(file, 0)?;
+            write(&mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+    }
+
+    Ok(std::fs::rename(partial, path)?)
+}
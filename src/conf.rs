@@ -1,16 +1,16 @@
 use anstyle::AnsiColor;
-use clap::{builder::Styles, Parser};
+use clap::{builder::Styles, parser::ValueSource, CommandFactory, FromArgMatches, Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use std::num::ParseIntError;
 use std::io::{Error, ErrorKind};
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct IpV4 {
     pub octets: [u8; 4],
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct IpV6 {
     pub octets: [u8; 16],
 }
@@ -184,12 +184,54 @@ impl FromStr for IpV6 {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum IpAddr {
     V4(IpV4),
     V6(IpV6),
 }
 
+impl IpAddr {
+    pub fn to_std(&self) -> std::net::IpAddr {
+        match self {
+            IpAddr::V4(ip) => std::net::IpAddr::from(ip.octets),
+            IpAddr::V6(ip) => std::net::IpAddr::from(ip.octets),
+        }
+    }
+
+    // private, loopback, link-local, multicast etc. - anything that isn't a routable public address
+    pub fn is_private(&self) -> bool {
+        match self.to_std() {
+            std::net::IpAddr::V4(ip) => {
+                ip.is_private()
+                    || ip.is_loopback()
+                    || ip.is_link_local()
+                    || ip.is_broadcast()
+                    || ip.is_multicast()
+                    || ip.is_unspecified()
+                    // carrier-grade NAT, 100.64.0.0/10
+                    || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+            }
+            std::net::IpAddr::V6(ip) => {
+                ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unspecified()
+                    // unique local (fc00::/7) and link-local (fe80::/10)
+                    || ip.segments()[0] & 0xfe00 == 0xfc00
+                    || ip.segments()[0] & 0xffc0 == 0xfe80
+            }
+        }
+    }
+}
+
+impl From<std::net::IpAddr> for IpAddr {
+    fn from(ip: std::net::IpAddr) -> Self {
+        match ip {
+            std::net::IpAddr::V4(ip) => IpAddr::V4(IpV4 { octets: ip.octets() }),
+            std::net::IpAddr::V6(ip) => IpAddr::V6(IpV6 { octets: ip.octets() }),
+        }
+    }
+}
+
 impl FromStr for IpAddr {
     type Err = Error;
 
@@ -202,11 +244,49 @@ impl FromStr for IpAddr {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy, Hash)]
 pub struct MacAddr {
     octets: [u8; 6],
 }
 
+impl MacAddr {
+    pub fn is_broadcast(&self) -> bool {
+        self.octets == [0xff; 6]
+    }
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.octets
+    }
+
+    // who a frame sent to this address is for: the group bit is set for broadcast and multicast addresses
+    pub fn cast(&self) -> Cast {
+        if self.is_broadcast() {
+            Cast::Broadcast
+        } else if self.octets[0] & 1 == 1 {
+            Cast::Multicast
+        } else {
+            Cast::Unicast
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cast {
+    Unicast,
+    Broadcast,
+    Multicast,
+}
+
+impl std::fmt::Display for Cast {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Cast::Unicast => write!(f, "unicast"),
+            Cast::Broadcast => write!(f, "broadcast"),
+            Cast::Multicast => write!(f, "multicast"),
+        }
+    }
+}
+
 impl From<[u8; 6]> for MacAddr {
     fn from(octets: [u8; 6]) -> Self {
         MacAddr { octets }
@@ -260,25 +340,104 @@ impl FromStr for MacAddr {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+// a MAC address on the command line, or the name of a host from --aliases, standing for its MACs
+#[derive(Clone, Debug)]
+pub enum MacAddrOrAlias {
+    Mac(MacAddr),
+    Alias(String),
+}
+
+impl FromStr for MacAddrOrAlias {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(mac) => Ok(MacAddrOrAlias::Mac(mac)),
+            // anything with a colon was meant to be a MAC
+            Err(e) if s.contains(':') => Err(e),
+            Err(_) => Ok(MacAddrOrAlias::Alias(s.to_string())),
+        }
+    }
+}
+
+// the MACs given, with any names looked up in --aliases
+fn resolve_macs(list: Option<Vec<MacAddrOrAlias>>, aliases: Option<&crate::aliases::Aliases>) -> Option<Vec<MacAddr>> {
+    let mut macs = Vec::new();
+    for mac in list? {
+        match (mac, aliases) {
+            (MacAddrOrAlias::Mac(mac), _) => macs.push(mac),
+            (MacAddrOrAlias::Alias(name), Some(aliases)) => match aliases.macs(&name) {
+                Some(x) if !x.is_empty() => macs.extend(x),
+                Some(_) => panic!("Alias \"{}\" has no MAC addresses, so filter it by IP (-F, -X or -I) instead", name),
+                None => panic!("Invalid MAC address or alias: {}", name),
+            },
+            (MacAddrOrAlias::Alias(name), None) => panic!("Invalid MAC address: {} (names need --aliases)", name),
+        }
+    }
+    Some(macs)
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy, Hash, PartialOrd, Ord)]
 pub enum Protocol {
     Tcp,
     Udp,
     Icmp,
-    Unknown,
+    Unknown,   // a frame we couldn't parse (and, in old logs, anything we couldn't name)
+    Ip(u8),    // an IP protocol we don't decode, by number
+    Ether(u16), // a non-IP frame, by ethertype
 }
 
 impl From<u8> for Protocol {
     fn from(num: u8) -> Self {
         match num {
-            1 => Protocol::Icmp,
+            1 | 58 => Protocol::Icmp, // ICMP and ICMPv6
             6 => Protocol::Tcp,
             17 => Protocol::Udp,
-            _ => Protocol::Unknown,
+            _ => Protocol::Ip(num),
         }
     }
 }
 
+impl Protocol {
+    // a best guess at what an unnamed protocol number is, from the IANA registry
+    fn guess_ip(num: u8) -> Option<&'static str> {
+        Some(match num {
+            0 => "HOPOPT",
+            2 => "IGMP",
+            4 => "IPIP",
+            41 => "IPv6",
+            47 => "GRE",
+            50 => "ESP",
+            51 => "AH",
+            88 => "EIGRP",
+            89 => "OSPF",
+            103 => "PIM",
+            112 => "VRRP",
+            115 => "L2TP",
+            132 => "SCTP",
+            136 => "UDPLite",
+            _ => return None,
+        })
+    }
+
+    fn guess_ether(ethertype: u16) -> Option<&'static str> {
+        Some(match ethertype {
+            0x0806 => "ARP",
+            0x0842 => "Wake-on-LAN",
+            0x8035 => "RARP",
+            0x8809 => "LACP",
+            0x8847 | 0x8848 => "MPLS",
+            0x8863 | 0x8864 => "PPPoE",
+            0x888e => "EAPOL",
+            0x88cc => "LLDP",
+            0x88e5 => "MACsec",
+            0x88f7 => "PTP",
+            0x9000 => "loopback",
+            _ => return None,
+        })
+    }
+}
+
 impl FromStr for Protocol {
     type Err = Error;
 
@@ -292,15 +451,347 @@ impl FromStr for Protocol {
     }
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum DumpMode {
+    Hex,
+    Ascii,
+    Both,
+}
+
+impl FromStr for DumpMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hex" => Ok(DumpMode::Hex),
+            "ascii" => Ok(DumpMode::Ascii),
+            "both" => Ok(DumpMode::Both),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid dump mode, expected hex, ascii or both",
+            )),
+        }
+    }
+}
+
+// --graph's rows: all the traffic, a row per protocol, or a row per busiest host
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum GraphSplit {
+    Total,
+    Protocol,
+    Host,
+}
+
+impl FromStr for GraphSplit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "total" => Ok(GraphSplit::Total),
+            "protocol" => Ok(GraphSplit::Protocol),
+            "host" => Ok(GraphSplit::Host),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid graph, expected total, protocol or host",
+            )),
+        }
+    }
+}
+
+// what to do when --max-disk is reached
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum QuotaAction {
+    Stop,
+    Prune,
+}
+
+impl FromStr for QuotaAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "stop" => Ok(QuotaAction::Stop),
+            "prune" => Ok(QuotaAction::Prune),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid quota action, expected stop or prune",
+            )),
+        }
+    }
+}
+
+// how the -l log is compressed, if it is
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum LogCompression {
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for LogCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gzip" | "gz" => Ok(LogCompression::Gzip),
+            "zstd" | "zst" => Ok(LogCompression::Zstd),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid log compression, expected gzip or zstd",
+            )),
+        }
+    }
+}
+
+// what reads frames off the interface: pnet's datalink channel, or our own AF_PACKET fanout sockets (Linux only)
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum Backend {
+    Pnet,
+    AfPacket,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pnet" => Ok(Backend::Pnet),
+            "afpacket" | "af_packet" => Ok(Backend::AfPacket),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid capture backend, expected pnet or afpacket",
+            )),
+        }
+    }
+}
+
+// how the -l log is encoded: JSON, to read, or CBOR, which is far smaller and quicker to write
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum LogFormat {
+    Json,
+    Binary,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(LogFormat::Json),
+            "binary" | "cbor" => Ok(LogFormat::Binary),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid log format, expected json or binary",
+            )),
+        }
+    }
+}
+
+// what `sniff convert` writes: a sniff log (JSON or binary), a JSON line per request, or a pcap
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum ConvertFormat {
+    Json,
+    Ndjson,
+    Binary,
+    Pcap,
+}
+
+impl ConvertFormat {
+    // by the file's extension, when --format doesn't say
+    pub fn from_path(path: &str) -> ConvertFormat {
+        match path.rsplit_once('.').map(|(_, x)| x.to_ascii_lowercase()).as_deref() {
+            Some("pcap") => ConvertFormat::Pcap,
+            Some("ndjson" | "jsonl") => ConvertFormat::Ndjson,
+            Some("cbor" | "bin") => ConvertFormat::Binary,
+            _ => ConvertFormat::Json,
+        }
+    }
+}
+
+impl FromStr for ConvertFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ConvertFormat::Json),
+            "ndjson" | "jsonl" => Ok(ConvertFormat::Ndjson),
+            "binary" | "cbor" => Ok(ConvertFormat::Binary),
+            "pcap" => Ok(ConvertFormat::Pcap),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid format, expected json, ndjson, binary or pcap",
+            )),
+        }
+    }
+}
+
+// the flow record format --netflow exports
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum NetflowVersion {
+    V9,
+    Ipfix,
+}
+
+impl FromStr for NetflowVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "9" | "v9" => Ok(NetflowVersion::V9),
+            "10" | "ipfix" => Ok(NetflowVersion::Ipfix),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid NetFlow version, expected 9 or ipfix",
+            )),
+        }
+    }
+}
+
+// which way a request went, relative to the capture interface's own addresses
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+    Local,
+}
+
+impl FromStr for Direction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "in" | "inbound" => Ok(Direction::Inbound),
+            "out" | "outbound" => Ok(Direction::Outbound),
+            "local" => Ok(Direction::Local),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid direction, expected in, out or local",
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Direction::Inbound => write!(f, "in"),
+            Direction::Outbound => write!(f, "out"),
+            Direction::Local => write!(f, "local"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum ThemeName {
+    Default,
+    Deuteranopia,
+    HighContrast,
+    Monochrome,
+}
+
+impl FromStr for ThemeName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "default" => Ok(ThemeName::Default),
+            "deuteranopia" | "colorblind" => Ok(ThemeName::Deuteranopia),
+            "high-contrast" => Ok(ThemeName::HighContrast),
+            "monochrome" | "mono" => Ok(ThemeName::Monochrome),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid theme, expected default, deuteranopia, high-contrast or monochrome",
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub enum ColorWhen {
+    Always,
+    Auto,
+    Never,
+}
+
+impl FromStr for ColorWhen {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(ColorWhen::Always),
+            "auto" => Ok(ColorWhen::Auto),
+            "never" => Ok(ColorWhen::Never),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Invalid color mode, expected always, auto or never")),
+        }
+    }
+}
+
+// one of the 16 standard terminal colors, which the terminal's own palette decides the look of
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Copy)]
+pub struct ColorName(pub u8);
+
+impl ColorName {
+    const NAMES: [&'static str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+    pub fn ansi(&self) -> AnsiColor {
+        [
+            AnsiColor::Black,
+            AnsiColor::Red,
+            AnsiColor::Green,
+            AnsiColor::Yellow,
+            AnsiColor::Blue,
+            AnsiColor::Magenta,
+            AnsiColor::Cyan,
+            AnsiColor::White,
+            AnsiColor::BrightBlack,
+            AnsiColor::BrightRed,
+            AnsiColor::BrightGreen,
+            AnsiColor::BrightYellow,
+            AnsiColor::BrightBlue,
+            AnsiColor::BrightMagenta,
+            AnsiColor::BrightCyan,
+            AnsiColor::BrightWhite,
+        ][self.0 as usize]
+    }
+}
+
+impl FromStr for ColorName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let (bright, name) = match s.strip_prefix("bright-").or_else(|| s.strip_prefix("bright")) {
+            Some(name) => (8, name),
+            None => (0, s.as_str()),
+        };
+
+        match ColorName::NAMES.iter().position(|x| *x == name) {
+            Some(i) => Ok(ColorName(bright + i as u8)),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid color, expected black, red, green, yellow, blue, magenta, cyan or white, optionally prefixed with bright-",
+            )),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub verbose: bool,
+    pub format: Option<crate::template::Template>,
     pub debug: bool,
     pub log_file: Option<String>,
+    pub session: Option<String>,
     pub exclude_ips: Option<Vec<IpAddrOrHostname>>,
     pub exclude_macs: Option<Vec<MacAddr>>,
+    pub exclude_broadcast: bool,
+    pub no_broadcast: bool,
+    pub multicast_only: bool,
+    pub ignore_self: bool,
     pub filter_ips: Option<Vec<IpAddrOrHostname>>,
     pub filter_macs: Option<Vec<MacAddr>>,
+    pub filter_vendors: Option<Vec<String>>,
+    pub oui_file: Option<String>,
+    pub aliases: Option<String>,
 
     pub highlight_ips: Option<Vec<IpAddrOrHostname>>,
     pub highlight_macs: Option<Vec<MacAddr>>,
@@ -308,15 +799,184 @@ pub struct Config {
     pub protocol: Option<Protocol>,
 
     pub load_from_file: Option<String>,
+    pub read: Option<String>,
     pub real_time_playback: bool,
+    pub from: Option<crate::window::TimeBound>,
+    pub to: Option<crate::window::TimeBound>,
     pub hostnames: bool,
     pub dont_collate: bool,
+    pub merge_bidirectional: bool,
+
+    pub interface: Option<String>,
+    pub monitor: bool,
+    pub handshake_dir: Option<String>,
+    pub record_fixture: Option<String>,
+    pub pcap: Option<String>,
+    pub ring_files: Option<usize>,
+    pub outputs: Vec<crate::sinks::Output>,
+    pub ring_file_size: Option<u64>,
+    pub fixture_packets: usize,
+
+    pub dump_payload: Option<DumpMode>,
+    pub graph: Option<GraphSplit>,
+    pub dump_bytes: usize,
+
+    pub inventory: bool,
+    pub ble: Option<u16>,
+
+    pub geoip: Option<Vec<String>>,
+    pub processes: bool,
+    pub process: Option<Vec<String>>,
+
+    pub metrics: Option<String>,
+
+    pub degrade_rate: Option<u64>,
+
+    pub interval: Option<std::time::Duration>,
+
+    pub raw_bytes: bool,
+
+    pub theme: ThemeName,
+    pub color: ColorWhen,
+    pub highlight_color: Option<ColorName>,
+
+    pub ring_size: usize,
+
+    pub backend: Backend,
+    pub capture_threads: usize,
+    pub sample: Option<u32>,
+
+    pub user: Option<String>, // to drop privileges to, once the capture's open
+
+    pub human_readable: bool,
+
+    pub filter: Option<crate::filter::Expr>,
+    pub trigger: Option<crate::filter::Expr>,
+    pub trigger_stop: Option<std::time::Duration>,
+    pub pre_roll: Option<std::time::Duration>,
+    pub match_payload: Option<Vec<PayloadPattern>>,
+
+    pub vlan: Option<u16>,
+    pub dscp: Option<Vec<crate::qos::Dscp>>,
+
+    pub replay: Option<String>,
+    pub replay_speed: f64,
+    pub rewrite_macs: Option<Vec<Rewrite<MacAddr>>>,
+    pub rewrite_ips: Option<Vec<Rewrite<std::net::IpAddr>>>,
+
+    pub direction: Option<Direction>,
+
+    pub latency_heatmap: Option<String>,
+    pub heatmap_bucket: u64,
+    pub ping_latency: bool,
+    pub service_latency: bool,
+    pub tcp_stalls: bool,
+    pub tcp_anomalies: bool,
+    pub detect_scans: bool,
+    pub ttl_anomalies: bool,
+    pub detect_credentials: bool,
+    pub ciphers: bool,
+    pub mtu_anomalies: bool,
+    pub tcp_connections: bool,
+    pub dual_stack: bool,
+    pub baseline: Option<String>,
+    pub baseline_window: std::time::Duration,
+
+    pub alerts: Option<String>,
+    pub rate_alerts: Option<Vec<RateAlert>>,
+    pub whitelist: Option<String>,
+
+    pub control: Option<String>,
+    pub control_edit: Option<String>,
+    pub export_dir: Option<String>,
+    pub attach: Option<String>,
+
+    pub max_disk: Option<u64>,
+    pub min_free: u64,
+    pub on_quota: QuotaAction,
+
+    pub push_url: Option<String>,
+    pub upload: Option<String>,
+    pub push_batch: usize,
+    pub netflow: Option<String>,
+    pub netflow_version: NetflowVersion,
+    pub flush_interval: Option<std::time::Duration>,
+
+    pub summary_json: Option<String>,
+
+    pub ip_proto: Option<Vec<u8>>,
+
+    pub log_rotate_size: Option<u64>,
+    pub log_rotate_interval: Option<std::time::Duration>,
+    pub log_rotate_gzip: bool,
+    pub log_compress: Option<LogCompression>,
+    pub log_format: LogFormat,
+    pub unwrap_proxies: bool,
+    pub decapsulate: bool,
+    pub tunnel_endpoints: bool,
+    pub log_keep: Option<usize>,
+
+    pub split_gro: bool,
+
+    pub bgp_peers: Option<Vec<std::net::IpAddr>>,
+    pub fhrp_routers: Option<Vec<std::net::IpAddr>>,
+    pub export_features: Option<String>,
+
+    pub snaplen: Option<usize>,
+    pub headers_only: bool,
+    pub verify_checksums: bool,
+
+    pub plugins: Option<Vec<String>>,
+
+    pub trace_pipeline: Option<u64>,
+
+    pub no_service_names: bool,
+    pub services: Option<Vec<String>>,
+
+    pub command: Option<Command>,
+}
+
+impl Config {
+    // the console's --output entries, if any were given
+    pub fn console_outputs(&self) -> Vec<&crate::sinks::Output> {
+        self.outputs
+            .iter()
+            .filter(|x| x.kind == crate::sinks::OutputKind::Console)
+            .collect()
+    }
+
+    // the kinds of frame --exclude-broadcast or --no-broadcast hide
+    pub fn excluded_casts(&self) -> &'static [Cast] {
+        if self.exclude_broadcast {
+            &[Cast::Broadcast, Cast::Multicast]
+        } else if self.no_broadcast {
+            &[Cast::Broadcast]
+        } else {
+            &[]
+        }
+    }
+}
+
+// what to do instead of capturing
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum Command {
+    Report { path: String, top: usize },
+    Diff { old: String, new: String, factor: f64, top: usize },
+    Interfaces,
+    Convert {
+        input: String,
+        output: String,
+        format: ConvertFormat,
+        filter: Option<crate::filter::Expr>,
+    },
+    Sessions,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum IpAddrOrHostname {
     Ip(IpAddr),
     Hostname(String),
+    HostnameSuffix(String), // *.example.com or .example.com, kept as .example.com
 }
 
 impl From<&str> for IpAddrOrHostname {
@@ -324,11 +984,150 @@ impl From<&str> for IpAddrOrHostname {
         if s.contains(':') {
             IpAddrOrHostname::Ip(s.parse().unwrap())
         } else {
-            IpAddrOrHostname::Hostname(s.to_string())
+            IpAddrOrHostname::hostname(s)
         }
     }
 }
 
+impl IpAddrOrHostname {
+    pub fn hostname(s: &str) -> IpAddrOrHostname {
+        match s.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => IpAddrOrHostname::HostnameSuffix(suffix.to_ascii_lowercase()),
+            _ if s.starts_with('.') => IpAddrOrHostname::HostnameSuffix(s.to_ascii_lowercase()),
+            _ => IpAddrOrHostname::Hostname(s.to_string()),
+        }
+    }
+
+    // whether a full host name (from reverse DNS, SNI and the like) is this one, or under this suffix
+    pub fn matches_name(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.');
+        match self {
+            IpAddrOrHostname::Ip(_) => false,
+            IpAddrOrHostname::Hostname(hostname) => name.eq_ignore_ascii_case(hostname),
+            IpAddrOrHostname::HostnameSuffix(suffix) => {
+                name.len() > suffix.len() && name.to_ascii_lowercase().ends_with(suffix.as_str())
+            }
+        }
+    }
+}
+
+// an OLD=NEW pair, e.g. for rewriting addresses on replay
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Rewrite<T> {
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: FromStr> FromStr for Rewrite<T> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, "Invalid rewrite, expected OLD=NEW");
+
+        let (from, to) = s.split_once('=').ok_or_else(invalid)?;
+
+        Ok(Rewrite {
+            from: from.parse().map_err(|_| invalid())?,
+            to: to.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// HOST=RATE, e.g. 10.0.0.12=5MBps: a host's traffic, both ways, shouldn't go over this many bytes per second
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RateAlert {
+    pub host: IpAddr,
+    pub limit: f64,
+}
+
+impl FromStr for RateAlert {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, limit) = s
+            .split_once('=')
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid rate alert, expected HOST=RATE, e.g. 10.0.0.12=5MBps"))?;
+
+        // a hostname is looked up once, up front
+        let host = match host.parse() {
+            Ok(ip) => ip,
+            Err(_) => dns_lookup::lookup_host(host)
+                .ok()
+                .and_then(|x| x.into_iter().next())
+                .and_then(|x| x.to_string().parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Couldn't resolve {}", host)))?,
+        };
+
+        Ok(RateAlert {
+            host,
+            limit: crate::units::parse_rate(limit)?,
+        })
+    }
+}
+
+impl std::fmt::Display for RateAlert {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} over {}", self.host, crate::units::human_rate(self.limit, None))
+    }
+}
+
+// a --match-payload regex, or a --match-hex byte string (turned into a regex matching exactly those bytes), looked for
+// in requests' raw bytes
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PayloadPattern(regex::bytes::Regex);
+
+impl PayloadPattern {
+    pub fn regex(s: &str) -> Result<PayloadPattern, Error> {
+        regex::bytes::Regex::new(s)
+            .map(PayloadPattern)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid payload regex: {}", e)))
+    }
+
+    // hex digits, optionally split up by spaces or colons, e.g. "de ad be ef" or "de:ad:be:ef"
+    pub fn hex(s: &str) -> Result<PayloadPattern, Error> {
+        let digits: String = s.chars().filter(|x| !matches!(x, ' ' | ':')).collect();
+        if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.chars().all(|x| x.is_ascii_hexdigit()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid hex bytes, expected e.g. deadbeef or de:ad:be:ef"));
+        }
+
+        let mut pattern = String::from("(?-u)");
+        for byte in digits.as_bytes().chunks(2) {
+            pattern += &format!("\\x{}", std::str::from_utf8(byte).unwrap().to_ascii_lowercase());
+        }
+        PayloadPattern::regex(&pattern)
+    }
+
+    // where it matches in the data
+    pub fn find_all(&self, data: &[u8]) -> Vec<std::ops::Range<usize>> {
+        self.0.find_iter(data).map(|x| x.range()).collect()
+    }
+
+    pub fn is_match(&self, data: &[u8]) -> bool {
+        self.0.is_match(data)
+    }
+}
+
+impl TryFrom<String> for PayloadPattern {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        PayloadPattern::regex(&s)
+    }
+}
+
+impl From<PayloadPattern> for String {
+    fn from(pattern: PayloadPattern) -> String {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl std::fmt::Display for PayloadPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}
+
 const STYLES: Styles = Styles::styled()
     .literal(AnsiColor::BrightCyan.on_default().bold())
     .header(AnsiColor::BrightGreen.on_default().bold())
@@ -339,37 +1138,220 @@ const STYLES: Styles = Styles::styled()
 #[derive(Parser)]
 #[command(styles=STYLES)]
 struct Args {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(subcommand)]
+    command: Option<Subcommands>,
+
+    // `sniff` on its own captures, as it always has
+    #[command(flatten)]
+    capture: CaptureArgs,
+}
+
+#[derive(Subcommand)]
+enum Subcommands {
+    /// Capture and print requests on an interface (the default)
+    Capture(Box<CaptureArgs>),
+
+    /// Re-transmit the packets in a saved log or pcap file on the interface
+    Replay(ReplayArgs),
+
+    /// Summarise a saved log or pcap file: top hosts and ports, protocol mix, busiest minute and volume
+    Report {
+        /// The log or pcap file to analyse
+        path: String,
+
+        /// How many hosts and ports to list
+        #[clap(long, default_value = "10")]
+        top: usize,
+
+        /// Only report from this time of day (12:30:00) or this far into the capture (90s)
+        #[clap(long)]
+        from: Option<crate::window::TimeBound>,
+
+        /// Only report up to this time of day (12:35:00) or this far into the capture (5m)
+        #[clap(long)]
+        to: Option<crate::window::TimeBound>,
+    },
+
+    /// Compare two saved logs or pcap files: protocols, hosts and ports only in one, and big changes in volume
+    Diff {
+        /// The log or pcap file from before
+        old: String,
+
+        /// The log or pcap file from after
+        new: String,
+
+        /// How much a volume (bytes/s, over each capture's span) has to go up or down by to count as changed
+        #[clap(long, default_value = "2")]
+        factor: f64,
+
+        /// How many differences to list of each kind
+        #[clap(long, default_value = "20")]
+        top: usize,
+    },
+
+    /// List the interfaces sniff can capture on
+    Interfaces,
+
+    /// Convert between sniff logs (JSON or binary), NDJSON and pcap files, optionally keeping only some requests
+    Convert {
+        /// The log, NDJSON or pcap file to read
+        input: String,
+
+        /// The file to write
+        output: String,
+
+        /// What to write: json, ndjson, binary or pcap (by default, by the output's extension: .pcap, .ndjson or
+        /// .jsonl, .cbor or .bin, otherwise a JSON log)
+        #[clap(long)]
+        format: Option<ConvertFormat>,
+
+        /// Only keep requests matching this expression (the same syntax as capture's --filter)
+        #[clap(long)]
+        filter: Option<crate::filter::Expr>,
+
+        /// Only keep requests from this time of day (12:30:00) or this far into the capture (90s)
+        #[clap(long)]
+        from: Option<crate::window::TimeBound>,
+
+        /// Only keep requests up to this time of day (12:35:00) or this far into the capture (5m)
+        #[clap(long)]
+        to: Option<crate::window::TimeBound>,
+    },
+
+    /// Manage the sessions --session keeps
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    /// List every session, with when it was started, its runs and what they captured
+    List,
+}
+
+// options that mean the same thing whatever sniff is doing
+#[derive(clap::Args)]
+struct CommonArgs {
+    /// debug mode
+    #[clap(short, long, global = true)]
+    debug: bool,
+
+    /// Network interface to capture on (or replay to), by name or, on Windows, adapter name; if not provided, the first non-loopback interface that is up is used
+    #[clap(short = 'n', long, global = true)]
+    interface: Option<String>,
+
+    /// Print exact byte counts instead of human-readable units and rates
+    #[clap(long, global = true)]
+    raw_bytes: bool,
+
+    /// Show port numbers without their service names, e.g. 443 rather than 443 (https)
+    #[clap(long, global = true)]
+    no_service_names: bool,
+}
+
+#[derive(clap::Args)]
+struct ReplayArgs {
+    /// The log or pcap file to replay
+    path: String,
+
+    /// Replay speed multiplier, e.g. 2 for twice as fast, or 0 to send as fast as possible
+    #[clap(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Rewrite MAC addresses, as OLD=NEW pairs
+    #[clap(long, value_delimiter = ',')]
+    rewrite_macs: Option<Vec<Rewrite<MacAddr>>>,
+
+    /// Rewrite IP addresses, as OLD=NEW pairs (checksums are recalculated)
+    #[clap(long, value_delimiter = ',')]
+    rewrite_ips: Option<Vec<Rewrite<std::net::IpAddr>>>,
+
+    /// Only replay from this time of day (12:30:00) or this far into the capture (90s)
+    #[clap(long)]
+    from: Option<crate::window::TimeBound>,
+
+    /// Only replay up to this time of day (12:35:00) or this far into the capture (5m)
+    #[clap(long)]
+    to: Option<crate::window::TimeBound>,
+}
+
+// parsed on its own too, for its defaults when running a subcommand other than capture
+#[derive(Parser)]
+#[clap(group(clap::ArgGroup::new("log").args(["log_file", "session"])))]
+struct CaptureArgs {
     /// Verbose mode - prints MAC addresses
     #[clap(short, long)]
     verbose: bool,
 
+    /// Lay out each request's line from a template of fields, e.g. "{time} {proto} {src}:{sport} -> {dst}:{dport} {bytes}"
+    #[clap(long, conflicts_with = "verbose")]
+    format: Option<crate::template::Template>,
+
     /// Path to the log file, if not provided, the program will not log
     #[clap(short, long)]
     log_file: Option<String>,
 
+    /// Keep the log, counters and filters in a named session, resuming it if it already exists (`sniff sessions list` lists them)
+    #[clap(long, conflicts_with_all = ["load_from_file", "attach"])]
+    session: Option<String>,
+
     /// Exclude IP addresses from the output
     #[clap(short = 'X', long, value_delimiter = ',')]
     exclude_ips: Option<Vec<IpAddrOrHostname>>,
 
-    /// Exclude MAC addresses from the output
+    /// Exclude MAC addresses (or names from --aliases) from the output
     #[clap(short = 'x', long, value_delimiter = ',')]
-    exclude_macs: Option<Vec<MacAddr>>,
+    exclude_macs: Option<Vec<MacAddrOrAlias>>,
+
+    /// Exclude broadcast and multicast frames (e.g. ARP and mDNS) from the output
+    #[clap(long)]
+    exclude_broadcast: bool,
+
+    /// Exclude broadcast frames (e.g. ARP and DHCP discovery) from the output, but not multicast
+    #[clap(long, conflicts_with = "exclude_broadcast")]
+    no_broadcast: bool,
 
-    /// Filter IP addresses
+    /// Only show multicast frames (e.g. mDNS, SSDP and IGMP)
+    #[clap(long, conflicts_with_all = ["exclude_broadcast", "no_broadcast"])]
+    multicast_only: bool,
+
+    /// Hide traffic to or from this machine (any of its interfaces' IP and MAC addresses), e.g. on a router or mirror port
+    #[clap(long)]
+    ignore_self: bool,
+
+    /// Filter IP addresses, hostnames, or hostname suffixes (*.example.com or .local)
     #[clap(short = 'F', long, value_delimiter = ',')]
     filter_ips: Option<Vec<IpAddrOrHostname>>,
 
-    /// Filter MAC addresses
+    /// Filter MAC addresses (or names from --aliases)
     #[clap(short, long, value_delimiter = ',')]
-    filter_macs: Option<Vec<MacAddr>>,
+    filter_macs: Option<Vec<MacAddrOrAlias>>,
+
+    /// Only show requests to or from devices by these vendors (part of the name, e.g. Apple), going by their MAC
+    #[clap(long = "filter-vendor", value_delimiter = ',')]
+    filter_vendors: Option<Vec<String>>,
+
+    /// MAC vendor database (IEEE oui.txt, Wireshark manuf or nmap-mac-prefixes) to use on top of the built-in one
+    #[clap(long)]
+    oui_file: Option<String>,
+
+    /// Names for hosts (TOML, name = "IP or MAC", or a list of them), shown instead of their addresses and usable in
+    /// filters
+    #[clap(long)]
+    aliases: Option<String>,
 
     /// Highlight IP addresses
     #[clap(short = 'I', long, value_delimiter = ',')]
     highlight_ips: Option<Vec<IpAddrOrHostname>>,
 
-    /// Highlight MAC addresses
+    /// Highlight MAC addresses (or names from --aliases)
     #[clap(short = 'i', long, value_delimiter = ',')]
-    highlight_macs: Option<Vec<MacAddr>>,
+    highlight_macs: Option<Vec<MacAddrOrAlias>>,
 
     /// Protocol to filter, omit for no filter (note that this is either TCP, UDP, or ICMP, not application layer protocols)
     protocol: Option<Protocol>,
@@ -378,10 +1360,22 @@ struct Args {
     #[clap(short = 'L', long)]
     load_from_file: Option<String>,
 
+    /// Capture from a pcap file instead of an interface, as fast as it can be read, with the file's timestamps
+    #[clap(long, conflicts_with_all = ["load_from_file", "attach"])]
+    read: Option<String>,
+
     /// Real-time playback from the log file
     #[clap(short, long)]
     real_time_playback: bool,
 
+    /// Only play back the log from this time of day (12:30:00) or this far into the capture (90s)
+    #[clap(long, requires = "load_from_file")]
+    from: Option<crate::window::TimeBound>,
+
+    /// Only play back the log up to this time of day (12:35:00) or this far into the capture (5m)
+    #[clap(long, requires = "load_from_file")]
+    to: Option<crate::window::TimeBound>,
+
     /// Print hostnames instead of IP addresses
     #[clap(short = 'H', long)]
     hostnames: bool,
@@ -390,17 +1384,439 @@ struct Args {
     #[clap(short = 'g', long)]
     suppress_gateway: bool,
 
-    /// debug mode
-    #[clap(short, long)]
-    debug: bool,
-
     /// packet collation
     #[clap(short = 'D', long)]
     dont_collate: bool,
+
+    /// Collate the replies to a request into it too, with the bytes each way (tx/rx); false shows each direction separately
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+    merge_bidirectional: bool,
+
+    /// Monitor mode - treat frames as radiotap + 802.11, decoding beacons, probes and deauths, and print a Wi-Fi roaming timeline per client on exit (on by itself for a monitor mode interface)
+    #[clap(short = 'm', long)]
+    monitor: bool,
+
+    /// Save complete WPA 4-way handshakes to one pcap per SSID/client in this directory (monitor mode only)
+    #[clap(long, requires = "monitor")]
+    handshake_dir: Option<String>,
+
+    /// Write every frame captured to this pcap file
+    #[clap(long)]
+    pcap: Option<String>,
+
+    /// Write the pcap as a ring of this many files, deleting the oldest, e.g. --ring-files 10 --ring-file-size 50M
+    #[clap(long, requires_all = ["pcap", "ring_file_size"])]
+    ring_files: Option<usize>,
+
+    /// Start the ring's next file once the current one reaches this size
+    #[clap(long, value_parser = crate::units::parse_size, requires_all = ["pcap", "ring_files"])]
+    ring_file_size: Option<u64>,
+
+    /// Also send requests here, with an optional filter, e.g. json:all.jsonl or "console where tcp"; repeat for more
+    #[clap(long = "output", value_name = "OUTPUT")]
+    outputs: Option<Vec<crate::sinks::Output>>,
+
+    /// Save the first few packets of each protocol seen, anonymised, to one pcap per protocol in this directory, as dissector test fixtures
+    #[clap(long)]
+    record_fixture: Option<String>,
+
+    /// How many packets of each protocol --record-fixture saves
+    #[clap(long, default_value = "5", requires = "record_fixture")]
+    fixture_packets: usize,
+
+    /// Print an xxd-style dump of each request's payload beneath it (hex, ascii or both)
+    #[clap(long, num_args = 0..=1, default_missing_value = "both")]
+    dump_payload: Option<DumpMode>,
+
+    /// Show a sparkline of bytes per second, redrawn in place, instead of the requests (total, protocol or host)
+    #[clap(long, num_args = 0..=1, default_missing_value = "total", conflicts_with_all = ["load_from_file", "attach"])]
+    graph: Option<GraphSplit>,
+
+    /// Maximum number of payload bytes to dump per request
+    #[clap(long, default_value_t = 256)]
+    dump_bytes: usize,
+
+    /// Print an inventory of every device seen (Ethernet, Wi-Fi and BLE) on exit
+    #[clap(long)]
+    inventory: bool,
+
+    /// Scan for Bluetooth LE advertisements on this HCI device (e.g. 0 for hci0) and add them to the inventory (requires the `ble` feature)
+    #[clap(long, requires = "inventory")]
+    ble: Option<u16>,
+
+    /// MaxMind GeoLite2 databases (.mmdb) used to annotate public IP addresses with country/ASN
+    #[clap(long, value_delimiter = ',')]
+    geoip: Option<Vec<String>>,
+
+    /// Show which local process owns each TCP or UDP connection, e.g. firefox (pid 4242) (Linux only)
+    #[clap(long)]
+    processes: bool,
+
+    /// Only show requests to or from these local processes, by name or pid (implies --processes)
+    #[clap(long, value_delimiter = ',')]
+    process: Option<Vec<String>>,
+
+    /// Serve capture and resource usage metrics (Prometheus format) over HTTP on this address, e.g. 127.0.0.1:9100
+    #[clap(long)]
+    metrics: Option<String>,
+
+    /// Switch to aggregated per-second output while more than this many requests/s are being printed
+    #[clap(long)]
+    degrade_rate: Option<u64>,
+
+    /// Print a line of totals (packets/s, bytes/s, active flows, drops) this often, e.g. 10s, between the requests
+    #[clap(long, value_parser = crate::units::parse_duration)]
+    interval: Option<std::time::Duration>,
+
+    /// Color theme: default, deuteranopia (colorblind-safe), high-contrast or monochrome
+    #[clap(long, default_value = "default")]
+    theme: ThemeName,
+
+    /// When to use colors: always, auto (only when printing to a terminal, and NO_COLOR isn't set) or never
+    #[clap(long, default_value = "auto")]
+    color: ColorWhen,
+
+    /// The same as --color never
+    #[clap(long)]
+    no_color: bool,
+
+    /// Color for highlighted requests (-I/-i) in place of the theme's, e.g. yellow or bright-blue
+    #[clap(long)]
+    highlight_color: Option<ColorName>,
+
+    /// Number of frames the capture thread can buffer ahead of processing before it starts dropping them
+    #[clap(long, default_value_t = 65536)]
+    ring_size: usize,
+
+    /// How frames are read off the interface: pnet (Npcap on Windows), or afpacket (Linux only) for several threads sharing the load
+    #[clap(long, default_value = "pnet")]
+    backend: Backend,
+
+    /// Number of AF_PACKET sockets (each with its own thread) to spread the capture over, with --backend afpacket
+    #[clap(long, default_value_t = 4)]
+    capture_threads: usize,
+
+    /// Only look at a random 1 in N frames, e.g. 1/100, scaling byte and packet counts up to match
+    #[clap(long, value_parser = crate::sampling::parse_rate)]
+    sample: Option<u32>,
+
+    /// Once the capture is open, drop root privileges and carry on as this user (Unix only)
+    #[clap(long)]
+    user: Option<String>,
+
+    /// Drop root privileges once the capture is open, becoming nobody unless --user says otherwise (Unix only)
+    #[clap(long)]
+    drop_privileges: bool,
+
+    /// Format numbers with thousands separators and timestamps as local wall-clock time, following the locale (LC_ALL/LC_NUMERIC/LANG)
+    #[clap(long)]
+    human_readable: bool,
+
+    /// Only show requests matching a tcpdump-style filter expression, e.g. "tcp port 443 and not host 10.0.0.1"
+    #[clap(long)]
+    filter: Option<crate::filter::Expr>,
+
+    /// Only count requests until one matches this filter expression (e.g. "host 10.0.0.5 and port 22"), then capture
+    #[clap(long)]
+    trigger: Option<crate::filter::Expr>,
+
+    /// With --trigger, stop capturing once this long (e.g. 30s) has passed since the last request matching it
+    #[clap(long, value_parser = crate::units::parse_duration, requires = "trigger")]
+    trigger_stop: Option<std::time::Duration>,
+
+    /// With --pcap, hold the last this long (e.g. 10s) of frames in memory, writing them only once --trigger or an
+    /// alert fires
+    #[clap(long, value_parser = crate::units::parse_duration, requires = "pcap")]
+    pre_roll: Option<std::time::Duration>,
+
+    /// Only show requests whose raw bytes match this regex (may be given more than once), highlighting the matches in
+    /// --dump-payload output
+    #[clap(long, value_parser = PayloadPattern::regex)]
+    match_payload: Option<Vec<PayloadPattern>>,
+
+    /// Only show requests containing these bytes, in hex (e.g. deadbeef or de:ad:be:ef; may be given more than once)
+    #[clap(long, value_parser = PayloadPattern::hex)]
+    match_hex: Option<Vec<PayloadPattern>>,
+
+    /// Only show requests from frames tagged with this 802.1Q/802.1ad VLAN ID
+    #[clap(long)]
+    vlan: Option<u16>,
+
+    /// Only show requests marked with these DSCPs, by name (ef, af41, cs6) or number, e.g. to check VoIP is marked EF
+    #[clap(long, value_delimiter = ',')]
+    dscp: Option<Vec<crate::qos::Dscp>>,
+
+    /// Only show requests going this way relative to the capture interface: in, out or local
+    #[clap(long)]
+    direction: Option<Direction>,
+
+    /// On exit, write the median round trip time per destination and time bucket to this file (.json for JSON, otherwise CSV)
+    #[clap(long)]
+    latency_heatmap: Option<String>,
+
+    /// Width of each latency heatmap time bucket, in seconds
+    #[clap(long, default_value_t = 10, requires = "latency_heatmap")]
+    heatmap_bucket: u64,
+
+    /// Match ICMP echo replies to their requests, showing each round trip time and a histogram of them at exit
+    #[clap(long)]
+    ping_latency: bool,
+
+    /// Time TCP handshakes and servers' first response bytes, reporting p50/p95/p99 for each service port at exit
+    #[clap(long)]
+    service_latency: bool,
+
+    /// Track TCP receive windows, calling out and totalling the time connections are stalled by a zero or full window
+    #[clap(long)]
+    tcp_stalls: bool,
+
+    /// Call out failed TCP handshakes, connections reset by one end and retransmission storms, and total them at exit
+    #[clap(long)]
+    tcp_anomalies: bool,
+
+    /// Call out port scans, host sweeps and floods of half-open SYNs as one line each, hiding the scanner's requests
+    #[clap(long)]
+    detect_scans: bool,
+
+    /// Call out TCP and UDP flows whose TTL (or hop limit) changes mid-connection, a sign of spoofing or rerouting
+    #[clap(long)]
+    ttl_anomalies: bool,
+
+    /// Call out logins sent in plaintext (FTP, POP3, SMTP AUTH, IMAP, HTTP Basic), without showing their passwords
+    #[clap(long)]
+    detect_credentials: bool,
+
+    /// Report the TLS and SSH versions and ciphers servers negotiate, calling out outdated and weak ones
+    #[clap(long)]
+    ciphers: bool,
+
+    /// Call out packets too big for the path MTU and senders that don't adapt, and report each host's largest packet
+    #[clap(long)]
+    mtu_anomalies: bool,
+
+    /// Print a line as each TCP connection ends, with its duration, bytes each way, and whether it was closed, reset or went idle
+    #[clap(long)]
+    tcp_connections: bool,
+
+    /// Match up IPv4 and IPv6 attempts at connecting to the same name, reporting which family won and how often IPv6 fails
+    #[clap(long)]
+    dual_stack: bool,
+
+    /// Learn what's normal for each local host (volume per protocol, ports, countries) into this file, then warn about deviations from it
+    #[clap(long)]
+    baseline: Option<String>,
+
+    /// How long --baseline learns for before it starts warning, e.g. 1h (carried over between captures until it's done)
+    #[clap(long, default_value = "1h", value_parser = crate::units::parse_duration, requires = "baseline")]
+    baseline_window: std::time::Duration,
+
+    /// Alert rules (TOML) to evaluate against every request, with console, webhook or command actions
+    #[clap(long)]
+    alerts: Option<String>,
+
+    /// Warn when a host's traffic goes over a rate, e.g. 10.0.0.12=5MBps (also an alert rule, with --alerts)
+    #[clap(long = "rate-alert", value_delimiter = ',')]
+    rate_alerts: Option<Vec<RateAlert>>,
+
+    /// Expected flows (TOML): only show, and call out, traffic that isn't one of them
+    #[clap(long)]
+    whitelist: Option<String>,
+
+    /// Listen on this Unix domain socket for other sniffs to --attach to, and for stats and the list of flows
+    #[clap(long)]
+    control: Option<String>,
+
+    /// Listen on this Unix domain socket, which only our own user can connect to, for commands that change the capture
+    #[clap(long)]
+    control_edit: Option<String>,
+
+    /// Where the --control-edit socket's export-flow writes its files; it can't write anywhere else
+    #[clap(long, requires = "control_edit")]
+    export_dir: Option<String>,
+
+    /// Watch the requests of a sniff running with --control on this socket, through this sniff's own filters
+    #[clap(long, conflicts_with_all = ["control", "control_edit", "load_from_file"])]
+    attach: Option<String>,
+
+    /// Maximum disk space for everything sniff writes (logs, handshake captures), e.g. 10G
+    #[clap(long, value_parser = crate::units::parse_size)]
+    max_disk: Option<u64>,
+
+    /// Stop capturing when free space on the output disk drops below this, e.g. 500M
+    #[clap(long, value_parser = crate::units::parse_size, default_value = "256M")]
+    min_free: u64,
+
+    /// What to do when --max-disk is reached: stop, or prune the oldest rotated logs and handshake captures
+    #[clap(long, default_value = "stop")]
+    on_quota: QuotaAction,
+
+    /// POST requests as batched JSON to this HTTP(S) endpoint, e.g. a central collector
+    #[clap(long)]
+    push_url: Option<String>,
+
+    /// Upload each finished log (rotated, or at the end of the capture) to s3://bucket/prefix or sftp://[user@]host[:port]/path
+    #[clap(long, requires = "log")]
+    upload: Option<String>,
+
+    /// Maximum number of requests per --push-url batch
+    #[clap(long, default_value = "100", requires = "push_url")]
+    push_batch: usize,
+
+    /// Export flows as NetFlow v9 or IPFIX records over UDP to this collector, e.g. 10.0.0.5:2055
+    #[clap(long)]
+    netflow: Option<String>,
+
+    /// The flow record format for --netflow: 9 (NetFlow v9) or ipfix
+    #[clap(long, default_value = "9", requires = "netflow")]
+    netflow_version: NetflowVersion,
+
+    /// Buffer output, the log file and --push-url batches, flushing them this often (e.g. 250ms) instead of per request
+    #[clap(long, value_parser = crate::units::parse_duration)]
+    flush_interval: Option<std::time::Duration>,
+
+    /// Write the end-of-run summary to this file as JSON
+    #[clap(long)]
+    summary_json: Option<String>,
+
+    /// Only show requests with these IP protocol numbers, e.g. 89 for OSPF
+    #[clap(long, value_delimiter = ',')]
+    ip_proto: Option<Vec<u8>>,
+
+    /// Roll the log over to a timestamped file once it reaches this size, e.g. 100M
+    #[clap(long, value_parser = crate::units::parse_size, requires = "log")]
+    log_rotate_size: Option<u64>,
+
+    /// Roll the log over to a timestamped file this often, e.g. 1h
+    #[clap(long, value_parser = crate::units::parse_duration, requires = "log")]
+    log_rotate_interval: Option<std::time::Duration>,
+
+    /// Gzip rotated log files
+    #[clap(long, requires = "log")]
+    log_rotate_gzip: bool,
+
+    /// Compress the log as it's written, with gzip or zstd (logs are decompressed automatically when loaded)
+    #[clap(long, requires = "log")]
+    log_compress: Option<LogCompression>,
+
+    /// Write the log as json or binary (CBOR, a fraction of the size; either is recognised when loaded)
+    #[clap(long, default_value = "json", requires = "log")]
+    log_format: LogFormat,
+
+    /// Follow HTTP CONNECT and SOCKS proxy connections to where they're really going, and show and count them by that
+    #[clap(long)]
+    unwrap_proxies: bool,
+
+    /// Take packets out of IP-in-IP, GRE, VXLAN and GTP-U tunnels, and show, filter and count them by the flow inside
+    #[clap(long)]
+    decapsulate: bool,
+
+    /// With --decapsulate and -v, show the tunnel each request came through under it
+    #[clap(long, requires = "decapsulate")]
+    tunnel_endpoints: bool,
+
+    /// Keep at most this many rotated log files, deleting the oldest
+    #[clap(long, requires = "log")]
+    log_keep: Option<usize>,
+
+    /// Count GRO/TSO super-packets as the segments they stand for, rather than as one big packet
+    #[clap(long)]
+    split_gro: bool,
+
+    /// Expected BGP peers; BGP from anyone else is flagged
+    #[clap(long, value_delimiter = ',')]
+    bgp_peers: Option<Vec<std::net::IpAddr>>,
+
+    /// Expected VRRP/HSRP routers; adverts from anyone else are flagged
+    #[clap(long, value_delimiter = ',')]
+    fhrp_routers: Option<Vec<std::net::IpAddr>>,
+
+    /// Write per-flow feature vectors (sizes, timings, TCP flags, payload entropy) to a .parquet or .csv file
+    #[clap(long)]
+    export_features: Option<String>,
+
+    /// Keep at most this many bytes of each packet for output (console, log, pcap), still counting full sizes
+    #[clap(long)]
+    snaplen: Option<usize>,
+
+    /// Keep only the IP and TCP/UDP/ICMP headers of each packet for output, dropping the payload
+    #[clap(long)]
+    headers_only: bool,
+
+    /// Check IPv4, TCP, UDP and ICMP checksums, marking requests with a packet that fails
+    #[clap(long)]
+    verify_checksums: bool,
+
+    /// Run this command as a plugin, adding the columns it answers with to each request (may be repeated)
+    #[clap(long = "plugin")]
+    plugins: Option<Vec<String>>,
+
+    /// Time 1 in N packets (default 100) through each pipeline stage, printing the timings under each and percentiles on exit
+    #[clap(long, num_args = 0..=1, default_missing_value = "100")]
+    trace_pipeline: Option<u64>,
+
+    /// Only show requests to or from these services, by name or port, e.g. ssh,https
+    #[clap(long = "service", value_delimiter = ',')]
+    services: Option<Vec<String>>,
 }
 
-pub fn get_conf() -> Config {
-    let args: Args = Args::parse();
+// the configuration, and the --aliases it names hosts with (already standing in for their addresses in it)
+pub fn get_conf() -> (Config, Option<crate::aliases::Aliases>) {
+    let matches = Args::command().get_matches();
+    let Args { common, command, capture } = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // capture options before a subcommand would otherwise be silently ignored
+    if let Some((name, _)) = matches.subcommand() {
+        let given = CaptureArgs::command()
+            .get_arguments()
+            .find(|x| matches.value_source(x.get_id().as_str()) == Some(ValueSource::CommandLine))
+            .map(|x| x.get_long().map(|long| format!("--{}", long)).unwrap_or_else(|| x.get_id().to_string().to_uppercase()));
+
+        if let Some(arg) = given {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    format!("'{}' is a capture option, so it can't be used with `sniff {}`", arg, name),
+                )
+                .exit();
+        }
+    }
+
+    let defaults = || CaptureArgs::parse_from(["sniff"]);
+
+    // the part of a saved capture to look at, whichever command it's for
+    let mut window = (None, None);
+
+    let (args, command, replay) = match command {
+        None => (capture, None, None),
+        Some(Subcommands::Capture(args)) => (*args, None, None),
+        Some(Subcommands::Replay(mut replay)) => {
+            window = (replay.from.take(), replay.to.take());
+            (defaults(), None, Some(replay))
+        }
+        Some(Subcommands::Report { path, top, from, to }) => {
+            window = (from, to);
+            (defaults(), Some(Command::Report { path, top }), None)
+        }
+        Some(Subcommands::Diff { old, new, factor, top }) => {
+            (defaults(), Some(Command::Diff { old, new, factor, top }), None)
+        }
+        Some(Subcommands::Interfaces) => (defaults(), Some(Command::Interfaces), None),
+        Some(Subcommands::Convert {
+            input,
+            output,
+            format,
+            filter,
+            from,
+            to,
+        }) => {
+            window = (from, to);
+            let format = format.unwrap_or_else(|| ConvertFormat::from_path(&output));
+            (defaults(), Some(Command::Convert { input, output, format, filter }), None)
+        }
+        Some(Subcommands::Sessions { command: SessionsCommand::List }) => (defaults(), Some(Command::Sessions), None),
+    };
+
+    let aliases = args.aliases.as_deref().map(crate::aliases::Aliases::load);
 
     let exclude_ips = args.exclude_ips.clone();
 
@@ -408,42 +1824,159 @@ pub fn get_conf() -> Config {
 
     // if suppress gateway is enabled, add _gateway and 192.168.1.254 to the exclude list
     if args.suppress_gateway {
-        updated_ips = match exclude_ips {
-            Some(ips) => ips,
-            None => Vec::new(),
-        };
+        updated_ips = exclude_ips.unwrap_or_default();
 
         updated_ips.push(IpAddrOrHostname::Hostname("_gateway".to_string()));
         updated_ips.push(IpAddrOrHostname::Ip(IpAddr::V4([192, 168, 1, 254].into())));
     } else {
-        updated_ips = match exclude_ips {
-            Some(ips) => ips,
-            None => Vec::new(),
-        };
+        updated_ips = exclude_ips.unwrap_or_default();
     }
 
-    Config {
+    let mut config = Config {
         verbose: args.verbose,
-        debug: args.debug,
+        format: args.format,
+        debug: common.debug,
         log_file: args.log_file,
+        session: args.session,
         exclude_ips: match updated_ips.len() {
             0 => None,
             _ => Some(updated_ips),
         },
-        exclude_macs: args.exclude_macs,
+        exclude_macs: resolve_macs(args.exclude_macs, aliases.as_ref()),
+        exclude_broadcast: args.exclude_broadcast,
+        no_broadcast: args.no_broadcast,
+        multicast_only: args.multicast_only,
+        ignore_self: args.ignore_self,
         filter_ips: args.filter_ips,
-        filter_macs: args.filter_macs,
+        filter_macs: resolve_macs(args.filter_macs, aliases.as_ref()),
+        filter_vendors: args.filter_vendors,
+        oui_file: args.oui_file,
+        aliases: args.aliases,
         highlight_ips: args.highlight_ips,
-        highlight_macs: args.highlight_macs,
+        highlight_macs: resolve_macs(args.highlight_macs, aliases.as_ref()),
         protocol: match args.protocol {
             Some(Protocol::Unknown) => None,
             _ => args.protocol,
         },
         load_from_file: args.load_from_file,
+        read: args.read,
         real_time_playback: args.real_time_playback,
+        from: args.from.or(window.0),
+        to: args.to.or(window.1),
         hostnames: args.hostnames,
         dont_collate: args.dont_collate,
+        merge_bidirectional: args.merge_bidirectional,
+        interface: common.interface,
+        monitor: args.monitor,
+        handshake_dir: args.handshake_dir,
+        record_fixture: args.record_fixture,
+        pcap: args.pcap,
+        ring_files: args.ring_files,
+        outputs: args.outputs.unwrap_or_default(),
+        ring_file_size: args.ring_file_size,
+        fixture_packets: args.fixture_packets,
+        dump_payload: args.dump_payload,
+        graph: args.graph,
+        dump_bytes: args.dump_bytes,
+        inventory: args.inventory,
+        ble: args.ble,
+        geoip: args.geoip,
+        processes: args.processes,
+        process: args.process,
+        metrics: args.metrics,
+        degrade_rate: args.degrade_rate,
+        interval: args.interval,
+        raw_bytes: common.raw_bytes,
+        theme: args.theme,
+        color: if args.no_color { ColorWhen::Never } else { args.color },
+        highlight_color: args.highlight_color,
+        ring_size: args.ring_size,
+        backend: args.backend,
+        capture_threads: args.capture_threads,
+        sample: args.sample,
+        user: args.user.or_else(|| args.drop_privileges.then(|| "nobody".to_string())),
+        human_readable: args.human_readable,
+        filter: args.filter,
+        trigger: args.trigger,
+        trigger_stop: args.trigger_stop,
+        pre_roll: args.pre_roll,
+        // either kind of pattern will do
+        match_payload: match (args.match_payload, args.match_hex) {
+            (None, None) => None,
+            (patterns, hex) => Some(patterns.into_iter().chain(hex).flatten().collect()),
+        },
+        vlan: args.vlan,
+        dscp: args.dscp,
+        replay_speed: replay.as_ref().map_or(1.0, |x| x.speed),
+        rewrite_macs: replay.as_ref().and_then(|x| x.rewrite_macs.clone()),
+        rewrite_ips: replay.as_ref().and_then(|x| x.rewrite_ips.clone()),
+        replay: replay.map(|x| x.path),
+        direction: args.direction,
+        latency_heatmap: args.latency_heatmap,
+        heatmap_bucket: args.heatmap_bucket,
+        ping_latency: args.ping_latency,
+        service_latency: args.service_latency,
+        tcp_stalls: args.tcp_stalls,
+        tcp_anomalies: args.tcp_anomalies,
+        detect_scans: args.detect_scans,
+        ttl_anomalies: args.ttl_anomalies,
+        detect_credentials: args.detect_credentials,
+        ciphers: args.ciphers,
+        mtu_anomalies: args.mtu_anomalies,
+        tcp_connections: args.tcp_connections,
+        dual_stack: args.dual_stack,
+        baseline: args.baseline,
+        baseline_window: args.baseline_window,
+        alerts: args.alerts,
+        rate_alerts: args.rate_alerts,
+        whitelist: args.whitelist,
+        control: args.control,
+        control_edit: args.control_edit,
+        export_dir: args.export_dir,
+        attach: args.attach,
+        max_disk: args.max_disk,
+        min_free: args.min_free,
+        on_quota: args.on_quota,
+        push_url: args.push_url,
+        upload: args.upload,
+        push_batch: args.push_batch.max(1),
+        netflow: args.netflow,
+        netflow_version: args.netflow_version,
+        flush_interval: args.flush_interval,
+        summary_json: args.summary_json,
+        ip_proto: args.ip_proto,
+        log_rotate_size: args.log_rotate_size,
+        log_rotate_interval: args.log_rotate_interval,
+        log_rotate_gzip: args.log_rotate_gzip,
+        log_compress: args.log_compress,
+        log_format: args.log_format,
+        unwrap_proxies: args.unwrap_proxies,
+        decapsulate: args.decapsulate,
+        tunnel_endpoints: args.tunnel_endpoints,
+        log_keep: args.log_keep,
+        split_gro: args.split_gro,
+        bgp_peers: args.bgp_peers,
+        fhrp_routers: args.fhrp_routers,
+        export_features: args.export_features,
+        snaplen: args.snaplen,
+        headers_only: args.headers_only,
+        verify_checksums: args.verify_checksums,
+        plugins: args.plugins,
+        trace_pipeline: args.trace_pipeline,
+        no_service_names: common.no_service_names,
+        services: args.services,
+        command,
+    };
+
+    // names from --aliases stand for their addresses in filters
+    if let Some(ref aliases) = aliases {
+        aliases.expand_config(&mut config);
+    }
+
+    if let Some(Err(e)) = config.alerts.as_deref().map(crate::alerts::AlertEngine::check) {
+        Args::command().error(clap::error::ErrorKind::InvalidValue, e).exit();
     }
+    (config, aliases)
 }
 
 impl std::fmt::Display for IpV4 {
@@ -453,16 +1986,9 @@ impl std::fmt::Display for IpV4 {
 }
 
 impl std::fmt::Display for IpV6 {
+    // RFC 5952's canonical form: lowercase, no leading zeros, and the longest run of zero groups as ::
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        // first convert the 16 8-bit octets into 8 16-bit octets
-        let mut new_octets: [u16; 8] = [0; 8];
-
-        for i in 0..8 {
-            new_octets[i] = (self.octets[i * 2] as u16) << 8 | self.octets[i * 2 + 1] as u16;
-        }
-
-        write!(f, "{:x}:{:x}:{:x}:{:x}:{:x}:{:x}:{:x}:{:x}", new_octets[0], new_octets[1], new_octets[2], new_octets[3], new_octets[4], new_octets[5], new_octets[6], new_octets[7])
-
+        write!(f, "{}", std::net::Ipv6Addr::from(self.octets))
     }
 }
 
@@ -475,6 +2001,16 @@ impl std::fmt::Display for IpAddr {
     }
 }
 
+impl std::fmt::Display for IpAddrOrHostname {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IpAddrOrHostname::Ip(ip) => write!(f, "{}", ip),
+            IpAddrOrHostname::Hostname(hostname) => write!(f, "{}", hostname),
+            IpAddrOrHostname::HostnameSuffix(suffix) => write!(f, "*{}", suffix),
+        }
+    }
+}
+
 impl std::fmt::Display for MacAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:x}:{:x}:{:x}:{:x}:{:x}:{:x}", self.octets[0], self.octets[1], self.octets[2], self.octets[3], self.octets[4], self.octets[5])
@@ -488,6 +2024,14 @@ impl std::fmt::Display for Protocol {
             Protocol::Udp => write!(f, "UDP"),
             Protocol::Icmp => write!(f, "ICMP"),
             Protocol::Unknown => write!(f, "???"),
+            Protocol::Ip(num) => match Protocol::guess_ip(*num) {
+                Some(name) => write!(f, "proto {} ({}?)", num, name),
+                None => write!(f, "proto {}", num),
+            },
+            Protocol::Ether(ethertype) => match Protocol::guess_ether(*ethertype) {
+                Some(name) => write!(f, "ethertype {:#06x} ({}?)", ethertype, name),
+                None => write!(f, "ethertype {:#06x}", ethertype),
+            },
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,230 @@
+// --tcp-connections: a line for each TCP connection as it ends, with how long it lasted, how much went each way and
+// why it ended (finished with FINs, reset, or idle for too long), where the requests only show it bit by bit
+//
+// connections still open at the end of the capture are only counted
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, locale::Locale, units};
+
+// a connection with nothing sent for this long is over, as far as we're concerned
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+// a copy of the segment just before it this soon is the same packet seen twice (e.g. on loopback), not a resend
+const DUPLICATE_WINDOW: Duration = Duration::from_millis(1);
+// the last ACK (or a stray retransmission) after a connection's ended isn't the start of another
+const CLOSED_LINGER: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Side {
+    bytes: u64,
+    packets: u64,
+    fin: bool,
+    last_segment: Option<(u32, u32, u8, SystemTime)>, // seq, ack, flags, when
+}
+
+struct Connection {
+    ends: [SocketAddr; 2], // client first, if we saw the handshake; otherwise the lower
+    opened: bool,          // whether we saw the handshake, so know when it started
+    first: SystemTime,
+    last: SystemTime,
+    sides: [Side; 2],
+}
+
+enum Reason {
+    Finished(SocketAddr), // by whoever sent the first FIN
+    Reset(SocketAddr),
+    Idle,
+}
+
+#[derive(Default)]
+struct Totals {
+    finished: u64,
+    reset: u64,
+    idle: u64,
+}
+
+pub struct ConnectionTracker {
+    locale: Option<Locale>,
+    connections: HashMap<(SocketAddr, SocketAddr), Connection>, // by the ends, lower first
+    closed: HashMap<(SocketAddr, SocketAddr), SystemTime>,      // recently, and when
+    totals: Totals,
+    checked: Option<SystemTime>,
+}
+
+impl ConnectionTracker {
+    pub fn new(locale: Option<Locale>) -> ConnectionTracker {
+        ConnectionTracker {
+            locale,
+            connections: HashMap::new(),
+            closed: HashMap::new(),
+            totals: Totals::default(),
+            checked: None,
+        }
+    }
+
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet).filter(|x| x.protocol == 6) else {
+            return;
+        };
+        // short frames are padded out, and the padding isn't part of the packet
+        let end = ip::total_len(packet).unwrap_or(packet.len()).min(packet.len());
+        let Some(segment) = packet.get(header.header_len..end).filter(|x| x.len() >= 20) else {
+            return;
+        };
+
+        let src = SocketAddr::new(header.src, u16::from_be_bytes([segment[0], segment[1]]));
+        let dst = SocketAddr::new(header.dst, u16::from_be_bytes([segment[2], segment[3]]));
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let acked = u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]);
+        let flags = segment[13];
+        let (fin, syn, rst, ack) = (
+            flags & 0x01 != 0,
+            flags & 0x02 != 0,
+            flags & 0x04 != 0,
+            flags & 0x10 != 0,
+        );
+
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.check(timestamp);
+        }
+
+        let key = if src < dst { (src, dst) } else { (dst, src) };
+
+        // a reset of something we know nothing about isn't a connection ending
+        if rst && !self.connections.contains_key(&key) {
+            return;
+        }
+        if self.closed.contains_key(&key) {
+            if !syn || ack {
+                return;
+            }
+            self.closed.remove(&key);
+        }
+
+        let connection = self.connections.entry(key).or_insert_with(|| Connection {
+            // the SYN's sender is the client; otherwise we can't tell
+            ends: if syn && !ack { [src, dst] } else { [key.0, key.1] },
+            opened: syn && !ack,
+            first: timestamp,
+            last: timestamp,
+            sides: Default::default(),
+        });
+        let this = if connection.ends[0] == src { 0 } else { 1 };
+        let side = &mut connection.sides[this];
+
+        if side
+            .last_segment
+            .is_some_and(|(last_seq, last_acked, last_flags, when)| {
+                (last_seq, last_acked, last_flags) == (seq, acked, flags)
+                    && timestamp.duration_since(when).unwrap_or_default() < DUPLICATE_WINDOW
+            })
+        {
+            return;
+        }
+        side.last_segment = Some((seq, acked, flags, timestamp));
+        side.bytes += end as u64;
+        side.packets += 1;
+        side.fin |= fin;
+        connection.last = timestamp;
+
+        if rst {
+            self.close(key, Reason::Reset(src));
+        } else if connection.sides.iter().all(|x| x.fin) {
+            let first_fin = if this == 0 {
+                connection.ends[1]
+            } else {
+                connection.ends[0]
+            };
+            self.close(key, Reason::Finished(first_fin));
+        }
+    }
+
+    // close connections that have gone quiet
+    fn check(&mut self, now: SystemTime) {
+        self.closed.retain(|_, when| now.duration_since(*when).unwrap_or_default() < CLOSED_LINGER);
+
+        let idle: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, x)| now.duration_since(x.last).unwrap_or_default() >= IDLE_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in idle {
+            self.close(key, Reason::Idle);
+        }
+    }
+
+    fn close(&mut self, key: (SocketAddr, SocketAddr), reason: Reason) {
+        let Some(connection) = self.connections.remove(&key) else {
+            return;
+        };
+        self.closed.insert(key, connection.last);
+
+        let reason = match reason {
+            Reason::Finished(by) => {
+                self.totals.finished += 1;
+                format!("closed by {}", by)
+            }
+            Reason::Reset(by) => {
+                self.totals.reset += 1;
+                format!("reset by {}", by)
+            }
+            Reason::Idle => {
+                self.totals.idle += 1;
+                format!("idle for {}s", IDLE_TIMEOUT.as_secs())
+            }
+        };
+
+        let duration = connection.last.duration_since(connection.first).unwrap_or_default();
+        let bytes = |side: &Side| {
+            format!(
+                "{} in {} packet{}",
+                units::human_bytes(side.bytes, self.locale.as_ref()),
+                side.packets,
+                if side.packets == 1 { "" } else { "s" }
+            )
+        };
+
+        outln!(
+            "TCP connection {} -> {} ended after {}{:.2}s ({}): {} sent, {} back",
+            connection.ends[0],
+            connection.ends[1],
+            // we can only say when we started seeing it
+            if connection.opened { "" } else { "at least " },
+            duration.as_secs_f64(),
+            reason,
+            bytes(&connection.sides[0]),
+            bytes(&connection.sides[1]),
+        );
+    }
+
+    pub fn print_report(mut self) {
+        // anything that's gone quiet by now has ended too
+        self.check(SystemTime::now());
+
+        let Totals { finished, reset, idle } = self.totals;
+        if finished + reset + idle == 0 && self.connections.is_empty() {
+            return;
+        }
+
+        println!("TCP connections:");
+        println!(
+            "    {} ended ({} closed, {} reset, {} idle), {} still open",
+            finished + reset + idle,
+            finished,
+            reset,
+            idle,
+            self.connections.len()
+        );
+    }
+}
@@ -0,0 +1,373 @@
+// Unix domain sockets for talking to a running capture
+// clients send one command per line; "attach" streams every request as a JSON line from then on,
+// so other people can watch the same capture with their own display filters, and the rest adjust the capture's own
+// filters without restarting it (and losing everything it's keeping track of). Only attach, stats and flows are
+// accepted on the --control socket, which is there to be shared; everything else needs the --control-edit one,
+// which only the user sniff runs as can connect to:
+//
+//     add-exclude 10.0.0.3     exclude an address, hostname or MAC address, as with -e/-x
+//     remove-exclude 10.0.0.3
+//     set-protocol udp         only show one protocol, as with -p; "any" shows them all again
+//     pause                    stop showing and logging requests (attached clients still get them)
+//     resume
+//     stats                    packets and requests so far
+//     flows                    recent flows, a line each after "ok N", most recently active first
+//     export-flow 7 x.json     write flow 7's packets to a .pcap file, or its metadata and payload to JSON, in the
+//                              --export-dir directory; never anywhere else, and never over an existing file
+
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    conf::{Config, IpAddr, IpAddrOrHostname, MacAddr, Protocol},
+    flowstore::FlowStore,
+    metrics::METRICS,
+    rules::RuleStats,
+    OutputState, RequestStats,
+};
+
+// how many events an attached client can fall behind by before it starts missing some
+const CLIENT_BACKLOG: usize = 4096;
+// what only clients of the --control-edit socket can do, as they change the capture or write files
+const EDITING: &[&str] = &["add-exclude", "remove-exclude", "set-protocol", "pause", "resume", "export-flow"];
+// the longest command we'll read, so a client can't have us buffer an endless line
+const MAX_LINE: u64 = 4096;
+
+// a change to the capture's filters, made by the capture loop between requests
+pub enum Edit {
+    AddExclude(Exclusion),
+    RemoveExclude(Exclusion),
+    SetProtocol(Option<Protocol>),
+}
+
+pub enum Exclusion {
+    Ip(IpAddrOrHostname),
+    Mac(MacAddr),
+}
+
+impl Edit {
+    fn parse(command: &str, argument: &str) -> Result<Edit, String> {
+        match command {
+            "add-exclude" => Ok(Edit::AddExclude(Exclusion::parse(argument)?)),
+            "remove-exclude" => Ok(Edit::RemoveExclude(Exclusion::parse(argument)?)),
+            "set-protocol" => match argument.to_ascii_lowercase().as_str() {
+                "any" | "all" => Ok(Edit::SetProtocol(None)),
+                protocol => match protocol.parse() {
+                    Ok(Protocol::Unknown) | Err(_) => Err(format!("unknown protocol: {}", argument)),
+                    Ok(protocol) => Ok(Edit::SetProtocol(Some(protocol))),
+                },
+            },
+            _ => Err(format!("unknown command: {}", command)),
+        }
+    }
+
+    pub fn apply(self, config: &mut Config, rules: &mut RuleStats) {
+        match self {
+            Edit::AddExclude(Exclusion::Ip(ip)) => {
+                let exclude_ips = config.exclude_ips.get_or_insert_with(Vec::new);
+                if !exclude_ips.contains(&ip) {
+                    rules.register("exclude ip", std::slice::from_ref(&ip));
+                    exclude_ips.push(ip);
+                }
+            }
+            Edit::AddExclude(Exclusion::Mac(mac)) => {
+                let exclude_macs = config.exclude_macs.get_or_insert_with(Vec::new);
+                if !exclude_macs.contains(&mac) {
+                    rules.register("exclude mac", &[mac]);
+                    exclude_macs.push(mac);
+                }
+            }
+            Edit::RemoveExclude(Exclusion::Ip(ip)) => {
+                if let Some(ref mut exclude_ips) = config.exclude_ips {
+                    exclude_ips.retain(|x| *x != ip);
+                }
+            }
+            Edit::RemoveExclude(Exclusion::Mac(mac)) => {
+                if let Some(ref mut exclude_macs) = config.exclude_macs {
+                    exclude_macs.retain(|x| *x != mac);
+                }
+            }
+            Edit::SetProtocol(protocol) => {
+                if let Some(protocol) = protocol.filter(|x| config.protocol != Some(*x)) {
+                    rules.register("protocol", &[protocol]);
+                }
+                config.protocol = protocol;
+            }
+        }
+    }
+}
+
+impl Exclusion {
+    fn parse(argument: &str) -> Result<Exclusion, String> {
+        if argument.is_empty() {
+            return Err("expected an address, hostname or MAC address".to_string());
+        }
+
+        if let Ok(mac) = argument.parse::<MacAddr>() {
+            Ok(Exclusion::Mac(mac))
+        } else if let Ok(ip) = argument.parse::<IpAddr>() {
+            Ok(Exclusion::Ip(IpAddrOrHostname::Ip(ip)))
+        } else {
+            Ok(Exclusion::Ip(IpAddrOrHostname::hostname(argument)))
+        }
+    }
+}
+
+pub struct ControlServer {
+    paths: Vec<String>,
+    // what every client's thread gets a copy of
+    client: Client,
+}
+
+impl ControlServer {
+    pub fn new(start_time: SystemTime, export_dir: Option<&str>) -> ControlServer {
+        let client = Client {
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            edits: Arc::new(Mutex::new(Vec::new())),
+            paused: Arc::new(AtomicBool::new(false)),
+            flows: Arc::new(Mutex::new(FlowStore::default())),
+            start_time,
+            export_dir: export_dir.map(PathBuf::from),
+            editing: false,
+        };
+        ControlServer {
+            paths: Vec::new(),
+            client,
+        }
+    }
+
+    // take clients on the socket at `path`; only those on an editing socket, which no other user can connect to, get to
+    // change the capture or write files
+    pub fn listen(&mut self, path: &str, editing: bool) -> std::io::Result<()> {
+        // a socket left behind by a previous run would stop us binding, but anything else there isn't ours to remove
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                return Err(std::io::Error::new(ErrorKind::AlreadyExists, "something other than a socket is there"));
+            }
+            Ok(_) if UnixStream::connect(path).is_err() => std::fs::remove_file(path)?,
+            _ => {}
+        }
+
+        let listener = if editing {
+            // created owner-only, rather than narrowed once bound, when someone else could already have connected
+            let umask = unsafe { libc::umask(0o177) };
+            let listener = UnixListener::bind(path);
+            unsafe { libc::umask(umask) };
+            listener?
+        } else {
+            UnixListener::bind(path)?
+        };
+        self.paths.push(path.to_string());
+
+        let client = Client {
+            editing,
+            ..self.client.clone()
+        };
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let client = client.clone();
+
+                std::thread::spawn(move || client.handle(stream));
+            }
+        });
+
+        Ok(())
+    }
+
+    // hand a request to every attached client, without ever blocking the capture on a slow one
+    pub fn publish(&self, stats: &RequestStats) {
+        let mut subscribers = self.client.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let line = Arc::new(serde_json::to_string(stats).unwrap());
+
+        subscribers.retain(|subscriber| match subscriber.try_send(line.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    // filter changes asked for since we last looked
+    pub fn take_edits(&self) -> Vec<Edit> {
+        std::mem::take(&mut *self.client.edits.lock().unwrap())
+    }
+
+    pub fn paused(&self) -> bool {
+        self.client.paused.load(Ordering::Relaxed)
+    }
+
+    // keep a packet for export-flow
+    pub fn observe(&self, packet: &[u8], timestamp: SystemTime) {
+        self.client.flows.lock().unwrap().observe(packet, timestamp);
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        for path in self.paths.iter() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// what each client's thread shares with the capture
+#[derive(Clone)]
+struct Client {
+    subscribers: Arc<Mutex<Vec<SyncSender<Arc<String>>>>>,
+    edits: Arc<Mutex<Vec<Edit>>>,
+    paused: Arc<AtomicBool>,
+    flows: Arc<Mutex<FlowStore>>,
+    start_time: SystemTime,
+    export_dir: Option<PathBuf>,
+    // connected to the --control-edit socket rather than --control's
+    editing: bool,
+}
+
+impl Client {
+    fn handle(self, stream: UnixStream) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.by_ref().take(MAX_LINE).read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) if !line.ends_with('\n') && line.len() as u64 == MAX_LINE => {
+                    let _ = writeln!(writer, "error command too long");
+                    return;
+                }
+                Ok(_) => {}
+            }
+            let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+
+            let reply = match command {
+                "" => continue,
+                command if !self.editing && EDITING.contains(&command) => {
+                    format!("error {} is only accepted on the --control-edit socket", command)
+                }
+                "attach" => {
+                    self.attach(writer);
+                    return;
+                }
+                "pause" | "resume" => {
+                    self.paused.store(command == "pause", Ordering::Relaxed);
+                    "ok".to_string()
+                }
+                "stats" => format!(
+                    "ok packets={} bytes={} requests={} dropped={} paused={}",
+                    METRICS.packets.load(Ordering::Relaxed),
+                    METRICS.bytes.load(Ordering::Relaxed),
+                    METRICS.events.load(Ordering::Relaxed),
+                    METRICS.dropped.load(Ordering::Relaxed),
+                    self.paused.load(Ordering::Relaxed),
+                ),
+                "flows" => {
+                    let flows = self.flows.lock().unwrap().list();
+                    std::iter::once(format!("ok {}", flows.len())).chain(flows).collect::<Vec<_>>().join("\n")
+                }
+                "export-flow" => match self.export(argument.trim()) {
+                    Ok(written) => format!("ok {}", written),
+                    Err(e) => format!("error {}", e),
+                },
+                // the capture picks it up before its next request
+                command => match Edit::parse(command, argument.trim()) {
+                    Ok(edit) => {
+                        self.edits.lock().unwrap().push(edit);
+                        "ok".to_string()
+                    }
+                    Err(e) => format!("error {}", e),
+                },
+            };
+
+            if writeln!(writer, "{}", reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    // exports only ever go to a new file directly inside --export-dir, since whoever's connected gets to write with
+    // our privileges
+    fn export(&self, argument: &str) -> Result<String, String> {
+        let Some((Ok(id), name)) = argument.split_once(' ').map(|(id, name)| (id.parse(), name.trim())) else {
+            return Err("expected a flow ID and a file name, e.g. export-flow 7 flow.pcap".to_string());
+        };
+        let Some(ref dir) = self.export_dir else {
+            return Err("export-flow needs sniff to be started with --export-dir".to_string());
+        };
+
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(file)), None) => self.flows.lock().unwrap().export(id, &dir.join(file)),
+            _ => Err(format!("'{}' isn't a file name; exports are written to --export-dir", name)),
+        }
+    }
+
+    fn attach(&self, mut writer: UnixStream) {
+        // the start time lets the client show timestamps relative to the start of our capture
+        let since_epoch = self.start_time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        if writeln!(writer, "ok {}", since_epoch.as_secs_f64()).is_err() {
+            return;
+        }
+
+        let (tx, rx) = sync_channel::<Arc<String>>(CLIENT_BACKLOG);
+        self.subscribers.lock().unwrap().push(tx);
+
+        // from here on this connection is a read-only event stream
+        for event in rx {
+            if writeln!(writer, "{}", event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// attach to another sniff's control socket, and print its requests through our own filters
+pub fn attach(path: &str, config: &Config) {
+    let mut stream = UnixStream::connect(path)
+        .unwrap_or_else(|e| panic!("Failed to connect to control socket {}: {}", path, e));
+    writeln!(stream, "attach").expect("Failed to attach to the capture");
+
+    let mut lines = BufReader::new(stream).lines();
+
+    let start_time = match lines.next().and_then(|x| x.ok()) {
+        Some(reply) if reply.starts_with("ok ") => {
+            let secs: f64 = reply[3..].trim().parse().unwrap_or(0.0);
+            SystemTime::UNIX_EPOCH + Duration::from_secs_f64(secs)
+        }
+        reply => panic!("Failed to attach to the capture: {}", reply.unwrap_or_default()),
+    };
+
+    let mut state = OutputState::new(config, config.aliases.as_deref().map(crate::aliases::Aliases::load));
+
+    for line in lines {
+        let Ok(line) = line else { break };
+
+        match serde_json::from_str::<RequestStats>(&line) {
+            Ok(stats) => crate::print_request(stats, config.clone(), start_time, &mut state),
+            Err(e) => eprintln!("Ignoring malformed event from the capture: {}", e),
+        }
+    }
+
+    state.flush(config, start_time);
+    println!("Capture ended");
+    state.print_reports();
+    state.finish_uploads(config.log_file.as_ref());
+}
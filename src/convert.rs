@@ -0,0 +1,211 @@
+// `sniff convert`: captures between the formats sniff reads and writes, so they can move between sniff versions,
+// other tools (Wireshark/tcpdump, jq) and workflows: sniff logs (JSON or binary, compressed or not), NDJSON (a JSON
+// request per line, as `--output json:` writes) and pcap files. Requests can be picked out with --filter, --from and
+// --to on the way
+//
+// logs and NDJSON keep their requests as they are; a pcap's frames become a request each, as if captured with
+// --dont-collate, and requests go into a pcap as their packets, rebuilt as Ethernet frames
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, BufWriter, Write},
+    time::SystemTime,
+};
+
+use crate::{
+    conf::{Config, ConvertFormat, LogFormat, Protocol},
+    filter::Expr,
+    ip, pcap, replay, window, PacketLog, RequestStats,
+};
+
+// what's read: the requests, each with its frame if it came from a pcap, so that goes back out untouched
+struct Input {
+    start_time: SystemTime,
+    requests: Vec<(RequestStats, Option<Vec<u8>>)>,
+    resolutions: HashMap<std::net::IpAddr, String>,
+}
+
+pub fn run(input: &str, output: &str, format: ConvertFormat, filter: Option<&Expr>, config: &Config) {
+    let Input {
+        start_time,
+        mut requests,
+        resolutions,
+    } = read(input);
+    let total = requests.len();
+
+    // filters match hostnames by the reverse DNS answers the log kept, if it kept any
+    let name = |ip: &crate::conf::IpAddr| resolutions.get(&ip.to_std()).cloned().unwrap_or_else(|| ip.to_string());
+    let window = window::Window::new(config.from.as_ref(), config.to.as_ref(), start_time);
+    requests.retain(|(stats, _)| {
+        window.contains(stats.timestamp)
+            && filter.is_none_or(|x| x.matches(stats, &name(&stats.orig_ip), &name(&stats.dest_ip)))
+    });
+
+    let result = match format {
+        ConvertFormat::Pcap => write_pcap(output, &requests),
+        ConvertFormat::Ndjson => write_ndjson(output, &requests),
+        ConvertFormat::Json | ConvertFormat::Binary => {
+            let logs = PacketLog {
+                version: crate::logfile::LOG_VERSION,
+                start_time,
+                packets: requests.iter().map(|(stats, _)| stats.clone()).collect(),
+                resolutions: resolutions.clone(),
+            };
+            let format = if format == ConvertFormat::Binary { LogFormat::Binary } else { LogFormat::Json };
+            crate::logfile::save(output, &logs, format, None)
+        }
+    };
+
+    match result {
+        Ok(()) => println!(
+            "Converted {} request{} from {} to {}{}",
+            requests.len(),
+            if requests.len() == 1 { "" } else { "s" },
+            input,
+            output,
+            if requests.len() < total {
+                format!(" ({} left out by --filter, --from and --to)", total - requests.len())
+            } else {
+                String::new()
+            }
+        ),
+        Err(e) => eprintln!("Failed to write {}: {}", output, e),
+    }
+}
+
+// a pcap, a sniff log or NDJSON, by what will read it
+fn read(path: &str) -> Input {
+    match pcap::PcapReader::open(path) {
+        Ok(reader) if reader.linktype == pcap::LINKTYPE_ETHERNET => {
+            let requests: Vec<_> = reader
+                .map(|(timestamp, frame)| (frame_stats(timestamp, &frame), Some(frame)))
+                .collect();
+            return Input {
+                start_time: requests.first().map(|(x, _)| x.timestamp).unwrap_or_else(SystemTime::now),
+                requests,
+                resolutions: HashMap::new(),
+            };
+        }
+        Ok(reader) => panic!("Can only convert Ethernet captures, {} has link type {}", path, reader.linktype),
+        Err(_) => {}
+    }
+
+    let log_error = match crate::logfile::load(path) {
+        Ok(logs) => {
+            return Input {
+                start_time: logs.start_time,
+                requests: logs.packets.into_iter().map(|x| (x, None)).collect(),
+                resolutions: logs.resolutions,
+            }
+        }
+        Err(crate::error::Error::Io(e)) => panic!("Failed to read {}: {}", path, e),
+        Err(e @ crate::error::Error::NewerLog(..)) => panic!("Cannot read {}: {}", path, e),
+        Err(e) => e,
+    };
+
+    // not a whole log, so it should be a request a line
+    let data = crate::compress::read(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+    let mut requests = Vec::new();
+    for (number, line) in BufReader::new(data.as_slice()).lines().enumerate() {
+        let line = line.unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<RequestStats>(&line) {
+            Ok(stats) => requests.push((stats, None)),
+            // nothing in it made sense as either, so the log's error says most
+            Err(_) if requests.is_empty() => panic!("{} is not a pcap file, a sniff log or NDJSON: {}", path, log_error),
+            Err(e) => panic!("Invalid request on line {} of {}: {}", number + 1, path, e),
+        }
+    }
+
+    Input {
+        start_time: requests.iter().map(|(x, _)| x.timestamp).min().unwrap_or_else(SystemTime::now),
+        requests,
+        resolutions: HashMap::new(),
+    }
+}
+
+fn write_pcap(path: &str, requests: &[(RequestStats, Option<Vec<u8>>)]) -> Result<(), crate::error::Error> {
+    let mut writer = pcap::PcapWriter::create(path, pcap::LINKTYPE_ETHERNET)?;
+    for (stats, frame) in requests.iter() {
+        let frames = match frame {
+            Some(frame) => vec![frame.clone()],
+            None => replay::frames(stats),
+        };
+
+        for frame in frames.iter() {
+            // packets cut short by --snaplen/--headers-only are written as truncated, with their real length
+            let original = crate::parse_frame(frame)
+                .ok()
+                .and_then(|packet| Some(frame.len() - packet.payload.len() + ip::total_len(&packet.payload)?))
+                .unwrap_or(frame.len());
+            writer.write_truncated(stats.timestamp, frame, original)?;
+        }
+    }
+    Ok(writer.flush()?)
+}
+
+fn write_ndjson(path: &str, requests: &[(RequestStats, Option<Vec<u8>>)]) -> Result<(), crate::error::Error> {
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+    for (stats, _) in requests.iter() {
+        serde_json::to_writer(&mut writer, stats)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(writer.flush()?)
+}
+
+// a frame as a request of its own, with everything we can decode from it
+pub fn frame_stats(timestamp: SystemTime, frame: &[u8]) -> RequestStats {
+    let packet = crate::parse_frame(frame).unwrap_or_else(|_| crate::raw_frame(frame));
+
+    let mut stats = RequestStats {
+        protocol: packet.protocol,
+        orig_ip: packet.orig_ip,
+        orig_mac: packet.orig_mac,
+        dest_ip: packet.dest_ip,
+        dest_mac: packet.dest_mac,
+        bytes: packet.payload.len() as u64,
+        packets: 1,
+        reply_bytes: 0,
+        reply_packets: 0,
+        timestamp,
+        raw: packet.payload,
+        captured: Vec::new(),
+        columns: Default::default(),
+        orig_geo: None,
+        dest_geo: None,
+        rate: None,
+        icmp: None,
+        vlan: packet.vlan,
+        direction: None,
+        routing: Vec::new(),
+        dhcp: None,
+        quic: None,
+        tunnel: None,
+        encapsulation: None,
+        orig_process: None,
+        dest_process: None,
+        rtt: None,
+        bad_checksum: None,
+        ttl: None,
+    };
+
+    if !matches!(stats.protocol, Protocol::Ether(_) | Protocol::Unknown) {
+        stats.ttl = crate::ip::parse(&stats.raw).map(|x| x.ttl);
+    }
+
+    if stats.protocol == Protocol::Icmp {
+        stats.icmp = crate::icmp::parse(&stats.raw, stats.raw.first().map(|x| x >> 4) == Some(6));
+    }
+
+    if matches!(stats.protocol, Protocol::Tcp | Protocol::Udp | Protocol::Ip(_)) {
+        stats.routing = crate::routing::decode(&stats);
+    }
+
+    if stats.protocol == Protocol::Udp {
+        stats.dhcp = crate::dhcp::decode(&stats);
+    }
+
+    stats
+}
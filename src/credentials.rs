@@ -0,0 +1,190 @@
+// --detect-credentials: logins sent in the clear, called out as they're seen so an audit can find what needs TLS:
+// FTP and POP3 USER/PASS, SMTP (and POP3) AUTH LOGIN/PLAIN, IMAP LOGIN and AUTHENTICATE PLAIN, and HTTP Basic
+// authentication (to a server or a proxy). Only the user name is shown, where it's there to read; passwords never
+// are, nor do they go anywhere else
+//
+// each connection's login is called out once, and logins are totalled per client and server at exit
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{base64, ip, theme::Theme};
+
+const TCP: u8 = 6;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+const REPORT_LOGINS: usize = 20;
+
+// client, server
+type Key = (SocketAddr, SocketAddr);
+
+#[derive(Default)]
+struct Connection {
+    user: Option<String>, // from a USER line, for the PASS that follows
+    reported: bool,
+    last: Option<SystemTime>,
+}
+
+pub struct CredentialDetector {
+    theme: Theme,
+    connections: HashMap<Key, Connection>,
+    logins: BTreeMap<(&'static str, String, SocketAddr), u64>, // (kind, client address, server) -> how many
+    checked: Option<SystemTime>,
+}
+
+impl CredentialDetector {
+    pub fn new(theme: Theme) -> CredentialDetector {
+        CredentialDetector {
+            theme,
+            connections: HashMap::new(),
+            logins: BTreeMap::new(),
+            checked: None,
+        }
+    }
+
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet).filter(|x| x.protocol == TCP) else {
+            return;
+        };
+        let Some((src_port, dst_port, payload)) = ip::transport(packet) else {
+            return;
+        };
+        if payload.is_empty() {
+            return;
+        }
+
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.connections.retain(|_, connection| {
+                connection
+                    .last
+                    .is_some_and(|x| timestamp.duration_since(x).unwrap_or_default() < IDLE_TIMEOUT)
+            });
+        }
+
+        // only what clients send is looked at, so the server is the destination
+        let key = (
+            SocketAddr::new(header.src, src_port),
+            SocketAddr::new(header.dst, dst_port),
+        );
+        let Some((kind, user)) = self.login(key, payload, timestamp) else {
+            return;
+        };
+
+        let connection = self.connections.entry(key).or_default();
+        if connection.reported {
+            return;
+        }
+        connection.reported = true;
+
+        *self.logins.entry((kind, key.0.ip().to_string(), key.1)).or_default() += 1;
+
+        let message = format!(
+            "*** plaintext {} login{}: {} -> {} (the password went unencrypted) ***",
+            kind,
+            user.map(|x| format!(" as {}", x)).unwrap_or_default(),
+            key.0,
+            key.1
+        );
+        outln!("{}", self.theme.paint(self.theme.warning, &message));
+    }
+
+    // the kind of login in a client's segment, and the user if it says
+    fn login(&mut self, key: Key, payload: &[u8], timestamp: SystemTime) -> Option<(&'static str, Option<String>)> {
+        let text = String::from_utf8_lossy(&payload[..payload.len().min(4096)]);
+        let first = text.lines().next()?;
+
+        // HTTP Basic is the user and password, base64'd, in a request's headers
+        if first.ends_with(" HTTP/1.1") || first.ends_with(" HTTP/1.0") {
+            for line in text.lines().skip(1).take_while(|x| !x.is_empty()) {
+                let Some((name, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let Some(("Basic" | "basic", encoded)) = value.trim().split_once(' ') else {
+                    continue;
+                };
+                let kind = match name.to_ascii_lowercase().as_str() {
+                    "authorization" => "HTTP Basic",
+                    "proxy-authorization" => "HTTP proxy Basic",
+                    _ => continue,
+                };
+
+                self.connections.entry(key).or_default().last = Some(timestamp);
+                let user = base64::decode(encoded.trim())
+                    .and_then(|x| String::from_utf8(x).ok())
+                    .and_then(|x| x.split_once(':').map(|(user, _)| user.to_string()));
+                return Some((kind, user));
+            }
+            return None;
+        }
+
+        // the rest are line-at-a-time commands, which a client sends a segment each
+        if !payload.ends_with(b"\n") || text.lines().count() != 1 {
+            return None;
+        }
+        let words: Vec<&str> = first.split_whitespace().collect();
+        let command = words.first()?.to_ascii_uppercase();
+        let port = key.1.port();
+
+        let connection = self.connections.entry(key).or_default();
+        match (command.as_str(), words.len()) {
+            // remembered for the PASS, which is what gives the login away
+            ("USER", 2) => {
+                connection.user = Some(words[1].to_string());
+                connection.last = Some(timestamp);
+                None
+            }
+            ("PASS", 2..) => {
+                connection.last = Some(timestamp);
+                let kind = if port == 110 { "POP3" } else { "FTP" };
+                Some((kind, connection.user.clone()))
+            }
+            ("AUTH", 2..) if matches!(words[1].to_ascii_uppercase().as_str(), "LOGIN" | "PLAIN") => {
+                connection.last = Some(timestamp);
+                let kind = if port == 110 { "POP3 AUTH" } else { "SMTP AUTH" };
+                Some((kind, None))
+            }
+            // IMAP's come after a tag, e.g. "a1 LOGIN user password"
+            (_, 4..) if words[1].eq_ignore_ascii_case("LOGIN") => {
+                connection.last = Some(timestamp);
+                Some(("IMAP", Some(words[2].trim_matches('"').to_string())))
+            }
+            (_, 3..) if words[1].eq_ignore_ascii_case("AUTHENTICATE") && words[2].eq_ignore_ascii_case("PLAIN") => {
+                connection.last = Some(timestamp);
+                Some(("IMAP", None))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn print_report(self) {
+        if self.logins.is_empty() {
+            return;
+        }
+
+        let mut logins: Vec<_> = self.logins.iter().collect();
+        logins.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+
+        println!("Plaintext logins:");
+        for ((kind, client, server), count) in logins.iter().take(REPORT_LOGINS) {
+            println!(
+                "    {} from {} to {}: {} connection{}",
+                kind,
+                client,
+                server,
+                count,
+                if **count == 1 { "" } else { "s" }
+            );
+        }
+        if logins.len() > REPORT_LOGINS {
+            println!("    ... and {} more", logins.len() - REPORT_LOGINS);
+        }
+    }
+}
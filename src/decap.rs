@@ -0,0 +1,225 @@
+// --decapsulate: packets carried through a tunnel are taken out of it, so the flow inside (its addresses, protocol and
+// ports) is what's shown, filtered and counted, rather than the tunnel endpoints every flow through it shares.
+// Tunnels inside tunnels are unwrapped too, up to MAX_DEPTH; the outermost is kept, and shown with
+// --tunnel-endpoints -v. Understood are:
+//
+//     IP-in-IP     IPv4 or IPv6 straight after the outer IP header (protocols 4 and 41, including 6in4)
+//     GRE          version 0, carrying IP or (transparent Ethernet bridging) Ethernet frames
+//     VXLAN        UDP to port 4789, carrying Ethernet frames
+//     GTP-U        UDP to port 2152, G-PDUs carrying IP
+//
+// where the inner packet's an Ethernet frame, its MAC addresses replace the outer ones too
+
+use serde::{Deserialize, Serialize};
+
+use crate::ip;
+
+const MAX_DEPTH: usize = 4;
+
+const IPIP: u8 = 4;
+const IPV6: u8 = 41;
+const GRE: u8 = 47;
+const UDP: u8 = 17;
+
+const VXLAN_PORT: u16 = 4789;
+const GTP_U_PORT: u16 = 2152;
+const GTP_G_PDU: u8 = 0xff;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_BRIDGING: u16 = 0x6558;
+
+// GRE header flags
+const GRE_CHECKSUM: u16 = 0x8000;
+const GRE_KEY: u16 = 0x2000;
+const GRE_SEQUENCE: u16 = 0x1000;
+
+// an inner Ethernet frame's source and destination
+type Macs = ([u8; 6], [u8; 6]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum TunnelKind {
+    IpInIp,
+    Gre,
+    Vxlan,
+    GtpU,
+}
+
+impl std::fmt::Display for TunnelKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TunnelKind::IpInIp => write!(f, "IP-in-IP"),
+            TunnelKind::Gre => write!(f, "GRE"),
+            TunnelKind::Vxlan => write!(f, "VXLAN"),
+            TunnelKind::GtpU => write!(f, "GTP-U"),
+        }
+    }
+}
+
+// the tunnel a packet came through, as it was on the wire
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Encapsulation {
+    pub kind: TunnelKind,
+    pub src: std::net::IpAddr,
+    pub dst: std::net::IpAddr,
+    pub id: Option<u32>, // GRE's key, VXLAN's VNI or GTP-U's TEID
+}
+
+impl std::fmt::Display for Encapsulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} tunnel {} -> {}", self.kind, self.src, self.dst)?;
+        match (self.kind, self.id) {
+            (TunnelKind::Gre, Some(id)) => write!(f, " (key {})", id),
+            (TunnelKind::Vxlan, Some(id)) => write!(f, " (VNI {})", id),
+            (TunnelKind::GtpU, Some(id)) => write!(f, " (TEID {:#010x})", id),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct Inner {
+    pub encapsulation: Encapsulation, // the outermost tunnel
+    pub offset: usize,                // where the innermost IP packet starts
+    pub macs: Option<Macs>,           // the innermost Ethernet frame's source and destination, if there was one
+}
+
+// the IP packet inside a tunnelled one, if it's in a tunnel
+pub fn decapsulate(packet: &[u8]) -> Option<Inner> {
+    let mut inner: Option<Inner> = None;
+
+    for _ in 0..MAX_DEPTH {
+        let offset = inner.as_ref().map_or(0, |x| x.offset);
+        let Some((encapsulation, start, macs)) = packet.get(offset..).and_then(unwrap) else {
+            break;
+        };
+
+        match inner {
+            Some(ref mut inner) => {
+                inner.offset += start;
+                inner.macs = macs.or(inner.macs);
+            }
+            None => {
+                inner = Some(Inner {
+                    encapsulation,
+                    offset: start,
+                    macs,
+                })
+            }
+        }
+    }
+
+    inner
+}
+
+// one layer: the tunnel, where the packet inside starts, and its MACs if it's in an Ethernet frame
+fn unwrap(packet: &[u8]) -> Option<(Encapsulation, usize, Option<Macs>)> {
+    let header = ip::parse(packet)?;
+    let at = header.header_len;
+    let encapsulation = |kind: TunnelKind, id: Option<u32>| Encapsulation {
+        kind,
+        src: header.src,
+        dst: header.dst,
+        id,
+    };
+
+    match header.protocol {
+        IPIP | IPV6 => {
+            ip::parse(packet.get(at..)?)?;
+            Some((encapsulation(TunnelKind::IpInIp, None), at, None))
+        }
+        GRE => {
+            let flags = u16::from_be_bytes(packet.get(at..at + 2)?.try_into().ok()?);
+            // version 1 is PPTP's, carrying PPP
+            if flags & 0x0007 != 0 {
+                return None;
+            }
+            let protocol = u16::from_be_bytes(packet.get(at + 2..at + 4)?.try_into().ok()?);
+
+            let mut start = at + 4;
+            if flags & GRE_CHECKSUM != 0 {
+                start += 4;
+            }
+            let mut key = None;
+            if flags & GRE_KEY != 0 {
+                key = Some(u32::from_be_bytes(packet.get(start..start + 4)?.try_into().ok()?));
+                start += 4;
+            }
+            if flags & GRE_SEQUENCE != 0 {
+                start += 4;
+            }
+
+            let (start, macs) = match protocol {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => (start, None),
+                ETHERTYPE_BRIDGING => ethernet(packet, start)?,
+                _ => return None,
+            };
+            ip::parse(packet.get(start..)?)?;
+            Some((encapsulation(TunnelKind::Gre, key), start, macs))
+        }
+        UDP => {
+            let (_, dst_port, _) = ip::transport(packet)?;
+            let start = at + 8;
+
+            match dst_port {
+                // flags (with I, a valid VNI, set), reserved, the VNI and reserved again
+                VXLAN_PORT if packet.get(start)? & 0x08 != 0 => {
+                    let vni = u32::from_be_bytes(packet.get(start + 4..start + 8)?.try_into().ok()?) >> 8;
+                    let (start, macs) = ethernet(packet, start + 8)?;
+                    ip::parse(packet.get(start..)?)?;
+                    Some((encapsulation(TunnelKind::Vxlan, Some(vni)), start, macs))
+                }
+                GTP_U_PORT => {
+                    let (start, teid) = gtp_u(packet, start)?;
+                    ip::parse(packet.get(start..)?)?;
+                    Some((encapsulation(TunnelKind::GtpU, Some(teid)), start, None))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// the IP packet in an Ethernet frame starting at `at`, past any VLAN tags, and the frame's source and destination
+fn ethernet(packet: &[u8], at: usize) -> Option<(usize, Option<Macs>)> {
+    let dst: [u8; 6] = packet.get(at..at + 6)?.try_into().ok()?;
+    let src: [u8; 6] = packet.get(at + 6..at + 12)?.try_into().ok()?;
+
+    let mut start = at + 12;
+    let mut ethertype = u16::from_be_bytes(packet.get(start..start + 2)?.try_into().ok()?);
+    while matches!(ethertype, 0x8100 | 0x88a8 | 0x9100) {
+        start += 4;
+        ethertype = u16::from_be_bytes(packet.get(start..start + 2)?.try_into().ok()?);
+    }
+    if !matches!(ethertype, ETHERTYPE_IPV4 | ETHERTYPE_IPV6) {
+        return None;
+    }
+
+    Some((start + 2, Some((src, dst))))
+}
+
+// a GTPv1-U G-PDU: flags (version 1, with E, S or PN meaning 4 more bytes), the message type, length and TEID, then
+// any extension headers, each a multiple of 4 bytes long with the next one's type in its last byte
+fn gtp_u(packet: &[u8], at: usize) -> Option<(usize, u32)> {
+    let flags = *packet.get(at)?;
+    if flags >> 5 != 1 || *packet.get(at + 1)? != GTP_G_PDU {
+        return None;
+    }
+    let teid = u32::from_be_bytes(packet.get(at + 4..at + 8)?.try_into().ok()?);
+
+    let mut start = at + 8;
+    if flags & 0x07 != 0 {
+        let mut next = *packet.get(start + 3)?;
+        start += 4;
+        while next != 0 {
+            let len = *packet.get(start)? as usize * 4;
+            if len == 0 {
+                return None;
+            }
+            next = *packet.get(start + len - 1)?;
+            start += len;
+        }
+    }
+
+    Some((start, teid))
+}
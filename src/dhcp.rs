@@ -0,0 +1,232 @@
+// DHCP (v4) transactions: who's asking for an address, what they're offered and whether they get it
+
+use std::{collections::BTreeMap, net::Ipv4Addr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::MacAddr, ip, RequestStats};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+// op, htype, hlen, hops, xid, secs, flags, ciaddr, yiaddr, siaddr, giaddr, chaddr, sname, file, then the cookie
+const OPTIONS_OFFSET: usize = 240;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+// option numbers (RFC 2132)
+const HOSTNAME: u8 = 12;
+const REQUESTED_IP: u8 = 50;
+const LEASE_TIME: u8 = 51;
+const MESSAGE_TYPE: u8 = 53;
+const SERVER_ID: u8 = 54;
+const END: u8 = 255;
+const PAD: u8 = 0;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DhcpInfo {
+    pub message_type: u8,
+    pub xid: u32,
+    pub client: MacAddr,
+    pub address: Option<Ipv4Addr>, // what the server is giving out (yiaddr), or the client already has (ciaddr)
+    pub requested: Option<Ipv4Addr>,
+    pub server: Option<Ipv4Addr>,
+    pub lease: Option<u32>, // seconds
+    pub hostname: Option<String>,
+}
+
+impl DhcpInfo {
+    pub fn kind(&self) -> &'static str {
+        match self.message_type {
+            1 => "DISCOVER",
+            2 => "OFFER",
+            3 => "REQUEST",
+            4 => "DECLINE",
+            5 => "ACK",
+            6 => "NAK",
+            7 => "RELEASE",
+            8 => "INFORM",
+            _ => "message",
+        }
+    }
+}
+
+impl std::fmt::Display for DhcpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DHCP {} xid {:#010x} for {}", self.kind(), self.xid, self.client)?;
+        if let Some(ref hostname) = self.hostname {
+            write!(f, " ({})", hostname)?;
+        }
+        if let Some(requested) = self.requested {
+            write!(f, ", requested {}", requested)?;
+        }
+        if let Some(address) = self.address {
+            write!(f, ", address {}", address)?;
+        }
+        if let Some(server) = self.server {
+            write!(f, ", server {}", server)?;
+        }
+        if let Some(lease) = self.lease {
+            write!(f, ", lease {}", format_lease(lease))?;
+        }
+        Ok(())
+    }
+}
+
+fn format_lease(seconds: u32) -> String {
+    match seconds {
+        u32::MAX => "infinite".to_string(),
+        x if x % 86400 == 0 => format!("{}d", x / 86400),
+        x if x % 3600 == 0 => format!("{}h", x / 3600),
+        x if x % 60 == 0 => format!("{}m", x / 60),
+        x => format!("{}s", x),
+    }
+}
+
+// the DHCP message in a request's first packet, if it's UDP between the DHCP ports
+pub fn decode(stats: &RequestStats) -> Option<DhcpInfo> {
+    let packet = ip::split_captured(&stats.raw, &stats.captured).into_iter().next()?;
+    let header = ip::parse(packet)?;
+    if header.protocol != 17 {
+        return None;
+    }
+
+    let (src_port, dst_port, payload) = ip::transport(packet)?;
+    let ports = [src_port, dst_port];
+    if !ports.contains(&SERVER_PORT) || !ports.iter().all(|x| *x == SERVER_PORT || *x == CLIENT_PORT) {
+        return None;
+    }
+
+    parse(payload)
+}
+
+fn parse(message: &[u8]) -> Option<DhcpInfo> {
+    if message.get(236..OPTIONS_OFFSET)? != MAGIC_COOKIE {
+        return None;
+    }
+
+    let address = |offset: usize| {
+        let address = Ipv4Addr::from(<[u8; 4]>::try_from(&message[offset..offset + 4]).unwrap());
+        (!address.is_unspecified()).then_some(address)
+    };
+
+    let mut info = DhcpInfo {
+        message_type: 0,
+        xid: u32::from_be_bytes(message[4..8].try_into().unwrap()),
+        client: MacAddr::from(<[u8; 6]>::try_from(&message[28..34]).unwrap()),
+        address: address(16).or_else(|| address(12)),
+        requested: None,
+        server: None,
+        lease: None,
+        hostname: None,
+    };
+
+    let mut pos = OPTIONS_OFFSET;
+    while let Some(&code) = message.get(pos) {
+        match code {
+            END => break,
+            PAD => {
+                pos += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = *message.get(pos + 1)? as usize;
+        let Some(value) = message.get(pos + 2..pos + 2 + len) else {
+            break;
+        };
+
+        match (code, len) {
+            (MESSAGE_TYPE, 1) => info.message_type = value[0],
+            (REQUESTED_IP, 4) => info.requested = Some(Ipv4Addr::from(<[u8; 4]>::try_from(value).unwrap())),
+            (SERVER_ID, 4) => info.server = Some(Ipv4Addr::from(<[u8; 4]>::try_from(value).unwrap())),
+            (LEASE_TIME, 4) => info.lease = Some(u32::from_be_bytes(value.try_into().unwrap())),
+            (HOSTNAME, _) => info.hostname = Some(String::from_utf8_lossy(value).to_string()),
+            _ => {}
+        }
+
+        pos += 2 + len;
+    }
+
+    // BOOTP without the DHCP message type option isn't what we're after
+    (info.message_type != 0).then_some(info)
+}
+
+// where each client got to, to spot the ones that keep asking and never get an address
+struct Client {
+    hostname: Option<String>,
+    last: &'static str,
+    address: Option<Ipv4Addr>,
+    lease: Option<u32>,
+    unanswered: u64, // DISCOVERs and REQUESTs since the last OFFER or ACK
+    naks: u64,
+}
+
+#[derive(Default)]
+pub struct DhcpMonitor {
+    clients: BTreeMap<[u8; 6], Client>,
+}
+
+impl DhcpMonitor {
+    pub fn observe(&mut self, stats: &RequestStats) {
+        let Some(ref info) = stats.dhcp else {
+            return;
+        };
+
+        let client = self.clients.entry(info.client.octets()).or_insert_with(|| Client {
+            hostname: None,
+            last: info.kind(),
+            address: None,
+            lease: None,
+            unanswered: 0,
+            naks: 0,
+        });
+
+        client.last = info.kind();
+        if info.hostname.is_some() {
+            client.hostname.clone_from(&info.hostname);
+        }
+
+        match info.message_type {
+            1 | 3 => client.unanswered += 1,
+            2 => client.unanswered = 0,
+            5 => {
+                client.unanswered = 0;
+                client.address = info.address;
+                client.lease = info.lease;
+            }
+            6 => client.naks += 1,
+            7 => client.address = None,
+            _ => {}
+        }
+    }
+
+    pub fn print_report(&self) {
+        if self.clients.is_empty() {
+            return;
+        }
+
+        println!("DHCP clients:");
+        for (mac, client) in self.clients.iter() {
+            let mut line = format!("    {}", MacAddr::from(*mac));
+            if let Some(ref hostname) = client.hostname {
+                line += &format!(" ({})", hostname);
+            }
+            match client.address {
+                Some(address) => line += &format!(": {}", address),
+                None => line += ": no address",
+            }
+            if let Some(lease) = client.lease {
+                line += &format!(" (lease {})", format_lease(lease));
+            }
+            line += &format!(", last {}", client.last);
+            if client.unanswered > 0 {
+                line += &format!(", {} unanswered", client.unanswered);
+            }
+            if client.naks > 0 {
+                line += &format!(", {} NAK{}", client.naks, if client.naks == 1 { "" } else { "s" });
+            }
+            println!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// `sniff diff OLD NEW`: what changed between two captures (logs or pcaps), e.g. before and after a firewall or
+// application change. For protocols, hosts and service ports in turn, it lists:
+//
+//     - those only in the old capture
+//     + those only in the new one
+//     ~ those in both whose volume changed by --factor or more
+//
+// the captures can be of different lengths, so volumes are compared as rates, over each capture's span
+
+use std::{collections::HashMap, hash::Hash, time::SystemTime};
+
+use crate::{
+    conf::{Config, Protocol},
+    report::{self, Record},
+    roles, services, units,
+};
+
+// changes to anything under this share of its capture's bytes are noise
+const MIN_SHARE: f64 = 0.01;
+
+#[derive(Default, Clone, Copy)]
+struct Totals {
+    packets: u64,
+    bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, record: &Record) {
+        self.packets += record.packets;
+        self.bytes += record.bytes;
+    }
+}
+
+// one capture, totalled every way it's compared
+struct Capture {
+    path: String,
+    records: usize,
+    total: Totals,
+    secs: f64, // its span, at least a second
+    protocols: HashMap<String, Totals>,
+    hosts: HashMap<String, Totals>,
+    ports: HashMap<(u16, Protocol), Totals>,
+}
+
+impl Capture {
+    fn load(path: &str) -> Capture {
+        let (_, records) = report::load(path, &mut roles::RoleTracker::default());
+
+        let first = records
+            .iter()
+            .map(|x| x.timestamp)
+            .min()
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let last = records.iter().map(|x| x.timestamp).max().unwrap_or(first);
+
+        let mut capture = Capture {
+            path: path.to_string(),
+            records: records.len(),
+            total: Totals::default(),
+            secs: last.duration_since(first).unwrap_or_default().as_secs_f64().max(1.0),
+            protocols: HashMap::new(),
+            hosts: HashMap::new(),
+            ports: HashMap::new(),
+        };
+
+        for record in records.iter() {
+            capture.total.add(record);
+            capture
+                .protocols
+                .entry(record.protocol.to_string())
+                .or_default()
+                .add(record);
+            capture.hosts.entry(record.orig.clone()).or_default().add(record);
+            if record.dest != record.orig {
+                capture.hosts.entry(record.dest.clone()).or_default().add(record);
+            }
+            // the lower port is (nearly always) the service
+            if let Some((src, dst)) = record.ports {
+                capture
+                    .ports
+                    .entry((src.min(dst), record.protocol))
+                    .or_default()
+                    .add(record);
+            }
+        }
+
+        capture
+    }
+
+    fn rate(&self, totals: &Totals) -> f64 {
+        totals.bytes as f64 / self.secs
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} packets ({}) in {} records over {}, average {}/s",
+            self.total.packets,
+            units::human_bytes(self.total.bytes, None),
+            self.records,
+            report::format_duration(std::time::Duration::from_secs_f64(self.secs)),
+            units::human_bytes(self.rate(&self.total) as u64, None)
+        )
+    }
+}
+
+pub fn run(old: &str, new: &str, factor: f64, top: usize, config: &Config) {
+    let (old, new) = (Capture::load(old), Capture::load(new));
+    let factor = factor.max(1.0);
+
+    println!("Diff of {} and {}:", old.path, new.path);
+    println!("    old: {}", old.describe());
+    println!("    new: {}", new.describe());
+
+    let services = services::Services::load();
+    let port_name = |(port, protocol): &(u16, Protocol)| match services.name(*port, *protocol) {
+        Some(name) if !config.no_service_names => format!("{}/{} ({})", port, protocol, name),
+        _ => format!("{}/{}", port, protocol),
+    };
+
+    let mut any = false;
+    any |= section("Protocols", &old, &new, |x| &x.protocols, |x| x.clone(), factor, top);
+    any |= section("Hosts", &old, &new, |x| &x.hosts, |x| x.clone(), factor, top);
+    any |= section("Ports", &old, &new, |x| &x.ports, port_name, factor, top);
+
+    if !any {
+        println!(
+            "No protocols, hosts or ports came or went, or changed in volume by {}x or more",
+            factor
+        );
+    }
+}
+
+// print one kind of difference, returning whether there were any
+fn section<K: Eq + Hash>(
+    title: &str,
+    old: &Capture,
+    new: &Capture,
+    totals: impl Fn(&Capture) -> &HashMap<K, Totals>,
+    name: impl Fn(&K) -> String,
+    factor: f64,
+    top: usize,
+) -> bool {
+    let (old_totals, new_totals) = (totals(old), totals(new));
+    let share = |capture: &Capture, x: &Totals| x.bytes as f64 / capture.total.bytes.max(1) as f64;
+
+    // (how big a difference, line)
+    let mut lines: Vec<(f64, String)> = Vec::new();
+
+    for (key, x) in old_totals.iter().filter(|(key, _)| !new_totals.contains_key(*key)) {
+        lines.push((
+            old.rate(x),
+            format!(
+                "- {:<40} {:>10} packets {:>12} (only in old)",
+                name(key),
+                x.packets,
+                units::human_bytes(x.bytes, None)
+            ),
+        ));
+    }
+    for (key, x) in new_totals.iter().filter(|(key, _)| !old_totals.contains_key(*key)) {
+        lines.push((
+            new.rate(x),
+            format!(
+                "+ {:<40} {:>10} packets {:>12} (only in new)",
+                name(key),
+                x.packets,
+                units::human_bytes(x.bytes, None)
+            ),
+        ));
+    }
+    for (key, before) in old_totals.iter() {
+        let Some(after) = new_totals.get(key) else {
+            continue;
+        };
+        if share(old, before).max(share(new, after)) < MIN_SHARE {
+            continue;
+        }
+
+        let (before_rate, after_rate) = (old.rate(before), new.rate(after));
+        let ratio = after_rate / before_rate.max(f64::EPSILON);
+        if ratio < factor && ratio > 1.0 / factor {
+            continue;
+        }
+
+        lines.push((
+            (after_rate - before_rate).abs(),
+            format!(
+                "~ {:<40} {:>10}/s -> {}/s ({}{:.1}x)",
+                name(key),
+                units::human_bytes(before_rate as u64, None),
+                units::human_bytes(after_rate as u64, None),
+                if ratio >= 1.0 { "up " } else { "down " },
+                if ratio >= 1.0 {
+                    ratio
+                } else {
+                    1.0 / ratio.max(f64::EPSILON)
+                },
+            ),
+        ));
+    }
+
+    if lines.is_empty() {
+        return false;
+    }
+
+    lines.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    let hidden = lines.len().saturating_sub(top);
+
+    println!("{}:", title);
+    for (_, line) in lines.iter().take(top) {
+        println!("    {}", line);
+    }
+    if hidden > 0 {
+        println!("    ... and {} more", hidden);
+    }
+
+    true
+}
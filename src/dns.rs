@@ -0,0 +1,90 @@
+// just enough DNS to pull the question out of a query, and the addresses out of a response
+
+use std::net::IpAddr;
+
+const HEADER_LEN: usize = 12;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+// the name asked about in a DNS query (not a response), e.g. "www.example.com"
+pub fn query_name(message: &[u8]) -> Option<String> {
+    let header = message.get(..HEADER_LEN)?;
+
+    let is_response = header[2] & 0x80 != 0;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    if is_response || questions == 0 {
+        return None;
+    }
+
+    question(message).map(|(name, _)| name)
+}
+
+// the name asked about in a DNS response, and the IPv4 and IPv6 addresses it was given (through any CNAMEs)
+pub fn answers(message: &[u8]) -> Option<(String, Vec<IpAddr>)> {
+    let header = message.get(..HEADER_LEN)?;
+
+    let is_response = header[2] & 0x80 != 0;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    if !is_response || questions != 1 {
+        return None;
+    }
+
+    // the question's type and class follow its name
+    let (name, end) = question(message)?;
+    let mut pos = end + 4;
+
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(message, pos)?;
+        let fixed = message.get(pos..pos + 10)?;
+        let kind = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let data = message.get(pos + 10..pos + 10 + len)?;
+
+        match (kind, len) {
+            (TYPE_A, 4) => addresses.push(IpAddr::from(<[u8; 4]>::try_from(data).unwrap())),
+            (TYPE_AAAA, 16) => addresses.push(IpAddr::from(<[u8; 16]>::try_from(data).unwrap())),
+            _ => {}
+        }
+        pos += 10 + len;
+    }
+
+    Some((name, addresses))
+}
+
+// the first question's name, and where it ends
+fn question(message: &[u8]) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut pos = HEADER_LEN;
+
+    loop {
+        let len = *message.get(pos)? as usize;
+        if len == 0 {
+            break;
+        }
+        // compression pointers don't appear in the first question
+        if len & 0xc0 != 0 {
+            return None;
+        }
+
+        let label = message.get(pos + 1..pos + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        pos += 1 + len;
+    }
+
+    Some((labels.join("."), pos + 1))
+}
+
+// past a name, which may end in a pointer to (the rest of) one earlier in the message
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            x if x & 0xc0 == 0xc0 => return Some(pos + 2),
+            _ => pos += 1 + len,
+        }
+    }
+}
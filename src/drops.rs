@@ -0,0 +1,73 @@
+// a warning between the requests whenever frames have been dropped, by the kernel (its socket buffer overflowing
+// before we read them, which only Linux tells us about) or by sniff (the ring between the capture threads and the
+// capture loop filling up), since from then on every count is short of what was really on the wire:
+//
+//     *** 1204 frames dropped in the last 10s (3.1% of 38840): 1200 by the kernel, 4 by sniff; counts are incomplete ***
+//
+// at most one every WARN_EVERY, so a capture that's falling behind doesn't fall further behind printing about it
+
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{metrics::METRICS, theme::Theme};
+
+const WARN_EVERY: Duration = Duration::from_secs(10);
+
+pub struct DropWarnings {
+    theme: Theme,
+    since: Instant,
+    // the counters as they were at the last warning
+    packets: u64,
+    dropped: u64,
+    kernel_dropped: u64,
+}
+
+impl DropWarnings {
+    pub fn new(theme: Theme) -> DropWarnings {
+        DropWarnings {
+            theme,
+            since: Instant::now(),
+            packets: METRICS.packets.load(Ordering::Relaxed),
+            dropped: METRICS.dropped.load(Ordering::Relaxed),
+            kernel_dropped: METRICS.kernel_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    // called from the capture loop
+    pub fn tick(&mut self) {
+        let elapsed = self.since.elapsed();
+        if elapsed < WARN_EVERY {
+            return;
+        }
+
+        let (packets, dropped, kernel_dropped) = (
+            METRICS.packets.load(Ordering::Relaxed),
+            METRICS.dropped.load(Ordering::Relaxed),
+            METRICS.kernel_dropped.load(Ordering::Relaxed),
+        );
+        let (by_sniff, by_kernel) = (dropped - self.dropped, kernel_dropped - self.kernel_dropped);
+        // what we read, and what the kernel dropped before we could
+        let arrived = packets - self.packets + by_kernel;
+
+        self.since = Instant::now();
+        (self.packets, self.dropped, self.kernel_dropped) = (packets, dropped, kernel_dropped);
+
+        if by_sniff + by_kernel == 0 {
+            return;
+        }
+
+        let message = format!(
+            "*** {} frame{} dropped in the last {}s ({:.1}% of {}): {} by the kernel, {} by sniff; counts are incomplete ***",
+            by_sniff + by_kernel,
+            if by_sniff + by_kernel == 1 { "" } else { "s" },
+            elapsed.as_secs(),
+            (by_sniff + by_kernel) as f64 / arrived.max(1) as f64 * 100.0,
+            arrived,
+            by_kernel,
+            by_sniff
+        );
+        outln!("{}", self.theme.paint(self.theme.warning, &message));
+    }
+}
@@ -0,0 +1,94 @@
+use std::ops::Range;
+
+use crate::{conf::DumpMode, theme::Theme};
+
+const BYTES_PER_LINE: usize = 16;
+
+// render a payload in the style of xxd, e.g.
+// 00000000: 4500 003c 1c46 4000 4006 b1e6 ac10 0a63  E..<.F@.@......c
+// with the bytes in `highlights` (e.g. --match-payload matches) picked out in both columns
+pub fn dump_payload(
+    data: &[u8],
+    mode: DumpMode,
+    max_bytes: usize,
+    highlights: &[Range<usize>],
+    theme: &Theme,
+) -> String {
+    let shown = &data[..data.len().min(max_bytes)];
+    let highlighted = |i: usize| highlights.iter().any(|x| x.contains(&i));
+
+    let mut out = String::new();
+
+    for (i, line) in shown.chunks(BYTES_PER_LINE).enumerate() {
+        let start = i * BYTES_PER_LINE;
+        out += &format!("{:08x}: ", start);
+
+        if mode != DumpMode::Ascii {
+            let mut cells = Vec::new();
+            for j in 0..BYTES_PER_LINE {
+                let mut cell = match line.get(j) {
+                    Some(byte) => format!("{:02x}", byte),
+                    None => "  ".to_string(),
+                };
+                if j % 2 == 1 {
+                    cell += " ";
+                }
+                cells.push((cell, j < line.len() && highlighted(start + j)));
+            }
+            out += &paint_runs(cells, theme);
+            out += " ";
+        }
+
+        if mode != DumpMode::Hex {
+            let cells = line
+                .iter()
+                .enumerate()
+                .map(|(j, x)| {
+                    let shown = if x.is_ascii_graphic() || *x == b' ' {
+                        *x as char
+                    } else {
+                        '.'
+                    };
+                    (shown.to_string(), highlighted(start + j))
+                })
+                .collect();
+            out += &paint_runs(cells, theme);
+        }
+
+        out = out.trim_end().to_string();
+        out += "\n";
+    }
+
+    if data.len() > shown.len() {
+        out += &format!("... {} more bytes\n", data.len() - shown.len());
+    }
+
+    out
+}
+
+// join up the cells of one column, highlighting each run of highlighted ones as a whole
+fn paint_runs(cells: Vec<(String, bool)>, theme: &Theme) -> String {
+    let mut out = String::new();
+    let mut run = String::new();
+    let mut in_run = false;
+
+    for (cell, highlighted) in cells {
+        if highlighted != in_run {
+            out += &flush(&mut run, in_run, theme);
+            in_run = highlighted;
+        }
+        run += &cell;
+    }
+    out += &flush(&mut run, in_run, theme);
+
+    out
+}
+
+fn flush(run: &mut String, highlighted: bool, theme: &Theme) -> String {
+    let text = std::mem::take(run);
+    if highlighted && !text.is_empty() {
+        theme.paint(theme.highlight, &text)
+    } else {
+        text
+    }
+}
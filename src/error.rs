@@ -0,0 +1,39 @@
+use thiserror::Error;
+
+// errors that shouldn't take the whole capture down with them
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("malformed log: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("malformed log: {0}")]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+
+    #[error("{0}")]
+    CborWrite(#[from] ciborium::ser::Error<std::io::Error>),
+
+    #[error("{0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+
+    #[error("log is from a newer sniff (format version {0}, this one reads up to {1})")]
+    NewerLog(u32, u32),
+
+    #[error("malformed frame: {0}")]
+    Malformed(&'static str),
+}
+
+// a read error we can't carry on from, e.g. the interface going away, rather than a one-off bad read
+pub fn is_fatal(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(code) = e.raw_os_error() {
+        return matches!(code, libc::ENETDOWN | libc::ENODEV | libc::ENXIO | libc::EBADF | libc::EPERM | libc::EACCES);
+    }
+
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::BrokenPipe
+    )
+}
@@ -0,0 +1,267 @@
+// --dual-stack: how connections to names with both IPv4 and IPv6 addresses turn out, going by the DNS answers and
+// TCP handshakes we see: a client's attempts at the same name and port close together are one connection (Happy
+// Eyeballs tries IPv6 first and IPv4 a moment later), and whichever family is answered first wins
+//
+// for checking a dual-stack rollout passively: how often IPv6 gets used, and how often it's tried but fails
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use crate::{conf::MacAddr, dns, ip};
+
+// Happy Eyeballs waits 250ms or so before trying the other family, so anything this close is the same connection
+const RACE_WINDOW: Duration = Duration::from_secs(2);
+// an attempt not answered by now has failed, and its connection is settled
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+// stop learning names here, so a busy resolver doesn't keep growing the table
+const MAX_NAMES: usize = 65536;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pending,
+    Connected(SystemTime),
+    Refused,
+}
+
+struct Attempt {
+    client: SocketAddr,
+    server: SocketAddr,
+    outcome: Outcome,
+}
+
+// one connection to a name, over one or both families
+struct Race {
+    client: MacAddr,
+    name: String,
+    port: u16,
+    started: SystemTime,
+    attempts: [Option<Attempt>; 2], // IPv4, IPv6
+}
+
+#[derive(Default)]
+struct Totals {
+    connections: u64,
+    v4_won: u64,
+    v6_won: u64,
+    v6_tried: u64,
+    v6_failed: u64,
+}
+
+#[derive(Default)]
+pub struct DualStackTracker {
+    names: HashMap<IpAddr, String>,
+    families: HashMap<String, [bool; 2]>, // which families each name has addresses in
+    races: Vec<Race>,
+    totals: BTreeMap<(String, u16), Totals>,
+}
+
+impl DualStackTracker {
+    // look at one IP packet, sent by `mac`: a DNS answer, or part of a TCP handshake
+    pub fn observe(&mut self, packet: &[u8], mac: MacAddr, timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet) else {
+            return;
+        };
+        let Some((src_port, dst_port, payload)) = ip::transport(packet) else {
+            return;
+        };
+
+        match header.protocol {
+            17 if src_port == 53 => self.learn(payload),
+            6 => {
+                let segment = &packet[header.header_len..];
+                let Some(&flags) = segment.get(13) else {
+                    return;
+                };
+                let (syn, rst, ack) = (flags & 0x02 != 0, flags & 0x04 != 0, flags & 0x10 != 0);
+                let src = SocketAddr::new(header.src, src_port);
+                let dst = SocketAddr::new(header.dst, dst_port);
+
+                if syn && !ack {
+                    self.settle(timestamp);
+                    self.attempt(mac, src, dst, timestamp);
+                } else if syn || rst {
+                    self.answer(
+                        dst,
+                        src,
+                        if rst {
+                            Outcome::Refused
+                        } else {
+                            Outcome::Connected(timestamp)
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn learn(&mut self, message: &[u8]) {
+        let Some((name, addresses)) = dns::answers(message) else {
+            return;
+        };
+        if self.names.len() >= MAX_NAMES {
+            return;
+        }
+
+        let families = self.families.entry(name.clone()).or_default();
+        for address in addresses {
+            families[address.is_ipv6() as usize] = true;
+            self.names.insert(address, name.clone());
+        }
+    }
+
+    fn attempt(&mut self, mac: MacAddr, client: SocketAddr, server: SocketAddr, timestamp: SystemTime) {
+        let Some(name) = self.names.get(&server.ip()) else {
+            return;
+        };
+        if self.families.get(name) != Some(&[true, true]) {
+            return;
+        }
+
+        let family = server.is_ipv6() as usize;
+        let attempt = Attempt {
+            client,
+            server,
+            outcome: Outcome::Pending,
+        };
+
+        // a retransmitted SYN is the same attempt
+        if self.races.iter().any(|x| {
+            x.attempts
+                .iter()
+                .flatten()
+                .any(|x| x.client == client && x.server == server)
+        }) {
+            return;
+        }
+
+        let race = self.races.iter_mut().find(|x| {
+            x.client == mac
+                && x.name == *name
+                && x.port == server.port()
+                && x.attempts[family].is_none()
+                && timestamp.duration_since(x.started).unwrap_or_default() < RACE_WINDOW
+        });
+
+        match race {
+            Some(race) => race.attempts[family] = Some(attempt),
+            None => {
+                let mut attempts = [None, None];
+                attempts[family] = Some(attempt);
+                self.races.push(Race {
+                    client: mac,
+                    name: name.clone(),
+                    port: server.port(),
+                    started: timestamp,
+                    attempts,
+                });
+            }
+        }
+    }
+
+    // the server's SYN-ACK or RST, for the attempt from `client` to `server`
+    fn answer(&mut self, client: SocketAddr, server: SocketAddr, outcome: Outcome) {
+        let attempt = self
+            .races
+            .iter_mut()
+            .flat_map(|x| x.attempts.iter_mut().flatten())
+            .find(|x| x.client == client && x.server == server && x.outcome == Outcome::Pending);
+
+        if let Some(attempt) = attempt {
+            attempt.outcome = outcome;
+        }
+    }
+
+    // count up the connections that have had long enough to finish
+    fn settle(&mut self, now: SystemTime) {
+        let (done, pending) = std::mem::take(&mut self.races)
+            .into_iter()
+            .partition(|x| now.duration_since(x.started).unwrap_or_default() >= ATTEMPT_TIMEOUT);
+        self.races = pending;
+
+        for race in done {
+            self.count(race);
+        }
+    }
+
+    fn count(&mut self, race: Race) {
+        let totals = self.totals.entry((race.name, race.port)).or_default();
+        totals.connections += 1;
+
+        let connected = |family: usize| match race.attempts[family] {
+            Some(Attempt {
+                outcome: Outcome::Connected(at),
+                ..
+            }) => Some(at),
+            _ => None,
+        };
+
+        match (connected(0), connected(1)) {
+            (Some(v4), Some(v6)) if v4 < v6 => totals.v4_won += 1,
+            (_, Some(_)) => totals.v6_won += 1,
+            (Some(_), None) => totals.v4_won += 1,
+            (None, None) => {}
+        }
+
+        if race.attempts[1].is_some() {
+            totals.v6_tried += 1;
+            if connected(1).is_none() {
+                totals.v6_failed += 1;
+            }
+        }
+    }
+
+    pub fn print_report(mut self) {
+        for race in std::mem::take(&mut self.races) {
+            self.count(race);
+        }
+        if self.totals.is_empty() {
+            return;
+        }
+
+        let sum = |f: fn(&Totals) -> u64| self.totals.values().map(f).sum::<u64>();
+        let (connections, v6_won, v6_tried, v6_failed) = (
+            sum(|x| x.connections),
+            sum(|x| x.v6_won),
+            sum(|x| x.v6_tried),
+            sum(|x| x.v6_failed),
+        );
+        let share = |n: u64, of: u64| n as f64 / of.max(1) as f64 * 100.0;
+
+        println!("Dual-stack connections (to names with IPv4 and IPv6 addresses):");
+        println!(
+            "    IPv6 used for {} of {} ({:.1}%); tried for {} and failed {} times ({:.1}%)",
+            v6_won,
+            connections,
+            share(v6_won, connections),
+            v6_tried,
+            v6_failed,
+            share(v6_failed, v6_tried),
+        );
+
+        let mut names: Vec<_> = self.totals.iter().collect();
+        names.sort_by_key(|(_, x)| std::cmp::Reverse(x.connections));
+
+        for ((name, port), totals) in names {
+            let mut line = format!(
+                "    {}:{}: {} connection{}, IPv6 {}, IPv4 {}",
+                name,
+                port,
+                totals.connections,
+                if totals.connections == 1 { "" } else { "s" },
+                totals.v6_won,
+                totals.v4_won,
+            );
+            if totals.v6_failed > 0 {
+                line += &format!(", IPv6 failed {} of {}", totals.v6_failed, totals.v6_tried);
+            }
+            if totals.v6_tried < totals.connections {
+                line += &format!(", IPv6 not tried {}", totals.connections - totals.v6_tried);
+            }
+            println!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// per-flow feature vectors (sizes, timings, TCP flags, payload entropy) for training anomaly detection
+// models on, written as Parquet or CSV so they load straight into pandas/polars/spark
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use parquet::{
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+use crate::{error::Error, ip};
+
+// a flow that's been quiet this long is over, and the next packet between the same ends starts a new one
+const FLOW_TIMEOUT: Duration = Duration::from_secs(120);
+const PRUNE_EVERY: u64 = 4096;
+
+// both directions of a conversation map to the same key: (lower end, higher end, IP protocol)
+type Key = ((IpAddr, u16), (IpAddr, u16), u8);
+
+// mean/variance without keeping every sample (Welford's algorithm), plus the extremes
+#[derive(Default)]
+struct Running {
+    count: u64,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Running {
+    fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn std(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            (self.m2 / (self.count - 1) as f64).sqrt()
+        }
+    }
+}
+
+// "forward" is whichever way the first packet we saw went
+struct Flow {
+    src: (IpAddr, u16),
+    dst: (IpAddr, u16),
+    protocol: u8,
+    start: SystemTime,
+    last: SystemTime,
+    fwd_packets: u64,
+    bwd_packets: u64,
+    fwd_bytes: u64,
+    bwd_bytes: u64,
+    sizes: Running,
+    gaps: Running, // inter-arrival times, in seconds
+    flags: [u64; 6], // SYN, FIN, RST, PSH, ACK, URG
+    histogram: [u64; 256], // payload byte values, for the entropy
+}
+
+impl Flow {
+    fn payload_bytes(&self) -> u64 {
+        self.histogram.iter().sum()
+    }
+
+    // Shannon entropy of the payload in bits per byte: ~8 for encrypted/compressed data, low for text
+    fn entropy(&self) -> f64 {
+        let total = self.payload_bytes() as f64;
+        self.histogram
+            .iter()
+            .filter(|x| **x > 0)
+            .map(|x| {
+                let p = *x as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+}
+
+// the TCP flags counted, as their column and their bit in the flags byte
+const FLAGS: [(&str, u8); 6] = [
+    ("syn_count", 0x02),
+    ("fin_count", 0x01),
+    ("rst_count", 0x04),
+    ("psh_count", 0x08),
+    ("ack_count", 0x10),
+    ("urg_count", 0x20),
+];
+
+#[derive(Default)]
+pub struct FeatureExporter {
+    active: HashMap<Key, Flow>,
+    finished: Vec<Flow>,
+    observed: u64,
+}
+
+impl FeatureExporter {
+    // look at one IP packet
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet) else {
+            return;
+        };
+
+        let (src_port, dst_port, payload) = ip::transport(packet).unwrap_or((0, 0, &[]));
+        let (src, dst) = ((header.src, src_port), (header.dst, dst_port));
+        let key = if src <= dst { (src, dst, header.protocol) } else { (dst, src, header.protocol) };
+
+        // an idle flow is finished, rather than carried on with a huge gap
+        if let Some(flow) = self.active.get(&key) {
+            if timestamp.duration_since(flow.last).unwrap_or_default() > FLOW_TIMEOUT {
+                let flow = self.active.remove(&key).unwrap();
+                self.finished.push(flow);
+            }
+        }
+
+        let flow = self.active.entry(key).or_insert_with(|| Flow {
+            src,
+            dst,
+            protocol: header.protocol,
+            start: timestamp,
+            last: timestamp,
+            fwd_packets: 0,
+            bwd_packets: 0,
+            fwd_bytes: 0,
+            bwd_bytes: 0,
+            sizes: Running::default(),
+            gaps: Running::default(),
+            flags: [0; 6],
+            histogram: [0; 256],
+        });
+
+        if flow.fwd_packets + flow.bwd_packets > 0 {
+            flow.gaps.add(timestamp.duration_since(flow.last).unwrap_or_default().as_secs_f64());
+        }
+        flow.last = timestamp;
+
+        if src == flow.src {
+            flow.fwd_packets += 1;
+            flow.fwd_bytes += packet.len() as u64;
+        } else {
+            flow.bwd_packets += 1;
+            flow.bwd_bytes += packet.len() as u64;
+        }
+        flow.sizes.add(packet.len() as f64);
+
+        if header.protocol == 6 {
+            if let Some(bits) = packet.get(header.header_len + 13) {
+                for (count, (_, flag)) in flow.flags.iter_mut().zip(FLAGS.iter()) {
+                    if bits & flag != 0 {
+                        *count += 1;
+                    }
+                }
+            }
+        }
+
+        for byte in payload {
+            flow.histogram[*byte as usize] += 1;
+        }
+
+        self.observed += 1;
+        if self.observed.is_multiple_of(PRUNE_EVERY) {
+            let idle: Vec<Key> = self
+                .active
+                .iter()
+                .filter(|(_, flow)| timestamp.duration_since(flow.last).unwrap_or_default() > FLOW_TIMEOUT)
+                .map(|(key, _)| *key)
+                .collect();
+            for key in idle {
+                let flow = self.active.remove(&key).unwrap();
+                self.finished.push(flow);
+            }
+        }
+    }
+
+    pub fn flow_count(&self) -> usize {
+        self.active.len() + self.finished.len()
+    }
+
+    // Parquet if the path ends in .parquet, otherwise CSV
+    pub fn write(self, path: &str) -> Result<(), Error> {
+        let mut flows: Vec<Flow> = self.finished.into_iter().chain(self.active.into_values()).collect();
+        flows.sort_by_key(|x| x.start);
+
+        let columns = columns(&flows);
+
+        if path.ends_with(".parquet") {
+            write_parquet(path, &columns)
+        } else {
+            write_csv(path, &columns, flows.len())
+        }
+    }
+}
+
+enum Values {
+    Text(Vec<String>),
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+}
+
+fn columns(flows: &[Flow]) -> Vec<(&'static str, Values)> {
+    let text = |f: &dyn Fn(&Flow) -> String| Values::Text(flows.iter().map(f).collect());
+    let int = |f: &dyn Fn(&Flow) -> u64| Values::Int(flows.iter().map(|x| f(x) as i64).collect());
+    let float = |f: &dyn Fn(&Flow) -> f64| Values::Float(flows.iter().map(f).collect());
+
+    let mut columns = vec![
+        ("start", float(&|x| x.start.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64())),
+        ("duration", float(&|x| x.last.duration_since(x.start).unwrap_or_default().as_secs_f64())),
+        ("src_ip", text(&|x| x.src.0.to_string())),
+        ("src_port", int(&|x| x.src.1 as u64)),
+        ("dst_ip", text(&|x| x.dst.0.to_string())),
+        ("dst_port", int(&|x| x.dst.1 as u64)),
+        ("protocol", int(&|x| x.protocol as u64)),
+        ("fwd_packets", int(&|x| x.fwd_packets)),
+        ("bwd_packets", int(&|x| x.bwd_packets)),
+        ("fwd_bytes", int(&|x| x.fwd_bytes)),
+        ("bwd_bytes", int(&|x| x.bwd_bytes)),
+        ("packet_size_min", float(&|x| x.sizes.min)),
+        ("packet_size_max", float(&|x| x.sizes.max)),
+        ("packet_size_mean", float(&|x| x.sizes.mean)),
+        ("packet_size_std", float(&|x| x.sizes.std())),
+        ("iat_min", float(&|x| x.gaps.min)),
+        ("iat_max", float(&|x| x.gaps.max)),
+        ("iat_mean", float(&|x| x.gaps.mean)),
+        ("iat_std", float(&|x| x.gaps.std())),
+    ];
+
+    for (i, (name, _)) in FLAGS.iter().enumerate() {
+        columns.push((name, Values::Int(flows.iter().map(|x| x.flags[i] as i64).collect())));
+    }
+
+    columns.push(("payload_bytes", int(&|x| x.payload_bytes())));
+    columns.push(("payload_entropy", float(&|x| x.entropy())));
+
+    columns
+}
+
+fn write_csv(path: &str, columns: &[(&str, Values)], rows: usize) -> Result<(), Error> {
+    let mut out = columns.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(",");
+    out += "\n";
+
+    for row in 0..rows {
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|(_, values)| match values {
+                Values::Text(x) => x[row].clone(),
+                Values::Int(x) => x[row].to_string(),
+                Values::Float(x) => format!("{:.6}", x[row]),
+            })
+            .collect();
+        out += &fields.join(",");
+        out += "\n";
+    }
+
+    Ok(std::fs::write(path, out)?)
+}
+
+fn write_parquet(path: &str, columns: &[(&str, Values)]) -> Result<(), Error> {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|(name, values)| match values {
+            Values::Text(_) => format!("REQUIRED BYTE_ARRAY {} (UTF8);", name),
+            Values::Int(_) => format!("REQUIRED INT64 {};", name),
+            Values::Float(_) => format!("REQUIRED DOUBLE {};", name),
+        })
+        .collect();
+    let schema = Arc::new(parse_message_type(&format!("message flow {{ {} }}", fields.join(" ")))?);
+
+    let file = std::fs::File::create(path)?;
+    let mut writer = SerializedFileWriter::new(file, schema, Arc::new(WriterProperties::builder().build()))?;
+    let mut row_group = writer.next_row_group()?;
+
+    // the column writers come out in schema order, which is the order of `columns`
+    for (_, values) in columns.iter() {
+        let Some(mut column) = row_group.next_column()? else {
+            break;
+        };
+
+        match (column.untyped(), values) {
+            (ColumnWriter::ByteArrayColumnWriter(w), Values::Text(x)) => {
+                let x: Vec<ByteArray> = x.iter().map(|x| ByteArray::from(x.as_str())).collect();
+                w.write_batch(&x, None, None)?;
+            }
+            (ColumnWriter::Int64ColumnWriter(w), Values::Int(x)) => {
+                w.write_batch(x, None, None)?;
+            }
+            (ColumnWriter::DoubleColumnWriter(w), Values::Float(x)) => {
+                w.write_batch(x, None, None)?;
+            }
+            _ => unreachable!("column type doesn't match the schema"),
+        }
+
+        column.close()?;
+    }
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
@@ -0,0 +1,375 @@
+// filter expressions, written in (a common subset of) tcpdump capture-filter syntax, e.g.
+// "tcp port 443 and not host 10.0.0.1" or "ether src 00:11:22:33:44:55 or (udp and dst portrange 5000-6000)"
+
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+use crate::{
+    conf::{MacAddr, Protocol},
+    ip,
+    qos::{Dscp, Qos},
+    RequestStats,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Direction {
+    Src,
+    Dst,
+    Either,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Host(Direction, std::net::IpAddr),
+    Hostname(Direction, String), // matched against the name shown for the address, with -H
+    Net(Direction, std::net::IpAddr, u8),
+    Port(Direction, u16, u16),   // inclusive range, a single port has both ends equal
+    Ether(Direction, MacAddr),
+    Proto(Protocol),
+    Version(u8), // "ip" or "ip6"
+    Vlan(Option<u16>), // "vlan" matches any tagged frame, "vlan 10" a specific ID
+    Dscp(Dscp),        // "dscp ef", or a number
+}
+
+impl Expr {
+    // orig_name/dest_name are the addresses as displayed, which are hostnames with -H
+    pub fn matches(&self, stats: &RequestStats, orig_name: &str, dest_name: &str) -> bool {
+        let either = |direction: Direction, matches: &dyn Fn(bool) -> bool| match direction {
+            Direction::Src => matches(true),
+            Direction::Dst => matches(false),
+            Direction::Either => matches(true) || matches(false),
+        };
+
+        match self {
+            Expr::And(a, b) => a.matches(stats, orig_name, dest_name) && b.matches(stats, orig_name, dest_name),
+            Expr::Or(a, b) => a.matches(stats, orig_name, dest_name) || b.matches(stats, orig_name, dest_name),
+            Expr::Not(a) => !a.matches(stats, orig_name, dest_name),
+            Expr::Host(direction, ip) => either(*direction, &|src| {
+                *ip == if src { stats.orig_ip.to_std() } else { stats.dest_ip.to_std() }
+            }),
+            Expr::Hostname(direction, name) => either(*direction, &|src| {
+                name == if src { orig_name } else { dest_name }
+            }),
+            Expr::Net(direction, net, prefix) => either(*direction, &|src| {
+                in_net(if src { stats.orig_ip.to_std() } else { stats.dest_ip.to_std() }, *net, *prefix)
+            }),
+            Expr::Port(direction, low, high) => match ip::transport(&stats.raw) {
+                Some((src_port, dst_port, _)) => either(*direction, &|src| {
+                    (*low..=*high).contains(if src { &src_port } else { &dst_port })
+                }),
+                None => false,
+            },
+            Expr::Ether(direction, mac) => either(*direction, &|src| {
+                *mac == if src { stats.orig_mac } else { stats.dest_mac }
+            }),
+            Expr::Proto(protocol) => stats.protocol == *protocol,
+            Expr::Version(version) => stats.raw.first().map(|x| x >> 4) == Some(*version),
+            Expr::Vlan(None) => stats.vlan.is_some(),
+            Expr::Vlan(id) => stats.vlan == *id,
+            Expr::Dscp(dscp) => Qos::parse(&stats.raw).is_some_and(|x| x.dscp == *dscp),
+        }
+    }
+}
+
+fn in_net(ip: std::net::IpAddr, net: std::net::IpAddr, prefix: u8) -> bool {
+    match (ip, net) {
+        (std::net::IpAddr::V4(ip), std::net::IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (std::net::IpAddr::V6(ip), std::net::IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s),
+            pos: 0,
+        };
+
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected '{}' in filter", token)));
+        }
+
+        Ok(expr)
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+// split on whitespace, with parentheses and "!" as tokens of their own
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+
+    for c in s.chars() {
+        match c {
+            '(' | ')' | '!' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                tokens.push(c.to_string());
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|x| x.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, Error> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| invalid("unexpected end of filter".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.and()?;
+        while matches!(self.peek(), Some("or") | Some("||")) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.not()?;
+        while matches!(self.peek(), Some("and") | Some("&&")) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, Error> {
+        if matches!(self.peek(), Some("not") | Some("!")) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        if self.peek() == Some("(") {
+            self.pos += 1;
+            let expr = self.or()?;
+            if self.next()? != ")" {
+                return Err(invalid("missing ')' in filter".to_string()));
+            }
+            return Ok(expr);
+        }
+
+        self.primitive()
+    }
+
+    fn direction(&mut self) -> Direction {
+        let direction = match self.peek() {
+            Some("src") => Direction::Src,
+            Some("dst") => Direction::Dst,
+            _ => return Direction::Either,
+        };
+        self.pos += 1;
+        direction
+    }
+
+    fn primitive(&mut self) -> Result<Expr, Error> {
+        let protocol = match self.peek() {
+            Some("tcp") => Some(Expr::Proto(Protocol::Tcp)),
+            Some("udp") => Some(Expr::Proto(Protocol::Udp)),
+            Some("icmp") => Some(Expr::Proto(Protocol::Icmp)),
+            Some("icmp6") => Some(Expr::And(
+                Box::new(Expr::Proto(Protocol::Icmp)),
+                Box::new(Expr::Version(6)),
+            )),
+            Some("ip6") => Some(Expr::Version(6)),
+            Some("ip") => {
+                self.pos += 1;
+                if self.peek() != Some("proto") {
+                    return Ok(Expr::Version(4));
+                }
+                self.pos += 1;
+                let number = self.next()?;
+                let protocol = match number.parse::<u8>() {
+                    Ok(number) => Protocol::from(number),
+                    // a misspelt name would otherwise match every protocol we don't know
+                    Err(_) => match number.parse()? {
+                        Protocol::Unknown => return Err(invalid(format!("unknown protocol '{}' in filter", number))),
+                        protocol => protocol,
+                    },
+                };
+                return Ok(Expr::Proto(protocol));
+            }
+            _ => None,
+        };
+
+        // "tcp port 80" is shorthand for "tcp and port 80"
+        if let Some(protocol) = protocol {
+            self.pos += 1;
+            if matches!(self.peek(), Some("src") | Some("dst") | Some("port") | Some("portrange")) {
+                return Ok(Expr::And(Box::new(protocol), Box::new(self.primitive()?)));
+            }
+            return Ok(protocol);
+        }
+
+        if self.peek() == Some("vlan") {
+            self.pos += 1;
+            let id = match self.peek().map(|x| x.parse::<u16>()) {
+                Some(Ok(id)) if id > 4095 => return Err(invalid(format!("invalid VLAN ID {}, expected 0-4095", id))),
+                Some(Ok(id)) => {
+                    self.pos += 1;
+                    Some(id)
+                }
+                _ => None,
+            };
+            return Ok(Expr::Vlan(id));
+        }
+
+        if self.peek() == Some("dscp") {
+            self.pos += 1;
+            return Ok(Expr::Dscp(self.next()?.parse()?));
+        }
+
+        if self.peek() == Some("ether") {
+            self.pos += 1;
+            if self.peek() == Some("proto") {
+                self.pos += 1;
+                let number = self.next()?;
+                let ethertype = match number.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                };
+                return match ethertype {
+                    Some(ethertype) => Ok(Expr::Proto(Protocol::Ether(ethertype))),
+                    None => Err(invalid(format!("invalid ethertype '{}'", number))),
+                };
+            }
+            let direction = self.direction();
+            if self.peek() == Some("host") {
+                self.pos += 1;
+            }
+            return Ok(Expr::Ether(direction, self.next()?.parse()?));
+        }
+
+        let direction = self.direction();
+        let token = self.next()?;
+
+        match token.as_str() {
+            "host" => {
+                let host = self.next()?;
+                Ok(match host.parse() {
+                    Ok(ip) => Expr::Host(direction, ip),
+                    Err(_) => Expr::Hostname(direction, host),
+                })
+            }
+            "net" => {
+                let net = self.next()?;
+                let (addr, prefix) = net.split_once('/').unwrap_or((&net, ""));
+                let addr: std::net::IpAddr = addr
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid network '{}'", net)))?;
+                let max = if addr.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    "" => max,
+                    prefix => prefix
+                        .parse()
+                        .ok()
+                        .filter(|x| *x <= max)
+                        .ok_or_else(|| invalid(format!("invalid network '{}'", net)))?,
+                };
+                Ok(Expr::Net(direction, addr, prefix))
+            }
+            "port" => {
+                let port = self.next()?;
+                let port = port
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid port '{}'", port)))?;
+                Ok(Expr::Port(direction, port, port))
+            }
+            "portrange" => {
+                let range = self.next()?;
+                let parsed = range
+                    .split_once('-')
+                    .and_then(|(low, high)| Some((low.parse().ok()?, high.parse().ok()?)));
+                match parsed {
+                    Some((low, high)) if low <= high => Ok(Expr::Port(direction, low, high)),
+                    _ => Err(invalid(format!("invalid port range '{}'", range))),
+                }
+            }
+            // a bare address after src/dst, e.g. "src 10.0.0.1"
+            _ if direction != Direction::Either => Ok(match token.parse() {
+                Ok(ip) => Expr::Host(direction, ip),
+                Err(_) => Expr::Hostname(direction, token),
+            }),
+            _ => Err(invalid(format!("unsupported filter primitive '{}'", token))),
+        }
+    }
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Direction::Src => write!(f, "src "),
+            Direction::Dst => write!(f, "dst "),
+            Direction::Either => Ok(()),
+        }
+    }
+}
+
+// written back out in tcpdump syntax, fully parenthesised
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expr::And(a, b) => write!(f, "({} and {})", a, b),
+            Expr::Or(a, b) => write!(f, "({} or {})", a, b),
+            Expr::Not(a) => write!(f, "not {}", a),
+            Expr::Host(direction, ip) => write!(f, "{}host {}", direction, ip),
+            Expr::Hostname(direction, name) => write!(f, "{}host {}", direction, name),
+            Expr::Net(direction, net, prefix) => write!(f, "{}net {}/{}", direction, net, prefix),
+            Expr::Port(direction, low, high) if low == high => write!(f, "{}port {}", direction, low),
+            Expr::Port(direction, low, high) => write!(f, "{}portrange {}-{}", direction, low, high),
+            Expr::Ether(direction, mac) => write!(f, "ether {}host {}", direction, mac),
+            Expr::Proto(Protocol::Ip(num)) => write!(f, "ip proto {}", num),
+            Expr::Proto(Protocol::Ether(ethertype)) => write!(f, "ether proto {:#06x}", ethertype),
+            Expr::Proto(protocol) => write!(f, "{}", protocol.to_string().to_lowercase()),
+            Expr::Version(4) => write!(f, "ip"),
+            Expr::Version(_) => write!(f, "ip6"),
+            Expr::Vlan(Some(id)) => write!(f, "vlan {}", id),
+            Expr::Vlan(None) => write!(f, "vlan"),
+            Expr::Dscp(dscp) => write!(f, "dscp {}", dscp.to_string().to_lowercase()),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// --record-fixture: the first few packets of each protocol seen, anonymised, one pcap per protocol (e.g.
+// ipv4-udp-53.pcap), to contribute back as dissector regression fixtures
+//
+// MAC and IP addresses are replaced, consistently so conversations still pair up, in the ethernet, ARP and IP
+// headers, with the checksums fixed up to match; payloads are kept as they are, since they're what the dissectors
+// are tested on, so look a fixture over before sending it anywhere
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::SystemTime,
+};
+
+use crate::{
+    checksum,
+    pcap::{PcapWriter, LINKTYPE_ETHERNET},
+};
+
+// a scan shouldn't leave a file per port behind
+const MAX_FIXTURES: usize = 64;
+
+pub struct FixtureRecorder {
+    dir: String,
+    per_protocol: usize,
+    fixtures: HashMap<String, (PcapWriter, usize)>, // name -> (file, packets written)
+    macs: HashMap<[u8; 6], [u8; 6]>,
+    ips: HashMap<IpAddr, IpAddr>,
+}
+
+impl FixtureRecorder {
+    pub fn new(dir: String, per_protocol: usize) -> FixtureRecorder {
+        std::fs::create_dir_all(&dir).expect("Failed to create fixture directory");
+
+        FixtureRecorder {
+            dir,
+            per_protocol,
+            fixtures: HashMap::new(),
+            macs: HashMap::new(),
+            ips: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, timestamp: SystemTime, frame: &[u8]) {
+        let Some(name) = fixture_name(frame) else {
+            return;
+        };
+
+        if !self.fixtures.contains_key(&name) {
+            if self.fixtures.len() >= MAX_FIXTURES {
+                return;
+            }
+
+            let path = format!("{}/{}.pcap", self.dir, name);
+            match PcapWriter::create(&path, LINKTYPE_ETHERNET) {
+                Ok(writer) => {
+                    self.fixtures.insert(name.clone(), (writer, 0));
+                }
+                Err(e) => {
+                    eprintln!("Failed to create fixture {}: {}", path, e);
+                    return;
+                }
+            }
+        }
+
+        if self.fixtures[&name].1 >= self.per_protocol {
+            return;
+        }
+
+        let mut frame = frame.to_vec();
+        self.anonymise(&mut frame);
+
+        let (writer, written) = self.fixtures.get_mut(&name).unwrap();
+        match writer.write_packet(timestamp, &frame) {
+            Ok(()) => *written += 1,
+            Err(e) => eprintln!("Failed to write fixture {}: {}", name, e),
+        }
+    }
+
+    pub fn finish(mut self) {
+        let mut packets = 0;
+        for (name, (writer, written)) in self.fixtures.iter_mut() {
+            if let Err(e) = writer.flush() {
+                eprintln!("Failed to write fixture {}: {}", name, e);
+            }
+            packets += *written;
+        }
+
+        println!(
+            "Saved {} fixture packet{} for {} protocol{} to {}",
+            packets,
+            if packets == 1 { "" } else { "s" },
+            self.fixtures.len(),
+            if self.fixtures.len() == 1 { "" } else { "s" },
+            self.dir,
+        );
+    }
+
+    fn anonymise(&mut self, frame: &mut [u8]) {
+        for offset in [0, 6] {
+            if let Some(mac) = frame.get_mut(offset..offset + 6) {
+                let anonymised = self.mac(mac.try_into().unwrap());
+                mac.copy_from_slice(&anonymised);
+            }
+        }
+
+        let Some((ethertype, offset)) = ethertype(frame) else {
+            return;
+        };
+        let packet = &mut frame[offset..];
+
+        match ethertype {
+            0x0800 => self.anonymise_ipv4(packet),
+            0x86dd => self.anonymise_ipv6(packet),
+            0x0806 => self.anonymise_arp(packet),
+            _ => {}
+        }
+    }
+
+    fn anonymise_ipv4(&mut self, packet: &mut [u8]) {
+        let Some(header) = crate::ip::parse(packet) else {
+            return;
+        };
+        let (IpAddr::V4(src), IpAddr::V4(dst)) = (self.ip(header.src), self.ip(header.dst)) else {
+            return;
+        };
+        packet[12..16].copy_from_slice(&src.octets());
+        packet[16..20].copy_from_slice(&dst.octets());
+
+        packet[10..12].fill(0);
+        let sum = checksum::internet(&packet[..header.header_len], 0);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        // the transport checksum covers the addresses too, if we have the whole segment to redo it with (and the total
+        // length makes sense)
+        let Some(end) = crate::ip::total_len(packet).filter(|x| *x >= header.header_len && *x <= packet.len()) else {
+            return;
+        };
+        let mut pseudo = [src.octets(), dst.octets()].concat();
+        pseudo.extend_from_slice(&[0, header.protocol]);
+        pseudo.extend_from_slice(&((end - header.header_len) as u16).to_be_bytes());
+        fix_transport_checksum(header.protocol, &pseudo, &mut packet[header.header_len..end], false);
+    }
+
+    fn anonymise_ipv6(&mut self, packet: &mut [u8]) {
+        let Some(header) = crate::ip::parse(packet) else {
+            return;
+        };
+        let (IpAddr::V6(src), IpAddr::V6(dst)) = (self.ip(header.src), self.ip(header.dst)) else {
+            return;
+        };
+        packet[8..24].copy_from_slice(&src.octets());
+        packet[24..40].copy_from_slice(&dst.octets());
+
+        let Some(end) = crate::ip::total_len(packet).filter(|x| *x <= packet.len()) else {
+            return;
+        };
+        let mut pseudo = [src.octets(), dst.octets()].concat();
+        pseudo.extend_from_slice(&((end - header.header_len) as u32).to_be_bytes());
+        pseudo.extend_from_slice(&[0, 0, 0, header.protocol]);
+        fix_transport_checksum(header.protocol, &pseudo, &mut packet[header.header_len..end], true);
+    }
+
+    // ethernet/IPv4 ARP carries a MAC and an address for each end
+    fn anonymise_arp(&mut self, packet: &mut [u8]) {
+        if packet.len() < 28 || packet[..6] != [0, 1, 8, 0, 6, 4] {
+            return;
+        }
+
+        for (mac, ip) in [(8, 14), (18, 24)] {
+            let anonymised = self.mac(packet[mac..mac + 6].try_into().unwrap());
+            packet[mac..mac + 6].copy_from_slice(&anonymised);
+
+            let address: [u8; 4] = packet[ip..ip + 4].try_into().unwrap();
+            if let IpAddr::V4(anonymised) = self.ip(IpAddr::from(address)) {
+                packet[ip..ip + 4].copy_from_slice(&anonymised.octets());
+            }
+        }
+    }
+
+    // broadcast, multicast and empty addresses say nothing about anyone, so they stay
+    fn mac(&mut self, mac: [u8; 6]) -> [u8; 6] {
+        if mac[0] & 1 == 1 || mac == [0; 6] {
+            return mac;
+        }
+
+        let n = self.macs.len() as u32 + 1;
+        *self.macs.entry(mac).or_insert_with(|| {
+            let n = n.to_be_bytes();
+            [0x02, 0, n[0], n[1], n[2], n[3]] // locally administered
+        })
+    }
+
+    // private addresses stay private and public ones public, from ranges set aside for examples and testing
+    fn ip(&mut self, ip: IpAddr) -> IpAddr {
+        let n = self.ips.len() as u32 + 1;
+
+        match ip {
+            IpAddr::V4(v4) if v4.is_multicast() || v4.is_broadcast() || v4.is_unspecified() || v4.is_loopback() => ip,
+            IpAddr::V6(v6) if v6.is_multicast() || v6.is_unspecified() || v6.is_loopback() => ip,
+            IpAddr::V4(v4) => *self.ips.entry(ip).or_insert_with(|| {
+                let base = if v4.is_private() || v4.is_link_local() { [10, 0, 0, 0] } else { [198, 18, 0, 0] };
+                IpAddr::V4(Ipv4Addr::from(u32::from(Ipv4Addr::from(base)) + n))
+            }),
+            IpAddr::V6(v6) => *self.ips.entry(ip).or_insert_with(|| {
+                let base: u128 = match v6.segments()[0] {
+                    x if x & 0xffc0 == 0xfe80 => 0xfe80 << 112,
+                    x if x & 0xe000 == 0x2000 => 0x2001_0db8 << 96,
+                    _ => 0xfd00 << 112,
+                };
+                IpAddr::V6(Ipv6Addr::from(base + n as u128))
+            }),
+        }
+    }
+}
+
+// the ethertype past any VLAN tags, and where its payload starts
+fn ethertype(frame: &[u8]) -> Option<(u16, usize)> {
+    let mut offset = 12;
+    let mut ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+
+    while matches!(ethertype, 0x8100 | 0x88a8 | 0x9100) {
+        offset += 4;
+        ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+    }
+
+    Some((ethertype, offset + 2))
+}
+
+// e.g. ipv4-tcp-443, ipv6-icmp, ether-0806: TCP and UDP by the lower of their ports, which is usually the service
+fn fixture_name(frame: &[u8]) -> Option<String> {
+    let (ethertype, offset) = ethertype(frame)?;
+    let version = match ethertype {
+        0x0800 => "ipv4",
+        0x86dd => "ipv6",
+        _ => return Some(format!("ether-{:04x}", ethertype)),
+    };
+
+    let packet = &frame[offset..];
+    let header = crate::ip::parse(packet)?;
+
+    let protocol = match (header.protocol, crate::ip::transport(packet)) {
+        (6, Some((src, dst, _))) => format!("tcp-{}", src.min(dst)),
+        (17, Some((src, dst, _))) => format!("udp-{}", src.min(dst)),
+        (1 | 58, _) => "icmp".to_string(),
+        (number, _) => format!("proto-{}", number),
+    };
+
+    Some(format!("{}-{}", version, protocol))
+}
+
+fn fix_transport_checksum(protocol: u8, pseudo: &[u8], segment: &mut [u8], ipv6: bool) {
+    let offset = match protocol {
+        6 => 16,
+        17 => 6,
+        58 => 2,
+        _ => return,
+    };
+    if segment.len() < offset + 2 {
+        return;
+    }
+
+    // an IPv4 UDP checksum of zero means there isn't one
+    if protocol == 17 && !ipv6 && segment[offset..offset + 2] == [0, 0] {
+        return;
+    }
+
+    segment[offset..offset + 2].fill(0);
+    let initial = pseudo.chunks(2).map(|x| u16::from_be_bytes([x[0], x[1]]) as u32).sum();
+    let mut sum = checksum::internet(segment, initial);
+    if protocol == 17 && sum == 0 {
+        sum = 0xffff;
+    }
+    segment[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+}
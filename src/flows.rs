@@ -0,0 +1,59 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::conf::{IpAddr, Protocol};
+
+// a unidirectional flow, as we see it at the IP layer
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub orig_ip: IpAddr,
+    pub dest_ip: IpAddr,
+    pub protocol: Protocol,
+}
+
+// how much weight a new observation gets in the smoothed rate
+const RATE_SMOOTHING: f64 = 0.5;
+
+// flows idle for longer than this are forgotten, so the table doesn't grow forever
+const FLOW_IDLE_SECS: u64 = 300;
+const PRUNE_EVERY: u64 = 4096;
+
+// tracks the throughput of each flow, so each request can be shown with its current rate
+#[derive(Default)]
+pub struct FlowRates {
+    flows: HashMap<FlowKey, (SystemTime, Option<f64>)>, // last request time, smoothed bytes/s
+    updates: u64,
+}
+
+impl FlowRates {
+    // record a request of `bytes` on a flow, returning the flow's rate in bytes/s (if it's been seen before)
+    pub fn update(&mut self, key: FlowKey, bytes: u64, timestamp: SystemTime) -> Option<f64> {
+        let rate = match self.flows.get(&key) {
+            Some((last, smoothed)) => {
+                let elapsed = timestamp.duration_since(*last).unwrap_or_default().as_secs_f64();
+                if elapsed <= 0.0 {
+                    *smoothed
+                } else {
+                    let instantaneous = bytes as f64 / elapsed;
+                    Some(match smoothed {
+                        Some(smoothed) => {
+                            RATE_SMOOTHING * instantaneous + (1.0 - RATE_SMOOTHING) * smoothed
+                        }
+                        None => instantaneous,
+                    })
+                }
+            }
+            None => None,
+        };
+
+        self.flows.insert(key, (timestamp, rate));
+
+        self.updates += 1;
+        if self.updates.is_multiple_of(PRUNE_EVERY) {
+            self.flows.retain(|_, (last, _)| {
+                timestamp.duration_since(*last).unwrap_or_default().as_secs() < FLOW_IDLE_SECS
+            });
+        }
+
+        rate
+    }
+}
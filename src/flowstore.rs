@@ -0,0 +1,293 @@
+// the packets of recent flows, kept for the control socket's `flows` and `export-flow ID PATH`, so one conversation
+// can be pulled out of a running capture in full, without logging everything else along with it
+//
+// a flow is both directions of a 5-tuple; its origin is whichever end sent the first packet we saw. An export to a
+// .pcap file has the flow's IP packets as they were captured, anything else gets JSON: the flow's metadata, a line per
+// packet, and what each end sent, with TCP put back in sequence order (retransmissions dropped, gaps counted)
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, pcap, units};
+
+// flows idle for longer than this are forgotten
+const FLOW_IDLE_SECS: u64 = 300;
+// at most this many flows are kept; past that the longest idle is dropped for each new one
+const MAX_FLOWS: usize = 256;
+// past this much captured data, a flow's packets are only counted, so a bulk transfer can't eat all our memory
+const MAX_FLOW_BYTES: usize = 256 * 1024;
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+type Endpoint = (IpAddr, u16);
+
+struct Packet {
+    timestamp: SystemTime,
+    from_orig: bool,
+    data: Vec<u8>,
+}
+
+struct Flow {
+    id: u64,
+    protocol: u8,
+    orig: Endpoint,
+    dest: Endpoint,
+    first: SystemTime,
+    last: SystemTime,
+    packets: u64,
+    bytes: u64,
+    stored: usize,
+    kept: Vec<Packet>,
+}
+
+#[derive(Default)]
+pub struct FlowStore {
+    flows: HashMap<(u8, Endpoint, Endpoint), Flow>, // keyed with the lower endpoint first
+    next_id: u64,
+}
+
+impl FlowStore {
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet) else {
+            return;
+        };
+        let (src_port, dst_port) = match ip::transport(packet) {
+            Some((src_port, dst_port, _)) => (src_port, dst_port),
+            None => (0, 0),
+        };
+        let (src, dst) = ((header.src, src_port), (header.dst, dst_port));
+        let key = (header.protocol, src.min(dst), src.max(dst));
+
+        if !self.flows.contains_key(&key) {
+            self.make_room(timestamp);
+            self.next_id += 1;
+            self.flows.insert(
+                key,
+                Flow {
+                    id: self.next_id,
+                    protocol: header.protocol,
+                    orig: src,
+                    dest: dst,
+                    first: timestamp,
+                    last: timestamp,
+                    packets: 0,
+                    bytes: 0,
+                    stored: 0,
+                    kept: Vec::new(),
+                },
+            );
+        }
+
+        let flow = self.flows.get_mut(&key).unwrap();
+        flow.last = timestamp;
+        flow.packets += 1;
+        flow.bytes += packet.len() as u64;
+
+        if flow.stored + packet.len() <= MAX_FLOW_BYTES {
+            flow.stored += packet.len();
+            flow.kept.push(Packet {
+                timestamp,
+                from_orig: src == flow.orig,
+                data: packet.to_vec(),
+            });
+        }
+    }
+
+    // forget idle flows, and the longest idle one if that's not enough
+    fn make_room(&mut self, now: SystemTime) {
+        let idle = Duration::from_secs(FLOW_IDLE_SECS);
+        self.flows
+            .retain(|_, flow| now.duration_since(flow.last).unwrap_or_default() < idle);
+
+        if self.flows.len() >= MAX_FLOWS {
+            if let Some(key) = self.flows.iter().min_by_key(|(_, flow)| flow.last).map(|(key, _)| *key) {
+                self.flows.remove(&key);
+            }
+        }
+    }
+
+    // one line per flow, most recently active first
+    pub fn list(&self) -> Vec<String> {
+        let mut flows: Vec<&Flow> = self.flows.values().collect();
+        flows.sort_by_key(|flow| std::cmp::Reverse(flow.last));
+
+        flows
+            .iter()
+            .map(|flow| {
+                format!(
+                    "{} {} {} -> {} {} packet{} {}{}",
+                    flow.id,
+                    protocol_name(flow.protocol),
+                    endpoint(flow.orig, flow.protocol),
+                    endpoint(flow.dest, flow.protocol),
+                    flow.packets,
+                    if flow.packets == 1 { "" } else { "s" },
+                    units::human_bytes(flow.bytes, None),
+                    if flow.kept.len() as u64 == flow.packets {
+                        ""
+                    } else {
+                        " (truncated)"
+                    }
+                )
+            })
+            .collect()
+    }
+
+    // write a flow to a new file at `path`, returning what was written; an existing file is never overwritten
+    pub fn export(&self, id: u64, path: &Path) -> Result<String, String> {
+        let flow = self
+            .flows
+            .values()
+            .find(|flow| flow.id == id)
+            .ok_or_else(|| format!("no flow {}", id))?;
+
+        let file = OpenOptions::new().write(true).create_new(true).open(path);
+        let written = file.and_then(|file| match path.extension() {
+            Some(x) if x == "pcap" => write_pcap(flow, file),
+            _ => write_json(flow, file),
+        });
+        written.map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+
+        Ok(format!("wrote {} packets of flow {} to {}", flow.kept.len(), id, path.display()))
+    }
+}
+
+fn write_pcap(flow: &Flow, file: File) -> std::io::Result<()> {
+    let mut writer = pcap::PcapWriter::new(file, pcap::LINKTYPE_RAW)?;
+    for packet in flow.kept.iter() {
+        writer.write_packet(packet.timestamp, &packet.data)?;
+    }
+    writer.flush()
+}
+
+fn write_json(flow: &Flow, mut file: File) -> std::io::Result<()> {
+    let secs = |x: SystemTime| {
+        x.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    };
+    let direction = |from_orig: bool| if from_orig { "orig" } else { "dest" };
+
+    let packets: Vec<serde_json::Value> = flow
+        .kept
+        .iter()
+        .map(|packet| {
+            serde_json::json!({
+                "time": packet.timestamp.duration_since(flow.first).unwrap_or_default().as_secs_f64(),
+                "from": direction(packet.from_orig),
+                "length": packet.data.len(),
+                "payload_length": payload(flow.protocol, &packet.data).map(|(_, x)| x.len()).unwrap_or(0),
+            })
+        })
+        .collect();
+
+    let stream = |from_orig: bool| {
+        let (data, missing) = reconstruct(flow, from_orig);
+        serde_json::json!({
+            "bytes": data.len(),
+            "missing": missing,
+            "hex": data.iter().map(|x| format!("{:02x}", x)).collect::<String>(),
+            "text": String::from_utf8_lossy(&data),
+        })
+    };
+
+    let json = serde_json::json!({
+        "id": flow.id,
+        "protocol": protocol_name(flow.protocol),
+        "orig": endpoint(flow.orig, flow.protocol),
+        "dest": endpoint(flow.dest, flow.protocol),
+        // seconds since the Unix epoch
+        "first": secs(flow.first),
+        "last": secs(flow.last),
+        "packets": flow.packets,
+        "bytes": flow.bytes,
+        "truncated": flow.kept.len() as u64 != flow.packets,
+        "packet_list": packets,
+        "payload": {
+            "orig": stream(true),
+            "dest": stream(false),
+        },
+    });
+
+    file.write_all(serde_json::to_string_pretty(&json)?.as_bytes())
+}
+
+// a packet's transport payload and, for TCP, its sequence number; the IP payload for anything but TCP and UDP
+fn payload(protocol: u8, packet: &[u8]) -> Option<(Option<u32>, &[u8])> {
+    match protocol {
+        TCP => {
+            let header = ip::parse(packet)?;
+            let segment = &packet[header.header_len..];
+            let seq = u32::from_be_bytes(segment.get(4..8)?.try_into().unwrap());
+            Some((Some(seq), ip::transport(packet)?.2))
+        }
+        UDP => Some((None, ip::transport(packet)?.2)),
+        _ => Some((None, &packet[ip::parse(packet)?.header_len..])),
+    }
+}
+
+// everything one end sent, and how many bytes of it we missed
+fn reconstruct(flow: &Flow, from_orig: bool) -> (Vec<u8>, u64) {
+    let payloads = flow
+        .kept
+        .iter()
+        .filter(|packet| packet.from_orig == from_orig)
+        .filter_map(|packet| payload(flow.protocol, &packet.data));
+
+    if flow.protocol != TCP {
+        return (payloads.flat_map(|(_, x)| x.iter().copied()).collect(), 0);
+    }
+
+    // segments in sequence order, relative to the first one seen so wrapping doesn't matter
+    let segments: Vec<(u32, &[u8])> = payloads
+        .filter_map(|(seq, x)| Some((seq?, x)))
+        .filter(|(_, x)| !x.is_empty())
+        .collect();
+    let Some(&(start, _)) = segments.first() else {
+        return (Vec::new(), 0);
+    };
+    let mut segments: Vec<(u32, &[u8])> = segments
+        .into_iter()
+        .map(|(seq, x)| (seq.wrapping_sub(start), x))
+        .collect();
+    segments.sort_by_key(|(seq, _)| *seq);
+
+    let (mut data, mut missing) = (Vec::new(), 0);
+    for (seq, x) in segments {
+        let end = data.len() as u64 + missing;
+        let seq = seq as u64;
+        if seq > end {
+            missing += seq - end;
+        }
+        // only what's past what we already have, so retransmissions aren't repeated
+        let skip = end.saturating_sub(seq) as usize;
+        data.extend_from_slice(x.get(skip..).unwrap_or_default());
+    }
+
+    (data, missing)
+}
+
+fn protocol_name(protocol: u8) -> String {
+    match protocol {
+        1 => "icmp".to_string(),
+        TCP => "tcp".to_string(),
+        UDP => "udp".to_string(),
+        58 => "icmpv6".to_string(),
+        protocol => format!("ip-proto-{}", protocol),
+    }
+}
+
+fn endpoint((ip, port): Endpoint, protocol: u8) -> String {
+    match (protocol, ip) {
+        (TCP | UDP, IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        (TCP | UDP, ip) => format!("{}:{}", ip, port),
+        (_, ip) => ip.to_string(),
+    }
+}
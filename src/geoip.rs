@@ -0,0 +1,91 @@
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::IpAddr, RequestStats};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+impl std::fmt::Display for GeoInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut parts = Vec::new();
+
+        if let Some(ref country) = self.country {
+            parts.push(country.clone());
+        }
+        if let Some(asn) = self.asn {
+            parts.push(format!("AS{}", asn));
+        }
+        if let Some(ref org) = self.as_org {
+            parts.push(org.clone());
+        }
+
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+// GeoLite2 ships country and ASN data as separate databases, so we query every one we were given
+pub struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    pub fn open(paths: &[String]) -> GeoIp {
+        GeoIp {
+            readers: paths
+                .iter()
+                .map(|path| {
+                    Reader::open_readfile(path)
+                        .unwrap_or_else(|e| panic!("Failed to open GeoIP database {}: {}", path, e))
+                })
+                .collect(),
+        }
+    }
+
+    // private addresses are never in the databases, so don't bother looking
+    pub fn lookup(&self, ip: &IpAddr) -> Option<GeoInfo> {
+        if ip.is_private() {
+            return None;
+        }
+
+        let ip = ip.to_std();
+        let mut info = GeoInfo::default();
+
+        for reader in self.readers.iter() {
+            if let Ok(country) = reader.lookup::<geoip2::Country>(ip) {
+                if let Some(iso_code) = country.country.and_then(|x| x.iso_code) {
+                    info.country = Some(iso_code.to_string());
+                }
+            }
+
+            if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) {
+                if asn.autonomous_system_number.is_some() {
+                    info.asn = asn.autonomous_system_number;
+                }
+                if let Some(org) = asn.autonomous_system_organization {
+                    info.as_org = Some(org.to_string());
+                }
+            }
+        }
+
+        if info == GeoInfo::default() {
+            return None;
+        }
+
+        Some(info)
+    }
+
+    // fill in the geo fields of a request, keeping any that were already there (e.g. from a log)
+    pub fn annotate(&self, stats: &mut RequestStats) {
+        if stats.orig_geo.is_none() {
+            stats.orig_geo = self.lookup(&stats.orig_ip);
+        }
+        if stats.dest_geo.is_none() {
+            stats.dest_geo = self.lookup(&stats.dest_ip);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// --graph: instead of a line per request, a sparkline of bytes per second across the terminal, redrawn in place
+// every second, for a quick sense of bursts. One row for all the traffic, or (with --graph protocol) a row each for
+// TCP, UDP, ICMP and everything else, or (with --graph host) one for each of the busiest hosts, by what they sent
+// and received over the time shown:
+//
+//     [graph] bytes/s, a column a second over the last 18s
+//     TCP             ▁▁▂▂▁▁▁▅█▇▃▁▁▁▁▂▂▁  now 12.5 KiB/s, peak 1.2 MiB/s
+//     UDP             ▁▁▁▁▁▁▁▁▁▂▁▁▁▁▁▁▁▁  now 320 B/s, peak 2.1 KiB/s
+//
+// everything captured counts, whatever the filters show; requests are still logged, pushed and so on, just not printed
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::IsTerminal,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    conf::{GraphSplit, IpAddr, Protocol},
+    locale::Locale,
+    output,
+    theme::Theme,
+    units,
+};
+
+const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const EVERY: Duration = Duration::from_secs(1);
+const TOP_HOSTS: usize = 5;
+// the label column, and the rates after the sparkline
+const LABEL_WIDTH: usize = 16;
+const RATES_WIDTH: usize = 36;
+// the most history kept, however wide the terminal
+const MAX_COLUMNS: usize = 1000;
+
+pub struct Graph {
+    split: GraphSplit,
+    theme: Theme,
+    locale: Option<Locale>,
+    in_place: bool,                          // only on a terminal; otherwise each graph follows the last
+    columns: VecDeque<HashMap<String, u64>>, // bytes per row, a second a column, the newest last
+    current: HashMap<String, u64>,
+    second_start: Instant,
+    drawn: Option<(usize, u64)>, // how many lines the last graph took, and output's line count after it
+}
+
+impl Graph {
+    pub fn new(split: GraphSplit, theme: Theme, locale: Option<Locale>) -> Graph {
+        Graph {
+            split,
+            theme,
+            locale,
+            in_place: std::io::stdout().is_terminal(),
+            columns: VecDeque::new(),
+            current: HashMap::new(),
+            second_start: Instant::now(),
+            drawn: None,
+        }
+    }
+
+    pub fn observe(&mut self, protocol: Protocol, orig: &IpAddr, dest: &IpAddr, bytes: u64) {
+        match self.split {
+            GraphSplit::Total => *self.current.entry("total".to_string()).or_default() += bytes,
+            GraphSplit::Protocol => {
+                let row = match protocol {
+                    Protocol::Tcp | Protocol::Udp | Protocol::Icmp => protocol.to_string(),
+                    _ => "other".to_string(),
+                };
+                *self.current.entry(row).or_default() += bytes;
+            }
+            // a host's row is its traffic both ways
+            GraphSplit::Host => {
+                for ip in [orig, dest] {
+                    if !ip.to_std().is_unspecified() {
+                        *self.current.entry(ip.to_string()).or_default() += bytes;
+                    }
+                }
+            }
+        }
+    }
+
+    // called from the capture loop, so the graph moves along when nothing's coming in
+    pub fn tick(&mut self) {
+        if self.second_start.elapsed() < EVERY {
+            return;
+        }
+        self.second_start += EVERY;
+        self.columns.push_back(std::mem::take(&mut self.current));
+        while self.columns.len() > MAX_COLUMNS {
+            self.columns.pop_front();
+        }
+
+        self.draw();
+    }
+
+    fn draw(&mut self) {
+        let width = terminal_width().saturating_sub(LABEL_WIDTH + RATES_WIDTH).max(10);
+        let shown: Vec<&HashMap<String, u64>> = self
+            .columns
+            .iter()
+            .skip(self.columns.len().saturating_sub(width))
+            .collect();
+
+        let mut lines = vec![format!(
+            "{} bytes/s, a column a second over the last {}s",
+            self.theme.paint(self.theme.highlight, "[graph]"),
+            shown.len()
+        )];
+        for row in self.rows(&shown) {
+            let rates: Vec<u64> = shown.iter().map(|x| x.get(&row).copied().unwrap_or(0)).collect();
+            let peak = rates.iter().copied().max().unwrap_or(0);
+
+            // scaled to the row's own peak, so a quiet row's bursts show up as well as a busy one's
+            let sparkline: String = rates
+                .iter()
+                .map(|&x| match x {
+                    0 => BLOCKS[0],
+                    x => BLOCKS[((x * 8).div_ceil(peak.max(1)) as usize).clamp(1, 8)],
+                })
+                .collect();
+
+            let label: String = row.chars().take(LABEL_WIDTH - 1).collect();
+            let style = match row.as_str() {
+                "TCP" => self.theme.tcp,
+                "UDP" => self.theme.udp,
+                "ICMP" => self.theme.icmp,
+                _ => anstyle::Style::new(),
+            };
+            // padded by hand, since the styling would count towards a width
+            lines.push(format!(
+                "{:<label_width$}{}{}  now {}, peak {}",
+                label,
+                self.theme.paint(style, &sparkline),
+                " ".repeat(width - rates.len()),
+                units::human_rate(*rates.last().unwrap_or(&0) as f64, self.locale.as_ref()),
+                units::human_rate(peak as f64, self.locale.as_ref()),
+                label_width = LABEL_WIDTH,
+            ));
+        }
+
+        // back up over the last graph, unless something else has been printed since
+        let mut text = String::new();
+        if let Some((height, line_count)) = self.drawn {
+            if self.in_place && line_count == output::lines() {
+                text += &format!("\x1b[{}F\x1b[J", height);
+            }
+        }
+        for line in lines.iter() {
+            text += line;
+            text += "\n";
+        }
+        output::text(&text);
+
+        self.drawn = Some((lines.len(), output::lines()));
+    }
+
+    // which rows to draw, in order
+    fn rows(&self, shown: &[&HashMap<String, u64>]) -> Vec<String> {
+        match self.split {
+            GraphSplit::Total => vec!["total".to_string()],
+            GraphSplit::Protocol => ["TCP", "UDP", "ICMP", "other"].iter().map(|x| x.to_string()).collect(),
+            GraphSplit::Host => {
+                let mut totals: HashMap<&String, u64> = HashMap::new();
+                for column in shown {
+                    for (host, bytes) in column.iter() {
+                        *totals.entry(host).or_default() += bytes;
+                    }
+                }
+                let mut hosts: Vec<(&String, u64)> = totals.into_iter().collect();
+                hosts.sort_by_key(|(host, bytes)| (std::cmp::Reverse(*bytes), host.to_string()));
+                hosts
+                    .into_iter()
+                    .take(TOP_HOSTS)
+                    .map(|(host, _)| host.clone())
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        return size.ws_col as usize;
+    }
+    columns_from_env()
+}
+
+#[cfg(not(unix))]
+fn terminal_width() -> usize {
+    columns_from_env()
+}
+
+fn columns_from_env() -> usize {
+    std::env::var("COLUMNS").ok().and_then(|x| x.parse().ok()).unwrap_or(80)
+}
@@ -0,0 +1,48 @@
+// with GRO/TSO offload, the kernel hands us "super-packets" that were (or will be) several segments on
+// the wire, so anything bigger than the interface's MTU is counted here, and can be split back up
+
+use crate::ip;
+
+// what we assume when the interface won't tell us
+const DEFAULT_MTU: usize = 1500;
+
+// the largest IP packet the interface puts on the wire, jumbo frames included
+pub fn interface_mtu(name: &str) -> usize {
+    std::fs::read_to_string(format!("/sys/class/net/{}/mtu", name))
+        .ok()
+        .and_then(|x| x.trim().parse().ok())
+        .unwrap_or(DEFAULT_MTU)
+}
+
+// an IP packet too big for the MTU: roughly how many segments it stands for, and the header bytes
+// each of the extra segments would have carried
+#[derive(Clone, Copy)]
+pub struct SuperPacket {
+    pub segments: u64,
+    pub header_len: usize,
+}
+
+pub fn detect(packet: &[u8], mtu: usize) -> Option<SuperPacket> {
+    if packet.len() <= mtu {
+        return None;
+    }
+
+    let header = ip::parse(packet)?;
+
+    // TCP is cut up at the MSS, so work out the segment count from the payload, not the whole packet
+    let header_len = match header.protocol {
+        6 => {
+            let tcp = packet.get(header.header_len..)?;
+            header.header_len + (*tcp.get(12)? as usize >> 4) * 4
+        }
+        _ => header.header_len,
+    };
+
+    let per_segment = mtu.checked_sub(header_len).filter(|x| *x > 0)?;
+    let payload = packet.len() - header_len;
+
+    Some(SuperPacket {
+        segments: payload.div_ceil(per_segment) as u64,
+        header_len,
+    })
+}
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::{
+    conf::MacAddr,
+    pcap::{PcapWriter, LINKTYPE_IEEE802_11_RADIOTAP},
+    wifi::{Dot11Frame, FrameType},
+};
+
+// LLC/SNAP header announcing an EAPOL (802.1X) payload
+const EAPOL_SNAP: [u8; 8] = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x88, 0x8e];
+const EAPOL_KEY: u8 = 3;
+
+// key information flags
+const KEY_INFO_INSTALL: u16 = 1 << 6;
+const KEY_INFO_ACK: u16 = 1 << 7;
+const KEY_INFO_MIC: u16 = 1 << 8;
+
+struct EapolKey {
+    message: usize, // 1 to 4, which message of the 4-way handshake this is
+    replay_counter: u64,
+}
+
+fn parse_eapol_key(body: &[u8]) -> Option<EapolKey> {
+    if body.get(..8)? != EAPOL_SNAP {
+        return None;
+    }
+
+    let eapol = &body[8..];
+
+    // version, type, length, then the key descriptor starts
+    if *eapol.get(1)? != EAPOL_KEY {
+        return None;
+    }
+
+    let key_info = u16::from_be_bytes([*eapol.get(5)?, *eapol.get(6)?]);
+    let replay_counter = u64::from_be_bytes(eapol.get(9..17)?.try_into().ok()?);
+    let nonce = eapol.get(17..49)?;
+
+    let ack = key_info & KEY_INFO_ACK != 0;
+    let mic = key_info & KEY_INFO_MIC != 0;
+    let install = key_info & KEY_INFO_INSTALL != 0;
+
+    let message = match (ack, mic, install) {
+        (true, false, _) => 1,
+        (true, true, true) => 3,
+        // messages 2 and 4 look alike, but only message 2 carries the supplicant's nonce
+        (false, true, false) if nonce.iter().any(|x| *x != 0) => 2,
+        (false, true, false) => 4,
+        _ => return None,
+    };
+
+    Some(EapolKey {
+        message,
+        replay_counter,
+    })
+}
+
+// the messages of one handshake seen so far, as (timestamp, raw frame, replay counter)
+#[derive(Default)]
+struct PendingHandshake {
+    messages: Vec<(SystemTime, Vec<u8>, u64)>,
+}
+
+pub struct HandshakeTracker {
+    dir: String,
+    ssids: HashMap<MacAddr, String>,
+    beacons: HashMap<MacAddr, (SystemTime, Vec<u8>)>,
+    pending: HashMap<(MacAddr, MacAddr), PendingHandshake>, // keyed on (bssid, client)
+    pub saved: usize,
+}
+
+impl HandshakeTracker {
+    pub fn new(dir: String) -> HandshakeTracker {
+        std::fs::create_dir_all(&dir).expect("Failed to create handshake directory");
+
+        HandshakeTracker {
+            dir,
+            ssids: HashMap::new(),
+            beacons: HashMap::new(),
+            pending: HashMap::new(),
+            saved: 0,
+        }
+    }
+
+    // feed a frame (and the raw radiotap frame it came from) into the tracker
+    // returns the path of the pcap file if this frame completed a handshake
+    pub fn observe(&mut self, frame: &Dot11Frame, raw: &[u8], timestamp: SystemTime) -> Option<String> {
+        // remember the SSID and a beacon for each BSSID, as crackers need one alongside the handshake
+        if let Some(ssid) = frame.ssid() {
+            let bssid = frame.addr3?;
+            self.ssids.insert(bssid, ssid);
+            self.beacons
+                .entry(bssid)
+                .or_insert_with(|| (timestamp, raw.to_vec()));
+            return None;
+        }
+
+        if frame.frame_type != FrameType::Data || frame.protected {
+            return None;
+        }
+
+        let key = parse_eapol_key(frame.body)?;
+
+        let (bssid, client) = if frame.from_ds {
+            (frame.addr2?, frame.addr1)
+        } else {
+            (frame.addr1, frame.addr2?)
+        };
+
+        let pending = self.pending.entry((bssid, client)).or_default();
+
+        // message 1 always starts a new attempt, anything else has to follow on from what we've got
+        let follows_on = match (key.message, pending.messages.last()) {
+            (1, _) => {
+                pending.messages.clear();
+                true
+            }
+            (2, Some((_, _, counter))) => pending.messages.len() == 1 && *counter == key.replay_counter,
+            (3, Some((_, _, counter))) => pending.messages.len() == 2 && *counter < key.replay_counter,
+            (4, Some((_, _, counter))) => pending.messages.len() == 3 && *counter == key.replay_counter,
+            _ => false,
+        };
+
+        if !follows_on {
+            pending.messages.clear();
+            return None;
+        }
+
+        pending
+            .messages
+            .push((timestamp, raw.to_vec(), key.replay_counter));
+
+        if pending.messages.len() < 4 {
+            return None;
+        }
+
+        let messages = std::mem::take(&mut pending.messages);
+
+        let ssid = self
+            .ssids
+            .get(&bssid)
+            .cloned()
+            .unwrap_or_else(|| bssid.to_string());
+
+        let path = format!(
+            "{}/{}_{}_{}.pcap",
+            self.dir,
+            sanitize(&ssid),
+            sanitize(&client.to_string()),
+            timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+
+        let mut writer = PcapWriter::create(&path, LINKTYPE_IEEE802_11_RADIOTAP)
+            .expect("Failed to create handshake file");
+
+        if let Some((timestamp, beacon)) = self.beacons.get(&bssid) {
+            writer.write_packet(*timestamp, beacon).unwrap();
+        }
+
+        for (timestamp, raw, _) in messages.iter() {
+            writer.write_packet(*timestamp, raw).unwrap();
+        }
+
+        writer.flush().unwrap();
+
+        self.saved += 1;
+
+        Some(path)
+    }
+}
+
+// make an SSID or MAC address safe to use in a file name
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+// the type and code of an ICMP or ICMPv6 message
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IcmpInfo {
+    pub v6: bool,
+    pub icmp_type: u8,
+    pub code: u8,
+}
+
+const IPV6_HEADER_LEN: usize = 40;
+
+// pull the ICMP type/code out of an IP packet carrying ICMP (v4) or ICMPv6
+pub fn parse(ip_packet: &[u8], v6: bool) -> Option<IcmpInfo> {
+    let offset = if v6 {
+        IPV6_HEADER_LEN
+    } else {
+        // IHL is the header length in 32-bit words
+        (*ip_packet.first()? & 0x0f) as usize * 4
+    };
+
+    Some(IcmpInfo {
+        v6,
+        icmp_type: *ip_packet.get(offset)?,
+        code: *ip_packet.get(offset + 1)?,
+    })
+}
+
+impl IcmpInfo {
+    pub fn describe(&self) -> String {
+        if self.v6 {
+            describe_v6(self.icmp_type, self.code)
+        } else {
+            describe_v4(self.icmp_type, self.code)
+        }
+    }
+}
+
+impl std::fmt::Display for IcmpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            if self.v6 { "ICMPv6" } else { "ICMP" },
+            self.describe()
+        )
+    }
+}
+
+fn describe_v4(icmp_type: u8, code: u8) -> String {
+    let with_code = |name: &str, reason: Option<&str>| match reason {
+        Some(reason) => format!("{} ({})", name, reason),
+        None => format!("{} (code {})", name, code),
+    };
+
+    match icmp_type {
+        0 => "echo reply".to_string(),
+        3 => with_code(
+            "destination unreachable",
+            match code {
+                0 => Some("net"),
+                1 => Some("host"),
+                2 => Some("protocol"),
+                3 => Some("port"),
+                4 => Some("fragmentation needed"),
+                5 => Some("source route failed"),
+                6 => Some("net unknown"),
+                7 => Some("host unknown"),
+                9 | 10 | 13 => Some("administratively prohibited"),
+                _ => None,
+            },
+        ),
+        4 => "source quench".to_string(),
+        5 => with_code(
+            "redirect",
+            match code {
+                0 => Some("net"),
+                1 => Some("host"),
+                2 => Some("TOS and net"),
+                3 => Some("TOS and host"),
+                _ => None,
+            },
+        ),
+        8 => "echo request".to_string(),
+        9 => "router advertisement".to_string(),
+        10 => "router solicitation".to_string(),
+        11 => match code {
+            0 => "time exceeded".to_string(),
+            1 => "time exceeded (fragment reassembly)".to_string(),
+            _ => with_code("time exceeded", None),
+        },
+        12 => "parameter problem".to_string(),
+        13 => "timestamp request".to_string(),
+        14 => "timestamp reply".to_string(),
+        _ => format!("type {} code {}", icmp_type, code),
+    }
+}
+
+fn describe_v6(icmp_type: u8, code: u8) -> String {
+    let with_code = |name: &str, reason: Option<&str>| match reason {
+        Some(reason) => format!("{} ({})", name, reason),
+        None => format!("{} (code {})", name, code),
+    };
+
+    match icmp_type {
+        1 => with_code(
+            "destination unreachable",
+            match code {
+                0 => Some("no route"),
+                1 => Some("administratively prohibited"),
+                2 => Some("beyond scope"),
+                3 => Some("address"),
+                4 => Some("port"),
+                5 => Some("source policy"),
+                6 => Some("reject route"),
+                _ => None,
+            },
+        ),
+        2 => "packet too big".to_string(),
+        3 => match code {
+            0 => "time exceeded".to_string(),
+            1 => "time exceeded (fragment reassembly)".to_string(),
+            _ => with_code("time exceeded", None),
+        },
+        4 => "parameter problem".to_string(),
+        128 => "echo request".to_string(),
+        129 => "echo reply".to_string(),
+        130 => "multicast listener query".to_string(),
+        131 | 143 => "multicast listener report".to_string(),
+        132 => "multicast listener done".to_string(),
+        133 => "router solicitation".to_string(),
+        134 => "router advertisement".to_string(),
+        135 => "neighbor solicitation".to_string(),
+        136 => "neighbor advertisement".to_string(),
+        137 => "redirect".to_string(),
+        _ => format!("type {} code {}", icmp_type, code),
+    }
+}
@@ -0,0 +1,122 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::{
+    conf::{IpAddr, MacAddr},
+    oui, roles,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeviceKind {
+    Ethernet,
+    WiFiAp,
+    WiFiClient,
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    Ble,
+}
+
+impl std::fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceKind::Ethernet => write!(f, "Ethernet"),
+            DeviceKind::WiFiAp => write!(f, "Wi-Fi AP"),
+            DeviceKind::WiFiClient => write!(f, "Wi-Fi client"),
+            DeviceKind::Ble => write!(f, "BLE"),
+        }
+    }
+}
+
+// everything we've learnt about one device, from whichever source saw it
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub mac: MacAddr,
+    pub kind: DeviceKind,
+    pub name: Option<String>,
+    pub details: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub signal: Option<i8>,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub frames: u64,
+}
+
+#[derive(Default)]
+pub struct Inventory {
+    devices: HashMap<MacAddr, Device>,
+}
+
+impl Inventory {
+    // record a sighting of a device, returning its entry so the caller can fill in what it knows
+    pub fn observe(&mut self, mac: MacAddr, kind: DeviceKind, timestamp: SystemTime) -> &mut Device {
+        let device = self.devices.entry(mac).or_insert_with(|| Device {
+            mac,
+            kind,
+            name: None,
+            details: None,
+            ip: None,
+            signal: None,
+            first_seen: timestamp,
+            last_seen: timestamp,
+            frames: 0,
+        });
+
+        // an AP also shows up as the transmitter of ordinary frames, don't let that demote it
+        if device.kind != DeviceKind::WiFiAp {
+            device.kind = kind;
+        }
+
+        device.last_seen = timestamp;
+        device.frames += 1;
+
+        device
+    }
+
+    // print the asset view: every device seen, grouped by kind
+    pub fn print_report(&self, start_time: SystemTime, roles: &roles::RoleTracker, vendors: &oui::Vendors) {
+        if self.devices.is_empty() {
+            println!("No devices seen");
+            return;
+        }
+
+        println!("Device inventory ({} devices):", self.devices.len());
+
+        let mut devices: Vec<_> = self.devices.values().collect();
+        devices.sort_by_key(|x| (x.kind, x.mac.to_string()));
+
+        for device in devices {
+            let secs = |time: SystemTime| {
+                time.duration_since(start_time)
+                    .unwrap_or_default()
+                    .as_secs_f32()
+            };
+
+            let mut line = format!("    {:<12} {}", device.kind.to_string(), vendors.describe(&device.mac));
+
+            if let Some(ref name) = device.name {
+                line += &format!(" \"{}\"", name);
+            }
+            if let Some(ref ip) = device.ip {
+                line += &format!(" {}", ip);
+            }
+            if let Some(ref details) = device.details {
+                line += &format!(" [{}]", details);
+            }
+            let roles = roles.roles(Some(device.mac), device.ip.as_ref());
+            if !roles.is_empty() {
+                line += &format!(" ({})", roles::describe(&roles));
+            }
+            if let Some(signal) = device.signal {
+                line += &format!(" {} dBm", signal);
+            }
+
+            line += &format!(
+                " - {} frame{}, seen {:.2}s to {:.2}s",
+                device.frames,
+                if device.frames == 1 { "" } else { "s" },
+                secs(device.first_seen),
+                secs(device.last_seen),
+            );
+
+            println!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::net::IpAddr;
+
+// the parts of an IPv4/IPv6 header the analysis modules care about
+pub struct IpHeader {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub protocol: u8,      // the protocol for IPv4, the next header for IPv6
+    pub header_len: usize, // where the transport header starts
+    pub ttl: u8,           // the TTL for IPv4, the hop limit for IPv6
+}
+
+pub fn parse(packet: &[u8]) -> Option<IpHeader> {
+    match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => {
+            let src: [u8; 4] = packet[12..16].try_into().unwrap();
+            let dst: [u8; 4] = packet[16..20].try_into().unwrap();
+            // IHL is the header length in 32-bit words
+            let header_len = (packet[0] & 0x0f) as usize * 4;
+
+            (header_len >= 20 && header_len <= packet.len()).then_some(IpHeader {
+                src: IpAddr::from(src),
+                dst: IpAddr::from(dst),
+                protocol: packet[9],
+                header_len,
+                ttl: packet[8],
+            })
+        }
+        6 if packet.len() >= 40 => {
+            let src: [u8; 16] = packet[8..24].try_into().unwrap();
+            let dst: [u8; 16] = packet[24..40].try_into().unwrap();
+
+            Some(IpHeader {
+                src: IpAddr::from(src),
+                dst: IpAddr::from(dst),
+                protocol: packet[6],
+                header_len: 40,
+                ttl: packet[7],
+            })
+        }
+        _ => None,
+    }
+}
+
+// source port, destination port and payload of a TCP or UDP packet
+pub fn transport(packet: &[u8]) -> Option<(u16, u16, &[u8])> {
+    let header = parse(packet)?;
+    let segment = &packet[header.header_len..];
+
+    let payload_offset = match header.protocol {
+        // data offset is the TCP header length in 32-bit words
+        6 => (*segment.get(12)? >> 4) as usize * 4,
+        17 => 8,
+        _ => return None,
+    };
+
+    Some((
+        u16::from_be_bytes([*segment.first()?, *segment.get(1)?]),
+        u16::from_be_bytes([*segment.get(2)?, *segment.get(3)?]),
+        segment.get(payload_offset..)?,
+    ))
+}
+
+// the packet's length according to its IP header
+pub fn total_len(packet: &[u8]) -> Option<usize> {
+    match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => Some(u16::from_be_bytes([packet[2], packet[3]]) as usize),
+        6 if packet.len() >= 40 => Some(40 + u16::from_be_bytes([packet[4], packet[5]]) as usize),
+        _ => None,
+    }
+}
+
+// how much of a packet --snaplen/--headers-only keep: at most `snaplen` bytes, and with `headers_only`,
+// just the IP header and the TCP/UDP/ICMP header after it
+pub fn snap_len(packet: &[u8], snaplen: Option<usize>, headers_only: bool) -> usize {
+    let mut len = packet.len().min(snaplen.unwrap_or(usize::MAX));
+
+    if headers_only {
+        if let Some(header) = parse(packet) {
+            let transport = match header.protocol {
+                6 => packet.get(header.header_len + 12).map_or(20, |x| (x >> 4) as usize * 4),
+                17 | 1 | 58 => 8,
+                _ => 0,
+            };
+            len = len.min(header.header_len + transport);
+        }
+    }
+
+    len
+}
+
+// split a request's raw data using the lengths each packet was cut down to, when they were
+pub fn split_captured<'a>(mut raw: &'a [u8], captured: &[u32]) -> Vec<&'a [u8]> {
+    if captured.is_empty() {
+        return split_packets(raw);
+    }
+
+    let mut packets = Vec::new();
+    for len in captured {
+        let (packet, rest) = raw.split_at((*len as usize).min(raw.len()));
+        packets.push(packet);
+        raw = rest;
+    }
+    packets
+}
+
+// a request's raw data is its packets back to back, so use the IP length fields to split them up again
+pub fn split_packets(mut raw: &[u8]) -> Vec<&[u8]> {
+    let mut packets = Vec::new();
+
+    loop {
+        // short frames are padded out to the Ethernet minimum, and the padding ends up in the raw data too
+        while raw.first() == Some(&0) {
+            raw = &raw[1..];
+        }
+
+        let Some(len) = total_len(raw) else {
+            break;
+        };
+
+        // offloaded (coalesced) packets can have a zero or short length, so just send what's left
+        if len == 0 || len > raw.len() {
+            packets.push(raw);
+            break;
+        }
+
+        packets.push(&raw[..len]);
+        raw = &raw[len..];
+    }
+
+    packets
+}
@@ -0,0 +1,75 @@
+// keys for a capture running in a terminal: Space pauses what's printed (packets are still counted, logged and kept
+// track of, so nothing's missing from the reports, and the kernel's buffer isn't left to overflow as with ctrl-z),
+// and q stops the capture as ctrl-c does, summary and all
+//
+// the terminal's taken out of line mode so keys arrive as they're pressed, and put back as it was when the capture's
+// over
+
+use std::io::{IsTerminal, Read};
+
+use crate::{output, theme::Theme};
+
+pub struct Keyboard {
+    saved: libc::termios,
+}
+
+impl Keyboard {
+    // nothing to listen to if stdin isn't a terminal (e.g. from a script), or isn't ours (e.g. run with &, where
+    // reading it would stop us)
+    pub fn start(theme: Theme, quit: fn()) -> Option<Keyboard> {
+        if !std::io::stdin().is_terminal() || unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp() } {
+            return None;
+        }
+
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return None;
+        }
+        // ctrl-c and friends still work as usual
+        let mut keys = saved;
+        keys.c_lflag &= !(libc::ICANON | libc::ECHO);
+        keys.c_cc[libc::VMIN] = 1;
+        keys.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &keys) } != 0 {
+            return None;
+        }
+
+        std::thread::spawn(move || {
+            let mut paused = false;
+            for key in std::io::stdin().lock().bytes() {
+                match key {
+                    Ok(b' ') => {
+                        paused = !paused;
+                        if paused {
+                            outln!("{}", theme.paint(theme.warning, "*** paused, Space to resume, q to quit ***"));
+                            output::pause(true);
+                        } else {
+                            let hidden = output::pause(false);
+                            let message = format!(
+                                "*** resumed, {} line{} not shown ***",
+                                hidden,
+                                if hidden == 1 { "" } else { "s" }
+                            );
+                            outln!("{}", theme.paint(theme.warning, &message));
+                        }
+                    }
+                    Ok(b'q') | Ok(b'Q') => {
+                        output::pause(false);
+                        quit();
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Some(Keyboard { saved })
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::ip;
+
+// probes that never get an answer are forgotten after this long
+const PENDING_TIMEOUT: Duration = Duration::from_secs(30);
+const PRUNE_EVERY: u64 = 4096;
+
+// upper bounds of the --ping-latency histogram's buckets, in milliseconds
+const PING_BUCKETS: [f64; 10] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0];
+const BAR_WIDTH: usize = 40;
+
+// something we've sent and are waiting for the answer to, as (client, server, id)
+// the id is the ports for TCP, and the identifier/sequence number for ICMP echo
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Probe {
+    client: IpAddr,
+    server: IpAddr,
+    id: (u16, u16),
+    icmp: bool,
+}
+
+// pings to one host, for --ping-latency
+#[derive(Default)]
+struct Pings {
+    rtts: Vec<f64>, // in milliseconds
+    lost: u64,      // requests never answered
+}
+
+// round trip times measured from TCP handshakes (SYN -> SYN-ACK) and ICMP echo request -> reply
+pub struct LatencyTracker {
+    bucket: Duration,
+    start_time: SystemTime,
+    pending: HashMap<Probe, SystemTime>,
+    samples: BTreeMap<(IpAddr, u64), Vec<f64>>, // (server, time bucket) -> RTTs in milliseconds
+    pings: BTreeMap<IpAddr, Pings>,
+    observed: u64,
+    last: SystemTime, // the latest packet's timestamp
+}
+
+impl LatencyTracker {
+    pub fn new(bucket: Duration, start_time: SystemTime) -> LatencyTracker {
+        LatencyTracker {
+            bucket,
+            start_time,
+            pending: HashMap::new(),
+            samples: BTreeMap::new(),
+            pings: BTreeMap::new(),
+            observed: 0,
+            last: start_time,
+        }
+    }
+
+    // look at one IP packet, matching answers up with the probes we saw go out; an echo reply's round trip time is
+    // returned, to be shown with it
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) -> Option<Duration> {
+        let header = ip::parse(packet)?;
+
+        let (src, dst, protocol) = (header.src, header.dst, header.protocol);
+        let segment = &packet[header.header_len..];
+        let mut rtt = None;
+
+        match protocol {
+            // TCP: a SYN starts the clock, the matching SYN-ACK stops it
+            6 if segment.len() >= 14 => {
+                let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+                let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+                let (syn, ack) = (segment[13] & 0x02 != 0, segment[13] & 0x10 != 0);
+
+                if syn && !ack {
+                    self.start(Probe { client: src, server: dst, id: (src_port, dst_port), icmp: false }, timestamp);
+                } else if syn && ack {
+                    self.finish(Probe { client: dst, server: src, id: (dst_port, src_port), icmp: false }, timestamp);
+                }
+            }
+            // ICMP/ICMPv6 echo request and reply, matched on identifier and sequence number
+            1 | 58 if segment.len() >= 8 => {
+                let id = (
+                    u16::from_be_bytes([segment[4], segment[5]]),
+                    u16::from_be_bytes([segment[6], segment[7]]),
+                );
+
+                match (protocol, segment[0]) {
+                    (1, 8) | (58, 128) => self.start(Probe { client: src, server: dst, id, icmp: true }, timestamp),
+                    (1, 0) | (58, 129) => rtt = self.finish(Probe { client: dst, server: src, id, icmp: true }, timestamp),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        self.observed += 1;
+        self.last = timestamp;
+        if self.observed.is_multiple_of(PRUNE_EVERY) {
+            let pings = &mut self.pings;
+            self.pending.retain(|probe, sent| {
+                let waiting = timestamp.duration_since(*sent).unwrap_or_default() < PENDING_TIMEOUT;
+                if !waiting && probe.icmp {
+                    pings.entry(probe.server).or_default().lost += 1;
+                }
+                waiting
+            });
+        }
+
+        rtt
+    }
+
+    fn start(&mut self, probe: Probe, timestamp: SystemTime) {
+        // a retransmitted SYN restarts the clock, otherwise we'd count the retransmission timeout as latency
+        self.pending.insert(probe, timestamp);
+    }
+
+    fn finish(&mut self, probe: Probe, timestamp: SystemTime) -> Option<Duration> {
+        let sent = self.pending.remove(&probe)?;
+
+        let rtt = timestamp.duration_since(sent).unwrap_or_default();
+        let bucket = timestamp.duration_since(self.start_time).unwrap_or_default().as_secs() / self.bucket.as_secs().max(1);
+
+        self.samples
+            .entry((probe.server, bucket))
+            .or_default()
+            .push(rtt.as_secs_f64() * 1000.0);
+
+        if probe.icmp {
+            self.pings.entry(probe.server).or_default().rtts.push(rtt.as_secs_f64() * 1000.0);
+        }
+        Some(rtt)
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.values().map(|x| x.len()).sum()
+    }
+
+    // destination -> (bucket, median RTT in ms, number of samples)
+    fn heatmap(&self) -> BTreeMap<IpAddr, Vec<(u64, f64, usize)>> {
+        let mut heatmap: BTreeMap<IpAddr, Vec<(u64, f64, usize)>> = BTreeMap::new();
+
+        for ((server, bucket), rtts) in self.samples.iter() {
+            heatmap
+                .entry(*server)
+                .or_default()
+                .push((*bucket, median(rtts), rtts.len()));
+        }
+
+        heatmap
+    }
+
+    // write the heatmap as JSON if the path ends in .json, otherwise as a CSV grid of destination x bucket
+    pub fn write_heatmap(&self, path: &str) -> std::io::Result<()> {
+        let heatmap = self.heatmap();
+        let bucket_secs = self.bucket.as_secs().max(1);
+
+        let out = if path.ends_with(".json") {
+            let destinations: serde_json::Map<String, serde_json::Value> = heatmap
+                .iter()
+                .map(|(server, cells)| {
+                    (
+                        server.to_string(),
+                        cells
+                            .iter()
+                            .map(|(bucket, median, samples)| {
+                                serde_json::json!({
+                                    "start": bucket * bucket_secs,
+                                    "median_rtt_ms": median,
+                                    "samples": samples,
+                                })
+                            })
+                            .collect(),
+                    )
+                })
+                .collect();
+
+            serde_json::to_string_pretty(&serde_json::json!({
+                "bucket_seconds": bucket_secs,
+                // seconds since the Unix epoch, so bucket starts can be turned back into wall-clock times
+                "start_time": self.start_time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+                "destinations": destinations,
+            }))?
+        } else {
+            let last = heatmap
+                .values()
+                .flat_map(|cells| cells.iter().map(|(bucket, _, _)| *bucket))
+                .max()
+                .unwrap_or(0);
+
+            // one column per bucket, headed by its start in seconds since the capture began
+            let mut out = String::from("destination");
+            for bucket in 0..=last {
+                out += &format!(",{}", bucket * bucket_secs);
+            }
+            out += "\n";
+
+            for (server, cells) in heatmap.iter() {
+                out += &server.to_string();
+                for bucket in 0..=last {
+                    out += ",";
+                    if let Some((_, median, _)) = cells.iter().find(|(x, _, _)| *x == bucket) {
+                        out += &format!("{:.3}", median);
+                    }
+                }
+                out += "\n";
+            }
+
+            out
+        };
+
+        std::fs::write(path, out)
+    }
+    // --ping-latency: round trip times to each host that answered pings, and how they're spread
+    pub fn print_ping_report(&self) {
+        // requests still waiting at the end may only just have been sent, so only long-unanswered ones are lost
+        let mut lost: BTreeMap<IpAddr, u64> = BTreeMap::new();
+        for (probe, sent) in self.pending.iter() {
+            if probe.icmp && self.last.duration_since(*sent).unwrap_or_default() >= PENDING_TIMEOUT {
+                *lost.entry(probe.server).or_default() += 1;
+            }
+        }
+
+        let mut servers: Vec<_> = self.pings.keys().chain(lost.keys()).collect();
+        servers.sort();
+        servers.dedup();
+        if servers.is_empty() {
+            return;
+        }
+
+        println!("Ping round trip times:");
+        for server in servers {
+            let rtts = self.pings.get(server).map(|x| &x.rtts[..]).unwrap_or_default();
+            let lost = self.pings.get(server).map_or(0, |x| x.lost) + lost.get(server).copied().unwrap_or(0);
+            let replies = rtts.len();
+            let mut line = format!("    {}: {} repl{}", server, replies, if replies == 1 { "y" } else { "ies" });
+            if replies > 0 {
+                let min = rtts.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = rtts.iter().copied().fold(0.0, f64::max);
+                line += &format!(", min/median/max {:.2}/{:.2}/{:.2} ms", min, median(rtts), max);
+            }
+            if lost > 0 {
+                line += &format!(", {} lost", lost);
+            }
+            println!("{}", line);
+        }
+
+        let mut counts = [0u64; PING_BUCKETS.len() + 1];
+        for rtt in self.pings.values().flat_map(|x| x.rtts.iter()) {
+            counts[PING_BUCKETS.iter().position(|x| rtt < x).unwrap_or(PING_BUCKETS.len())] += 1;
+        }
+        // only the buckets from the fastest to the slowest reply
+        let (Some(first), Some(last)) = (counts.iter().position(|x| *x > 0), counts.iter().rposition(|x| *x > 0)) else {
+            return;
+        };
+        let most = *counts.iter().max().unwrap();
+
+        println!("    histogram:");
+        for (i, count) in counts.iter().enumerate().take(last + 1).skip(first) {
+            let range = match i {
+                0 => format!("< {} ms", PING_BUCKETS[0]),
+                i if i == PING_BUCKETS.len() => format!(">= {} ms", PING_BUCKETS[i - 1]),
+                i => format!("{}-{} ms", PING_BUCKETS[i - 1], PING_BUCKETS[i]),
+            };
+            let bar = (*count as usize * BAR_WIDTH).div_ceil(most as usize);
+            println!("    {:>11} | {:<width$} {}", range, "#".repeat(bar), count, width = BAR_WIDTH);
+        }
+    }
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::SystemTime;
+
+// number and time formatting conventions, picked from the usual locale environment variables
+#[derive(Clone, Debug)]
+pub struct Locale {
+    pub thousands: char,
+    pub decimal: char,
+    pub twelve_hour: bool,
+}
+
+impl Locale {
+    pub fn from_env() -> Locale {
+        // LC_ALL overrides everything, then the category-specific variables, then LANG
+        let locale = ["LC_ALL", "LC_NUMERIC", "LC_TIME", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|x| !x.is_empty())
+            .unwrap_or_default();
+
+        // e.g. "de_DE.UTF-8" -> ("de", "DE")
+        let locale = locale.split(['.', '@']).next().unwrap_or("");
+        let mut parts = locale.split(['_', '-']);
+        let language = parts.next().unwrap_or("").to_ascii_lowercase();
+        let region = parts.next().unwrap_or("").to_ascii_uppercase();
+
+        let (thousands, decimal) = match language.as_str() {
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => ('.', ','),
+            "fr" | "ru" | "pl" | "cs" | "sv" | "fi" | "nb" | "uk" | "sk" | "hu" => ('\u{202f}', ','),
+            _ if region == "CH" => ('\'', '.'),
+            _ => (',', '.'),
+        };
+
+        let twelve_hour = matches!(region.as_str(), "US" | "CA" | "AU" | "NZ" | "PH" | "IN")
+            && language == "en";
+
+        Locale {
+            thousands,
+            decimal,
+            twelve_hour,
+        }
+    }
+
+    // 1234567 -> "1,234,567"
+    pub fn number(&self, n: u64) -> String {
+        let digits = n.to_string();
+        let mut out = String::new();
+
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                out.push(self.thousands);
+            }
+            out.push(c);
+        }
+
+        out
+    }
+
+    // 1.5 -> "1,5" in locales with a decimal comma
+    pub fn decimal(&self, value: f64, places: usize) -> String {
+        let formatted = format!("{:.*}", places, value);
+
+        let (whole, fraction) = match formatted.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+
+        let negative = whole.starts_with('-');
+        let whole = self.number(whole.trim_start_matches('-').parse().unwrap_or(0));
+
+        let mut out = if negative { format!("-{}", whole) } else { whole };
+        if let Some(fraction) = fraction {
+            out.push(self.decimal);
+            out += fraction;
+        }
+
+        out
+    }
+
+    // wall-clock time of day in the local timezone, e.g. "14:03:07.250" or "2:03:07.250 PM"
+    pub fn time(&self, time: SystemTime) -> String {
+        let since_epoch = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let (hour, minute, second) = local_time_of_day(since_epoch.as_secs() as i64);
+        let millis = since_epoch.subsec_millis();
+
+        if self.twelve_hour {
+            let display_hour = match hour % 12 {
+                0 => 12,
+                x => x,
+            };
+            format!(
+                "{}:{:02}:{:02}{}{:03} {}",
+                display_hour,
+                minute,
+                second,
+                self.decimal,
+                millis,
+                if hour < 12 { "AM" } else { "PM" }
+            )
+        } else {
+            format!("{:02}:{:02}:{:02}{}{:03}", hour, minute, second, self.decimal, millis)
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn local_time_of_day(secs: i64) -> (u32, u32, u32) {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return utc_time_of_day(secs);
+    }
+
+    (tm.tm_hour as u32, tm.tm_min as u32, tm.tm_sec as u32)
+}
+
+#[cfg(not(unix))]
+pub fn local_time_of_day(secs: i64) -> (u32, u32, u32) {
+    utc_time_of_day(secs)
+}
+
+fn utc_time_of_day(secs: i64) -> (u32, u32, u32) {
+    let of_day = secs.rem_euclid(86400) as u32;
+    (of_day / 3600, (of_day / 60) % 60, of_day % 60)
+}
@@ -0,0 +1,94 @@
+// -l logs on disk: JSON by default, so they can be read and picked apart with jq, or CBOR with --log-format binary,
+// which is a fraction of the size and much quicker to write; either can be compressed as well, and loading tells them
+// all apart by their first bytes, so nothing needs telling what a log is
+//
+// logs carry a format version, and older ones are brought up to date as they're loaded (schema/log.schema.json
+// describes the current one, as JSON)
+
+use std::io::Write;
+
+use serde::Deserialize;
+
+use crate::{
+    compress,
+    conf::{LogCompression, LogFormat},
+    error::Error,
+    PacketLog,
+};
+
+// CBOR's "self-described CBOR" tag, which binary logs start with; JSON can't start with it
+const CBOR_MAGIC: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+// bumped whenever a change to the log's layout needs more than a serde default to read logs from before it, with a
+// step in migrate() to match; logs from before there was a version are version 0
+pub const LOG_VERSION: u32 = 1;
+
+// just enough of a log to tell which version it is, when it won't load as this one
+#[derive(Deserialize)]
+struct Version {
+    #[serde(default)]
+    version: u32,
+}
+
+pub fn load(path: &str) -> Result<PacketLog, Error> {
+    let data = compress::read(path)?;
+
+    let mut logs = match decode::<PacketLog>(&data) {
+        Ok(logs) => logs,
+        Err(e) => {
+            // a log from a newer sniff gets a better error than whatever it was that didn't parse
+            return match decode::<Version>(&data) {
+                Ok(Version { version }) if version > LOG_VERSION => Err(Error::NewerLog(version, LOG_VERSION)),
+                _ => Err(e),
+            };
+        }
+    };
+    if logs.version > LOG_VERSION {
+        return Err(Error::NewerLog(logs.version, LOG_VERSION));
+    }
+
+    migrate(&mut logs);
+    Ok(logs)
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(data: &[u8]) -> Result<T, Error> {
+    match data.strip_prefix(&CBOR_MAGIC) {
+        Some(data) => Ok(ciborium::from_reader(data)?),
+        None => Ok(serde_json::from_slice(data)?),
+    }
+}
+
+// bring an older log up to date, a version at a time
+fn migrate(logs: &mut PacketLog) {
+    while logs.version < LOG_VERSION {
+        match logs.version {
+            // from before logs had a version: every field added since has a default, so there's nothing to change
+            0 => {}
+            _ => unreachable!("no migration from log version {}", logs.version),
+        }
+        logs.version += 1;
+    }
+}
+
+pub fn save(path: &str, logs: &PacketLog, format: LogFormat, compression: Option<LogCompression>) -> Result<(), Error> {
+    // a compressed log can't be written over in place, so it's streamed out whole each time
+    match compression {
+        Some(compression) => compress::write(path, compression, |writer| encode(writer, logs, format)),
+        None => {
+            let mut data = Vec::new();
+            encode(&mut data, logs, format)?;
+            Ok(std::fs::write(path, data)?)
+        }
+    }
+}
+
+fn encode(writer: &mut dyn Write, logs: &PacketLog, format: LogFormat) -> Result<(), Error> {
+    match format {
+        LogFormat::Json => serde_json::to_writer(writer, logs)?,
+        LogFormat::Binary => {
+            writer.write_all(&CBOR_MAGIC)?;
+            ciborium::into_writer(logs, writer)?;
+        }
+    }
+    Ok(())
+}
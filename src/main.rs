@@ -1,245 +1,1569 @@
-mod conf;
+// println!, through the --flush-interval buffer; for anything printed per request
+macro_rules! outln {
+    ($($arg:tt)*) => {
+        crate::output::line(format_args!($($arg)*))
+    };
+}
 
-use conf::{IpAddr, IpAddrOrHostname, MacAddr, Protocol};
+mod adaptive;
+#[cfg(target_os = "linux")]
+mod afpacket;
+mod alerts;
+mod aliases;
+mod anomalies;
+mod base64;
+mod baseline;
+#[cfg(all(feature = "ble", target_os = "linux"))]
+mod ble;
+mod capture;
+mod checksum;
+mod ciphers;
+mod compress;
+mod conf;
+mod connections;
+mod credentials;
+mod decap;
+#[cfg(unix)]
+mod control;
+mod convert;
+mod dhcp;
+mod diff;
+mod dns;
+mod drops;
+mod dump;
+mod error;
+mod eyeballs;
+mod features;
+mod filter;
+mod fixture;
+mod flows;
+#[cfg(unix)]
+mod flowstore;
+mod geoip;
+mod graph;
+mod gro;
+mod handshake;
+mod icmp;
+mod inventory;
+#[cfg(unix)]
+mod keyboard;
+mod ip;
+mod latency;
+mod locale;
+mod logfile;
+mod metrics;
+mod mtu;
+mod multicast;
+mod netflow;
+mod oui;
+mod output;
+mod pcap;
+mod pcapout;
+mod plugins;
+mod preroll;
+#[cfg(unix)]
+mod privileges;
+mod process;
+mod proxy;
+mod push;
+mod qos;
+mod quic;
+mod quota;
+mod reassembly;
+mod resolve;
+mod replay;
+mod report;
+mod roles;
+mod rotate;
+mod routing;
+mod sampling;
+mod scans;
+mod services;
+mod session;
+mod sinks;
+mod smtp;
+mod sni;
+mod rules;
+mod stalls;
+mod template;
+mod theme;
+mod ticker;
+mod timing;
+mod trace;
+mod trigger;
+mod ttl;
+mod units;
+mod upload;
+mod whitelist;
+mod wifi;
+mod window;
+
+use conf::{Cast, Direction, IpAddr, IpAddrOrHostname, MacAddr, Protocol};
+use metrics::METRICS;
+use template::Field;
+use crossbeam_queue::ArrayQueue;
 use serde::{Deserialize, Serialize};
 
-use std::{
-    io::{Read, Seek, Write},
-    time::SystemTime,
-};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use pnet::{
+    datalink,
+    packet::{Packet, PrimitiveValues},
+};
+
+// the longest a request collated from both directions can last, before the next packet starts another
+const MAX_MERGED_REQUEST: Duration = Duration::from_secs(1);
+
+// cleared by the ctrl-c handler, so the capture loop can stop and print any reports
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+fn main() {
+    let (mut config, aliases) = conf::get_conf();
+
+    if config.debug {
+        println!("{:#?}", config);
+    }
+
+    match config.command {
+        Some(conf::Command::Report { ref path, top }) => {
+            report::run(path, top, &config);
+            return;
+        }
+        Some(conf::Command::Diff {
+            ref old,
+            ref new,
+            factor,
+            top,
+        }) => {
+            diff::run(old, new, factor, top, &config);
+            return;
+        }
+        Some(conf::Command::Interfaces) => {
+            capture::print_interfaces();
+            return;
+        }
+        Some(conf::Command::Convert {
+            ref input,
+            ref output,
+            format,
+            ref filter,
+        }) => {
+            convert::run(input, output, format, filter.as_ref(), &config);
+            return;
+        }
+        Some(conf::Command::Sessions) => {
+            session::list();
+            return;
+        }
+        None => {}
+    }
+
+    if let Some(interval) = config.flush_interval.filter(|x| !x.is_zero()) {
+        output::buffer(interval);
+    }
+
+    // watching someone else's capture, so there's nothing to capture ourselves
+    if let Some(ref path) = config.attach {
+        #[cfg(not(unix))]
+        panic!("Cannot attach to {}: control sockets are only supported on Unix", path);
+
+        #[cfg(unix)]
+        {
+            control::attach(path, &config);
+            return;
+        }
+    }
+
+    // if we have to load from a file, do that in a seperate loop and then return
+    if config.load_from_file.is_some() {
+        // first, load all the packets from the file
+        let fname = config.clone().load_from_file.unwrap();
+
+        let mut logs = logfile::load(&fname).unwrap_or_else(|e| panic!("Failed to read {}: {}", fname, e));
+
+        let start_time = logs.start_time;
+
+        let window = window::Window::new(config.from.as_ref(), config.to.as_ref(), start_time);
+        logs.packets.retain(|x| window.contains(x.timestamp));
+
+        let geoip = config.geoip.as_ref().map(|paths| geoip::GeoIp::open(paths));
+
+        // logs from before GeoIP annotation (or captured without it) can still be annotated on playback
+        if let Some(ref geoip) = geoip {
+            for packet in logs.packets.iter_mut() {
+                geoip.annotate(packet);
+            }
+        }
+
+        let mut plugins = plugins::Plugins::new(config.plugins.as_deref().unwrap_or_default());
+        for packet in logs.packets.iter_mut() {
+            plugins.annotate(packet);
+        }
+
+        // if real time playback is enabled, then we need to play back the packets in real time, by sleeping for the difference between the current time and the time of the packet
+        let mut state = OutputState::new(&config, aliases.clone());
+
+        // show names as they resolved at capture time, only resolving addresses the log has no answer for
+        state.resolutions = logs.resolutions.clone();
+
+        if config.real_time_playback {
+            // with --from, playback starts from there rather than the start of the capture
+            let mut amount_slept = window
+                .from
+                .and_then(|x| x.duration_since(start_time).ok())
+                .map_or(0.0, |x| x.as_secs_f32());
+            for packet in logs.packets.iter() {
+                let time_diff = packet
+                    .timestamp
+                    .duration_since(start_time)
+                    .unwrap_or_default()
+                    .as_secs_f32();
+                // a request logged out of order is played straight away
+                let time_diff = (time_diff - amount_slept).max(0.0);
+
+                std::thread::sleep(std::time::Duration::from_secs_f32(time_diff));
+
+                print_request(packet.clone(), config.clone(), start_time, &mut state);
+
+                amount_slept += time_diff;
+            }
+        } else {
+            // no point degrading output when we're printing as fast as we can anyway
+            state.governor = None;
+
+            for packet in logs.packets.iter() {
+                print_request(packet.clone(), config.clone(), start_time, &mut state);
+            }
+        }
+
+        state.flush(&config, start_time);
+        state.print_reports();
+        state.finish_uploads(config.log_file.as_ref());
+
+        return;
+    }
+
+    // now the main loop
+    let mut file = config.read.as_ref().map(|path| capture::read_file(path));
+
+    // a file's frames came from wherever it was captured, so none of them are ours unless -n says which interface
+    let interface = match (&file, config.interface.as_deref()) {
+        (Some(_), None) => capture::no_interface(),
+        (_, name) => capture::find_interface(name),
+    };
+
+    // a monitor mode interface (or radiotap file) gives us 802.11 frames whether or not we were asked to expect them
+    config.monitor |= file.as_ref().map_or_else(|| wifi::is_monitor(&interface.name), |(_, monitor)| *monitor);
+
+    let mut channel = file.is_none().then(|| capture::open(&interface));
+
+    // before anything looks at the log or the filters, which a session can set
+    let session = config.session.clone().map(|name| {
+        session::Session::open(&name, &mut config).unwrap_or_else(|e| panic!("Failed to open session {}: {}", name, e))
+    });
+
+    ctrlc::set_handler(|| RUNNING.store(false, Ordering::SeqCst))
+        .expect("Failed to set ctrl-c handler");
+
+    if let (Some(ref path), Some((ref mut tx, _))) = (&config.replay, &mut channel) {
+        replay::replay(path, tx.as_mut(), &config);
+        return;
+    }
+
+    let mut current_requests: Vec<ProcessedPacket> = Vec::new();
+    let mut collating_since = SystemTime::now(); // when the first of current_requests was seen
+
+    let mut roaming = wifi::RoamingTracker::default();
+    let mut management = wifi::ManagementLog::default();
+
+    let inventory = Arc::new(Mutex::new(inventory::Inventory::default()));
+
+    if let Some(dev) = config.ble {
+        #[cfg(all(feature = "ble", target_os = "linux"))]
+        ble::spawn_scanner(dev, inventory.clone()).expect("Failed to start BLE scanning");
+
+        #[cfg(not(all(feature = "ble", target_os = "linux")))]
+        panic!("Cannot scan hci{}: sniff was built without BLE support (enable the `ble` feature, Linux only)", dev);
+    }
+
+    let geoip = config.geoip.as_ref().map(|paths| geoip::GeoIp::open(paths));
+
+    let mut processes = (config.processes || config.process.is_some()).then(process::ProcessTable::new);
+
+    let mut plugins = plugins::Plugins::new(config.plugins.as_deref().unwrap_or_default());
+
+    let mut handshakes = config
+        .handshake_dir
+        .clone()
+        .map(handshake::HandshakeTracker::new);
+
+    let mut fixtures = config
+        .record_fixture
+        .clone()
+        .map(|dir| fixture::FixtureRecorder::new(dir, config.fixture_packets));
+
+    let mut pcap_out = config.pcap.as_ref().map(|path| {
+        let linktype = if config.monitor { pcap::LINKTYPE_IEEE802_11_RADIOTAP } else { pcap::LINKTYPE_ETHERNET };
+        pcapout::PcapOutput::new(path, linktype, config.snaplen, config.ring_files.zip(config.ring_file_size))
+    });
+    let mut pre_roll = match config.pre_roll {
+        Some(length) => {
+            if config.trigger.is_none() && config.alerts.is_none() && config.rate_alerts.is_none() {
+                panic!("--pre-roll needs --trigger, --alerts or --rate-alert to say when to write the pcap");
+            }
+            Some(preroll::PreRoll::new(length))
+        }
+        // nothing before the trigger goes in the pcap either
+        None if config.trigger.is_some() && config.pcap.is_some() => Some(preroll::PreRoll::until_trigger()),
+        None => None,
+    };
+
+    let mut state = OutputState::new(&config, aliases.clone());
+    state.zone = Some(interface.name.clone()).filter(|x| !x.is_empty());
+
+    let mut flow_rates = flows::FlowRates::default();
+
+    let mut quic_flows = quic::QuicFlows::default();
+
+    let mut ticker = config
+        .interval
+        .filter(|x| !x.is_zero())
+        .map(|x| ticker::IntervalTicker::new(x, Instant::now(), state.theme.clone(), state.locale.clone()));
+    let mut drop_warnings = drops::DropWarnings::new(state.theme.clone());
+    let mut graph = config
+        .graph
+        .map(|x| graph::Graph::new(x, state.theme.clone(), state.locale.clone()));
+
+    // anything bigger than this was coalesced by the NIC or kernel
+    let mtu = gro::interface_mtu(&interface.name);
+
+    let mut disk = quota::DiskGuard::new(&config, state.theme.clone());
+
+    // a file's capture is taken to start at its first frame, as with a replayed pcap
+    let start_time = match file {
+        Some((ref mut frames, _)) => frames.peek().map_or_else(SystemTime::now, |(timestamp, _)| *timestamp),
+        None => SystemTime::now(),
+    };
+
+    let mut trigger = config
+        .trigger
+        .clone()
+        .map(|expr| trigger::Trigger::new(expr, config.trigger_stop, state.theme.clone(), start_time));
+    let started = Instant::now();
+
+    #[cfg(unix)]
+    let control = (config.control.is_some() || config.control_edit.is_some()).then(|| {
+        let mut server = control::ControlServer::new(start_time, config.export_dir.as_deref());
+        for (path, editing) in [(&config.control, false), (&config.control_edit, true)] {
+            if let Some(path) = path {
+                server
+                    .listen(path, editing)
+                    .unwrap_or_else(|e| panic!("Failed to listen on the control socket {}: {}", path, e));
+            }
+        }
+        server
+    });
+
+    #[cfg(not(unix))]
+    if let Some(path) = config.control.as_ref().or(config.control_edit.as_ref()) {
+        panic!("Cannot listen on {}: control sockets are only supported on Unix", path);
+    }
+
+    // restores the terminal when it's dropped, at the end of the capture
+    #[cfg(unix)]
+    let _keyboard = keyboard::Keyboard::start(state.theme.clone(), || RUNNING.store(false, Ordering::SeqCst));
+
+    let mut tally = config.summary_json.as_ref().map(|_| metrics::Tally::default());
+
+    let mut pusher = config.push_url.as_ref().map(|url| push::Pusher::new(url, config.push_batch, config.flush_interval));
+
+    let mut latency = (config.latency_heatmap.is_some() || config.ping_latency)
+        .then(|| latency::LatencyTracker::new(Duration::from_secs(config.heatmap_bucket), start_time));
+
+    let mut features = config.export_features.as_ref().map(|_| features::FeatureExporter::default());
+
+    let mut service_latency = config.service_latency.then(timing::ServiceLatency::default);
+
+    let mut stalls = config.tcp_stalls.then(|| stalls::StallTracker::new(state.theme.clone()));
+
+    let mut dual_stack = config.dual_stack.then(eyeballs::DualStackTracker::default);
+
+    let mut fragments = reassembly::Reassembler::default();
+
+    let mut anomalies = config.tcp_anomalies.then(|| anomalies::AnomalyTracker::new(state.theme.clone()));
+    let mut ttl_changes = config.ttl_anomalies.then(|| ttl::TtlTracker::new(state.theme.clone()));
+    let mut ciphers = config.ciphers.then(|| ciphers::CipherTracker::new(state.theme.clone()));
+    let mut mtu_anomalies = config
+        .mtu_anomalies
+        .then(|| mtu::MtuTracker::new(state.theme.clone(), mtu));
+    let mut credentials = config
+        .detect_credentials
+        .then(|| credentials::CredentialDetector::new(state.theme.clone()));
+
+    let mut connections = config.tcp_connections.then(|| connections::ConnectionTracker::new(state.locale.clone()));
+
+    let mut netflow = config
+        .netflow
+        .as_ref()
+        .map(|collector| netflow::NetflowExporter::new(collector, config.netflow_version, config.sample.unwrap_or(1)));
+
+    let mut baseline = config
+        .baseline
+        .as_ref()
+        .map(|path| baseline::BaselineTracker::new(path, config.baseline_window, state.theme.clone()));
+
+    if let Some(ref addr) = config.metrics {
+        metrics::serve(addr, started).expect("Failed to start metrics endpoint");
+    }
+
+    // the capture thread does nothing but copy frames into the ring, so a slow consumer (printing,
+    // DNS lookups, logging) costs us frames we can count, rather than kernel drops we can't
+    let ring = Arc::new(ArrayQueue::new(config.ring_size.max(1)));
+
+    let sample_rate = config.sample.unwrap_or(1);
+    METRICS.sample_rate.store(sample_rate as u64, Ordering::Relaxed);
+    let captures = match file {
+        Some((frames, _)) => vec![capture::spawn_file(frames, sample_rate, ring.clone(), &RUNNING)],
+        None => capture::start(
+            config.backend,
+            &interface,
+            config.capture_threads,
+            sample_rate,
+            channel.expect("Failed to open the interface"),
+            ring.clone(),
+            &RUNNING,
+        ),
+    };
+
+    // nothing from here on needs root, and it's where untrusted packets get picked apart
+    if let Some(ref user) = config.user {
+        #[cfg(unix)]
+        privileges::drop_to(user);
+
+        #[cfg(not(unix))]
+        panic!("Cannot drop privileges to {}: only supported on Unix", user);
+    }
+
+    // write out the packets collated so far as one request, stamped with `timestamp`: that of the packet that ended it,
+    // or of the last one, at the end of the capture
+    macro_rules! write_request {
+        ($timestamp:expr, $ended_by_packet:expr) => {{
+            let timestamp: SystemTime = $timestamp;
+            let mut sample = current_requests.iter().find_map(|x| x.traced.clone());
+            if let Some(ref mut sample) = sample {
+                sample.lap("collate");
+            }
+
+            let mut total_bytes = 0;
+            let mut total_packets = 0;
+            let (mut reply_bytes, mut reply_packets) = (0, 0);
+
+            for req in current_requests.iter() {
+                let before = (total_bytes, total_packets);
+                total_bytes += req.len;
+                total_packets += 1;
+
+                // each of the segments a super-packet was made from had its own headers on the wire
+                if let (Some(super_packet), true) = (req.super_packet, config.split_gro) {
+                    let extra = super_packet.segments.saturating_sub(1) as usize;
+                    total_packets += extra;
+                    total_bytes += extra * super_packet.header_len;
+                }
+                // and each of a reassembled datagram's fragments was a packet of its own
+                if let Some(fragments) = req.fragments {
+                    total_packets += fragments.saturating_sub(1) as usize;
+                }
+
+                // sent back by the first packet's destination
+                let first = &current_requests[0];
+                if req.orig_mac == first.dest_mac && req.orig_ip == first.dest_ip && req.orig_ip != first.orig_ip {
+                    reply_bytes += total_bytes - before.0;
+                    reply_packets += total_packets - before.1;
+                }
+            }
+
+            // each packet sampled stands for the ones that weren't
+            let scale = sample_rate as usize;
+            let (total_bytes, total_packets) = (total_bytes * scale, total_packets * scale);
+            let (reply_bytes, reply_packets) = (reply_bytes * scale, reply_packets * scale);
+
+            let mut stats = RequestStats {
+                protocol: current_requests[0].protocol,
+                // the addresses of the request itself, not of the packet that ended it
+                orig_ip: current_requests[0].orig_ip.clone(),
+                orig_mac: current_requests[0].orig_mac,
+                dest_ip: current_requests[0].dest_ip.clone(),
+                dest_mac: current_requests[0].dest_mac,
+                bytes: total_bytes as u64,
+                packets: total_packets as u64,
+                reply_bytes: reply_bytes as u64,
+                reply_packets: reply_packets as u64,
+                timestamp,
+                raw: current_requests
+                    .iter()
+                    .map(|x| x.payload.clone())
+                    .flatten()
+                    .collect(),
+                captured: if current_requests.iter().any(|x| x.payload.len() < x.len) {
+                    current_requests.iter().map(|x| x.payload.len() as u32).collect()
+                } else {
+                    Vec::new()
+                },
+                orig_geo: None,
+                dest_geo: None,
+                rate: None,
+                icmp: None,
+                vlan: current_requests[0].vlan,
+                direction: None,
+                routing: Vec::new(),
+                dhcp: None,
+                quic: None,
+                tunnel: current_requests.iter().find_map(|x| x.tunnel.clone()),
+                encapsulation: current_requests.iter().find_map(|x| x.encapsulation.clone()),
+                orig_process: None,
+                dest_process: None,
+                rtt: current_requests.iter().find_map(|x| x.rtt).map(|x| x.as_secs_f64() * 1000.0),
+                bad_checksum: current_requests.iter().find_map(|x| x.bad_checksum),
+                ttl: match current_requests[0].protocol {
+                    Protocol::Ether(_) | Protocol::Unknown => None,
+                    _ => ip::parse(&current_requests[0].payload).map(|x| x.ttl),
+                },
+                columns: BTreeMap::new(),
+            };
+
+            stats.direction = direction(&stats, &interface);
+
+            if stats.protocol == Protocol::Icmp {
+                let first = &current_requests[0].payload;
+                stats.icmp = icmp::parse(first, first.first().map(|x| x >> 4) == Some(6));
+            }
+
+            if matches!(stats.protocol, Protocol::Tcp | Protocol::Udp | Protocol::Ip(_)) {
+                stats.routing = routing::decode(&stats);
+            }
+
+            if stats.protocol == Protocol::Udp {
+                stats.dhcp = dhcp::decode(&stats);
+                stats.quic = quic_flows.annotate(&stats);
+            }
+
+            let flow = flows::FlowKey {
+                orig_ip: stats.orig_ip.clone(),
+                dest_ip: stats.dest_ip.clone(),
+                protocol: stats.protocol,
+            };
+            if let Some(ref mut ticker) = ticker {
+                ticker.observe(&flow);
+            }
+            stats.rate = flow_rates.update(flow, stats.bytes, stats.timestamp);
+
+            if let Some(ref geoip) = geoip {
+                geoip.annotate(&mut stats);
+            }
+
+            if let Some(ref mut processes) = processes {
+                processes.annotate(&mut stats);
+            }
+
+            plugins.annotate(&mut stats);
+
+            // after GeoIP, for the countries
+            if let Some(ref mut baseline) = baseline {
+                baseline.observe(&stats);
+            }
+
+            #[cfg(unix)]
+            let paused = match control {
+                Some(ref control) => {
+                    control.publish(&stats);
+                    // filter changes from control clients take effect from this request on
+                    for edit in control.take_edits() {
+                        edit.apply(&mut config, &mut state.rules);
+                    }
+                    control.paused()
+                }
+                None => false,
+            };
+            #[cfg(not(unix))]
+            let paused = false;
+
+            // until the trigger fires, requests are only counted
+            let triggered = trigger.as_mut().is_none_or(|x| x.observe(&stats));
+
+            if let (Some(ref mut pre_roll), Some(ref mut pcap_out), true) = (&mut pre_roll, &mut pcap_out, trigger.is_some())
+            {
+                match triggered {
+                    true => pre_roll.trigger(pcap_out),
+                    false => pre_roll.passed($ended_by_packet),
+                }
+            }
+
+            if let (Some(ref mut pusher), true) = (&mut pusher, triggered) {
+                pusher.push(&stats);
+            }
+
+            if let Some(ref mut tally) = tally {
+                tally.record(&stats);
+            }
+
+            if let (Some(ref mut trace), Some(ref mut sample)) = (&mut state.trace, &mut sample) {
+                sample.lap("enrich");
+                trace.filtered = None;
+            }
+
+            if !paused && triggered {
+                print_request(stats, config.clone(), start_time, &mut state);
+                METRICS.events.fetch_add(1, Ordering::Relaxed);
+            }
+
+            if let (Some(alerted), Some(ref mut pre_roll), Some(ref mut pcap_out)) =
+                (state.alerted.take(), &mut pre_roll, &mut pcap_out)
+            {
+                pre_roll.alert(pcap_out, alerted);
+            }
+
+            if let (Some(ref mut trace), Some(mut sample)) = (&mut state.trace, sample) {
+                // split at the point it got through the filters, if it did
+                let shown = match trace.filtered.take() {
+                    Some(filtered) => {
+                        sample.lap_at("filter", filtered);
+                        sample.lap("output");
+                        true
+                    }
+                    None => {
+                        sample.lap("filter");
+                        false
+                    }
+                };
+                trace.finish(sample, shown);
+            }
+
+            current_requests.clear();
+        }};
+    }
+
+    // keep processing until ctrl-c (or the end of --read's file), then drain whatever is left in the ring
+    let mut last_seen = start_time; // the last packet's timestamp, for a request still going at the end
+    while RUNNING.load(Ordering::SeqCst) || !ring.is_empty() {
+        METRICS
+            .queue_depth
+            .store(current_requests.len() as u64, Ordering::Relaxed);
+        METRICS.ring_depth.store(ring.len() as u64, Ordering::Relaxed);
+
+        if let Some(ref mut governor) = state.governor {
+            governor.tick();
+        }
+
+        if let Some(ref mut ticker) = ticker {
+            ticker.tick();
+        }
+        drop_warnings.tick();
+        if let Some(ref mut graph) = graph {
+            graph.tick();
+        }
+
+        // stop straight away, rather than writing out what's left in the ring
+        if let Some(ref mut disk) = disk {
+            if disk.tick() {
+                RUNNING.store(false, Ordering::SeqCst);
+                break;
+            }
+        }
+
+        // --trigger-stop goes by the capture's own clock: live, that's now, even with nothing arriving, and with --read,
+        // each frame's timestamp as it's read (below)
+        if config.read.is_none() && trigger.as_ref().is_some_and(|x| x.tick(SystemTime::now())) {
+            RUNNING.store(false, Ordering::SeqCst);
+            break;
+        }
+
+        let (timestamp, received, data) = match ring.pop() {
+            Some(frame) => frame,
+            None => {
+                // woken by the capture thread as soon as there's something to do
+                std::thread::park_timeout(Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        if trigger.as_ref().is_some_and(|x| x.tick(timestamp)) {
+            RUNNING.store(false, Ordering::SeqCst);
+            break;
+        }
+        let packet = &data[..];
+
+        if let Some(ref mut pcap_out) = pcap_out {
+            match pre_roll {
+                Some(ref mut pre_roll) => pre_roll.write(pcap_out, timestamp, packet),
+                None => pcap_out.write(timestamp, packet),
+            }
+        }
+
+        // in monitor mode, frames are 802.11 rather than ethernet, so they go through the roaming tracker instead
+        if config.monitor {
+            let radiotap = match wifi::parse_radiotap(packet) {
+                Some(radiotap) => radiotap,
+                None => continue,
+            };
+
+            let frame = match wifi::parse_dot11(&packet[radiotap.len..]) {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            if config.inventory {
+                if let (Some(ssid), Some(bssid)) = (frame.ssid(), frame.addr3) {
+                    let mut inventory = inventory.lock().unwrap();
+                    let device = inventory.observe(bssid, inventory::DeviceKind::WiFiAp, timestamp);
+                    device.name = Some(ssid);
+                    device.signal = radiotap.signal;
+                }
+            }
+
+            if let Some(ref mut handshakes) = handshakes {
+                if let Some(path) = handshakes.observe(&frame, packet, timestamp) {
+                    outln!(
+                        "Wi-Fi at {:.2}s: saved WPA handshake to {}",
+                        timestamp
+                            .duration_since(start_time)
+                            .unwrap_or_default()
+                            .as_secs_f32(),
+                        path,
+                    );
+                }
+            }
+
+            let event = roaming.observe(&frame, radiotap.signal, timestamp);
+            if event.is_none() {
+                if let Some(description) = management.describe(&frame, radiotap.signal) {
+                    outln!(
+                        "Wi-Fi at {:.2}s: {}",
+                        timestamp.duration_since(start_time).unwrap_or_default().as_secs_f32(),
+                        description,
+                    );
+                }
+            }
+
+            if let Some(event) = event {
+                if config.inventory {
+                    let mut inventory = inventory.lock().unwrap();
+                    let device = inventory.observe(event.client, inventory::DeviceKind::WiFiClient, event.timestamp);
+                    device.details = Some(event.describe());
+                    if event.signal.is_some() {
+                        device.signal = event.signal;
+                    }
+                }
+
+                outln!(
+                    "Wi-Fi at {:.2}s: {} {}",
+                    event
+                        .timestamp
+                        .duration_since(start_time)
+                        .unwrap_or_default()
+                        .as_secs_f32(),
+                    event.client,
+                    event.describe(),
+                );
+            }
+
+            continue;
+        }
+
+        if let Some(ref mut fixtures) = fixtures {
+            fixtures.record(timestamp, packet);
+        }
+
+        // first, check if the origin ip and the dest ip are the same as the last packet
+
+        // if so, append to the current_requests and continue
+        // if not, process the current_requests and then clear it
+
+        let mut sample = None;
+        if state.trace.as_mut().is_some_and(|trace| trace.sample()) {
+            let mut started = trace::Sample::new(received);
+            started.lap("queue");
+            sample = Some(started);
+        }
+
+        // a frame we can't make sense of is still shown and logged, rather than lost (or fatal)
+        let mut packet = match parse_frame(packet) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                METRICS.malformed.fetch_add(1, Ordering::Relaxed);
+                if config.debug {
+                    eprintln!("{}", e);
+                }
+                raw_frame(packet)
+            }
+        };
+
+        match packet.dest_mac.cast() {
+            Cast::Unicast => METRICS.unicast.fetch_add(1, Ordering::Relaxed),
+            Cast::Broadcast => METRICS.broadcast.fetch_add(1, Ordering::Relaxed),
+            Cast::Multicast => METRICS.multicast.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let is_ip = !matches!(packet.protocol, Protocol::Ether(_) | Protocol::Unknown);
+
+        if let Some(ref mut graph) = graph {
+            graph.observe(packet.protocol, &packet.orig_ip, &packet.dest_ip, packet.len as u64);
+        }
+
+        // sizes as they were on the wire, so before fragments are put back together
+        if let (Some(ref mut mtu_anomalies), true) = (&mut mtu_anomalies, is_ip) {
+            mtu_anomalies.observe(&packet.payload, timestamp);
+        }
+
+        // fragments go no further until their datagram is complete, which then goes on as one packet
+        if is_ip {
+            match fragments.push(&packet.payload, timestamp) {
+                reassembly::Fragment::Whole => {}
+                reassembly::Fragment::Held => continue,
+                reassembly::Fragment::Complete(datagram, count) => {
+                    packet.len = datagram.len();
+                    packet.payload = datagram;
+                    packet.fragments = Some(count);
+                }
+            }
+        }
+
+        // from here on, a tunnelled packet is the one inside
+        let inner = (config.decapsulate && is_ip).then(|| decap::decapsulate(&packet.payload)).flatten();
+        if let Some(inner) = inner {
+            if let Some(header) = ip::parse(&packet.payload[inner.offset..]) {
+                packet.orig_ip = header.src.into();
+                packet.dest_ip = header.dst.into();
+                packet.protocol = Protocol::from(header.protocol);
+                if let Some((src, dst)) = inner.macs {
+                    packet.orig_mac = MacAddr::from(src);
+                    packet.dest_mac = MacAddr::from(dst);
+                }
+                packet.payload.drain(..inner.offset);
+                packet.len -= inner.offset;
+                packet.encapsulation = Some(inner.encapsulation);
+            }
+        }
+
+        // a reassembled datagram was never bigger than the MTU on the wire
+        packet.super_packet = if is_ip && packet.fragments.is_none() { gro::detect(&packet.payload, mtu) } else { None };
+
+        // a super-packet's checksums are left for the NIC to work out as it splits it up
+        if config.verify_checksums && is_ip && packet.super_packet.is_none() {
+            packet.bad_checksum = checksum::verify(&packet.payload);
+            if packet.bad_checksum.is_some() {
+                METRICS.bad_checksums.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        if let Some(super_packet) = packet.super_packet {
+            METRICS.oversized.fetch_add(1, Ordering::Relaxed);
+            METRICS.oversized_segments.fetch_add(super_packet.segments, Ordering::Relaxed);
+
+            if config.split_gro {
+                let extra = super_packet.segments.saturating_sub(1);
+                METRICS.packets.fetch_add(extra, Ordering::Relaxed);
+                METRICS.bytes.fetch_add(extra * super_packet.header_len as u64, Ordering::Relaxed);
+            }
+        }
+
+        if config.inventory {
+            let mut inventory = inventory.lock().unwrap();
+            let device = inventory.observe(packet.orig_mac, inventory::DeviceKind::Ethernet, timestamp);
+            if is_ip {
+                device.ip = Some(packet.orig_ip.clone());
+            }
+        }
+
+        if let (Some(ref mut latency), true) = (&mut latency, is_ip) {
+            let rtt = latency.observe(&packet.payload, timestamp);
+            if config.ping_latency {
+                packet.rtt = rtt;
+            }
+        }
+
+        if let (Some(ref mut features), true) = (&mut features, is_ip) {
+            features.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut service_latency), true) = (&mut service_latency, is_ip) {
+            service_latency.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut stalls), true) = (&mut stalls, is_ip) {
+            stalls.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut anomalies), true) = (&mut anomalies, is_ip) {
+            anomalies.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut scans), true) = (&mut state.scans, is_ip) {
+            scans.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut ttl_changes), true) = (&mut ttl_changes, is_ip) {
+            ttl_changes.observe(&packet.payload, timestamp);
+        }
+        if let (Some(ref mut credentials), true) = (&mut credentials, is_ip) {
+            credentials.observe(&packet.payload, timestamp);
+        }
+        if let (Some(ref mut ciphers), true) = (&mut ciphers, is_ip) {
+            ciphers.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut connections), true) = (&mut connections, is_ip) {
+            connections.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut netflow), true) = (&mut netflow, is_ip) {
+            netflow.observe(&packet.payload, timestamp);
+        }
+
+        // kept for the control socket's export-flow
+        #[cfg(unix)]
+        if let (Some(ref control), true) = (&control, is_ip) {
+            control.observe(&packet.payload, timestamp);
+        }
+
+        if let (Some(ref mut dual_stack), true) = (&mut dual_stack, is_ip) {
+            dual_stack.observe(&packet.payload, packet.orig_mac, timestamp);
+        }
+
+        if config.unwrap_proxies && is_ip {
+            packet.tunnel = state.proxies.observe(&packet.payload, timestamp);
+        }
+
+        // everything above sees the whole packet; only what's kept for output is cut down
+        packet.payload.truncate(ip::snap_len(&packet.payload, config.snaplen, config.headers_only));
+
+        if let Some(mut sample) = sample {
+            sample.lap("dissect");
+            packet.traced = Some(sample);
+        }
+
+        last_seen = timestamp;
+
+        // a request ends at the first packet that isn't part of it
+        let ends_request = current_requests.last().is_some_and(|last_packet| {
+            // the same two hosts, one way or (with --merge-bidirectional) either
+            let same_way = last_packet.orig_mac == packet.orig_mac && last_packet.dest_mac == packet.dest_mac;
+            let reply = last_packet.orig_mac == packet.dest_mac && last_packet.dest_mac == packet.orig_mac;
+
+            // both ways, two hosts can keep a request going for ever, so it's cut into pieces as it goes
+            let fresh = !config.merge_bidirectional
+                || timestamp.duration_since(collating_since).unwrap_or_default() < MAX_MERGED_REQUEST;
+
+            !((same_way || (reply && config.merge_bidirectional))
+                && fresh
+                && last_packet.vlan == packet.vlan
+                && config.protocol != Some(Protocol::Icmp)
+                && !config.dont_collate)
+        });
+        if ends_request {
+            write_request!(timestamp, true);
+        }
+
+        if current_requests.is_empty() {
+            collating_since = timestamp;
+        }
+        current_requests.push(packet);
+    }
+
+    // and the request still being collated when the capture ended, or --trigger-stop stopped it
+    if !current_requests.is_empty() {
+        write_request!(last_seen, false);
+    }
+
+    for capture in captures {
+        capture.join().unwrap();
+    }
+    state.flush(&config, start_time);
+
+    if let Some(pusher) = pusher {
+        pusher.finish();
+    }
+    if let Some(netflow) = netflow {
+        netflow.finish(last_seen);
+    }
+
+    metrics::print_summary(started, state.locale.as_ref());
+    state.print_reports();
+    if let Some(stalls) = stalls {
+        stalls.print_report();
+    }
+    if let Some(anomalies) = anomalies {
+        anomalies.print_report();
+    }
+    if let Some(ttl_changes) = ttl_changes {
+        ttl_changes.print_report();
+    }
+    if let Some(mtu_anomalies) = mtu_anomalies {
+        mtu_anomalies.print_report();
+    }
+    if let Some(credentials) = credentials {
+        credentials.print_report();
+    }
+    if let Some(ciphers) = ciphers {
+        ciphers.print_report();
+    }
+    if let Some(connections) = connections {
+        connections.print_report();
+    }
+    if let Some(dual_stack) = dual_stack {
+        dual_stack.print_report();
+    }
+    if let Some(baseline) = baseline {
+        baseline.print_report();
+    }
+    if let (Some(ref latency), true) = (&latency, config.ping_latency) {
+        latency.print_ping_report();
+    }
+    if let Some(service_latency) = service_latency {
+        service_latency.print_report(&state.services, !config.no_service_names, usize::MAX);
+    }
+    state.finish_uploads(config.log_file.as_ref());
+
+    if let (Some(tally), Some(path)) = (tally, config.summary_json.as_ref()) {
+        match metrics::write_summary_json(path, started, start_time, &tally, &state.rules) {
+            Ok(()) => println!("Saved summary to {}", path),
+            Err(e) => eprintln!("Failed to write summary to {}: {}", path, e),
+        }
+    }
+
+    if let Some(session) = session {
+        session.finish();
+    }
+
+    if config.monitor {
+        roaming.print_report(start_time);
+    }
+
+    if config.inventory {
+        inventory.lock().unwrap().print_report(start_time, &state.roles, &state.vendors);
+    }
+
+    if let (Some(latency), Some(path)) = (latency, config.latency_heatmap.as_ref()) {
+        match latency.write_heatmap(path) {
+            Ok(()) => println!("Saved latency heatmap ({} RTT samples) to {}", latency.sample_count(), path),
+            Err(e) => eprintln!("Failed to write latency heatmap to {}: {}", path, e),
+        }
+    }
+
+    if let (Some(features), Some(path)) = (features, config.export_features.as_ref()) {
+        let flows = features.flow_count();
+        match features.write(path) {
+            Ok(()) => println!("Saved features for {} flows to {}", flows, path),
+            Err(e) => eprintln!("Failed to write flow features to {}: {}", path, e),
+        }
+    }
+
+    if let Some(fixtures) = fixtures {
+        fixtures.finish();
+    }
+
+    if let Some(pcap_out) = pcap_out {
+        pcap_out.finish();
+    }
+
+    if let Some(handshakes) = handshakes {
+        println!("Saved {} WPA handshake{}", handshakes.saved, if handshakes.saved == 1 { "" } else { "s" });
+    }
+}
+
+#[derive(Clone)]
+struct ProcessedPacket {
+    orig_ip: IpAddr,
+    orig_mac: MacAddr,
+    dest_ip: IpAddr,
+    dest_mac: MacAddr,
+    protocol: Protocol,
+    payload: Vec<u8>,
+    len: usize, // of the payload as it was on the wire, before any --snaplen
+    traced: Option<trace::Sample>, // for --trace-pipeline
+    vlan: Option<u16>,
+    super_packet: Option<gro::SuperPacket>,
+    tunnel: Option<proxy::Tunnel>, // where it's really going, if it's through a proxy (with --unwrap-proxies)
+    encapsulation: Option<decap::Encapsulation>, // the tunnel it came out of, with --decapsulate
+    rtt: Option<Duration>,         // since the request, if it's an echo reply (with --ping-latency)
+    fragments: Option<u64>,        // how many IPv4 fragments it was put back together from, if it was fragmented
+    bad_checksum: Option<checksum::Layer>, // with --verify-checksums
+}
+
+// 802.1Q/802.1ad tags sit between the MAC addresses and the real ethertype, and may be stacked (QinQ)
+// returns the real ethertype, the payload after the tags, and the outermost VLAN ID
+fn strip_vlan_tags(
+    mut ethertype: pnet::packet::ethernet::EtherType,
+    mut payload: &[u8],
+) -> (pnet::packet::ethernet::EtherType, &[u8], Option<u16>) {
+    let mut vlan = None;
+
+    while matches!(ethertype.0, 0x8100 | 0x88a8 | 0x9100) && payload.len() >= 4 {
+        // tag control information: 3 bits priority, 1 bit drop eligible, 12 bits VLAN ID
+        if vlan.is_none() {
+            vlan = Some(u16::from_be_bytes([payload[0], payload[1]]) & 0x0fff);
+        }
+        ethertype = pnet::packet::ethernet::EtherType(u16::from_be_bytes([payload[2], payload[3]]));
+        payload = &payload[4..];
+    }
+
+    (ethertype, payload, vlan)
+}
+
+fn parse_frame(frame: &[u8]) -> Result<ProcessedPacket, error::Error> {
+    let ether = pnet::packet::ethernet::EthernetPacket::new(frame)
+        .ok_or(error::Error::Malformed("shorter than an ethernet header"))?;
+
+    let (ethertype, payload, vlan) = strip_vlan_tags(ether.get_ethertype(), ether.payload());
+
+    // non-IP frames (ARP, LLDP etc.) have no addresses of their own, so they're shown by MAC instead
+    let (orig_ip, dest_ip, protocol) = if ethertype == pnet::packet::ethernet::EtherTypes::Ipv4 {
+        let ip = pnet::packet::ipv4::Ipv4Packet::new(payload).ok_or(error::Error::Malformed("truncated IPv4 header"))?;
+        (
+            IpAddr::V4(ip.get_source().to_primitive_values().into()),
+            IpAddr::V4(ip.get_destination().to_primitive_values().into()),
+            Protocol::from(ip.get_next_level_protocol().0),
+        )
+    } else if ethertype == pnet::packet::ethernet::EtherTypes::Ipv6 {
+        let ip = pnet::packet::ipv6::Ipv6Packet::new(payload).ok_or(error::Error::Malformed("truncated IPv6 header"))?;
+        (
+            IpAddr::V6(ip.get_source().to_primitive_values().into()),
+            IpAddr::V6(ip.get_destination().to_primitive_values().into()),
+            // the next header field, rather than a protocol field
+            Protocol::from(ip.get_next_header().0),
+        )
+    } else {
+        (IpAddr::V4((0, 0, 0, 0).into()), IpAddr::V4((0, 0, 0, 0).into()), Protocol::Ether(ethertype.0))
+    };
+
+    Ok(ProcessedPacket {
+        orig_ip,
+        orig_mac: MacAddr::from(ether.get_source().to_primitive_values()),
+        dest_ip,
+        dest_mac: MacAddr::from(ether.get_destination().to_primitive_values()),
+        protocol,
+        payload: payload.to_vec(),
+        len: payload.len(),
+        vlan,
+        super_packet: None,
+        traced: None,
+        tunnel: None,
+        encapsulation: None,
+        rtt: None,
+        fragments: None,
+        bad_checksum: None,
+    })
+}
+
+// an unparsed record of a frame we couldn't decode: whatever MAC addresses it has, and its bytes
+fn raw_frame(frame: &[u8]) -> ProcessedPacket {
+    let mac = |offset: usize| {
+        frame
+            .get(offset..offset + 6)
+            .map(|x| MacAddr::from((x[0], x[1], x[2], x[3], x[4], x[5])))
+            .unwrap_or(MacAddr::from((0, 0, 0, 0, 0, 0)))
+    };
+
+    ProcessedPacket {
+        orig_ip: IpAddr::V4((0, 0, 0, 0).into()),
+        orig_mac: mac(6),
+        dest_ip: IpAddr::V4((0, 0, 0, 0).into()),
+        dest_mac: mac(0),
+        protocol: Protocol::Unknown,
+        payload: frame.get(14..).unwrap_or_default().to_vec(),
+        len: frame.len().saturating_sub(14),
+        vlan: None,
+        super_packet: None,
+        traced: None,
+        tunnel: None,
+        encapsulation: None,
+        rtt: None,
+        fragments: None,
+        bad_checksum: None,
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct RequestStats {
+    protocol: Protocol,
+    orig_ip: IpAddr,
+    orig_mac: MacAddr,
+    dest_ip: IpAddr,
+    dest_mac: MacAddr,
 
-use pnet::{
-    datalink,
-    packet::{Packet, PrimitiveValues},
-};
+    bytes: u64,
+    packets: u64,
 
-fn main() {
-    let config = conf::get_conf();
+    // how much of that came back the other way, with --merge-bidirectional
+    #[serde(default)]
+    reply_bytes: u64,
+    #[serde(default)]
+    reply_packets: u64,
 
-    if config.debug {
-        println!("{:#?}", config);
-    }
+    timestamp: SystemTime,
 
-    // if we have to load from a file, do that in a seperate loop and then return
-    if config.load_from_file.is_some() {
-        // first, load all the packets from the file
-        let fname = config.clone().load_from_file.unwrap();
+    #[serde(with = "serde_bytes")]
+    raw: Vec<u8>, // the raw packet data, but with the headers stripped, leaving just the payload
 
-        let mut file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(fname)
-            .unwrap();
+    #[serde(default)]
+    orig_geo: Option<geoip::GeoInfo>,
+    #[serde(default)]
+    dest_geo: Option<geoip::GeoInfo>,
 
-        let mut data = String::new();
-        file.read_to_string(&mut data).unwrap();
+    #[serde(default)]
+    rate: Option<f64>, // the flow's throughput in bytes/s when this request was seen
 
-        let logs: PacketLog = serde_json::from_str(&data).unwrap();
+    #[serde(default)]
+    icmp: Option<icmp::IcmpInfo>,
 
-        let start_time = logs.start_time;
+    #[serde(default)]
+    vlan: Option<u16>, // the outermost 802.1Q/802.1ad tag, if the frame had one
 
-        // if real time playback is enabled, then we need to play back the packets in real time, by sleeping for the difference between the current time and the time of the packet
-        if config.real_time_playback {
-            let mut amount_slept = 0.0;
-            for packet in logs.packets.iter() {
-                let time_diff = packet
-                    .timestamp
-                    .duration_since(start_time)
-                    .unwrap()
-                    .as_secs_f32();
-                let time_diff = time_diff - amount_slept;
+    #[serde(default)]
+    direction: Option<Direction>, // none if neither end is the capture interface, e.g. in promiscuous mode
 
-                std::thread::sleep(std::time::Duration::from_secs_f32(time_diff));
+    #[serde(default)]
+    routing: Vec<routing::Message>, // OSPF/BGP messages carried by the request
 
-                print_request(packet.clone(), config.clone(), start_time);
+    #[serde(default)]
+    dhcp: Option<dhcp::DhcpInfo>,
 
-                amount_slept += time_diff;
-            }
-        } else {
-            for packet in logs.packets.iter() {
-                print_request(packet.clone(), config.clone(), start_time);
-            }
-        }
+    #[serde(default)]
+    quic: Option<quic::QuicInfo>, // the QUIC connection a UDP request is part of, if we saw it start
 
-        return;
-    }
+    #[serde(default)]
+    tunnel: Option<proxy::Tunnel>, // the proxied connection's real destination, with --unwrap-proxies
 
-    // now the main loop
-    // Get the list of available network interfaces
-    let interfaces = datalink::interfaces();
-
-    // Select the network interface to capture packets from
-    let interface = interfaces
-        .into_iter()
-        .find(|iface| iface.is_up() && !iface.is_loopback())
-        .expect("Failed to find a suitable network interface");
-
-    // Create a channel to receive packets on the selected interface
-    let (_tx, mut rx) = match datalink::channel(&interface, Default::default()) {
-        Ok(datalink::Channel::Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => panic!("Unsupported channel type"),
-        Err(e) => panic!("Failed to create channel: {}", e),
-    };
+    #[serde(default)]
+    encapsulation: Option<decap::Encapsulation>, // the tunnel its packets came through, with --decapsulate
 
-    let mut current_requests: Vec<ProcessedPacket> = Vec::new();
+    #[serde(default)]
+    orig_process: Option<process::Process>, // the local process at each end, with --processes
+    #[serde(default)]
+    dest_process: Option<process::Process>,
 
-    let start_time = SystemTime::now();
+    #[serde(default)]
+    rtt: Option<f64>, // an echo reply's round trip time in milliseconds, with --ping-latency
 
-    loop {
-        match rx.next() {
-            Ok(packet) => {
+    #[serde(default)]
+    bad_checksum: Option<checksum::Layer>, // the first checksum that failed in any of its packets, with --verify-checksums
 
-                // first, check if the origin ip and the dest ip are the same as the last packet
+    #[serde(default)]
+    ttl: Option<u8>, // its first packet's TTL, or hop limit for IPv6
 
-                // if so, append to the current_requests and continue
-                // if not, process the current_requests and then clear it
+    #[serde(default)]
+    captured: Vec<u32>, // how much of each packet is in raw, if --snaplen/--headers-only cut any short
 
-                let ether = pnet::packet::ethernet::EthernetPacket::new(&packet).unwrap();
+    #[serde(default)]
+    columns: BTreeMap<String, String>, // extra columns from plugins
+}
 
-                let packet = ProcessedPacket {
-                    orig_mac: MacAddr::from(ether.get_source().to_primitive_values()),
-                    dest_mac: MacAddr::from(ether.get_destination().to_primitive_values()),
-                    protocol: Protocol::from(ether.payload()[9]),
-                    payload: ether.payload().to_vec(),
-                };
+// inbound if the destination is one of the interface's own addresses, outbound if the origin is, local if both are
+fn direction(stats: &RequestStats, interface: &datalink::NetworkInterface) -> Option<Direction> {
+    let ours = |ip: &IpAddr, mac: &MacAddr| {
+        interface.ips.iter().any(|x| x.ip() == ip.to_std())
+            || interface.mac.map(|x| MacAddr::from(x.octets())) == Some(*mac)
+    };
 
-                let orig_ip = if ether.get_ethertype() == pnet::packet::ethernet::EtherTypes::Ipv4 {
-                    let ip = pnet::packet::ipv4::Ipv4Packet::new(ether.payload()).unwrap();
-                    IpAddr::V4(ip.get_source().to_primitive_values().into())
-                } else {
-                    let ip = pnet::packet::ipv6::Ipv6Packet::new(ether.payload());
+    match (ours(&stats.orig_ip, &stats.orig_mac), ours(&stats.dest_ip, &stats.dest_mac)) {
+        (true, true) => Some(Direction::Local),
+        (true, false) => Some(Direction::Outbound),
+        (false, true) => Some(Direction::Inbound),
+        (false, false) => None,
+    }
+}
 
-                    if ip.is_none() {
-                        continue;
-                    }
-                    IpAddr::V6(ip.unwrap().get_source().to_primitive_values().into())
-                };
+// one of this machine's own addresses, for --ignore-self
+#[derive(Clone, Copy, PartialEq)]
+enum OwnAddress {
+    Ip(std::net::IpAddr),
+    Mac(MacAddr),
+}
 
-                let dest_ip = if ether.get_ethertype() == pnet::packet::ethernet::EtherTypes::Ipv4 {
-                    let ip = pnet::packet::ipv4::Ipv4Packet::new(ether.payload()).unwrap();
-                    IpAddr::V4(ip.get_destination().to_primitive_values().into())
-                } else {
-                    let ip = pnet::packet::ipv6::Ipv6Packet::new(ether.payload()).unwrap();
-                    IpAddr::V6(ip.get_destination().to_primitive_values().into())
-                };
+impl std::fmt::Display for OwnAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OwnAddress::Ip(ip) => write!(f, "{}", ip),
+            OwnAddress::Mac(mac) => write!(f, "{}", mac),
+        }
+    }
+}
 
-                if current_requests.len() == 0 {
-                    current_requests.push(packet);
-                    continue;
-                } else {
-                    let last_packet = current_requests.last().unwrap();
-
-                    if last_packet.orig_mac == packet.orig_mac
-                        && last_packet.dest_mac == packet.dest_mac && config.protocol != Some(Protocol::Icmp) && !config.dont_collate
-                    {
-                        current_requests.push(packet);
-                        continue;
-                    } else {
-                        // process the current_requests
-                        let mut total_bytes = 0;
-                        let mut total_packets = 0;
-
-                        for req in current_requests.iter() {
-                            total_bytes += req.payload.len();
-                            total_packets += 1;
-                        }
-
-                        let stats = RequestStats {
-                            protocol: current_requests[0].protocol,
-                            orig_ip: orig_ip,
-                            orig_mac: current_requests[0].orig_mac,
-                            dest_ip: dest_ip,
-                            dest_mac: current_requests[0].dest_mac,
-                            bytes: total_bytes as u64,
-                            packets: total_packets as u64,
-                            timestamp: SystemTime::now(),
-                            raw: current_requests
-                                .iter()
-                                .map(|x| x.payload.clone())
-                                .flatten()
-                                .collect(),
-                        };
-
-                        print_request(stats, config.clone(), start_time);
-
-                        current_requests.clear();
-                        current_requests.push(packet);
-                    }
-                }
+// every address of every interface on this machine, not just the one we're capturing on: a router's traffic out of its
+// other ports is still its own
+fn own_addresses() -> Vec<OwnAddress> {
+    let mut own = Vec::new();
+    for interface in datalink::interfaces() {
+        let ips = interface.ips.iter().map(|x| OwnAddress::Ip(x.ip()));
+        // loopback's all-zero MAC isn't anyone's
+        let mac = interface.mac.filter(|x| x.octets() != [0; 6]).map(|x| OwnAddress::Mac(MacAddr::from(x.octets())));
+
+        for address in ips.chain(mac) {
+            if !own.contains(&address) {
+                own.push(address);
             }
-            Err(e) => panic!("Failed to receive packet: {}", e),
         }
     }
+    own
 }
 
-#[derive(Clone)]
-struct ProcessedPacket {
-    orig_mac: MacAddr,
-    dest_mac: MacAddr,
-    protocol: Protocol,
-    payload: Vec<u8>,
+// state that lives across calls to print_request
+struct OutputState {
+    governor: Option<adaptive::RateGovernor>,
+    theme: theme::Theme,
+    locale: Option<locale::Locale>,
+    rules: rules::RuleStats,
+    // reverse DNS answers, saved alongside the log so playback shows names as they resolved at capture time
+    resolutions: HashMap<std::net::IpAddr, String>,
+    alerts: Option<alerts::AlertEngine>,
+    alerted: Option<SystemTime>, // when the last request an alert fired on was seen, for --pre-roll
+    whitelist: Option<whitelist::Whitelist>,
+    scans: Option<scans::ScanDetector>,
+    rotator: Option<rotate::LogRotator>,
+    uploader: Option<upload::Uploader>,
+    routing: routing::RoutingMonitor,
+    dhcp: dhcp::DhcpMonitor,
+    roles: roles::RoleTracker,
+    services: services::Services,
+    vendors: oui::Vendors,
+    proxies: proxy::ProxyTracker,
+    server_names: sni::ServerNames,
+    casts: multicast::CastTotals,
+    own_addresses: Vec<OwnAddress>, // with --ignore-self
+    host_addresses: resolve::HostAddresses,
+    aliases: Option<aliases::Aliases>,
+    zone: Option<String>,           // the interface link-local addresses are shown on, e.g. fe80::1%eth0
+    trace: Option<trace::PipelineTrace>,
+    // requests waiting to go into the log, which is rewritten every --flush-interval rather than every request
+    pending_log: Vec<RequestStats>,
+    log_flushed: Instant,
+    sinks: sinks::Sinks,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct RequestStats {
-    protocol: Protocol,
-    orig_ip: IpAddr,
-    orig_mac: MacAddr,
-    dest_ip: IpAddr,
-    dest_mac: MacAddr,
+impl OutputState {
+    fn new(config: &conf::Config, aliases: Option<aliases::Aliases>) -> OutputState {
+        let theme = theme::Theme::new(config.theme, config.color, config.highlight_color);
+        let locale = config.human_readable.then(locale::Locale::from_env);
 
-    bytes: u64,
-    packets: u64,
+        let mut rules = rules::RuleStats::new(config);
 
-    timestamp: SystemTime,
+        let mut alerts = config.alerts.as_ref().map(|path| alerts::AlertEngine::load(path, theme.clone()));
+        if let Some(ref rate_alerts) = config.rate_alerts {
+            let alerts = alerts.get_or_insert_with(|| alerts::AlertEngine::new(theme.clone()));
+            for alert in rate_alerts {
+                alerts.add_rate_alert(alert);
+            }
+        }
+        if let (Some(ref mut alerts), Some(ref aliases)) = (&mut alerts, &aliases) {
+            alerts.expand_aliases(aliases);
+        }
+        if let Some(ref alerts) = alerts {
+            rules.register("alert", &alerts.names());
+        }
 
-    raw: Vec<u8>, // the raw packet data, but with the headers stripped, leaving just the payload
+        let whitelist = config.whitelist.as_ref().map(|path| {
+            let mut whitelist = whitelist::Whitelist::load(path, theme.clone());
+            if let Some(ref aliases) = aliases {
+                whitelist.expand_aliases(aliases);
+            }
+            rules.register("whitelist", &whitelist.names());
+            whitelist
+        });
+
+        let scans = config.detect_scans.then(|| scans::ScanDetector::new(theme.clone()));
+
+        let own_addresses = if config.ignore_self { own_addresses() } else { Vec::new() };
+        rules.register("ignore self", &own_addresses);
+
+        let uploader = config.upload.as_deref().map(upload::Uploader::new);
+
+        let routing = routing::RoutingMonitor::new(config.bgp_peers.clone(), config.fhrp_routers.clone(), theme.clone());
+
+        OutputState {
+            governor: config.degrade_rate.map(|threshold| {
+                adaptive::RateGovernor::new(threshold, config.raw_bytes, theme.clone(), locale.clone())
+            }),
+            theme,
+            locale,
+            rules,
+            resolutions: HashMap::new(),
+            alerts,
+            alerted: None,
+            whitelist,
+            scans,
+            rotator: rotate::LogRotator::new(config, uploader.as_ref().map(|x| x.queue())),
+            uploader,
+            routing,
+            dhcp: dhcp::DhcpMonitor::default(),
+            roles: roles::RoleTracker::default(),
+            services: services::Services::load(),
+            vendors: oui::Vendors::load(config.oui_file.as_deref()),
+            proxies: proxy::ProxyTracker::default(),
+            server_names: sni::ServerNames::default(),
+            casts: multicast::CastTotals::default(),
+            own_addresses,
+            host_addresses: resolve::HostAddresses::new(config, aliases.as_ref()),
+            aliases,
+            zone: config.interface.clone(),
+            trace: config.trace_pipeline.map(trace::PipelineTrace::new),
+            pending_log: Vec::new(),
+            log_flushed: Instant::now(),
+            sinks: sinks::Sinks::new(config),
+        }
+    }
+
+    // everything we've been keeping count of, for the end of the run
+    fn print_reports(&self) {
+        self.rules.print_report(self.locale.as_ref());
+        self.routing.print_report();
+        self.dhcp.print_report();
+        self.roles.print_report(&self.theme);
+        self.proxies.print_report(self.locale.as_ref());
+        self.casts.print_report(self.locale.as_ref());
+        if let Some(ref whitelist) = self.whitelist {
+            whitelist.print_report();
+        }
+        if let Some(ref scans) = self.scans {
+            scans.print_report();
+        }
+        if let Some(ref trace) = self.trace {
+            trace.print_report();
+        }
+    }
+
+    fn write_log(&mut self, config: &conf::Config, start_time: SystemTime) {
+        self.log_flushed = Instant::now();
+        let (Some(path), false) = (config.log_file.as_ref(), self.pending_log.is_empty()) else {
+            return;
+        };
+
+        if let Some(ref mut rotator) = self.rotator {
+            rotator.maybe_rotate(path);
+        }
+        let pending = std::mem::take(&mut self.pending_log);
+        if let Err(e) = log_to_file(pending, path.clone(), start_time, &self.resolutions, config) {
+            eprintln!("Failed to write to the log: {}", e);
+        }
+    }
+
+    // everything still buffered, before the reports
+    fn flush(&mut self, config: &conf::Config, start_time: SystemTime) {
+        self.write_log(config, start_time);
+        self.sinks.finish();
+        output::flush();
+    }
+
+    // upload the log we've been writing to, and wait for anything still on its way (e.g. from rotation)
+    fn finish_uploads(&mut self, log_file: Option<&String>) {
+        let Some(uploader) = self.uploader.take() else {
+            return;
+        };
+
+        // it holds a sender too, which has to go before the uploads can be waited on
+        self.rotator = None;
+
+        if let Some(path) = log_file.filter(|x| std::path::Path::new(x).exists()) {
+            uploader.upload(std::path::Path::new(path));
+        }
+        uploader.finish();
+    }
+
+    // resolve once and remember the answer, unless a recorded answer was loaded from the log
+    fn resolve(&mut self, ip: std::net::IpAddr) -> String {
+        self.resolutions
+            .entry(ip)
+            .or_insert_with(|| dns_lookup::lookup_addr(&ip).unwrap_or(ip.to_string()))
+            .clone()
+    }
 }
 
-fn print_request(stats: RequestStats, config: conf::Config, start_time: SystemTime) {
+fn print_request(stats: RequestStats, config: conf::Config, start_time: SystemTime, state: &mut OutputState) {
+    state.rules.evaluate();
+
+    // alerts see everything, whatever the display filters say
+    if let Some(ref mut alerts) = state.alerts {
+        if alerts.evaluate(&stats, &mut state.rules) {
+            state.alerted = Some(stats.timestamp);
+        }
+    }
+    state.routing.observe(&stats);
+    state.dhcp.observe(&stats);
+    state.roles.observe(&stats);
+    state.proxies.record(&stats);
+    state.server_names.observe(&stats);
+    state.casts.observe(&stats);
+
+    if let Some(protocol) = config.protocol {
+        if !state.rules.check("protocol", &[protocol], |x| *x == stats.protocol) {
+            return;
+        }
+    }
 
-    if config.protocol.is_some() {
-        let protocol = config.clone().protocol.unwrap();
-        if stats.protocol != protocol {
+    if let Some(ref ip_proto) = config.ip_proto {
+        let number = ip::parse(&stats.raw).map(|x| x.protocol);
+        if !state.rules.check("ip proto", ip_proto, |x| number == Some(*x)) {
             return;
         }
     }
 
     // start time is when the program started (ie. when the user pressed enter)
 
+    // frames without IP addresses (non-IP, or unparsed) are shown by MAC
+    let by_mac = matches!(stats.protocol, Protocol::Ether(_))
+        || (stats.protocol == Protocol::Unknown && stats.orig_ip.to_std().is_unspecified());
+
+    // a name from --aliases goes before anything else, and is shown as it is
+    let alias = |ip, mac| state.aliases.as_ref().and_then(|x| x.name(ip, mac, by_mac)).map(str::to_string);
+    let (orig_alias, dest_alias) = (alias(&stats.orig_ip, stats.orig_mac), alias(&stats.dest_ip, stats.dest_mac));
+    let (orig_aliased, dest_aliased) = (orig_alias.is_some(), dest_alias.is_some());
+
     let mut orig_ip: String;
 
-    if config.hostnames {
-        orig_ip = {
-            let ip: std::net::IpAddr = match stats.clone().orig_ip {
-                IpAddr::V4(ip) => std::net::IpAddr::from(ip.octets),
-                IpAddr::V6(ip) => std::net::IpAddr::from(ip.octets),
-            };
-            dns_lookup::lookup_addr(&ip).unwrap_or(ip.to_string())
-        };
+    if let Some(alias) = orig_alias {
+        orig_ip = alias;
+    } else if by_mac {
+        orig_ip = stats.orig_mac.to_string();
+    } else if config.hostnames {
+        orig_ip = state.resolve(stats.orig_ip.to_std());
     } else {
         orig_ip = stats.orig_ip.to_string();
     }
 
     let mut dest_ip: String;
 
-    if config.hostnames {
-        dest_ip = {
-            let ip: std::net::IpAddr = match stats.clone().dest_ip {
-                IpAddr::V4(ip) => std::net::IpAddr::from(ip.octets),
-                IpAddr::V6(ip) => std::net::IpAddr::from(ip.octets),
-            };
-
-            dns_lookup::lookup_addr(&ip).unwrap_or(ip.to_string())
-        };
+    if let Some(alias) = dest_alias {
+        dest_ip = alias;
+    } else if by_mac {
+        dest_ip = stats.dest_mac.to_string();
+    } else if config.hostnames {
+        dest_ip = state.resolve(stats.dest_ip.to_std());
     } else {
         dest_ip = stats.dest_ip.to_string();
     }
 
 
+    // hostname filters go by the full names, before they're cut down
+    let mut names = Vec::new();
+    for (name, ip) in [(&orig_ip, &stats.orig_ip), (&dest_ip, &stats.dest_ip)] {
+        if *name != ip.to_string() {
+            names.push(name.clone());
+        }
+        if let Some(server_name) = state.server_names.get(ip.to_std()) {
+            names.push(server_name.to_string());
+        }
+    }
+    if let Some(ref tunnel) = stats.tunnel {
+        names.push(tunnel.host.clone());
+    }
+
     // now, remove all but the TLD from the hostname (the last two parts of the domain)
-    if stats.orig_ip.to_string() != orig_ip {
+    if !orig_aliased && stats.orig_ip.to_string() != orig_ip {
         let orig_ip_splitted = orig_ip.split('.').collect::<Vec<&str>>();
         orig_ip = match orig_ip_splitted.len() {
             0 => stats.orig_ip.to_string(), // IPv6
@@ -249,7 +1573,7 @@ fn print_request(stats: RequestStats, config: conf::Config, start_time: SystemTi
         };    
     }
     
-    if stats.dest_ip.to_string() != dest_ip {
+    if !dest_aliased && stats.dest_ip.to_string() != dest_ip {
         let dest_ip_splitted = dest_ip.split('.').collect::<Vec<&str>>();
         dest_ip = match dest_ip_splitted.len() {
             0 => stats.dest_ip.to_string(), // IPv6
@@ -261,135 +1585,461 @@ fn print_request(stats: RequestStats, config: conf::Config, start_time: SystemTi
 
 
 
-    if config.clone().log_file.is_some() {
-        log_to_file(stats.clone(), config.clone().log_file.unwrap(), start_time);
+    if config.log_file.is_some() {
+        state.pending_log.push(stats.clone());
+        if config.flush_interval.is_none_or(|x| state.log_flushed.elapsed() >= x) {
+            state.write_log(&config, start_time);
+        }
     }
+    state.sinks.write(&stats, &orig_ip, &dest_ip);
 
 
     // first, check if we should be printing this request: check exclude/include filters
-    if config.exclude_ips.is_some() {
-        let exclude_ips = config.clone().exclude_ips.unwrap();
-        if exclude_ips.contains(&IpAddrOrHostname::Hostname(orig_ip.clone())) || exclude_ips.contains(&IpAddrOrHostname::Hostname(dest_ip.clone())) {
+    // every matching rule is counted, so the exit report shows which rules are actually doing anything
+    let ip_matches = |rule: &IpAddrOrHostname| match rule {
+        IpAddrOrHostname::Hostname(hostname) => {
+            *hostname == orig_ip
+                || *hostname == dest_ip
+                || names.iter().any(|x| rule.matches_name(x))
+                || (!by_mac
+                    && !state.aliases.as_ref().is_some_and(|x| x.contains(hostname))
+                    && (state.host_addresses.matches(hostname, stats.orig_ip.to_std())
+                        || state.host_addresses.matches(hostname, stats.dest_ip.to_std())))
+        }
+        IpAddrOrHostname::HostnameSuffix(_) => names.iter().any(|x| rule.matches_name(x)),
+        IpAddrOrHostname::Ip(ip) => *ip == stats.orig_ip || *ip == stats.dest_ip,
+    };
+    let mac_matches = |rule: &MacAddr| *rule == stats.orig_mac || *rule == stats.dest_mac;
+
+    if let Some(ref exclude_ips) = config.exclude_ips {
+        if state.rules.check("exclude ip", exclude_ips, ip_matches) {
+            return;
+        }
+    }
+    if let Some(ref exclude_macs) = config.exclude_macs {
+        if state.rules.check("exclude mac", exclude_macs, mac_matches) {
+            return;
+        }
+    }
+
+    let own = |address: &OwnAddress| match address {
+        OwnAddress::Ip(ip) => *ip == stats.orig_ip.to_std() || *ip == stats.dest_ip.to_std(),
+        OwnAddress::Mac(mac) => *mac == stats.orig_mac || *mac == stats.dest_mac,
+    };
+    if state.rules.check("ignore self", &state.own_addresses, own) {
+        return;
+    }
+
+    let cast = stats.dest_mac.cast();
+    if state.rules.check("exclude cast", config.excluded_casts(), |x| *x == cast) {
+        return;
+    }
+    if config.multicast_only && !state.rules.check("cast", &[Cast::Multicast], |x| *x == cast) {
+        return;
+    }
+
+    if let Some(ref filter_ips) = config.filter_ips {
+        if !state.rules.check("filter ip", filter_ips, ip_matches) {
+            return;
+        }
+    }
+
+    if let Some(ref filter_macs) = config.filter_macs {
+        if !state.rules.check("filter mac", filter_macs, mac_matches) {
             return;
         }
+    }
 
-        if exclude_ips.contains(&IpAddrOrHostname::Ip(stats.clone().orig_ip)) || exclude_ips.contains(&IpAddrOrHostname::Ip(stats.dest_ip)) {
+    if let Some(ref filter_vendors) = config.filter_vendors {
+        let macs = [stats.orig_mac, stats.dest_mac];
+        if !state.rules.check("filter vendor", filter_vendors, |x| state.vendors.matches(x, &macs)) {
             return;
         }
     }
-    if config.exclude_macs.is_some() {
-        let exclude_macs = config.clone().exclude_macs.unwrap();
-        if exclude_macs.contains(&stats.orig_mac) || exclude_macs.contains(&stats.dest_mac) {
+
+    if let Some(ref filter_processes) = config.process {
+        let owners = [&stats.orig_process, &stats.dest_process];
+        let matches = |name: &String| owners.iter().any(|x| x.as_ref().is_some_and(|x| x.matches(name)));
+        if !state.rules.check("process", filter_processes, matches) {
             return;
         }
     }
 
-    if config.filter_ips.is_some() {
-        let include_ips = config.clone().filter_ips.unwrap();
-        if !include_ips.contains(&IpAddrOrHostname::Hostname(orig_ip.clone())) && !include_ips.contains(&IpAddrOrHostname::Hostname(dest_ip.clone())) {
+    if let Some(direction) = config.direction {
+        if !state.rules.check("direction", &[direction], |x| stats.direction == Some(*x)) {
             return;
         }
     }
 
-    if config.filter_macs.is_some() {
-        let include_macs = config.clone().filter_macs.unwrap();
-        if !include_macs.contains(&stats.orig_mac) && !include_macs.contains(&stats.dest_mac) {
+    if let Some(vlan) = config.vlan {
+        if !state.rules.check("vlan", &[vlan], |x| stats.vlan == Some(*x)) {
             return;
         }
     }
 
+    if let Some(ref dscp) = config.dscp {
+        let marked = qos::Qos::parse(&stats.raw).map(|x| x.dscp);
+        if !state.rules.check("dscp", dscp, |x| marked == Some(*x)) {
+            return;
+        }
+    }
 
-    if config.highlight_macs.is_some() {
-        let highlight_macs = config.clone().highlight_macs.unwrap();
-        if highlight_macs.contains(&stats.orig_mac) || highlight_macs.contains(&stats.dest_mac) {
-            print!("\x1b[1;31m"); // red
-        } else {
-            print!("\x1b[0m");
+    if let Some(ref filter) = config.filter {
+        if !state.rules.check("filter", &[filter], |x| x.matches(&stats, &orig_ip, &dest_ip)) {
+            return;
         }
-    } else if config.highlight_ips.is_some() {
-        let highlight_ips = config.clone().highlight_ips.unwrap();
-        if highlight_ips.contains(&IpAddrOrHostname::Hostname(orig_ip.clone())) || highlight_ips.contains(&IpAddrOrHostname::Hostname(dest_ip.clone())) {
-            print!("\x1b[1;31m"); // red
-        } else {
-            print!("\x1b[0m");
+    }
+
+    // with --output, the console only shows what one of its own outputs lets through
+    if !config.outputs.is_empty() {
+        let consoles = config.console_outputs();
+        if !state.rules.check("output", &consoles, |x| x.matches(&stats, &orig_ip, &dest_ip)) {
+            return;
+        }
+    }
+
+    // the patterns are looked for in everything that's shown by --dump-payload, headers and all
+    if let Some(ref patterns) = config.match_payload {
+        if !state.rules.check("match payload", patterns, |x| x.is_match(&stats.raw)) {
+            return;
+        }
+    }
+
+    let ports = match stats.protocol {
+        Protocol::Tcp | Protocol::Udp => ip::transport(&stats.raw).map(|(src, dst, _)| (src, dst)),
+        _ => None,
+    };
+
+    if let Some(ref services) = config.services {
+        let matches = |service: &String| {
+            ports.is_some_and(|(src, dst)| {
+                state.services.matches(service, src, stats.protocol) || state.services.matches(service, dst, stats.protocol)
+            })
+        };
+        if !state.rules.check("service", services, matches) {
+            return;
+        }
+    }
+
+    // in a quiet network, only what's outside the expected flows is worth showing
+    if let Some(ref mut whitelist) = state.whitelist {
+        let matched = whitelist.check(&stats, &orig_ip, &dest_ip);
+        for name in matched.iter() {
+            state.rules.hit("whitelist", name);
+        }
+        if !matched.is_empty() {
+            return;
+        }
+    }
+
+    // a scan is one line, rather than a request per port it tries
+    if let Some(ref mut scans) = state.scans {
+        if scans.hide(stats.orig_ip.to_std()) {
+            return;
+        }
+    }
+
+    // under heavy load, this request may only be counted towards a per-second total
+    if let Some(ref mut governor) = state.governor {
+        if !governor.record(&stats) {
+            return;
+        }
+    }
+
+    if let Some(ref mut trace) = state.trace {
+        trace.filtered = Some(Instant::now());
+    }
+
+    let highlighted = if let Some(ref highlight_macs) = config.highlight_macs {
+        state.rules.check("highlight mac", highlight_macs, mac_matches)
+    } else if let Some(ref highlight_ips) = config.highlight_ips {
+        state.rules.check("highlight ip", highlight_ips, ip_matches)
+    } else {
+        false
+    };
+
+
+
+    // the graph takes the requests' place
+    if config.graph.is_some() {
+        return;
+    }
+
+    // a link-local address only means something on its link, so it gets the interface as its zone
+    if let (Some(ref zone), false) = (&state.zone, by_mac) {
+        for (shown, ip) in [(&mut orig_ip, &stats.orig_ip), (&mut dest_ip, &stats.dest_ip)] {
+            let link_local = matches!(ip.to_std(), std::net::IpAddr::V6(x) if x.is_unicast_link_local());
+            if link_local && *shown == ip.to_string() {
+                *shown = format!("{}%{}", shown, zone);
+            }
+        }
+    }
+
+    // --format has the addresses on their own, and everything else as fields of its own
+    let (orig_name, dest_name) = (orig_ip.clone(), dest_ip.clone());
+
+    // the service is usually on the lower port, and an ephemeral port's "name" would just be noise
+    if let Some((src, dst)) = ports {
+        let with_port = |address: &str, port: u16| {
+            let service = match state.services.name(port, stats.protocol) {
+                Some(name) if !config.no_service_names && port == src.min(dst) => format!(" ({})", name),
+                _ => String::new(),
+            };
+            // IPv6 addresses need brackets to tell the port apart
+            if address.contains(':') {
+                format!("[{}]:{}{}", address, port, service)
+            } else {
+                format!("{}:{}{}", address, port, service)
+            }
+        };
+        orig_ip = with_port(&orig_ip, src);
+        dest_ip = with_port(&dest_ip, dst);
+    }
+
+    // a proxy's end is shown as where the connection through it is really going
+    if let Some(ref tunnel) = stats.tunnel {
+        if tunnel.proxy.ip() == stats.dest_ip.to_std() {
+            dest_ip = format!("{} via {}", tunnel, dest_ip);
+        } else if tunnel.proxy.ip() == stats.orig_ip.to_std() {
+            orig_ip = format!("{} via {}", tunnel, orig_ip);
         }
+    }
+
+    // and local ones with the process they belong to
+    if let Some(ref process) = stats.orig_process {
+        orig_ip = format!("{} [{}]", orig_ip, process);
+    }
+    if let Some(ref process) = stats.dest_process {
+        dest_ip = format!("{} [{}]", dest_ip, process);
+    }
+
+    // annotate public addresses with their country/ASN, if we know it
+    if let Some(ref geo) = stats.orig_geo {
+        orig_ip = format!("{} [{}]", orig_ip, geo);
+    }
+    if let Some(ref geo) = stats.dest_geo {
+        dest_ip = format!("{} [{}]", dest_ip, geo);
+    }
+
+    // ICMP messages are labelled with what they actually are, e.g. "ICMP echo request", and routing protocols and
+    // DHCP by name
+    let protocol = match (stats.icmp, stats.routing.first(), &stats.dhcp) {
+        (Some(icmp), _, _) => match stats.rtt {
+            Some(rtt) => format!("{} ({:.2} ms)", icmp, rtt),
+            None => icmp.to_string(),
+        },
+        (None, Some(message), _) => message.protocol().to_string(),
+        (None, None, Some(dhcp)) => format!("DHCP {}", dhcp.kind()),
+        (None, None, None) => match stats.quic {
+            Some(ref quic) => quic.to_string(),
+            None => stats.protocol.to_string(),
+        },
+    };
+
+    // a highlighted line is styled as a whole, otherwise just the protocol gets its color
+    let protocol = if highlighted {
+        protocol
     } else {
-        print!("\x1b[0m");
+        state.theme.paint(state.theme.protocol(stats.protocol), &protocol)
+    };
+
+    // where the request was seen: its VLAN, and which way it went relative to us
+    let mut context = match stats.vlan {
+        Some(vlan) => format!(" on VLAN {}", vlan),
+        None => String::new(),
+    };
+    if let Some(direction) = stats.direction {
+        context += &format!(" [{}]", direction);
+    }
+    // and who it was for at the link layer, if not just the one host
+    let cast = stats.dest_mac.cast();
+    match multicast::group(&stats) {
+        Some(group) => context += &format!(" [{} {}]", cast, group),
+        None if cast != Cast::Unicast => context += &format!(" [{}]", cast),
+        None => {}
+    }
+    if let Some(layer) = stats.bad_checksum {
+        context += &format!(" {}", state.theme.paint(state.theme.warning, &format!("[bad {} checksum]", layer)));
     }
 
+    // plugin columns go at the end, as name=value
+    let columns = if stats.columns.is_empty() {
+        String::new()
+    } else {
+        let columns: Vec<String> = stats.columns.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        format!(" {{{}}}", columns.join(", "))
+    };
 
+    // with its replies merged in, the request went both ways, and how much went each way is worth knowing
+    let merged = stats.reply_packets > 0;
+    let arrow = if merged { "<->" } else { "->" };
+    let tx_rx = |stats: &RequestStats| {
+        let bytes = |n: u64| units::format_bytes(n, None, config.raw_bytes, state.locale.as_ref());
+        (bytes(stats.bytes.saturating_sub(stats.reply_bytes)), bytes(stats.reply_bytes))
+    };
+    let traffic = {
+        let total = units::format_bytes(stats.bytes, stats.rate, config.raw_bytes, state.locale.as_ref());
+        match merged {
+            true => {
+                let (tx, rx) = tx_rx(&stats);
+                format!("{} (tx {}, rx {})", total, tx, rx)
+            }
+            false => total,
+        }
+    };
 
     // print the stats
-    if config.verbose {
-        println!(
-            "{} (IPv{}) ({} packet{}) at {:.2}s: {} ({}) -> {} ({}) {}B",
-            stats.protocol,
+    let line = if let Some(ref template) = config.format {
+        template.render(|field| match field {
+            Field::Time => Some(format_time(stats.timestamp, start_time, state.locale.as_ref())),
+            Field::Proto => Some(protocol.clone()),
+            Field::Src => Some(orig_name.clone()),
+            Field::Sport => ports.map(|(src, _)| src.to_string()),
+            Field::Dst => Some(dest_name.clone()),
+            Field::Dport => ports.map(|(_, dst)| dst.to_string()),
+            Field::Bytes => Some(units::format_bytes(stats.bytes, None, config.raw_bytes, state.locale.as_ref())),
+            Field::Rate => stats.rate.map(|x| units::human_rate(x, state.locale.as_ref())),
+            Field::Packets => Some(stats.packets.to_string()),
+            Field::Tx => Some(tx_rx(&stats).0),
+            Field::Rx => Some(tx_rx(&stats).1),
+            Field::Ipv => Some(if stats.orig_ip.to_std().is_ipv6() { "6" } else { "4" }.to_string()),
+            Field::Direction => stats.direction.map(|x| x.to_string()),
+            Field::Vlan => stats.vlan.map(|x| x.to_string()),
+            Field::Ttl => stats.ttl.map(|x| x.to_string()),
+            Field::Dscp => qos::Qos::parse(&stats.raw).map(|x| x.dscp.to_string()),
+            Field::Ecn => qos::Qos::parse(&stats.raw).map(|x| x.ecn_name().to_string()),
+            Field::FlowLabel => qos::Qos::parse(&stats.raw).and_then(|x| x.flow_label).map(|x| format!("{:#x}", x)),
+            Field::SrcMac => Some(stats.orig_mac.to_string()),
+            Field::DstMac => Some(stats.dest_mac.to_string()),
+            Field::SrcVendor => state.vendors.name(&stats.orig_mac).map(|x| x.to_string()),
+            Field::DstVendor => state.vendors.name(&stats.dest_mac).map(|x| x.to_string()),
+            Field::SrcGeo => stats.orig_geo.as_ref().map(|x| x.to_string()),
+            Field::DstGeo => stats.dest_geo.as_ref().map(|x| x.to_string()),
+            Field::Service => ports.and_then(|(src, dst)| state.services.name(src.min(dst), stats.protocol)).map(|x| x.to_string()),
+            Field::Columns => (!stats.columns.is_empty()).then(|| {
+                stats.columns.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join(", ")
+            }),
+        })
+    } else if config.verbose {
+        format!(
+            "{} (IPv{}{}{}) ({} packet{}) at {}{}: {} ({}) {} {} ({}) {}{}",
+            protocol,
             match stats.orig_ip {
                 IpAddr::V4(_) => 4,
                 IpAddr::V6(_) => 6,
             },
-            stats.packets,
+            match (stats.ttl, &stats.orig_ip) {
+                (Some(ttl), IpAddr::V4(_)) => format!(", TTL {}", ttl),
+                (Some(ttl), IpAddr::V6(_)) => format!(", hop limit {}", ttl),
+                (None, _) => String::new(),
+            },
+            // QoS markings, if there are any
+            match qos::Qos::parse(&stats.raw).map(|x| x.describe()) {
+                Some(qos) if !qos.is_empty() => format!(", {}", qos),
+                _ => String::new(),
+            },
+            match state.locale {
+                Some(ref locale) => locale.number(stats.packets),
+                None => stats.packets.to_string(),
+            },
             if stats.packets == 1 { "" } else { "s" },
-            stats
-                .timestamp
-                .duration_since(start_time)
-                .unwrap()
-                .as_secs_f32(),
+            format_time(stats.timestamp, start_time, state.locale.as_ref()),
+            context,
             orig_ip,
-            stats.orig_mac,
+            state.vendors.describe(&stats.orig_mac),
+            arrow,
             dest_ip,
-            stats.dest_mac,
-            stats.bytes,
-        );
+            state.vendors.describe(&stats.dest_mac),
+            traffic,
+            columns,
+        )
     } else {
-        println!(
-            "{} at {:.2}s: {} -> {}: {} bytes",
-            stats.protocol,
-            stats
-                .timestamp
-                .duration_since(start_time)
-                .unwrap()
-                .as_secs_f32(),
+        format!(
+            "{} at {}{}: {} {} {}: {}{}",
+            protocol,
+            format_time(stats.timestamp, start_time, state.locale.as_ref()),
+            context,
             orig_ip,
+            arrow,
             dest_ip,
-            stats.bytes,
-        );
+            traffic,
+            columns,
+        )
+    };
+
+    if highlighted {
+        outln!("{}", state.theme.paint(state.theme.highlight, &line));
+    } else {
+        outln!("{}", line);
+    }
+
+    for message in stats.routing.iter() {
+        outln!("    {}", message);
+    }
+    if let Some(ref dhcp) = stats.dhcp {
+        outln!("    {}", dhcp);
+    }
+    if let (Some(ref encapsulation), true) = (&stats.encapsulation, config.verbose && config.tunnel_endpoints) {
+        outln!("    {}", encapsulation);
+    }
+
+    if let Some(mode) = config.dump_payload {
+        let matches: Vec<_> = config.match_payload.iter().flatten().flat_map(|x| x.find_all(&stats.raw)).collect();
+        output::text(&dump::dump_payload(&stats.raw, mode, config.dump_bytes, &matches, &state.theme));
+    }
+}
+
+// seconds since the start of the capture, or local wall-clock time with --human-readable; a frame stamped before the
+// first one (merged or multi-queue captures aren't always in order) shows as the start
+fn format_time(timestamp: SystemTime, start_time: SystemTime, locale: Option<&locale::Locale>) -> String {
+    match locale {
+        Some(locale) => locale.time(timestamp),
+        None => format!(
+            "{:.2}s",
+            timestamp
+                .duration_since(start_time)
+                .unwrap_or_default()
+                .as_secs_f32()
+        ),
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct PacketLog {
+    #[serde(default)]
+    version: u32, // logfile::LOG_VERSION, for logs written since there was one
     packets: Vec<RequestStats>,
     start_time: SystemTime,
+
+    #[serde(default)]
+    resolutions: HashMap<std::net::IpAddr, String>, // reverse DNS answers seen during capture, for --hostnames on playback
 }
 
-fn log_to_file(stats: RequestStats, fname: String, start_time: SystemTime) {
+fn log_to_file(
+    stats: Vec<RequestStats>,
+    fname: String,
+    start_time: SystemTime,
+    resolutions: &HashMap<std::net::IpAddr, String>,
+    config: &conf::Config,
+) -> Result<(), error::Error> {
     // first, load any existing data from the file
     // then, append the new data
     // then, write the new data to the file
 
-    let mut file = std::fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(fname)
-        .unwrap();
-
-    let mut data = String::new();
-    file.read_to_string(&mut data).unwrap();
-
-    let mut logs: PacketLog = serde_json::from_str(&data).unwrap_or(PacketLog {
-        packets: Vec::new(),
-        start_time: start_time,
-    });
-
-    logs.packets.push(stats);
-
-    let new_data = serde_json::to_string(&logs).unwrap();
+    let mut logs = match logfile::load(&fname) {
+        Ok(logs) => logs,
+        // anything else (a log from a newer sniff, or one that doesn't parse) is left as it is, not saved over
+        Err(error::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => PacketLog {
+            version: logfile::LOG_VERSION,
+            packets: Vec::new(),
+            start_time,
+            resolutions: HashMap::new(),
+        },
+        Err(e) => return Err(e),
+    };
 
-    // seek to the beginning of the file
-    file.seek(std::io::SeekFrom::Start(0)).unwrap();
+    logs.packets.extend(stats);
+    logs.resolutions.extend(resolutions.iter().map(|(ip, name)| (*ip, name.clone())));
 
-    // write the new data
-    file.write_all(new_data.as_bytes()).unwrap();
+    logfile::save(&fname, &logs, config.log_format, config.log_compress)
 }
@@ -0,0 +1,396 @@
+use crate::{conf::IpAddr, locale::Locale, rules::RuleStats, units, RequestStats};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    net::TcpListener,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+const TOP_TALKERS: usize = 10;
+
+// counters shared between the capture loop, the metrics endpoint and the exit summary
+pub struct Metrics {
+    pub packets: AtomicU64,
+    pub bytes: AtomicU64,
+    pub events: AtomicU64,      // requests (collated packets) passed to the output
+    pub queue_depth: AtomicU64, // packets waiting in the collation buffer
+    pub ring_depth: AtomicU64,  // frames captured but not yet processed
+    pub dropped: AtomicU64,     // frames dropped because the ring was full
+    pub kernel_received: AtomicU64, // frames the kernel's packet sockets received, dropped ones included (Linux only)
+    pub kernel_dropped: AtomicU64,  // frames the kernel dropped before we could read them (Linux only)
+    pub oversized: AtomicU64,   // GRO/TSO super-packets, bigger than the interface's MTU
+    pub oversized_segments: AtomicU64, // roughly how many segments they stood for on the wire
+    pub malformed: AtomicU64,   // frames we couldn't parse, passed on as raw records
+    pub read_errors: AtomicU64, // failed reads from the capture channel that we carried on after
+    pub unicast: AtomicU64,     // frames by who they were sent to at the link layer
+    pub broadcast: AtomicU64,
+    pub multicast: AtomicU64,
+    pub reassembled: AtomicU64,       // IPv4 datagrams put back together from their fragments
+    pub fragments_expired: AtomicU64, // fragments given up on, when the rest of their datagram never came
+    pub fragments_dropped: AtomicU64, // fragments thrown away with a datagram that went over the limits on them
+    pub sample_rate: AtomicU64,       // N, with --sample 1/N
+    pub sampled: AtomicU64,           // frames the sampler kept (all of them, without --sample)
+    pub bad_checksums: AtomicU64,     // packets failing an IP or transport checksum, with --verify-checksums
+}
+
+pub static METRICS: Metrics = Metrics {
+    packets: AtomicU64::new(0),
+    bytes: AtomicU64::new(0),
+    events: AtomicU64::new(0),
+    queue_depth: AtomicU64::new(0),
+    ring_depth: AtomicU64::new(0),
+    dropped: AtomicU64::new(0),
+    kernel_received: AtomicU64::new(0),
+    kernel_dropped: AtomicU64::new(0),
+    oversized: AtomicU64::new(0),
+    oversized_segments: AtomicU64::new(0),
+    malformed: AtomicU64::new(0),
+    read_errors: AtomicU64::new(0),
+    unicast: AtomicU64::new(0),
+    broadcast: AtomicU64::new(0),
+    multicast: AtomicU64::new(0),
+    reassembled: AtomicU64::new(0),
+    fragments_expired: AtomicU64::new(0),
+    fragments_dropped: AtomicU64::new(0),
+    sample_rate: AtomicU64::new(1),
+    sampled: AtomicU64::new(0),
+    bad_checksums: AtomicU64::new(0),
+};
+
+// sniff's own resource usage, so users can tell whether we're the bottleneck
+pub struct ResourceUsage {
+    pub cpu_user: Duration,
+    pub cpu_system: Duration,
+    pub rss_kb: Option<u64>,
+    pub max_rss_kb: u64,
+}
+
+#[cfg(unix)]
+pub fn resource_usage() -> ResourceUsage {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+
+    let to_duration = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+
+    // ru_maxrss is in kilobytes on Linux, but bytes on macOS
+    let max_rss_kb = if cfg!(target_os = "macos") {
+        usage.ru_maxrss as u64 / 1024
+    } else {
+        usage.ru_maxrss as u64
+    };
+
+    ResourceUsage {
+        cpu_user: to_duration(usage.ru_utime),
+        cpu_system: to_duration(usage.ru_stime),
+        rss_kb: current_rss_kb(),
+        max_rss_kb,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn resource_usage() -> ResourceUsage {
+    ResourceUsage {
+        cpu_user: Duration::ZERO,
+        cpu_system: Duration::ZERO,
+        rss_kb: None,
+        max_rss_kb: 0,
+    }
+}
+
+// the second field of /proc/self/statm is the resident set size, in pages
+#[cfg(unix)]
+fn current_rss_kb() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+    Some(pages * page_size / 1024)
+}
+
+pub fn print_summary(started: Instant, locale: Option<&Locale>) {
+    let elapsed = started.elapsed().as_secs_f64();
+    let usage = resource_usage();
+
+    let packets = METRICS.packets.load(Ordering::Relaxed);
+    let bytes = METRICS.bytes.load(Ordering::Relaxed);
+    let events = METRICS.events.load(Ordering::Relaxed);
+
+    let number = |n: u64| match locale {
+        Some(locale) => locale.number(n),
+        None => n.to_string(),
+    };
+
+    println!("Summary:");
+    println!(
+        "    {} packets ({}) in {} requests over {:.2}s ({:.1} events/s)",
+        number(packets),
+        match locale {
+            Some(locale) => units::human_bytes(bytes, Some(locale)),
+            None => format!("{} bytes", bytes),
+        },
+        number(events),
+        elapsed,
+        events as f64 / elapsed.max(f64::EPSILON),
+    );
+    println!(
+        "    CPU time {:.2}s user, {:.2}s system ({:.1}% of one core)",
+        usage.cpu_user.as_secs_f64(),
+        usage.cpu_system.as_secs_f64(),
+        (usage.cpu_user + usage.cpu_system).as_secs_f64() / elapsed.max(f64::EPSILON) * 100.0,
+    );
+    println!(
+        "    memory {} KiB resident, {} KiB peak; collation queue depth {}, ring depth {}",
+        usage
+            .rss_kb
+            .map(|x| x.to_string())
+            .unwrap_or("?".to_string()),
+        usage.max_rss_kb,
+        METRICS.queue_depth.load(Ordering::Relaxed),
+        METRICS.ring_depth.load(Ordering::Relaxed),
+    );
+    println!(
+        "    {} frames dropped by sniff (ring buffer full)",
+        number(METRICS.dropped.load(Ordering::Relaxed)),
+    );
+    // what the kernel saw, which is the only way to know about frames that never reached us
+    let (kernel_received, kernel_dropped) = (
+        METRICS.kernel_received.load(Ordering::Relaxed),
+        METRICS.kernel_dropped.load(Ordering::Relaxed),
+    );
+    if kernel_received > 0 {
+        println!(
+            "    {} frames received by the kernel, {} dropped there ({:.2}%, not read in time)",
+            number(kernel_received),
+            number(kernel_dropped),
+            kernel_dropped as f64 / kernel_received as f64 * 100.0,
+        );
+    }
+
+    let (malformed, read_errors) = (METRICS.malformed.load(Ordering::Relaxed), METRICS.read_errors.load(Ordering::Relaxed));
+    if malformed > 0 || read_errors > 0 {
+        println!(
+            "    {} malformed frames (shown as ???), {} failed reads",
+            number(malformed),
+            number(read_errors),
+        );
+    }
+
+    let casts = [
+        ("unicast", METRICS.unicast.load(Ordering::Relaxed)),
+        ("broadcast", METRICS.broadcast.load(Ordering::Relaxed)),
+        ("multicast", METRICS.multicast.load(Ordering::Relaxed)),
+    ];
+    let frames: u64 = casts.iter().map(|(_, n)| n).sum();
+    if frames > 0 {
+        let shares: Vec<String> = casts
+            .iter()
+            .map(|(cast, n)| format!("{:.1}% {}", *n as f64 / frames as f64 * 100.0, cast))
+            .collect();
+        println!("    frames {}", shares.join(", "));
+    }
+
+    let oversized = METRICS.oversized.load(Ordering::Relaxed);
+    if oversized > 0 {
+        println!(
+            "    {} frames larger than the MTU (GRO/TSO super-packets), about {} segments on the wire",
+            number(oversized),
+            number(METRICS.oversized_segments.load(Ordering::Relaxed)),
+        );
+    }
+
+    let (reassembled, expired) = (METRICS.reassembled.load(Ordering::Relaxed), METRICS.fragments_expired.load(Ordering::Relaxed));
+    let dropped = METRICS.fragments_dropped.load(Ordering::Relaxed);
+    if reassembled > 0 || expired > 0 || dropped > 0 {
+        println!(
+            "    {} fragmented datagrams reassembled, {} fragments timed out waiting for the rest",
+            number(reassembled),
+            number(expired),
+        );
+    }
+    if dropped > 0 {
+        println!("    {} fragments dropped, in datagrams with too many of them", number(dropped));
+    }
+
+    let sample_rate = METRICS.sample_rate.load(Ordering::Relaxed);
+    if sample_rate > 1 {
+        println!(
+            "    sampled 1 in {}: {} frames looked at, with request and flow counts scaled up to match",
+            number(sample_rate),
+            number(METRICS.sampled.load(Ordering::Relaxed)),
+        );
+    }
+
+    let bad_checksums = METRICS.bad_checksums.load(Ordering::Relaxed);
+    if bad_checksums > 0 {
+        println!(
+            "    {} packets with bad checksums (if they're all outgoing, that's usually checksum offload)",
+            number(bad_checksums),
+        );
+    }
+}
+
+// per-protocol and per-host totals, only kept when a machine-readable summary was asked for
+#[derive(Default)]
+pub struct Tally {
+    protocols: BTreeMap<String, Totals>,
+    talkers: HashMap<IpAddr, Totals>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Totals {
+    requests: u64,
+    packets: u64,
+    bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, stats: &RequestStats) {
+        self.requests += 1;
+        self.packets += stats.packets;
+        self.bytes += stats.bytes;
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "requests": self.requests,
+            "packets": self.packets,
+            "bytes": self.bytes,
+        })
+    }
+}
+
+impl Tally {
+    pub fn record(&mut self, stats: &RequestStats) {
+        self.protocols.entry(stats.protocol.to_string()).or_default().add(stats);
+        self.talkers.entry(stats.orig_ip.clone()).or_default().add(stats);
+    }
+}
+
+// the exit summary as JSON, so automated runs can be archived and compared
+pub fn write_summary_json(path: &str, started: Instant, start_time: SystemTime, tally: &Tally, rules: &RuleStats) -> std::io::Result<()> {
+    let usage = resource_usage();
+
+    let mut talkers: Vec<(&IpAddr, &Totals)> = tally.talkers.iter().collect();
+    talkers.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.to_string().cmp(&b.0.to_string())));
+
+    let summary = serde_json::json!({
+        "start_time": start_time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+        "duration": started.elapsed().as_secs_f64(),
+        "packets": METRICS.packets.load(Ordering::Relaxed),
+        "bytes": METRICS.bytes.load(Ordering::Relaxed),
+        "requests": METRICS.events.load(Ordering::Relaxed),
+        "dropped": METRICS.dropped.load(Ordering::Relaxed),
+        "kernel_received": METRICS.kernel_received.load(Ordering::Relaxed),
+        "kernel_dropped": METRICS.kernel_dropped.load(Ordering::Relaxed),
+        "malformed": METRICS.malformed.load(Ordering::Relaxed),
+        "read_errors": METRICS.read_errors.load(Ordering::Relaxed),
+        "oversized": METRICS.oversized.load(Ordering::Relaxed),
+        "oversized_segments": METRICS.oversized_segments.load(Ordering::Relaxed),
+        "unicast": METRICS.unicast.load(Ordering::Relaxed),
+        "broadcast": METRICS.broadcast.load(Ordering::Relaxed),
+        "multicast": METRICS.multicast.load(Ordering::Relaxed),
+        "reassembled": METRICS.reassembled.load(Ordering::Relaxed),
+        "fragments_expired": METRICS.fragments_expired.load(Ordering::Relaxed),
+        "fragments_dropped": METRICS.fragments_dropped.load(Ordering::Relaxed),
+        "bad_checksums": METRICS.bad_checksums.load(Ordering::Relaxed),
+        "protocols": tally
+            .protocols
+            .iter()
+            .map(|(protocol, totals)| (protocol.clone(), totals.to_json()))
+            .collect::<serde_json::Map<_, _>>(),
+        "top_talkers": talkers
+            .iter()
+            .take(TOP_TALKERS)
+            .map(|(ip, totals)| {
+                let mut entry = totals.to_json();
+                entry["ip"] = ip.to_string().into();
+                entry
+            })
+            .collect::<Vec<_>>(),
+        "alerts": rules
+            .hits()
+            .filter(|(kind, _, _)| *kind == "alert")
+            .map(|(_, name, hits)| (name.to_string(), hits.into()))
+            .collect::<serde_json::Map<_, _>>(),
+        "rules_evaluated": rules.evaluated(),
+        "rules": rules
+            .hits()
+            .filter(|(kind, _, _)| *kind != "alert")
+            .map(|(kind, value, hits)| serde_json::json!({ "kind": kind, "value": value, "hits": hits }))
+            .collect::<Vec<_>>(),
+        "cpu_user": usage.cpu_user.as_secs_f64(),
+        "cpu_system": usage.cpu_system.as_secs_f64(),
+        "max_rss_kb": usage.max_rss_kb,
+    });
+
+    std::fs::write(path, serde_json::to_string_pretty(&summary).unwrap())
+}
+
+// render the counters in the Prometheus text exposition format
+fn render(started: Instant) -> String {
+    let usage = resource_usage();
+    let elapsed = started.elapsed().as_secs_f64();
+    let events = METRICS.events.load(Ordering::Relaxed);
+
+    let mut out = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        out += &format!("# HELP sniff_{} {}\n# TYPE sniff_{} {}\nsniff_{} {}\n", name, help, name, kind, name, value);
+    };
+
+    metric("packets_total", "counter", "Packets captured", METRICS.packets.load(Ordering::Relaxed).to_string());
+    metric("bytes_total", "counter", "Bytes captured", METRICS.bytes.load(Ordering::Relaxed).to_string());
+    metric("events_total", "counter", "Requests passed to the output", events.to_string());
+    metric("events_per_second", "gauge", "Average requests per second since start", format!("{:.3}", events as f64 / elapsed.max(f64::EPSILON)));
+    metric("queue_depth", "gauge", "Packets waiting in the collation buffer", METRICS.queue_depth.load(Ordering::Relaxed).to_string());
+    metric("ring_depth", "gauge", "Frames captured but not yet processed", METRICS.ring_depth.load(Ordering::Relaxed).to_string());
+    metric("dropped_total", "counter", "Frames dropped because the ring buffer was full", METRICS.dropped.load(Ordering::Relaxed).to_string());
+    metric("kernel_received_total", "counter", "Frames the kernel's packet sockets received, dropped ones included (Linux)", METRICS.kernel_received.load(Ordering::Relaxed).to_string());
+    metric("kernel_dropped_total", "counter", "Frames the kernel dropped before they could be read (Linux)", METRICS.kernel_dropped.load(Ordering::Relaxed).to_string());
+    metric("malformed_total", "counter", "Frames that couldn't be parsed", METRICS.malformed.load(Ordering::Relaxed).to_string());
+    metric("read_errors_total", "counter", "Failed reads from the capture channel", METRICS.read_errors.load(Ordering::Relaxed).to_string());
+    metric("oversized_total", "counter", "Frames larger than the MTU (GRO/TSO super-packets)", METRICS.oversized.load(Ordering::Relaxed).to_string());
+    metric("unicast_total", "counter", "Frames sent to a single MAC address", METRICS.unicast.load(Ordering::Relaxed).to_string());
+    metric("broadcast_total", "counter", "Frames sent to the broadcast MAC address", METRICS.broadcast.load(Ordering::Relaxed).to_string());
+    metric("multicast_total", "counter", "Frames sent to a multicast MAC address", METRICS.multicast.load(Ordering::Relaxed).to_string());
+    metric("reassembled_total", "counter", "IPv4 datagrams reassembled from fragments", METRICS.reassembled.load(Ordering::Relaxed).to_string());
+    metric("fragments_expired_total", "counter", "IPv4 fragments whose datagram was never completed", METRICS.fragments_expired.load(Ordering::Relaxed).to_string());
+    metric("fragments_dropped_total", "counter", "IPv4 fragments dropped with a datagram over the fragment limits", METRICS.fragments_dropped.load(Ordering::Relaxed).to_string());
+    metric("bad_checksums_total", "counter", "Packets failing an IP or transport checksum (with --verify-checksums)", METRICS.bad_checksums.load(Ordering::Relaxed).to_string());
+    metric("sampled_total", "counter", "Frames kept by --sample (all of them without it)", METRICS.sampled.load(Ordering::Relaxed).to_string());
+    metric("cpu_user_seconds_total", "counter", "User CPU time consumed by sniff", format!("{:.3}", usage.cpu_user.as_secs_f64()));
+    metric("cpu_system_seconds_total", "counter", "System CPU time consumed by sniff", format!("{:.3}", usage.cpu_system.as_secs_f64()));
+    metric("resident_memory_bytes", "gauge", "Resident set size of sniff", (usage.rss_kb.unwrap_or(0) * 1024).to_string());
+    metric("max_resident_memory_bytes", "gauge", "Peak resident set size of sniff", (usage.max_rss_kb * 1024).to_string());
+
+    out
+}
+
+// serve the metrics over plain HTTP from a background thread, e.g. for Prometheus to scrape
+pub fn serve(addr: &str, started: Instant) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+
+            // we serve the same thing whatever the request, so just drain it
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+
+            let body = render(started);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,271 @@
+// --mtu-anomalies: packet sizes as they are on the wire (before fragments are put back together), for diagnosing MTU
+// mismatches. Two things are called out as they happen:
+//
+//     a router saying a packet was too big (ICMP "fragmentation needed", ICMPv6 "packet too big") for a flow that's
+//     been sending packets bigger than the MTU it gives, with DF set, so they're being dropped on the way
+//     the same flow still sending those packets a while after it was told, i.e. path MTU discovery isn't working
+//
+// and at exit, each host's largest packet and frame, how many of its datagrams were fragmented, and the smallest path
+// MTU it was told about. Packets bigger than the interface's MTU are offload super-packets (see gro.rs), and skipped
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, theme::Theme};
+
+const ICMP: u8 = 1;
+const ICMPV6: u8 = 58;
+const IPV6_FRAGMENT: u8 = 44;
+
+const ICMP_UNREACHABLE: u8 = 3;
+const ICMP_FRAGMENTATION_NEEDED: u8 = 4;
+const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+
+// the size of an Ethernet header, for frame sizes
+const ETHERNET_HEADER: usize = 14;
+// the usual MTU, which jumbo frames are bigger than
+const STANDARD_MTU: usize = 1500;
+
+// how long a sender has to adapt once it's been told the path MTU
+const ADAPT_WITHIN: Duration = Duration::from_secs(2);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+const MAX_HOSTS: usize = 65536;
+const REPORT_HOSTS: usize = 20;
+
+// protocol, source, destination
+type Key = (u8, SocketAddr, SocketAddr);
+
+#[derive(Default)]
+struct Flow {
+    largest_df: usize,                     // the largest packet it's sent that routers mustn't fragment
+    path_mtu: Option<(usize, SystemTime)>, // what a router said, and when
+    warned: bool,                          // that it's not adapting to it
+    last: Option<SystemTime>,
+}
+
+#[derive(Default)]
+struct Host {
+    largest: usize,
+    fragmented: u64,
+    path_mtu: Option<usize>, // the smallest any router's told it
+}
+
+pub struct MtuTracker {
+    theme: Theme,
+    mtu: usize,
+    flows: HashMap<Key, Flow>,
+    hosts: HashMap<IpAddr, Host>,
+    checked: Option<SystemTime>,
+}
+
+impl MtuTracker {
+    pub fn new(theme: Theme, mtu: usize) -> MtuTracker {
+        MtuTracker {
+            theme,
+            mtu,
+            flows: HashMap::new(),
+            hosts: HashMap::new(),
+            checked: None,
+        }
+    }
+
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet) else {
+            return;
+        };
+        let size = ip::total_len(packet).unwrap_or(packet.len());
+        if size > self.mtu {
+            return;
+        }
+
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.flows.retain(|_, flow| {
+                flow.last
+                    .is_some_and(|x| timestamp.duration_since(x).unwrap_or_default() < IDLE_TIMEOUT)
+            });
+        }
+
+        let v6 = header.src.is_ipv6();
+        let (fragment, first_fragment) = if v6 {
+            (
+                header.protocol == IPV6_FRAGMENT,
+                header.protocol == IPV6_FRAGMENT && fragment_offset_v6(packet) == Some(0),
+            )
+        } else {
+            let flags = u16::from_be_bytes([packet[6], packet[7]]);
+            // more fragments, or an offset
+            (flags & 0x3fff != 0, flags & 0x2000 != 0 && flags & 0x1fff == 0)
+        };
+
+        if self.hosts.len() < MAX_HOSTS || self.hosts.contains_key(&header.src) {
+            let host = self.hosts.entry(header.src).or_default();
+            host.largest = host.largest.max(size);
+            if first_fragment {
+                host.fragmented += 1;
+            }
+        }
+
+        let icmp = packet.get(header.header_len..).unwrap_or_default();
+        match (header.protocol, icmp.first(), icmp.get(1)) {
+            (ICMP, Some(&ICMP_UNREACHABLE), Some(&ICMP_FRAGMENTATION_NEEDED)) if icmp.len() >= 8 => {
+                let mtu = u16::from_be_bytes([icmp[6], icmp[7]]) as usize;
+                self.too_big(header.src, &icmp[8..], mtu, timestamp);
+                return;
+            }
+            (ICMPV6, Some(&ICMPV6_PACKET_TOO_BIG), Some(0)) if icmp.len() >= 8 => {
+                let mtu = u32::from_be_bytes([icmp[4], icmp[5], icmp[6], icmp[7]]) as usize;
+                self.too_big(header.src, &icmp[8..], mtu, timestamp);
+                return;
+            }
+            _ => {}
+        }
+
+        // every IPv6 packet is DF, as routers never fragment them
+        let dont_fragment = v6 || packet[6] & 0x40 != 0;
+        if fragment || !dont_fragment {
+            return;
+        }
+        let Some(key) = flow_key(packet) else {
+            return;
+        };
+
+        let flow = self.flows.entry(key).or_default();
+        flow.last = Some(timestamp);
+        flow.largest_df = flow.largest_df.max(size);
+
+        // told, and given time to adapt, but still sending packets that won't fit
+        let Some((mtu, told)) = flow.path_mtu else {
+            return;
+        };
+        if size <= mtu || flow.warned || timestamp.duration_since(told).unwrap_or_default() < ADAPT_WITHIN {
+            return;
+        }
+        flow.warned = true;
+
+        let message = format!(
+            "*** still sending {}-byte packets with DF set {:.1}s after being told the path MTU is {}: {} -> {} \
+             (path MTU discovery isn't working) ***",
+            size,
+            timestamp.duration_since(told).unwrap_or_default().as_secs_f64(),
+            mtu,
+            key.1,
+            key.2
+        );
+        outln!("{}", self.theme.paint(self.theme.anomaly, &message));
+    }
+
+    // a router's (or host's) `from` message that the packet starting `quoted` was too big for an MTU of `mtu`
+    fn too_big(&mut self, from: IpAddr, quoted: &[u8], mtu: usize, timestamp: SystemTime) {
+        let Some(key) = flow_key(quoted) else {
+            return;
+        };
+
+        if let Some(host) = self.hosts.get_mut(&key.1.ip()) {
+            host.path_mtu = Some(host.path_mtu.map_or(mtu, |x| x.min(mtu)));
+        }
+
+        let flow = self.flows.entry(key).or_default();
+        flow.last = Some(timestamp);
+        let repeated = flow.path_mtu.is_some_and(|(x, _)| x == mtu);
+        if !repeated {
+            flow.path_mtu = Some((mtu, timestamp));
+            flow.warned = false;
+        }
+        if repeated || flow.largest_df <= mtu {
+            return;
+        }
+
+        let message = format!(
+            "*** {}-byte packets with DF set don't fit the path MTU of {} (says {}): {} -> {} ***",
+            flow.largest_df, mtu, from, key.1, key.2
+        );
+        outln!("{}", self.theme.paint(self.theme.warning, &message));
+    }
+
+    pub fn print_report(self) {
+        if self.hosts.is_empty() {
+            return;
+        }
+
+        let mut hosts: Vec<_> = self.hosts.iter().collect();
+        hosts.sort_by_key(|(ip, host)| (std::cmp::Reverse(host.largest), **ip));
+
+        println!("Largest packets by host (interface MTU {}):", self.mtu);
+        for (ip, host) in hosts.iter().take(REPORT_HOSTS) {
+            let mut notes = Vec::new();
+            if host.largest > STANDARD_MTU {
+                notes.push("jumbo".to_string());
+            }
+            if host.fragmented > 0 {
+                notes.push(format!(
+                    "{} datagram{} fragmented",
+                    host.fragmented,
+                    if host.fragmented == 1 { "" } else { "s" }
+                ));
+            }
+            if let Some(mtu) = host.path_mtu {
+                notes.push(format!("told the path MTU is {}", mtu));
+            }
+
+            println!(
+                "    {}: {} bytes ({}-byte frames){}",
+                ip,
+                host.largest,
+                host.largest + ETHERNET_HEADER,
+                if notes.is_empty() {
+                    String::new()
+                } else {
+                    format!(", {}", notes.join(", "))
+                }
+            );
+        }
+        if hosts.len() > REPORT_HOSTS {
+            println!("    ... and {} more", hosts.len() - REPORT_HOSTS);
+        }
+
+        // a host sending jumbo frames to one that tops out at 1500 has its packets dropped, if they share a link
+        let jumbo = hosts.iter().filter(|(_, host)| host.largest > STANDARD_MTU).count();
+        let standard = hosts.iter().filter(|(_, host)| host.largest == STANDARD_MTU).count();
+        if jumbo > 0 && standard > 0 {
+            println!(
+                "    {} host{} sent jumbo frames and {} topped out at {}; an MTU mismatch, if they're on the same link",
+                jumbo,
+                if jumbo == 1 { "" } else { "s" },
+                standard,
+                STANDARD_MTU
+            );
+        }
+    }
+}
+
+// a packet's flow, from its IP header and the first 4 bytes after (which is all an ICMP error has to quote)
+fn flow_key(packet: &[u8]) -> Option<Key> {
+    let header = ip::parse(packet)?;
+    let ports = packet.get(header.header_len..header.header_len + 4);
+    let (src_port, dst_port) = match (header.protocol, ports) {
+        (6 | 17, Some(ports)) => (
+            u16::from_be_bytes([ports[0], ports[1]]),
+            u16::from_be_bytes([ports[2], ports[3]]),
+        ),
+        _ => (0, 0),
+    };
+
+    Some((
+        header.protocol,
+        SocketAddr::new(header.src, src_port),
+        SocketAddr::new(header.dst, dst_port),
+    ))
+}
+
+// where an IPv6 fragment (its fragment header straight after the fixed one) goes in its datagram
+fn fragment_offset_v6(packet: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes([*packet.get(42)?, *packet.get(43)?]) >> 3)
+}
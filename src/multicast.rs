@@ -0,0 +1,133 @@
+// broadcast and multicast traffic: which well-known group a multicast request was sent to (mDNS, SSDP, IGMP and the
+// like), to show next to its [multicast] marker, and how much of each there was, for the exit report. Broadcast and
+// multicast chatter is most of what a quiet LAN carries, so knowing what it is makes it easier to filter out
+//
+// groups go by IP address; frames that aren't IP, like spanning tree's, go by their multicast MAC address
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{
+    conf::{Cast, MacAddr},
+    ip,
+    locale::Locale,
+    units, RequestStats,
+};
+
+const IGMP: u8 = 2;
+
+const IPV4_GROUPS: &[(Ipv4Addr, &str)] = &[
+    (Ipv4Addr::new(224, 0, 0, 1), "all hosts"),
+    (Ipv4Addr::new(224, 0, 0, 2), "all routers"),
+    (Ipv4Addr::new(224, 0, 0, 5), "OSPF"),
+    (Ipv4Addr::new(224, 0, 0, 6), "OSPF DR"),
+    (Ipv4Addr::new(224, 0, 0, 9), "RIP"),
+    (Ipv4Addr::new(224, 0, 0, 13), "PIM"),
+    (Ipv4Addr::new(224, 0, 0, 18), "VRRP"),
+    (Ipv4Addr::new(224, 0, 0, 22), "IGMPv3"),
+    (Ipv4Addr::new(224, 0, 0, 102), "HSRPv2"),
+    (Ipv4Addr::new(224, 0, 0, 251), "mDNS"),
+    (Ipv4Addr::new(224, 0, 0, 252), "LLMNR"),
+    (Ipv4Addr::new(224, 0, 1, 1), "NTP"),
+    (Ipv4Addr::new(239, 255, 255, 250), "SSDP"),
+    (Ipv4Addr::new(239, 255, 255, 253), "SLP"),
+];
+
+const IPV6_GROUPS: &[(Ipv6Addr, &str)] = &[
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x1), "all nodes"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x2), "all routers"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x5), "OSPFv3"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x6), "OSPFv3 DR"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x9), "RIPng"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc), "SSDP"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xd), "PIM"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x12), "VRRP"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x16), "MLDv2"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb), "mDNS"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x1, 0x2), "DHCPv6"),
+    (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x1, 0x3), "LLMNR"),
+];
+
+const MAC_GROUPS: &[([u8; 6], &str)] = &[
+    ([0x01, 0x80, 0xc2, 0x00, 0x00, 0x00], "STP"),
+    ([0x01, 0x80, 0xc2, 0x00, 0x00, 0x02], "LACP"),
+    ([0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e], "LLDP"),
+    ([0x01, 0x00, 0x0c, 0xcc, 0xcc, 0xcc], "CDP"),
+    ([0x01, 0x00, 0x0c, 0xcc, 0xcc, 0xcd], "PVST+"),
+];
+
+// the well-known group a multicast request went to, if it's one
+pub fn group(stats: &RequestStats) -> Option<&'static str> {
+    if stats.dest_mac.cast() != Cast::Multicast {
+        return None;
+    }
+
+    let by_ip = match stats.dest_ip.to_std() {
+        IpAddr::V4(ip) => IPV4_GROUPS.iter().find(|(x, _)| *x == ip).map(|(_, name)| *name),
+        // the solicited-node groups are neighbour discovery's, one for every address
+        IpAddr::V6(ip) if ip.segments()[..6] == [0xff02, 0, 0, 0, 0, 1] && ip.segments()[6] >> 8 == 0xff => {
+            Some("solicited-node")
+        }
+        IpAddr::V6(ip) => IPV6_GROUPS.iter().find(|(x, _)| *x == ip).map(|(_, name)| *name),
+    };
+
+    by_ip
+        .or_else(|| ip::parse(&stats.raw).filter(|x| x.protocol == IGMP).map(|_| "IGMP"))
+        .or_else(|| {
+            MAC_GROUPS
+                .iter()
+                .find(|(x, _)| MacAddr::from(*x) == stats.dest_mac)
+                .map(|(_, name)| *name)
+        })
+}
+
+#[derive(Default)]
+struct Totals {
+    requests: u64,
+    bytes: u64,
+}
+
+// how much broadcast and multicast there was, and to which groups
+#[derive(Default)]
+pub struct CastTotals {
+    totals: BTreeMap<String, Totals>,
+}
+
+impl CastTotals {
+    pub fn observe(&mut self, stats: &RequestStats) {
+        let kind = match stats.dest_mac.cast() {
+            Cast::Unicast => return,
+            Cast::Broadcast => "broadcast".to_string(),
+            Cast::Multicast => match group(stats) {
+                Some(group) => format!("multicast {}", group),
+                None => "multicast (other)".to_string(),
+            },
+        };
+
+        let totals = self.totals.entry(kind).or_default();
+        totals.requests += 1;
+        totals.bytes += stats.bytes;
+    }
+
+    pub fn print_report(&self, locale: Option<&Locale>) {
+        if self.totals.is_empty() {
+            return;
+        }
+
+        let mut totals: Vec<_> = self.totals.iter().collect();
+        totals.sort_by_key(|(_, x)| std::cmp::Reverse(x.bytes));
+
+        println!("Broadcast and multicast:");
+        for (kind, totals) in totals {
+            println!(
+                "    {}: {} request{}, {}",
+                kind,
+                totals.requests,
+                if totals.requests == 1 { "" } else { "s" },
+                units::human_bytes(totals.bytes, locale)
+            );
+        }
+    }
+}
@@ -0,0 +1,362 @@
+// --netflow COLLECTOR:PORT: sniff as a flow exporter, for hosts where softflowd and the like can't be installed. Every IP
+// packet is counted against its (unidirectional) flow, and flows are sent to the collector over UDP as NetFlow v9 or
+// IPFIX (--netflow-version) records once they end:
+//
+//     a TCP flow once it sends a FIN or RST
+//     any flow after 15s with nothing sent, or after 60s of traffic (and then counted afresh)
+//     whatever's left at the end of the capture
+//
+// templates go out in the first export packet and once a minute after that, so a collector started later picks them up
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, SystemTime},
+};
+
+use crate::{conf::NetflowVersion, ip};
+
+const ACTIVE_TIMEOUT: Duration = Duration::from_secs(60);
+const INACTIVE_TIMEOUT: Duration = Duration::from_secs(15);
+const TEMPLATE_EVERY: Duration = Duration::from_secs(60);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+// records in each export packet, keeping it well under a typical MTU
+const MAX_RECORDS: usize = 24;
+// forgotten all at once when there are this many, rather than exporting a flood
+const MAX_FLOWS: usize = 65536;
+
+const TEMPLATE_V4: u16 = 256;
+const TEMPLATE_V6: u16 = 257;
+
+// information element ids, the same in NetFlow v9 and IPFIX
+const IN_BYTES: u16 = 1;
+const IN_PKTS: u16 = 2;
+const PROTOCOL: u16 = 4;
+const TCP_FLAGS: u16 = 6;
+const L4_SRC_PORT: u16 = 7;
+const IPV4_SRC_ADDR: u16 = 8;
+const L4_DST_PORT: u16 = 11;
+const IPV4_DST_ADDR: u16 = 12;
+const LAST_SWITCHED: u16 = 21; // NetFlow v9: milliseconds of system uptime
+const FIRST_SWITCHED: u16 = 22;
+const IPV6_SRC_ADDR: u16 = 27;
+const IPV6_DST_ADDR: u16 = 28;
+const FLOW_START_MILLISECONDS: u16 = 152; // IPFIX: milliseconds since the epoch
+const FLOW_END_MILLISECONDS: u16 = 153;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    src: IpAddr,
+    dst: IpAddr,
+    src_port: u16,
+    dst_port: u16, // ICMP's type and code, as NetFlow has it
+    protocol: u8,
+}
+
+struct Flow {
+    first: SystemTime,
+    last: SystemTime,
+    packets: u64,
+    bytes: u64,
+    tcp_flags: u8, // every flag seen, ORed together
+}
+
+#[derive(Default)]
+struct Totals {
+    flows: u64,
+    packets: u64,
+    failed: u64,
+}
+
+pub struct NetflowExporter {
+    socket: UdpSocket,
+    collector: SocketAddr,
+    version: NetflowVersion,
+    sample: u64,                 // each packet counted stands for this many, with --sample
+    started: Option<SystemTime>, // NetFlow v9's system uptime counts from the first packet
+    flows: HashMap<FlowKey, Flow>,
+    sequence: u32,
+    templates_sent: Option<SystemTime>,
+    checked: Option<SystemTime>,
+    totals: Totals,
+}
+
+impl NetflowExporter {
+    pub fn new(collector: &str, version: NetflowVersion, sample: u32) -> NetflowExporter {
+        let collector = collector
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut x| x.next())
+            .unwrap_or_else(|| panic!("Invalid NetFlow collector {}, expected host:port", collector));
+        let bind = if collector.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind).unwrap_or_else(|e| panic!("Failed to open a socket for NetFlow: {}", e));
+
+        NetflowExporter {
+            socket,
+            collector,
+            version,
+            sample: sample as u64,
+            started: None,
+            flows: HashMap::new(),
+            sequence: 0,
+            templates_sent: None,
+            checked: None,
+            totals: Totals::default(),
+        }
+    }
+
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet) else {
+            return;
+        };
+        let segment = packet.get(header.header_len..).unwrap_or_default();
+
+        let (src_port, dst_port) = match header.protocol {
+            6 | 17 => match ip::transport(packet) {
+                Some((src_port, dst_port, _)) => (src_port, dst_port),
+                None => (0, 0),
+            },
+            // ICMP and ICMPv6
+            1 | 58 if segment.len() >= 2 => (0, u16::from_be_bytes([segment[0], segment[1]])),
+            _ => (0, 0),
+        };
+        let tcp_flags = match header.protocol {
+            6 => segment.get(13).copied().unwrap_or_default(),
+            _ => 0,
+        };
+
+        self.started.get_or_insert(timestamp);
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.expire(timestamp);
+        }
+
+        let key = FlowKey {
+            src: header.src,
+            dst: header.dst,
+            src_port,
+            dst_port,
+            protocol: header.protocol,
+        };
+        if !self.flows.contains_key(&key) && self.flows.len() >= MAX_FLOWS {
+            self.flows.clear();
+        }
+        let flow = self.flows.entry(key).or_insert(Flow {
+            first: timestamp,
+            last: timestamp,
+            packets: 0,
+            bytes: 0,
+            tcp_flags: 0,
+        });
+        flow.last = timestamp;
+        flow.packets += self.sample;
+        flow.bytes += ip::total_len(packet).unwrap_or(packet.len()) as u64 * self.sample;
+        flow.tcp_flags |= tcp_flags;
+
+        // FIN or RST: this side of the connection is done
+        if tcp_flags & 0x05 != 0 {
+            if let Some(flow) = self.flows.remove(&key) {
+                self.export(vec![(key, flow)], timestamp);
+            }
+        }
+    }
+
+    // export flows that have gone quiet, or have been going for long enough
+    fn expire(&mut self, now: SystemTime) {
+        let since = |time: SystemTime| now.duration_since(time).unwrap_or_default();
+        let expired: Vec<FlowKey> = self
+            .flows
+            .iter()
+            .filter(|(_, x)| since(x.last) >= INACTIVE_TIMEOUT || since(x.first) >= ACTIVE_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+
+        let flows = expired
+            .into_iter()
+            .filter_map(|key| Some((key, self.flows.remove(&key)?)))
+            .collect();
+        self.export(flows, now);
+    }
+
+    fn export(&mut self, flows: Vec<(FlowKey, Flow)>, now: SystemTime) {
+        for chunk in flows.chunks(MAX_RECORDS) {
+            let templates = self
+                .templates_sent
+                .is_none_or(|x| now.duration_since(x).unwrap_or_default() >= TEMPLATE_EVERY);
+            if templates {
+                self.templates_sent = Some(now);
+            }
+
+            let packet = self.packet(chunk, templates, now);
+            match self.socket.send_to(&packet, self.collector) {
+                Ok(_) => {
+                    self.totals.flows += chunk.len() as u64;
+                    self.totals.packets += 1;
+                }
+                Err(e) => {
+                    if self.totals.failed == 0 {
+                        eprintln!("Failed to export flows to {}: {}", self.collector, e);
+                    }
+                    self.totals.failed += chunk.len() as u64;
+                }
+            }
+        }
+    }
+
+    // an export packet: the header, the templates if it's time for them, then a data set for each address family
+    fn packet(&mut self, flows: &[(FlowKey, Flow)], templates: bool, now: SystemTime) -> Vec<u8> {
+        let ipfix = self.version == NetflowVersion::Ipfix;
+        let mut sets = Vec::new();
+        let mut records = 0;
+
+        if templates {
+            let mut set = Vec::new();
+            for template in [TEMPLATE_V4, TEMPLATE_V6] {
+                let fields = self.fields(template);
+                set.extend_from_slice(&template.to_be_bytes());
+                set.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+                for (id, len) in fields {
+                    set.extend_from_slice(&id.to_be_bytes());
+                    set.extend_from_slice(&len.to_be_bytes());
+                }
+                records += 1;
+            }
+            // the template set's id: 0 in NetFlow v9, 2 in IPFIX
+            push_set(&mut sets, if ipfix { 2 } else { 0 }, &set);
+        }
+
+        for template in [TEMPLATE_V4, TEMPLATE_V6] {
+            let mut set = Vec::new();
+            for (key, flow) in flows
+                .iter()
+                .filter(|(x, _)| x.src.is_ipv4() == (template == TEMPLATE_V4))
+            {
+                self.record(&mut set, key, flow);
+                records += 1;
+            }
+            if !set.is_empty() {
+                push_set(&mut sets, template, &set);
+            }
+        }
+
+        let unix_secs = now.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs() as u32;
+        let mut packet = Vec::with_capacity(20 + sets.len());
+        if ipfix {
+            // version, length, export time, sequence (of data records sent before this packet), observation domain
+            packet.extend_from_slice(&10u16.to_be_bytes());
+            packet.extend_from_slice(&((16 + sets.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&unix_secs.to_be_bytes());
+            packet.extend_from_slice(&self.sequence.to_be_bytes());
+            packet.extend_from_slice(&0u32.to_be_bytes());
+            self.sequence = self.sequence.wrapping_add(flows.len() as u32);
+        } else {
+            // version, record count, system uptime, unix seconds, sequence (of export packets), source id
+            packet.extend_from_slice(&9u16.to_be_bytes());
+            packet.extend_from_slice(&(records as u16).to_be_bytes());
+            packet.extend_from_slice(&self.uptime(now).to_be_bytes());
+            packet.extend_from_slice(&unix_secs.to_be_bytes());
+            packet.extend_from_slice(&self.sequence.to_be_bytes());
+            packet.extend_from_slice(&0u32.to_be_bytes());
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        packet.extend_from_slice(&sets);
+        packet
+    }
+
+    // (information element, length) for each field of a template
+    fn fields(&self, template: u16) -> Vec<(u16, u16)> {
+        let (src, dst, len) = match template {
+            TEMPLATE_V4 => (IPV4_SRC_ADDR, IPV4_DST_ADDR, 4),
+            _ => (IPV6_SRC_ADDR, IPV6_DST_ADDR, 16),
+        };
+        let (first, last, time_len) = match self.version {
+            NetflowVersion::V9 => (FIRST_SWITCHED, LAST_SWITCHED, 4),
+            NetflowVersion::Ipfix => (FLOW_START_MILLISECONDS, FLOW_END_MILLISECONDS, 8),
+        };
+
+        vec![
+            (src, len),
+            (dst, len),
+            (L4_SRC_PORT, 2),
+            (L4_DST_PORT, 2),
+            (PROTOCOL, 1),
+            (TCP_FLAGS, 1),
+            (IN_PKTS, 8),
+            (IN_BYTES, 8),
+            (first, time_len),
+            (last, time_len),
+        ]
+    }
+
+    // a data record, in the order of fields()
+    fn record(&self, set: &mut Vec<u8>, key: &FlowKey, flow: &Flow) {
+        for address in [key.src, key.dst] {
+            match address {
+                IpAddr::V4(x) => set.extend_from_slice(&x.octets()),
+                IpAddr::V6(x) => set.extend_from_slice(&x.octets()),
+            }
+        }
+        set.extend_from_slice(&key.src_port.to_be_bytes());
+        set.extend_from_slice(&key.dst_port.to_be_bytes());
+        set.push(key.protocol);
+        set.push(flow.tcp_flags);
+        set.extend_from_slice(&flow.packets.to_be_bytes());
+        set.extend_from_slice(&flow.bytes.to_be_bytes());
+
+        for time in [flow.first, flow.last] {
+            match self.version {
+                NetflowVersion::V9 => set.extend_from_slice(&self.uptime(time).to_be_bytes()),
+                NetflowVersion::Ipfix => {
+                    let millis = time
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64;
+                    set.extend_from_slice(&millis.to_be_bytes());
+                }
+            }
+        }
+    }
+
+    fn uptime(&self, time: SystemTime) -> u32 {
+        self.started
+            .map_or(Duration::ZERO, |x| time.duration_since(x).unwrap_or_default())
+            .as_millis() as u32
+    }
+
+    // export whatever's still open as of the last packet, then report how it went
+    pub fn finish(mut self, now: SystemTime) {
+        let flows = self.flows.drain().collect();
+        self.export(flows, now);
+
+        let Totals { flows, packets, failed } = self.totals;
+        println!(
+            "Exported {} flow{} to {} as {} in {} packet{}{}",
+            flows,
+            if flows == 1 { "" } else { "s" },
+            self.collector,
+            match self.version {
+                NetflowVersion::V9 => "NetFlow v9",
+                NetflowVersion::Ipfix => "IPFIX",
+            },
+            packets,
+            if packets == 1 { "" } else { "s" },
+            if failed > 0 {
+                format!(", {} failed", failed)
+            } else {
+                String::new()
+            },
+        );
+    }
+}
+
+// a set (flowset, in NetFlow v9) with its id and length, padded out to a multiple of four bytes
+fn push_set(sets: &mut Vec<u8>, id: u16, set: &[u8]) {
+    let padding = (4 - set.len() % 4) % 4;
+    sets.extend_from_slice(&id.to_be_bytes());
+    sets.extend_from_slice(&((4 + set.len() + padding) as u16).to_be_bytes());
+    sets.extend_from_slice(set);
+    sets.extend(std::iter::repeat_n(0, padding));
+}
@@ -0,0 +1,190 @@
+// MAC address -> vendor, e.g. b8:27:eb -> "Raspberry Pi Foundation", from the IEEE registry wherever the system has a
+// copy (or a file given with --oui-file), on top of a built-in table of the common ones for systems without one
+//
+// reads the IEEE's oui.txt ("B8-27-EB   (hex)  Raspberry Pi Foundation"), Wireshark's manuf
+// ("B8:27:EB  Raspberr  Raspberry Pi Foundation", with /28 and /36 blocks) and nmap's nmap-mac-prefixes
+// ("B827EB Raspberry Pi Foundation")
+
+use std::collections::HashMap;
+
+use crate::conf::MacAddr;
+
+const OUI_FILES: &[&str] = &[
+    "/usr/share/ieee-data/oui.txt",
+    "/usr/share/wireshark/manuf",
+    "/usr/share/nmap/nmap-mac-prefixes",
+];
+
+// the registry hands out 24-bit prefixes, and 28- and 36-bit blocks inside some of them
+const PREFIX_LENGTHS: [u8; 3] = [36, 28, 24];
+
+const BUILTIN: &[(u32, &str)] = &[
+    (0x00000c, "Cisco Systems, Inc"),
+    (0x000393, "Apple, Inc."),
+    (0x0003ff, "Microsoft Corporation"),
+    (0x00044b, "NVIDIA"),
+    (0x0004f2, "Polycom"),
+    (0x00055d, "D-Link Systems, Inc."),
+    (0x000569, "VMware, Inc."),
+    (0x00090f, "Fortinet, Inc."),
+    (0x000a95, "Apple, Inc."),
+    (0x000c29, "VMware, Inc."),
+    (0x000db9, "PC Engines GmbH"),
+    (0x000e58, "Sonos, Inc."),
+    (0x000f66, "Cisco-Linksys, LLC"),
+    (0x001132, "Synology Incorporated"),
+    (0x0012fb, "Samsung Electronics Co.,Ltd"),
+    (0x00155d, "Microsoft Corporation"),
+    (0x00163e, "Xensource, Inc."),
+    (0x00166c, "Samsung Electronics Co.,Ltd"),
+    (0x001788, "Philips Lighting BV"),
+    (0x0017f2, "Apple, Inc."),
+    (0x00180a, "Cisco Meraki"),
+    (0x0018f3, "ASUSTek COMPUTER INC."),
+    (0x001a11, "Google, Inc."),
+    (0x001a92, "ASUSTek COMPUTER INC."),
+    (0x001b17, "Palo Alto Networks"),
+    (0x001b21, "Intel Corporate"),
+    (0x001b63, "Apple, Inc."),
+    (0x001ba9, "Brother Industries, LTD."),
+    (0x001c14, "VMware, Inc."),
+    (0x001c42, "Parallels, Inc."),
+    (0x001cb3, "Apple, Inc."),
+    (0x001d0f, "TP-LINK TECHNOLOGIES CO.,LTD."),
+    (0x001e67, "Intel Corporate"),
+    (0x001f33, "Netgear"),
+    (0x00216a, "Intel Corporate"),
+    (0x002500, "Apple, Inc."),
+    (0x0026ab, "Seiko Epson Corporation"),
+    (0x0026bb, "Apple, Inc."),
+    (0x004096, "Cisco Systems, Inc"),
+    (0x005056, "VMware, Inc."),
+    (0x0050f2, "Microsoft Corporation"),
+    (0x008077, "Brother Industries, LTD."),
+    (0x00e04c, "Realtek Semiconductor Corp."),
+    (0x080027, "PCS Systemtechnik GmbH"),
+    (0x18b430, "Nest Labs Inc."),
+    (0x240ac4, "Espressif Inc."),
+    (0x28cdc1, "Raspberry Pi Trading Ltd"),
+    (0x30aea4, "Espressif Inc."),
+    (0x3c5ab4, "Google, Inc."),
+    (0x3cd92b, "Hewlett Packard"),
+    (0x44650d, "Amazon Technologies Inc."),
+    (0x50c7bf, "TP-LINK TECHNOLOGIES CO.,LTD."),
+    (0x525400, "QEMU virtual NIC"),
+    (0xb0be76, "TP-LINK TECHNOLOGIES CO.,LTD."),
+    (0xb827eb, "Raspberry Pi Foundation"),
+    (0xd83add, "Raspberry Pi Trading Ltd"),
+    (0xdca632, "Raspberry Pi Trading Ltd"),
+    (0xe45f01, "Raspberry Pi Trading Ltd"),
+    (0xecfabc, "Espressif Inc."),
+    (0xf4f5d8, "Google, Inc."),
+    (0xfc65de, "Amazon Technologies Inc."),
+];
+
+pub struct Vendors {
+    names: HashMap<(u8, u64), String>, // (prefix length, prefix) -> vendor
+}
+
+impl Vendors {
+    pub fn load(path: Option<&str>) -> Vendors {
+        let mut names: HashMap<(u8, u64), String> = BUILTIN
+            .iter()
+            .map(|(oui, name)| ((24, *oui as u64), name.to_string()))
+            .collect();
+
+        // the system's registry takes precedence over ours, and a file we're given over both
+        for file in OUI_FILES {
+            if let Ok(data) = std::fs::read_to_string(file) {
+                parse(&data, &mut names);
+            }
+        }
+        if let Some(path) = path {
+            let data =
+                std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read OUI file {}: {}", path, e));
+            parse(&data, &mut names);
+        }
+
+        Vendors { names }
+    }
+
+    pub fn name(&self, mac: &MacAddr) -> Option<&str> {
+        let address = mac.octets().iter().fold(0u64, |acc, x| acc << 8 | *x as u64);
+
+        PREFIX_LENGTHS
+            .iter()
+            .find_map(|bits| self.names.get(&(*bits, address >> (48 - bits))))
+            .map(|x| x.as_str())
+    }
+
+    // a MAC address with its vendor, if it's known, e.g. "b8:27:eb:12:34:56 (Raspberry Pi Foundation)"
+    pub fn describe(&self, mac: &MacAddr) -> String {
+        match self.name(mac) {
+            Some(vendor) => format!("{} ({})", mac, vendor),
+            None => mac.to_string(),
+        }
+    }
+
+    // a vendor given as part of its name, e.g. "apple", matches either end of a request
+    pub fn matches(&self, vendor: &str, macs: &[MacAddr]) -> bool {
+        let vendor = vendor.to_lowercase();
+        macs.iter()
+            .any(|mac| self.name(mac).is_some_and(|x| x.to_lowercase().contains(&vendor)))
+    }
+}
+
+fn parse(data: &str, names: &mut HashMap<(u8, u64), String>) {
+    for line in data.lines() {
+        // oui.txt's indented lines are the vendor's postal address, where a postcode can look like a prefix
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((prefix, rest)) = line.split_once(|c: char| c.is_whitespace()) else {
+            continue;
+        };
+
+        // oui.txt gives each prefix twice, as "B8-27-EB   (hex)" and "B827EB     (base 16)", with the same name
+        let rest = rest.trim_start();
+        let name = match rest.strip_prefix("(hex)").or_else(|| rest.strip_prefix("(base 16)")) {
+            Some(name) => name.trim(),
+            // manuf has a short name before the full one, and sometimes only the short one
+            None if line.contains('\t') => rest
+                .split('\t')
+                .map(|x| x.trim())
+                .rfind(|x| !x.is_empty())
+                .unwrap_or_default(),
+            None => rest.trim(),
+        };
+        if name.is_empty() {
+            continue;
+        }
+
+        if let Some(key) = parse_prefix(prefix) {
+            names.insert(key, name.to_string());
+        }
+    }
+}
+
+// "B8:27:EB", "B8-27-EB", "B827EB" or "00:1B:C5:00:00:00/36" -> (prefix length, prefix)
+fn parse_prefix(prefix: &str) -> Option<(u8, u64)> {
+    let (digits, bits) = match prefix.split_once('/') {
+        Some((digits, bits)) => (digits, Some(bits.parse::<u8>().ok()?)),
+        None => (prefix, None),
+    };
+
+    let hex: String = digits.chars().filter(|x| *x != ':' && *x != '-' && *x != '.').collect();
+    if hex.is_empty() || hex.len() > 12 || !hex.chars().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let bits = bits.unwrap_or(hex.len() as u8 * 4);
+    if !PREFIX_LENGTHS.contains(&bits) {
+        return None;
+    }
+
+    // the prefix is the top bits of the address, however many digits it was written with
+    let value = u64::from_str_radix(&hex, 16).ok()? << (48 - hex.len() * 4);
+    Some((bits, value >> (48 - bits)))
+}
@@ -0,0 +1,89 @@
+// stdout for what a capture prints as it goes; with --flush-interval it's buffered and written out every so often,
+// rather than a write (and a lock) per line, which is most of the CPU at high event rates
+
+use std::{
+    fmt::Arguments,
+    io::{BufWriter, Stdout, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+struct Buffered {
+    out: BufWriter<Stdout>,
+    interval: Duration,
+    flushed: Instant,
+}
+
+static BUFFERED: Mutex<Option<Buffered>> = Mutex::new(None);
+
+// while paused from the keyboard, what would have been printed is dropped (and counted), and the capture carries on
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static HIDDEN: AtomicU64 = AtomicU64::new(0);
+// lines printed so far, so --graph can tell whether it's still the last thing on the screen
+static LINES: AtomicU64 = AtomicU64::new(0);
+
+pub fn buffer(interval: Duration) {
+    *BUFFERED.lock().unwrap() = Some(Buffered {
+        out: BufWriter::with_capacity(BUFFER_SIZE, std::io::stdout()),
+        interval,
+        flushed: Instant::now(),
+    });
+
+    // a quiet spell shouldn't leave lines sitting in the buffer
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        flush();
+    });
+}
+
+// a line, as println! would print it
+pub fn line(args: Arguments) {
+    text(&format!("{}\n", args));
+}
+
+// as print! would print it
+pub fn text(text: &str) {
+    if PAUSED.load(Ordering::Relaxed) {
+        HIDDEN.fetch_add(text.matches('\n').count() as u64, Ordering::Relaxed);
+        return;
+    }
+    LINES.fetch_add(text.matches('\n').count() as u64, Ordering::Relaxed);
+
+    let mut buffered = BUFFERED.lock().unwrap();
+    let Some(ref mut buffered) = *buffered else {
+        print!("{}", text);
+        return;
+    };
+
+    let _ = buffered.out.write_all(text.as_bytes());
+    if buffered.flushed.elapsed() >= buffered.interval {
+        let _ = buffered.out.flush();
+        buffered.flushed = Instant::now();
+    }
+}
+
+pub fn lines() -> u64 {
+    LINES.load(Ordering::Relaxed)
+}
+
+// write out anything buffered, e.g. before the reports at the end
+pub fn flush() {
+    if let Some(ref mut buffered) = *BUFFERED.lock().unwrap() {
+        let _ = buffered.out.flush();
+        buffered.flushed = Instant::now();
+    }
+}
+
+// stop or start printing, returning how many lines weren't shown while it was stopped
+pub fn pause(paused: bool) -> u64 {
+    if paused {
+        flush();
+    }
+    PAUSED.store(paused, Ordering::Relaxed);
+    HIDDEN.swap(0, Ordering::Relaxed)
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    time::{Duration, SystemTime},
+};
+
+// link types, as used in the pcap global header
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101; // IPv4/IPv6 packets with no link-layer header
+pub const LINKTYPE_IEEE802_11_RADIOTAP: u32 = 127;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const SNAPLEN: u32 = 65535;
+
+// a minimal writer for the classic (libpcap) file format, readable by Wireshark/tcpdump/aircrack-ng
+pub struct PcapWriter {
+    file: BufWriter<File>,
+}
+
+impl PcapWriter {
+    pub fn create(path: &str, linktype: u32) -> std::io::Result<PcapWriter> {
+        PcapWriter::new(File::create(path)?, linktype)
+    }
+
+    pub fn new(file: File, linktype: u32) -> std::io::Result<PcapWriter> {
+        let mut file = BufWriter::new(file);
+
+        file.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        file.write_all(&2u16.to_le_bytes())?; // major version
+        file.write_all(&4u16.to_le_bytes())?; // minor version
+        file.write_all(&0i32.to_le_bytes())?; // timezone offset
+        file.write_all(&0u32.to_le_bytes())?; // timestamp accuracy
+        file.write_all(&SNAPLEN.to_le_bytes())?;
+        file.write_all(&linktype.to_le_bytes())?;
+
+        Ok(PcapWriter { file })
+    }
+
+    pub fn write_packet(&mut self, timestamp: SystemTime, data: &[u8]) -> std::io::Result<()> {
+        self.write_truncated(timestamp, data, data.len())
+    }
+
+    // a packet we only have the first part of, which was `original_len` bytes on the wire
+    pub fn write_truncated(&mut self, timestamp: SystemTime, data: &[u8], original_len: usize) -> std::io::Result<()> {
+        let since_epoch = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let captured = data.len().min(SNAPLEN as usize);
+
+        self.file
+            .write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.file
+            .write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.file.write_all(&(captured as u32).to_le_bytes())?;
+        self.file.write_all(&(original_len.max(data.len()) as u32).to_le_bytes())?;
+        self.file.write_all(&data[..captured])?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// reads classic pcap files in either byte order, with microsecond or nanosecond timestamps, a frame at a time so a
+// big file is never all in memory at once
+pub struct PcapReader {
+    file: BufReader<File>,
+    big_endian: bool,
+    nanos: bool,
+    pub linktype: u32,
+}
+
+impl PcapReader {
+    pub fn open(path: &str) -> std::io::Result<PcapReader> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut header = [0; 24];
+        file.read_exact(&mut header).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => Error::new(ErrorKind::InvalidData, "Not a pcap file"),
+            _ => e,
+        })?;
+
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let (big_endian, nanos) = match magic {
+            0xa1b2c3d4 => (false, false),
+            0xa1b23c4d => (false, true),
+            0xd4c3b2a1 => (true, false),
+            0x4d3cb2a1 => (true, true),
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Not a pcap file (pcapng is not supported)")),
+        };
+
+        let mut reader = PcapReader {
+            file,
+            big_endian,
+            nanos,
+            linktype: 0,
+        };
+        reader.linktype = reader.u32(header[20..24].try_into().unwrap());
+
+        Ok(reader)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        let mut bytes = [0; 4];
+        self.file.read_exact(&mut bytes).ok()?;
+        Some(self.u32(bytes))
+    }
+
+    fn u32(&self, bytes: [u8; 4]) -> u32 {
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+impl Iterator for PcapReader {
+    type Item = (SystemTime, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let secs = self.read_u32()?;
+        let fraction = self.read_u32()?;
+        let captured = self.read_u32()? as usize;
+        let _original = self.read_u32()?;
+
+        // read as far as the file goes, rather than trusting a (maybe corrupt) length enough to allocate it up front
+        let mut data = Vec::new();
+        (&mut self.file).take(captured as u64).read_to_end(&mut data).ok()?;
+        if data.len() < captured {
+            return None;
+        }
+
+        let fraction = if self.nanos {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+
+        Some((SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64) + fraction, data))
+    }
+}
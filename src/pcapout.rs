@@ -0,0 +1,175 @@
+// --pcap: every frame captured, as it came off the interface, written to a pcap file for Wireshark/tcpdump
+//
+// with --ring-files N --ring-file-size M it's a flight recorder instead: frames go to capture-00001.pcap, capture-00002.pcap
+// and so on, each started once the last reaches M bytes, and only the newest N are kept, so sniff can run for ever in
+// bounded disk space. The numbering carries on from files left by an earlier run, which count towards the N
+
+use std::{collections::VecDeque, path::Path, time::SystemTime};
+
+use crate::pcap::PcapWriter;
+
+const GLOBAL_HEADER: u64 = 24;
+const RECORD_HEADER: u64 = 16;
+
+struct Ring {
+    files: usize,
+    size: u64,
+    stem: String, // the path without its extension, which the sequence number goes after
+    extension: String,
+    sequence: u64,
+    kept: VecDeque<String>, // oldest first, the current file last
+}
+
+pub struct PcapOutput {
+    path: String, // of the current file
+    linktype: u32,
+    snaplen: Option<usize>,
+    writer: Option<PcapWriter>, // none once writing's failed
+    written: u64,               // bytes in the current file
+    packets: u64,
+    ring: Option<Ring>,
+}
+
+impl PcapOutput {
+    pub fn new(path: &str, linktype: u32, snaplen: Option<usize>, ring: Option<(usize, u64)>) -> PcapOutput {
+        let ring = ring.map(|(files, size)| {
+            let (stem, extension) = match Path::new(path).extension() {
+                Some(extension) => (
+                    path[..path.len() - extension.len() - 1].to_string(),
+                    extension.to_string_lossy().to_string(),
+                ),
+                None => (path.to_string(), "pcap".to_string()),
+            };
+            let kept = existing(&stem, &extension);
+            let sequence = kept.back().map(|(sequence, _)| *sequence).unwrap_or(0);
+
+            Ring {
+                files: files.max(1),
+                size,
+                stem,
+                extension,
+                sequence,
+                kept: kept.into_iter().map(|(_, path)| path).collect(),
+            }
+        });
+
+        let mut output = PcapOutput {
+            path: path.to_string(),
+            linktype,
+            snaplen,
+            writer: None,
+            written: 0,
+            packets: 0,
+            ring,
+        };
+        if let Err(e) = output.open() {
+            panic!("Failed to create {}: {}", output.path, e);
+        }
+
+        output
+    }
+
+    // start the next file: the only one, or the next in the ring, dropping the oldest if there are too many
+    fn open(&mut self) -> std::io::Result<()> {
+        if let Some(ref mut ring) = self.ring {
+            ring.sequence += 1;
+            self.path = format!("{}-{:05}.{}", ring.stem, ring.sequence, ring.extension);
+            ring.kept.push_back(self.path.clone());
+
+            while ring.kept.len() > ring.files {
+                let oldest = ring.kept.pop_front().unwrap();
+                if let Err(e) = std::fs::remove_file(&oldest) {
+                    eprintln!("Failed to remove {}: {}", oldest, e);
+                }
+            }
+        }
+
+        self.writer = Some(PcapWriter::create(&self.path, self.linktype)?);
+        self.written = GLOBAL_HEADER;
+        Ok(())
+    }
+
+    pub fn write(&mut self, timestamp: SystemTime, frame: &[u8]) {
+        let captured = &frame[..frame.len().min(self.snaplen.unwrap_or(usize::MAX))];
+        let record = RECORD_HEADER + captured.len() as u64;
+
+        // a file gets at least one frame, however big
+        if self
+            .ring
+            .as_ref()
+            .is_some_and(|ring| self.written > GLOBAL_HEADER && self.written + record > ring.size)
+        {
+            let rotated = self
+                .writer
+                .as_mut()
+                .map(|x| x.flush())
+                .unwrap_or(Ok(()))
+                .and_then(|_| self.open());
+            if let Err(e) = rotated {
+                eprintln!("Failed to start {}, no longer writing the pcap: {}", self.path, e);
+                self.writer = None;
+            }
+        }
+
+        let Some(ref mut writer) = self.writer else {
+            return;
+        };
+        if let Err(e) = writer.write_truncated(timestamp, captured, frame.len()) {
+            eprintln!("Failed to write to {}, no longer writing the pcap: {}", self.path, e);
+            self.writer = None;
+            return;
+        }
+        self.written += record;
+        self.packets += 1;
+    }
+
+    pub fn finish(mut self) {
+        if let Some(ref mut writer) = self.writer {
+            if let Err(e) = writer.flush() {
+                eprintln!("Failed to write to {}: {}", self.path, e);
+            }
+        }
+
+        match self.ring {
+            Some(ring) => println!(
+                "Wrote {} packet{} to a ring of {} file{} ending {}",
+                self.packets,
+                if self.packets == 1 { "" } else { "s" },
+                ring.kept.len(),
+                if ring.kept.len() == 1 { "" } else { "s" },
+                self.path
+            ),
+            None => println!(
+                "Wrote {} packet{} to {}",
+                self.packets,
+                if self.packets == 1 { "" } else { "s" },
+                self.path
+            ),
+        }
+    }
+}
+
+// ring files from an earlier run, by sequence number
+fn existing(stem: &str, extension: &str) -> VecDeque<(u64, String)> {
+    let stem = Path::new(stem);
+    let dir = match stem.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}-", stem.file_name().unwrap_or_default().to_string_lossy());
+    let suffix = format!(".{}", extension);
+
+    let mut files: Vec<(u64, String)> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let sequence = name.strip_prefix(&prefix)?.strip_suffix(&suffix)?.parse().ok()?;
+            Some((sequence, entry.path().to_string_lossy().to_string()))
+        })
+        .collect();
+    files.sort();
+
+    files.into()
+}
@@ -0,0 +1,168 @@
+// extra named columns on each request, from dissectors built into sniff or from external scripts (--plugin),
+// which end up everywhere the request goes: the console, the log, --push-url and --control clients
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use crate::RequestStats;
+
+// how long a script gets to answer before the request goes out without its columns
+const SCRIPT_TIMEOUT: Duration = Duration::from_millis(200);
+
+// anything that can add columns to a request
+pub trait Plugin {
+    fn columns(&mut self, stats: &RequestStats) -> BTreeMap<String, String>;
+}
+
+// the registered plugins, run in order, so a later plugin's column replaces an earlier one of the same name
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Plugins {
+    pub fn new(scripts: &[String]) -> Plugins {
+        let mut plugins = Plugins::default();
+        for command in scripts {
+            let script = Script::spawn(command).unwrap_or_else(|e| panic!("Failed to start plugin {}: {}", command, e));
+            plugins.register(Box::new(script));
+        }
+        plugins
+    }
+
+    pub fn register(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn annotate(&mut self, stats: &mut RequestStats) {
+        for plugin in self.plugins.iter_mut() {
+            let columns = plugin.columns(stats);
+            stats.columns.extend(columns);
+        }
+    }
+}
+
+// a long-running command that's sent each request as a JSON line, {"id": N, "request": {...}}, and answers
+// each with a line of its own, {"id": N, "columns": {"name": "value", ...}}
+struct Script {
+    command: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    replies: Receiver<String>,
+    next_id: u64,
+    behind: bool, // still catching up after a timeout, so don't wait on it again
+    failed: bool,
+}
+
+impl Script {
+    fn spawn(command: &str) -> std::io::Result<Script> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        // read on our own thread, so a slow or stuck script can be given up on
+        let stdout = child.stdout.take().unwrap();
+        let (tx, replies) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Script {
+            command: command.to_string(),
+            stdin: child.stdin.take(),
+            child,
+            replies,
+            next_id: 0,
+            behind: false,
+            failed: false,
+        })
+    }
+
+    fn fail(&mut self, reason: &str) {
+        if !self.failed {
+            eprintln!("Plugin {} {}, so some requests will be missing its columns", self.command, reason);
+            self.failed = true;
+        }
+    }
+}
+
+impl Plugin for Script {
+    fn columns(&mut self, stats: &RequestStats) -> BTreeMap<String, String> {
+        let Some(ref mut stdin) = self.stdin else {
+            return BTreeMap::new();
+        };
+
+        self.next_id += 1;
+        let id = self.next_id;
+
+        let request = serde_json::json!({ "id": id, "request": stats });
+        if writeln!(stdin, "{}", request).and_then(|_| stdin.flush()).is_err() {
+            self.stdin = None;
+            self.fail("exited");
+            return BTreeMap::new();
+        }
+
+        // answers to requests we already gave up on are skipped
+        loop {
+            let timeout = if self.behind { Duration::ZERO } else { SCRIPT_TIMEOUT };
+            let line = match self.replies.recv_timeout(timeout) {
+                Ok(line) => line,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.behind = true;
+                    self.fail("is too slow");
+                    return BTreeMap::new();
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    self.stdin = None;
+                    self.fail("exited");
+                    return BTreeMap::new();
+                }
+            };
+
+            let Ok(reply) = serde_json::from_str::<serde_json::Value>(&line) else {
+                self.fail("sent something that isn't JSON");
+                continue;
+            };
+
+            match reply["id"].as_u64() {
+                Some(reply_id) if reply_id < id => continue,
+                Some(reply_id) if reply_id == id => self.behind = false,
+                _ => return BTreeMap::new(),
+            }
+
+            // strings as they are, anything else as JSON
+            return reply["columns"]
+                .as_object()
+                .map(|columns| {
+                    columns
+                        .iter()
+                        .map(|(name, value)| {
+                            let value = value.as_str().map(|x| x.to_string()).unwrap_or_else(|| value.to_string());
+                            (name.clone(), value)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+        }
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        self.stdin = None;
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,108 @@
+// --pre-roll DURATION: the way a hardware analyser keeps what led up to its trigger. With --pcap and a --trigger or
+// alert rules, frames aren't written as they're captured but held in memory, the last DURATION of them, and only go
+// into the pcap once something fires, oldest first, ahead of everything after. A trigger captures for good from then
+// on; an alert keeps the pcap going for DURATION after it (a post-roll as long as the pre-roll), and further alerts
+// extend that, before frames are held again
+//
+// the buffer's also bounded in bytes, so a flood can't take all the memory, at the cost of some of the pre-roll
+//
+// a --trigger without --pre-roll holds frames the same way, but only those of the request being collated, so the
+// pcap starts with the request that fired it rather than having everything before
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime},
+};
+
+use crate::pcapout::PcapOutput;
+
+const MAX_BYTES: usize = 256 * 1024 * 1024;
+
+pub struct PreRoll {
+    length: Option<Duration>, // none for just the request being collated
+    frames: VecDeque<(SystemTime, Vec<u8>)>, // oldest first
+    bytes: usize,
+    live_until: Option<SystemTime>, // frames up to then are written straight away
+    fired_for_good: bool,
+}
+
+impl PreRoll {
+    pub fn new(length: Duration) -> PreRoll {
+        PreRoll {
+            length: Some(length),
+            ..PreRoll::until_trigger()
+        }
+    }
+
+    pub fn until_trigger() -> PreRoll {
+        PreRoll {
+            length: None,
+            frames: VecDeque::new(),
+            bytes: 0,
+            live_until: None,
+            fired_for_good: false,
+        }
+    }
+
+    // write the frame, or hold it until something fires
+    pub fn write(&mut self, pcap_out: &mut PcapOutput, timestamp: SystemTime, frame: &[u8]) {
+        if self.fired_for_good || self.live_until.is_some_and(|x| timestamp <= x) {
+            pcap_out.write(timestamp, frame);
+            return;
+        }
+
+        self.frames.push_back((timestamp, frame.to_vec()));
+        self.bytes += frame.len();
+
+        while let Some((oldest, data)) = self.frames.front() {
+            let expired = self
+                .length
+                .is_some_and(|x| timestamp.duration_since(*oldest).unwrap_or_default() > x);
+            if !expired && self.bytes <= MAX_BYTES {
+                break;
+            }
+            self.bytes -= data.len();
+            self.frames.pop_front();
+        }
+    }
+
+    // the trigger's fired, so everything's written from now on
+    pub fn trigger(&mut self, pcap_out: &mut PcapOutput) {
+        if !self.fired_for_good {
+            self.fired_for_good = true;
+            self.drain(pcap_out);
+        }
+    }
+
+    // a request's been let through without firing the trigger: with no pre-roll, its frames aren't wanted, only the
+    // one that ended it (if a frame did), which is the start of the next
+    pub fn passed(&mut self, keep_last: bool) {
+        if self.length.is_some() || self.fired_for_good {
+            return;
+        }
+        let last = self.frames.pop_back().filter(|_| keep_last);
+        self.frames.clear();
+        self.bytes = 0;
+        if let Some((timestamp, frame)) = last {
+            self.bytes = frame.len();
+            self.frames.push_back((timestamp, frame));
+        }
+    }
+
+    // an alert on a request seen at `timestamp`: write what led up to it, and what follows for as long again
+    pub fn alert(&mut self, pcap_out: &mut PcapOutput, timestamp: SystemTime) {
+        let Some(length) = self.length else {
+            return;
+        };
+        let until = timestamp + length;
+        self.live_until = Some(self.live_until.map_or(until, |x| x.max(until)));
+        self.drain(pcap_out);
+    }
+
+    fn drain(&mut self, pcap_out: &mut PcapOutput) {
+        for (timestamp, frame) in self.frames.drain(..) {
+            pcap_out.write(timestamp, &frame);
+        }
+        self.bytes = 0;
+    }
+}
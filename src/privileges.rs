@@ -0,0 +1,29 @@
+// --user/--drop-privileges: capturing needs root, but picking apart what's captured doesn't, so once the capture
+// sockets are open we become an unprivileged user, and a bug in any of the parsers can't be turned into root
+//
+// everything opened before then (the capture sockets, --control, --metrics) carries on working; anything opened
+// after (the -l log, rotated logs, /proc for --processes) has to be allowed for the new user
+
+use std::ffi::CString;
+
+// become `user`, group and all, for good
+pub fn drop_to(user: &str) {
+    let name = CString::new(user).unwrap_or_else(|_| panic!("Invalid user name {:?}", user));
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        panic!("Cannot drop privileges: there's no user {}", user);
+    }
+    let (uid, gid) = unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) };
+
+    // groups first, while we're still allowed to change them
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+            panic!("Failed to drop privileges to {}: {}", user, std::io::Error::last_os_error());
+        }
+    }
+
+    // there's no point carrying on if we could get root back
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        panic!("Failed to drop privileges to {}: root could be regained", user);
+    }
+}
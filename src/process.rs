@@ -0,0 +1,202 @@
+// --processes: which local process owns each end of a TCP or UDP request, going by the sockets in /proc/net and the
+// file descriptors in /proc/<pid>/fd (Linux only); it's the first thing to ask about traffic you weren't expecting
+//
+// sockets come and go far faster than it's worth rereading /proc for, so the table is only reread when a lookup
+// misses, and at most every REFRESH_EVERY; a connection that opens and closes in between isn't attributed
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conf::{Direction, Protocol},
+    ip, RequestStats,
+};
+
+const REFRESH_EVERY: Duration = Duration::from_millis(250);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Process {
+    pub name: String,
+    pub pid: u32,
+}
+
+impl std::fmt::Display for Process {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (pid {})", self.name, self.pid)
+    }
+}
+
+impl Process {
+    // a --process filter: a name, or a pid
+    pub fn matches(&self, filter: &str) -> bool {
+        self.name == filter || filter.parse() == Ok(self.pid)
+    }
+}
+
+// (TCP or not, local address, local port)
+type SocketKey = (bool, IpAddr, u16);
+
+pub struct ProcessTable {
+    sockets: HashMap<SocketKey, Process>,
+    refreshed: Option<Instant>,
+}
+
+impl ProcessTable {
+    pub fn new() -> ProcessTable {
+        if std::fs::metadata("/proc/net/tcp").is_err() {
+            panic!("Showing processes needs /proc/net, which is only on Linux");
+        }
+
+        ProcessTable {
+            sockets: HashMap::new(),
+            refreshed: None,
+        }
+    }
+
+    // fill in the process fields of a request, for whichever of its ends are this host's own, keeping any that were
+    // already there (e.g. from a log)
+    pub fn annotate(&mut self, stats: &mut RequestStats) {
+        let tcp = match stats.protocol {
+            Protocol::Tcp => true,
+            Protocol::Udp => false,
+            _ => return,
+        };
+        let Some((src, dst)) = ip::transport(&stats.raw).map(|(src, dst, _)| (src, dst)) else {
+            return;
+        };
+        let (orig, dest) = match stats.direction {
+            Some(Direction::Outbound) => (true, false),
+            Some(Direction::Inbound) => (false, true),
+            Some(Direction::Local) => (true, true),
+            None => return,
+        };
+
+        if orig && stats.orig_process.is_none() {
+            stats.orig_process = self.lookup((tcp, stats.orig_ip.to_std(), src));
+        }
+        if dest && stats.dest_process.is_none() {
+            stats.dest_process = self.lookup((tcp, stats.dest_ip.to_std(), dst));
+        }
+    }
+
+    fn lookup(&mut self, key: SocketKey) -> Option<Process> {
+        if let Some(process) = self.find(key) {
+            return Some(process);
+        }
+
+        if self.refreshed.is_some_and(|x| x.elapsed() < REFRESH_EVERY) {
+            return None;
+        }
+        self.refresh();
+        self.find(key)
+    }
+
+    // a socket bound to the wildcard address (a listening server, or UDP) owns the port on every address
+    fn find(&self, (tcp, ip, port): SocketKey) -> Option<Process> {
+        let unspecified = match ip {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        [ip, unspecified, IpAddr::V6(Ipv6Addr::UNSPECIFIED)]
+            .iter()
+            .find_map(|x| self.sockets.get(&(tcp, *x, port)))
+            .cloned()
+    }
+
+    fn refresh(&mut self) {
+        self.refreshed = Some(Instant::now());
+
+        let mut inodes = HashMap::new();
+        for (path, tcp) in [
+            ("/proc/net/tcp", true),
+            ("/proc/net/tcp6", true),
+            ("/proc/net/udp", false),
+            ("/proc/net/udp6", false),
+        ] {
+            let Ok(table) = std::fs::read_to_string(path) else {
+                continue;
+            };
+            for line in table.lines().skip(1) {
+                if let Some((ip, port, inode)) = parse_socket(line) {
+                    inodes.insert(inode, (tcp, ip, port));
+                }
+            }
+        }
+
+        self.sockets.clear();
+        let Ok(entries) = std::fs::read_dir("/proc") else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|x| x.parse::<u32>().ok()) else {
+                continue;
+            };
+            // processes can be gone by the time we look, or not ours to look at
+            let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+                continue;
+            };
+            let mut name = None;
+
+            for fd in fds.flatten() {
+                let Ok(target) = std::fs::read_link(fd.path()) else {
+                    continue;
+                };
+                let Some(inode) = target
+                    .to_str()
+                    .and_then(|x| x.strip_prefix("socket:["))
+                    .and_then(|x| x.strip_suffix(']'))
+                    .and_then(|x| x.parse::<u64>().ok())
+                else {
+                    continue;
+                };
+                let Some(key) = inodes.get(&inode) else {
+                    continue;
+                };
+
+                let name = name.get_or_insert_with(|| {
+                    std::fs::read_to_string(entry.path().join("comm"))
+                        .map(|x| x.trim_end().to_string())
+                        .unwrap_or_else(|_| "?".to_string())
+                });
+                // a socket shared after a fork is put down to the first process found with it
+                self.sockets.entry(*key).or_insert_with(|| Process {
+                    name: name.clone(),
+                    pid,
+                });
+            }
+        }
+    }
+}
+
+// a line of /proc/net/{tcp,udp}{,6}: "sl local_address rem_address st ... uid timeout inode ..."
+fn parse_socket(line: &str) -> Option<(IpAddr, u16, u64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (address, port) = fields.get(1)?.split_once(':')?;
+    let inode = fields.get(9)?.parse().ok()?;
+    // sockets in TIME_WAIT no longer belong to anything
+    if inode == 0 {
+        return None;
+    }
+
+    // the address is in 32-bit words, each in the kernel's byte order
+    let mut bytes = Vec::new();
+    for word in 0..address.len() / 8 {
+        let word = u32::from_str_radix(address.get(word * 8..word * 8 + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?),
+        // IPv4 on a dual-stack socket shows up mapped into IPv6
+        16 => IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?).to_canonical(),
+        _ => return None,
+    };
+
+    Some((ip, u16::from_str_radix(port, 16).ok()?, inode))
+}
@@ -0,0 +1,368 @@
+// --unwrap-proxies: HTTP CONNECT tunnels and SOCKS4/5 connections, recognised by how the client opens them, so what
+// goes through a proxy can be put down to where it's really going rather than to the proxy
+//
+// only connections we see start (their SYN) are looked at, so nothing in the middle of a stream can be mistaken for a
+// handshake; connections already open when the capture started stay attributed to the proxy
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ip, locale::Locale, units, RequestStats};
+
+// handshakes are over in a round trip or two, but a tunnel can sit idle for a long time between requests
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+const TUNNEL_TIMEOUT: Duration = Duration::from_secs(3600);
+const PRUNE_EVERY: u64 = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ProxyKind {
+    Connect,
+    Socks4,
+    Socks5,
+}
+
+impl std::fmt::Display for ProxyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProxyKind::Connect => write!(f, "CONNECT"),
+            ProxyKind::Socks4 => write!(f, "SOCKS4"),
+            ProxyKind::Socks5 => write!(f, "SOCKS5"),
+        }
+    }
+}
+
+// where a connection through a proxy is really going
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tunnel {
+    pub kind: ProxyKind,
+    pub host: String, // a hostname, if the client asked for one, otherwise an address
+    pub port: u16,
+    pub proxy: SocketAddr,
+}
+
+impl std::fmt::Display for Tunnel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // IPv6 addresses need brackets to tell the port apart
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+// how far into its handshake a connection is
+enum State {
+    Opened,            // SYN seen, nothing sent yet
+    Greeted,           // SOCKS5 authentication methods offered, waiting for the client's request
+    Requested(Tunnel), // waiting for the proxy to say yes
+    Established(Tunnel),
+}
+
+struct Connection {
+    state: State,
+    last: SystemTime,
+    next_seq: [Option<u32>; 2], // where each end's (client, server) next new data starts
+}
+
+#[derive(Default)]
+struct Totals {
+    connections: u64,
+    refused: u64,
+    packets: u64,
+    bytes: u64,
+}
+
+#[derive(Default)]
+pub struct ProxyTracker {
+    connections: HashMap<(SocketAddr, SocketAddr), Connection>, // (client, server) -> handshake so far
+    targets: BTreeMap<(String, SocketAddr, ProxyKind), Totals>, // (target, proxy, kind) -> what went through
+    observed: u64,
+}
+
+impl ProxyTracker {
+    // look at one IP packet, following the handshakes of any TCP connections we saw open, and return where it's
+    // really going if it's part of a tunnel
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) -> Option<Tunnel> {
+        let header = ip::parse(packet).filter(|x| x.protocol == 6)?;
+        // short frames are padded out, and the padding isn't part of the segment
+        let end = ip::total_len(packet).unwrap_or(packet.len()).min(packet.len());
+        let segment = packet.get(header.header_len..end).filter(|x| x.len() >= 20)?;
+
+        let src = SocketAddr::new(header.src, u16::from_be_bytes([segment[0], segment[1]]));
+        let dst = SocketAddr::new(header.dst, u16::from_be_bytes([segment[2], segment[3]]));
+        let flags = segment[13];
+        let (fin, syn, rst, ack) = (
+            flags & 0x01 != 0,
+            flags & 0x02 != 0,
+            flags & 0x04 != 0,
+            flags & 0x10 != 0,
+        );
+        let payload = segment.get((segment[12] >> 4) as usize * 4..).unwrap_or_default();
+
+        self.observed += 1;
+        if self.observed.is_multiple_of(PRUNE_EVERY) {
+            self.prune(timestamp);
+        }
+
+        if syn && !ack {
+            self.connections.insert(
+                (src, dst),
+                Connection {
+                    state: State::Opened,
+                    last: timestamp,
+                    next_seq: [None; 2],
+                },
+            );
+            return None;
+        }
+
+        let (key, from_client) = if self.connections.contains_key(&(src, dst)) {
+            ((src, dst), true)
+        } else if self.connections.contains_key(&(dst, src)) {
+            ((dst, src), false)
+        } else {
+            return None;
+        };
+
+        let connection = self.connections.get_mut(&key).unwrap();
+        connection.last = timestamp;
+
+        if fin || rst {
+            return match self.connections.remove(&key)?.state {
+                State::Established(tunnel) => Some(tunnel),
+                _ => None,
+            };
+        }
+        if payload.is_empty() {
+            return tunnel(connection);
+        }
+
+        // a retransmission (or the second copy loopback captures show) would look like the next step of the handshake
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let next_seq = &mut connection.next_seq[if from_client { 0 } else { 1 }];
+        if next_seq.is_some_and(|x| (seq.wrapping_add(payload.len() as u32).wrapping_sub(x) as i32) <= 0) {
+            return tunnel(connection);
+        }
+        *next_seq = Some(seq.wrapping_add(payload.len() as u32));
+
+        let state = std::mem::replace(&mut connection.state, State::Opened);
+        let next = match (state, from_client) {
+            (State::Opened, true) => match socks5_greeting(payload) {
+                true => Some(State::Greeted),
+                false => connect_request(payload, key.1)
+                    .or_else(|| socks4_request(payload, key.1))
+                    .map(State::Requested),
+            },
+            // the method the proxy picked, and how any authentication went
+            (State::Greeted, false) => match payload {
+                [5, 0xff] => None,
+                [1, status] if *status != 0 => None,
+                _ => Some(State::Greeted),
+            },
+            (State::Greeted, true) => match socks5_request(payload, key.1) {
+                Some(tunnel) => Some(State::Requested(tunnel)),
+                // a username and password
+                None if payload.first() == Some(&1) => Some(State::Greeted),
+                None => None,
+            },
+            (State::Requested(tunnel), false) => {
+                let totals = self
+                    .targets
+                    .entry((tunnel.to_string(), tunnel.proxy, tunnel.kind))
+                    .or_default();
+                if accepted(tunnel.kind, payload) {
+                    totals.connections += 1;
+                    Some(State::Established(tunnel))
+                } else {
+                    totals.refused += 1;
+                    None
+                }
+            }
+            // some clients don't wait for the proxy's answer before starting on what they're tunnelling
+            (State::Requested(tunnel), true) => Some(State::Requested(tunnel)),
+            (State::Established(tunnel), _) => Some(State::Established(tunnel)),
+            // a server that speaks first isn't a proxy we know
+            (State::Opened, false) => None,
+        };
+
+        match next {
+            Some(state) => {
+                let connection = self.connections.get_mut(&key).unwrap();
+                connection.state = state;
+                tunnel(connection)
+            }
+            // not a proxy, so there's nothing more to follow
+            None => {
+                self.connections.remove(&key);
+                None
+            }
+        }
+    }
+
+    // count a request towards its tunnel's real destination
+    pub fn record(&mut self, stats: &RequestStats) {
+        let Some(ref tunnel) = stats.tunnel else {
+            return;
+        };
+
+        let totals = self
+            .targets
+            .entry((tunnel.to_string(), tunnel.proxy, tunnel.kind))
+            .or_default();
+        totals.packets += stats.packets;
+        totals.bytes += stats.bytes;
+    }
+
+    fn prune(&mut self, now: SystemTime) {
+        self.connections.retain(|_, connection| {
+            let timeout = match connection.state {
+                State::Established(_) => TUNNEL_TIMEOUT,
+                _ => HANDSHAKE_TIMEOUT,
+            };
+            now.duration_since(connection.last).unwrap_or_default() < timeout
+        });
+    }
+
+    pub fn print_report(&self, locale: Option<&Locale>) {
+        if self.targets.is_empty() {
+            return;
+        }
+
+        let mut targets: Vec<_> = self.targets.iter().collect();
+        targets.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.bytes));
+
+        println!("Proxied destinations:");
+        for ((target, proxy, kind), totals) in targets {
+            let mut counts = Vec::new();
+            if totals.packets > 0 {
+                counts.push(format!(
+                    "{} packet{}, {}",
+                    totals.packets,
+                    if totals.packets == 1 { "" } else { "s" },
+                    units::human_bytes(totals.bytes, locale)
+                ));
+            }
+            // connections are counted as they open, so not when playing a log back
+            if totals.connections > 0 {
+                counts.push(format!(
+                    "{} connection{}",
+                    totals.connections,
+                    if totals.connections == 1 { "" } else { "s" }
+                ));
+            }
+            if totals.refused > 0 {
+                counts.push(format!("{} refused", totals.refused));
+            }
+            println!("    {} via {} ({}): {}", target, proxy, kind, counts.join(", "));
+        }
+    }
+}
+
+fn tunnel(connection: &Connection) -> Option<Tunnel> {
+    match connection.state {
+        State::Established(ref tunnel) => Some(tunnel.clone()),
+        _ => None,
+    }
+}
+
+// "CONNECT example.com:443 HTTP/1.1"
+fn connect_request(payload: &[u8], proxy: SocketAddr) -> Option<Tunnel> {
+    let line = payload.strip_prefix(b"CONNECT ")?;
+    let authority = std::str::from_utf8(line.split(|x| *x == b' ').next()?).ok()?;
+
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => rest.split_once("]:")?,
+        None => authority.rsplit_once(':')?,
+    };
+
+    Some(Tunnel {
+        kind: ProxyKind::Connect,
+        host: host.to_string(),
+        port: port.parse().ok()?,
+        proxy,
+    })
+}
+
+// version 5, then the number of authentication methods on offer and the methods themselves
+fn socks5_greeting(payload: &[u8]) -> bool {
+    payload.len() >= 3 && payload[0] == 5 && payload[1] > 0 && payload.len() == 2 + payload[1] as usize
+}
+
+// version 5, CONNECT, a reserved byte, then an IPv4 address, a hostname or an IPv6 address, and the port
+fn socks5_request(payload: &[u8], proxy: SocketAddr) -> Option<Tunnel> {
+    if payload.get(..3)? != [5, 1, 0] {
+        return None;
+    }
+
+    let (host, rest) = match payload.get(3)? {
+        1 => (
+            Ipv4Addr::from(<[u8; 4]>::try_from(payload.get(4..8)?).ok()?).to_string(),
+            &payload[8..],
+        ),
+        3 => {
+            let len = *payload.get(4)? as usize;
+            (
+                std::str::from_utf8(payload.get(5..5 + len)?).ok()?.to_string(),
+                &payload[5 + len..],
+            )
+        }
+        4 => (
+            Ipv6Addr::from(<[u8; 16]>::try_from(payload.get(4..20)?).ok()?).to_string(),
+            &payload[20..],
+        ),
+        _ => return None,
+    };
+
+    Some(Tunnel {
+        kind: ProxyKind::Socks5,
+        host,
+        port: u16::from_be_bytes(rest.get(..2)?.try_into().ok()?),
+        proxy,
+    })
+}
+
+// version 4, CONNECT, the port, an IPv4 address and a user ID; SOCKS4a gives 0.0.0.x as the address and a hostname
+// after the user ID instead
+fn socks4_request(payload: &[u8], proxy: SocketAddr) -> Option<Tunnel> {
+    if payload.len() < 9 || payload[..2] != [4, 1] || payload.last() != Some(&0) {
+        return None;
+    }
+
+    let port = u16::from_be_bytes([payload[2], payload[3]]);
+    let address = Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]);
+    let mut fields = payload[8..payload.len() - 1].split(|x| *x == 0);
+    let _user = fields.next()?;
+
+    let host = match (address.octets(), fields.next()) {
+        ([0, 0, 0, x], Some(hostname)) if x != 0 => std::str::from_utf8(hostname).ok()?.to_string(),
+        _ => IpAddr::V4(address).to_string(),
+    };
+
+    Some(Tunnel {
+        kind: ProxyKind::Socks4,
+        host,
+        port,
+        proxy,
+    })
+}
+
+// the proxy's answer to the request: a 2xx status, or success in SOCKS terms
+fn accepted(kind: ProxyKind, payload: &[u8]) -> bool {
+    match kind {
+        ProxyKind::Connect => {
+            payload.starts_with(b"HTTP/1.")
+                && payload
+                    .split(|x| *x == b' ')
+                    .nth(1)
+                    .is_some_and(|x| x.starts_with(b"2"))
+        }
+        ProxyKind::Socks4 => payload.get(..2) == Some(&[0, 0x5a]),
+        ProxyKind::Socks5 => payload.get(..2) == Some(&[5, 0]),
+    }
+}
@@ -0,0 +1,140 @@
+// batches requests up and POSTs them to a collector as a JSON array, from a background thread
+// a failed batch is retried with exponential backoff, and dropped once we run out of retries
+
+use std::{
+    sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::RequestStats;
+
+// how long a partial batch can wait before it's sent anyway, unless --flush-interval says otherwise
+const FLUSH_EVERY: Duration = Duration::from_secs(5);
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+pub struct Pusher {
+    tx: Option<SyncSender<String>>,
+    worker: Option<JoinHandle<PushStats>>,
+    dropped: u64, // requests we couldn't queue because the collector is too far behind
+}
+
+#[derive(Default)]
+struct PushStats {
+    sent: u64,
+    failed: u64,
+}
+
+impl Pusher {
+    pub fn new(url: &str, batch_size: usize, flush_every: Option<Duration>) -> Pusher {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            panic!("Invalid push URL {}, expected http:// or https://", url);
+        }
+
+        // enough slack to ride out a few retries before we start dropping
+        let (tx, rx) = sync_channel((batch_size * 10).max(10_000));
+        let url = url.to_string();
+        let flush_every = flush_every.unwrap_or(FLUSH_EVERY);
+
+        Pusher {
+            tx: Some(tx),
+            worker: Some(std::thread::spawn(move || run(url, batch_size, flush_every, rx))),
+            dropped: 0,
+        }
+    }
+
+    // never blocks the capture; if the collector can't keep up, requests are dropped and counted
+    pub fn push(&mut self, stats: &RequestStats) {
+        let Some(ref tx) = self.tx else { return };
+
+        match tx.try_send(serde_json::to_string(stats).unwrap()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => self.dropped += 1,
+        }
+    }
+
+    // send whatever's still queued, then report how it went
+    pub fn finish(mut self) {
+        drop(self.tx.take());
+
+        let stats = self.worker.take().unwrap().join().unwrap_or_default();
+
+        println!(
+            "Pushed {} request{}{}{}",
+            stats.sent,
+            if stats.sent == 1 { "" } else { "s" },
+            if stats.failed > 0 { format!(", {} failed", stats.failed) } else { String::new() },
+            if self.dropped > 0 { format!(", {} dropped (collector too slow)", self.dropped) } else { String::new() },
+        );
+    }
+}
+
+fn run(url: String, batch_size: usize, flush_every: Duration, rx: Receiver<String>) -> PushStats {
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build();
+    let mut stats = PushStats::default();
+    let mut batch: Vec<String> = Vec::with_capacity(batch_size);
+    let mut oldest = Instant::now();
+
+    loop {
+        let open = match rx.recv_timeout(flush_every.saturating_sub(oldest.elapsed())) {
+            Ok(line) => {
+                if batch.is_empty() {
+                    oldest = Instant::now();
+                }
+                batch.push(line);
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => false,
+        };
+
+        let due = batch.len() >= batch_size || (!batch.is_empty() && oldest.elapsed() >= flush_every);
+
+        if due || (!open && !batch.is_empty()) {
+            let count = batch.len() as u64;
+            if send(&agent, &url, &batch) {
+                stats.sent += count;
+            } else {
+                stats.failed += count;
+            }
+            batch.clear();
+            oldest = Instant::now();
+        }
+
+        if !open {
+            return stats;
+        }
+    }
+}
+
+// the requests are already serialised, so the body is just a JSON array of them
+fn send(agent: &ureq::Agent, url: &str, batch: &[String]) -> bool {
+    let body = format!("[{}]", batch.join(","));
+    let mut backoff = FIRST_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+
+        match result {
+            Ok(_) => return true,
+            // a 4xx means the collector doesn't want this batch, so sending it again won't help
+            Err(ureq::Error::Status(code, _)) if (400..500).contains(&code) && code != 429 => {
+                eprintln!("Collector {} rejected a batch of {} requests: HTTP {}", url, batch.len(), code);
+                return false;
+            }
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                eprintln!("Failed to push to {} (attempt {}/{}), retrying in {}s: {}", url, attempt, MAX_ATTEMPTS, backoff.as_secs(), e);
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(e) => eprintln!("Failed to push {} requests to {}, giving up: {}", batch.len(), url, e),
+        }
+    }
+
+    false
+}
@@ -0,0 +1,131 @@
+// QoS markings from a request's first IP header, for debugging VoIP and other traffic that's meant to be prioritised:
+// its DSCP (the top 6 bits of IPv4's old TOS byte, or IPv6's traffic class) and ECN (the bottom 2), and for IPv6 the
+// flow label. DSCPs go by their per-hop behaviour names (EF, AF41, CS6, ...) where they have one
+
+use std::{
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+// the per-hop behaviours with names, from RFCs 2474, 2597, 3246, 5865 and 8622
+const NAMES: [(&str, u8); 23] = [
+    ("be", 0),
+    ("le", 1),
+    ("cs1", 8),
+    ("af11", 10),
+    ("af12", 12),
+    ("af13", 14),
+    ("cs2", 16),
+    ("af21", 18),
+    ("af22", 20),
+    ("af23", 22),
+    ("cs3", 24),
+    ("af31", 26),
+    ("af32", 28),
+    ("af33", 30),
+    ("cs4", 32),
+    ("af41", 34),
+    ("af42", 36),
+    ("af43", 38),
+    ("cs5", 40),
+    ("va", 44),
+    ("ef", 46),
+    ("cs6", 48),
+    ("cs7", 56),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Dscp(pub u8);
+
+impl FromStr for Dscp {
+    type Err = Error;
+
+    // a name (ef, af41, cs6; be, default or cs0 for 0) or a number from 0 to 63
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        if let Some((_, value)) = NAMES.iter().find(|(name, _)| *name == s) {
+            return Ok(Dscp(*value));
+        }
+        match s.as_str() {
+            "default" | "cs0" => Ok(Dscp(0)),
+            _ => match s.parse::<u8>() {
+                Ok(value) if value < 64 => Ok(Dscp(value)),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid DSCP {}, expected a name like ef, af41 or cs6, or a number from 0 to 63",
+                        s
+                    ),
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Dscp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match NAMES.iter().find(|(_, value)| *value == self.0) {
+            Some((name, _)) => write!(f, "{}", name.to_uppercase()),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+pub struct Qos {
+    pub dscp: Dscp,
+    pub ecn: u8,
+    pub flow_label: Option<u32>, // IPv6 only
+}
+
+impl Qos {
+    // an IP packet's markings
+    pub fn parse(packet: &[u8]) -> Option<Qos> {
+        match packet.first()? >> 4 {
+            4 => {
+                let tos = *packet.get(1)?;
+                Some(Qos {
+                    dscp: Dscp(tos >> 2),
+                    ecn: tos & 0x03,
+                    flow_label: None,
+                })
+            }
+            6 => {
+                let first = u32::from_be_bytes(packet.get(0..4)?.try_into().ok()?);
+                let traffic_class = (first >> 20) as u8;
+                Some(Qos {
+                    dscp: Dscp(traffic_class >> 2),
+                    ecn: traffic_class & 0x03,
+                    flow_label: Some(first & 0x000f_ffff),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn ecn_name(&self) -> &'static str {
+        match self.ecn {
+            0 => "Not-ECT",
+            1 => "ECT(1)",
+            2 => "ECT(0)",
+            _ => "CE",
+        }
+    }
+
+    // only what's been set, e.g. "DSCP EF, ECT(0), flow label 0x5f3a1", for the verbose line; unmarked traffic (best
+    // effort, no ECN and no flow label) gets nothing
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.dscp.0 != 0 {
+            parts.push(format!("DSCP {}", self.dscp));
+        }
+        if self.ecn != 0 {
+            parts.push(self.ecn_name().to_string());
+        }
+        if let Some(label) = self.flow_label.filter(|x| *x != 0) {
+            parts.push(format!("flow label {:#x}", label));
+        }
+        parts.join(", ")
+    }
+}
@@ -0,0 +1,314 @@
+// QUIC (and so HTTP/3): UDP flows that start with a client's Initial packet are labelled as such rather than as plain
+// UDP, with the server name the client asked for. Initial packets are encrypted, but with keys derived from the
+// connection ID in their header (RFC 9001 5.2), so anyone watching can decrypt them to read the ClientHello inside
+//
+// the rest of the connection can't be read, so the label is remembered for the flow, by its two ends
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+};
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes128Gcm, Nonce,
+};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{conf::Protocol, ip, sni, RequestStats};
+
+const VERSION_1: u32 = 0x00000001;
+const VERSION_2: u32 = 0x6b3343cf;
+
+// forgotten all at once when there are this many, rather than keeping track of which are stale
+const MAX_FLOWS: usize = 65536;
+// more than any ClientHello needs, even split over several Initial packets
+const MAX_CRYPTO: u64 = 16384;
+
+// CRYPTO frames' data, by offset
+type Fragments = Vec<(u64, Vec<u8>)>;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuicInfo {
+    pub version: u32,
+    pub server: SocketAddr,
+    pub server_name: Option<String>,
+    pub http3: bool, // the client offered HTTP/3 (h3) in its ALPN
+}
+
+impl std::fmt::Display for QuicInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", if self.http3 { "HTTP/3" } else { "QUIC" })?;
+        if let Some(ref name) = self.server_name {
+            write!(f, " ({})", name)?;
+        }
+        Ok(())
+    }
+}
+
+struct Flow {
+    info: QuicInfo,
+    crypto: BTreeMap<u64, Vec<u8>>, // the ClientHello so far, by offset
+    read: bool,                     // whether we've had all of it
+}
+
+#[derive(Default)]
+pub struct QuicFlows {
+    flows: HashMap<(SocketAddr, SocketAddr), Flow>, // by the ends, lower first
+}
+
+impl QuicFlows {
+    // the QUIC connection a UDP request belongs to, if it's one we saw start
+    pub fn annotate(&mut self, stats: &RequestStats) -> Option<QuicInfo> {
+        if stats.protocol != Protocol::Udp {
+            return None;
+        }
+
+        let mut found = None;
+        for packet in ip::split_packets(&stats.raw) {
+            let (Some(header), Some((src_port, dst_port, payload))) = (ip::parse(packet), ip::transport(packet)) else {
+                continue;
+            };
+            let (src, dst) = (
+                SocketAddr::new(header.src, src_port),
+                SocketAddr::new(header.dst, dst_port),
+            );
+            let key = if src < dst { (src, dst) } else { (dst, src) };
+
+            if let Some((version, crypto)) = client_initial(payload) {
+                if !self.flows.contains_key(&key) && self.flows.len() >= MAX_FLOWS {
+                    self.flows.clear();
+                }
+                let flow = self.flows.entry(key).or_insert_with(|| Flow {
+                    info: QuicInfo {
+                        version,
+                        server: dst,
+                        server_name: None,
+                        http3: false,
+                    },
+                    crypto: BTreeMap::new(),
+                    read: false,
+                });
+                flow.add_crypto(crypto);
+            }
+
+            if let Some(flow) = self.flows.get(&key) {
+                found = Some(flow.info.clone());
+            }
+        }
+
+        found
+    }
+}
+
+impl Flow {
+    fn add_crypto(&mut self, fragments: Fragments) {
+        if self.read {
+            return;
+        }
+        for (offset, data) in fragments {
+            if offset + (data.len() as u64) <= MAX_CRYPTO {
+                self.crypto.insert(offset, data);
+            }
+        }
+
+        // as much of the ClientHello as has arrived in order
+        let mut hello = Vec::new();
+        for (offset, data) in self.crypto.iter() {
+            let offset = *offset as usize;
+            if offset > hello.len() {
+                break;
+            }
+            hello.extend_from_slice(data.get(hello.len() - offset..).unwrap_or_default());
+        }
+
+        // handshake type (1) and length (3)
+        let Some(length) = hello
+            .get(1..4)
+            .map(|x| u32::from_be_bytes([0, x[0], x[1], x[2]]) as usize)
+        else {
+            return;
+        };
+        if hello.len() < 4 + length {
+            return;
+        }
+
+        if let Some(client_hello) = sni::parse_client_hello(&hello[..4 + length]) {
+            self.info.http3 = client_hello.alpn.iter().any(|x| x == "h3" || x.starts_with("h3-"));
+            self.info.server_name = client_hello.server_name;
+        }
+        self.crypto.clear();
+        self.read = true;
+    }
+}
+
+// the version and CRYPTO frames of a client's Initial packet, decrypted
+fn client_initial(data: &[u8]) -> Option<(u32, Fragments)> {
+    let first = *data.first()?;
+    // a long header, with the fixed bit set
+    if first & 0xc0 != 0xc0 {
+        return None;
+    }
+
+    let version = u32::from_be_bytes(data.get(1..5)?.try_into().ok()?);
+    let (salt, prefix, initial_type): (&[u8], &[u8], u8) = match version {
+        VERSION_1 => (
+            &[
+                0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad, 0xcc,
+                0xbb, 0x7f, 0x0a,
+            ],
+            b"quic ",
+            0,
+        ),
+        VERSION_2 => (
+            &[
+                0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe, 0x6e, 0x26, 0x9d, 0xcb, 0xf9,
+                0xbd, 0x2e, 0xd9,
+            ],
+            b"quicv2 ",
+            1,
+        ),
+        _ => return None,
+    };
+    if (first >> 4) & 0x03 != initial_type {
+        return None;
+    }
+
+    let dcid_len = *data.get(5)? as usize;
+    if dcid_len > 20 {
+        return None;
+    }
+    let dcid = data.get(6..6 + dcid_len)?;
+    let mut at = 6 + dcid_len;
+    at += 1 + *data.get(at)? as usize; // source connection id
+    let (token_len, n) = varint(data.get(at..)?)?;
+    at += n + token_len as usize;
+    let (length, n) = varint(data.get(at..)?)?;
+    at += n;
+    let pn_offset = at;
+    let packet_end = pn_offset.checked_add(length as usize)?;
+    if packet_end > data.len() {
+        return None;
+    }
+
+    // the client's keys, from the destination connection id it picked
+    let (_, initial) = Hkdf::<Sha256>::extract(Some(salt), dcid);
+    let client = Hkdf::<Sha256>::from_prk(&expand_label(&initial, b"client in", 32)?).ok()?;
+    let label = |name: &[u8]| [prefix, name].concat();
+    let key = expand_label(&client, &label(b"key"), 16)?;
+    let iv = expand_label(&client, &label(b"iv"), 12)?;
+    let hp = expand_label(&client, &label(b"hp"), 16)?;
+
+    // take off the header protection, which hides the packet number (and its length)
+    let mut mask = GenericArray::clone_from_slice(data.get(pn_offset + 4..pn_offset + 20)?);
+    aes::Aes128::new(GenericArray::from_slice(&hp)).encrypt_block(&mut mask);
+
+    let first = first ^ (mask[0] & 0x0f);
+    let pn_len = (first & 0x03) as usize + 1;
+    let mut header = data.get(..pn_offset + pn_len)?.to_vec();
+    header[0] = first;
+    let mut packet_number: u64 = 0;
+    for i in 0..pn_len {
+        header[pn_offset + i] ^= mask[1 + i];
+        packet_number = packet_number << 8 | header[pn_offset + i] as u64;
+    }
+
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&iv);
+    for (i, byte) in packet_number.to_be_bytes().iter().enumerate() {
+        nonce[4 + i] ^= byte;
+    }
+
+    // a server's Initial (or anything that only looks like one) doesn't decrypt with the client's keys
+    let payload = Aes128Gcm::new(GenericArray::from_slice(&key))
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: data.get(pn_offset + pn_len..packet_end)?,
+                aad: &header,
+            },
+        )
+        .ok()?;
+
+    Some((version, crypto_frames(&payload)))
+}
+
+// the CRYPTO frames among an Initial packet's frames, which can only be PADDING, PING, ACK, CRYPTO and
+// CONNECTION_CLOSE
+fn crypto_frames(mut data: &[u8]) -> Fragments {
+    let mut frames = Vec::new();
+
+    let next = |data: &mut &[u8]| -> Option<u64> {
+        let (value, n) = varint(data)?;
+        *data = &data[n..];
+        Some(value)
+    };
+
+    while !data.is_empty() {
+        let Some(kind) = next(&mut data) else {
+            break;
+        };
+        match kind {
+            0x00 | 0x01 => {} // PADDING, PING
+            // ACK (with ECN counts): largest, delay, range count, first range, then the ranges
+            0x02 | 0x03 => {
+                let ranges = (|| {
+                    next(&mut data)?;
+                    next(&mut data)?;
+                    let count = next(&mut data)?;
+                    next(&mut data)?;
+                    for _ in 0..count * 2 + if kind == 0x03 { 3 } else { 0 } {
+                        next(&mut data)?;
+                    }
+                    Some(())
+                })();
+                if ranges.is_none() {
+                    break;
+                }
+            }
+            0x06 => {
+                let (Some(offset), Some(len)) = (next(&mut data), next(&mut data)) else {
+                    break;
+                };
+                let Some(crypto) = data.get(..len as usize) else {
+                    break;
+                };
+                frames.push((offset, crypto.to_vec()));
+                data = &data[len as usize..];
+            }
+            _ => break,
+        }
+    }
+
+    frames
+}
+
+// HKDF-Expand-Label from TLS 1.3, with an empty context
+fn expand_label(secret: &Hkdf<Sha256>, label: &[u8], len: usize) -> Option<Vec<u8>> {
+    let label = [b"tls13 ", label].concat();
+    let mut info = Vec::new();
+    info.extend_from_slice(&(len as u16).to_be_bytes());
+    info.push(label.len() as u8);
+    info.extend_from_slice(&label);
+    info.push(0);
+
+    let mut out = vec![0; len];
+    secret.expand(&info, &mut out).ok()?;
+    Some(out)
+}
+
+// a QUIC variable-length integer, and how many bytes it took
+fn varint(data: &[u8]) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = 1 << (first >> 6);
+    let bytes = data.get(..len)?;
+
+    let mut value = (first & 0x3f) as u64;
+    for byte in &bytes[1..] {
+        value = value << 8 | *byte as u64;
+    }
+    Some((value, len))
+}
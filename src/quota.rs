@@ -0,0 +1,180 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    conf::{Config, QuotaAction},
+    rotate,
+    theme::Theme,
+    units,
+};
+
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+// warn once usage passes this share of the quota, and prune back down to it
+const WARN_AT: f64 = 0.9;
+
+// keeps what sniff writes (logs and handshake captures) within --max-disk, and stops before the disk fills up
+pub struct DiskGuard {
+    max_bytes: Option<u64>,
+    min_free: u64,
+    action: QuotaAction,
+    files: Vec<PathBuf>, // files we append to, and can't prune (though their rotated copies can be)
+    dirs: Vec<PathBuf>,  // directories we fill with files, oldest first to go
+    theme: Theme,
+    last_check: Instant,
+    warned: bool,
+}
+
+impl DiskGuard {
+    // nothing to guard unless we're writing something
+    pub fn new(config: &Config, theme: Theme) -> Option<DiskGuard> {
+        let files: Vec<PathBuf> = config.log_file.iter().map(PathBuf::from).collect();
+        let dirs: Vec<PathBuf> = config.handshake_dir.iter().map(PathBuf::from).collect();
+
+        if files.is_empty() && dirs.is_empty() {
+            return None;
+        }
+
+        Some(DiskGuard {
+            max_bytes: config.max_disk,
+            min_free: config.min_free,
+            action: config.on_quota,
+            files,
+            dirs,
+            theme,
+            last_check: Instant::now(),
+            warned: false,
+        })
+    }
+
+    // called from the capture loop; returns true if the capture has to stop
+    pub fn tick(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_EVERY {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        if let Some(free) = self.free_space() {
+            if free < self.min_free {
+                self.warn(&format!(
+                    "*** only {} free on the capture disk (minimum {}), stopping ***",
+                    units::human_bytes(free, None),
+                    units::human_bytes(self.min_free, None)
+                ));
+                return true;
+            }
+        }
+
+        let Some(max_bytes) = self.max_bytes else {
+            return false;
+        };
+
+        let mut used = self.usage();
+
+        if used as f64 >= max_bytes as f64 * WARN_AT && !self.warned {
+            self.warned = true;
+            self.warn(&format!(
+                "*** {} of {} disk quota used ***",
+                units::human_bytes(used, None),
+                units::human_bytes(max_bytes, None)
+            ));
+        }
+
+        if used < max_bytes {
+            return false;
+        }
+
+        if self.action == QuotaAction::Prune {
+            let target = (max_bytes as f64 * WARN_AT) as u64;
+
+            for (path, size) in self.prunable() {
+                if used <= target {
+                    break;
+                }
+                if std::fs::remove_file(&path).is_ok() {
+                    used = used.saturating_sub(size);
+                    self.warn(&format!("*** disk quota reached, removed {} ***", path.display()));
+                }
+            }
+
+            if used < max_bytes {
+                self.warned = false;
+                return false;
+            }
+        }
+
+        self.warn(&format!(
+            "*** disk quota of {} reached ({} used), stopping ***",
+            units::human_bytes(max_bytes, None),
+            units::human_bytes(used, None)
+        ));
+        true
+    }
+
+    fn warn(&self, message: &str) {
+        outln!("{}", self.theme.paint(self.theme.warning, message));
+    }
+
+    fn usage(&self) -> u64 {
+        let files: u64 = self
+            .files
+            .iter()
+            .filter_map(|x| std::fs::metadata(x).ok())
+            .map(|x| x.len())
+            .sum();
+
+        files + self.prunable().iter().map(|(_, size)| size).sum::<u64>()
+    }
+
+    // rotated logs and files in our output directories, oldest first
+    fn prunable(&self) -> Vec<(PathBuf, u64)> {
+        let mut files: Vec<(SystemTime, PathBuf, u64)> = self
+            .dirs
+            .iter()
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|x| x.path()))
+            .chain(self.files.iter().flat_map(|x| rotate::rotated_files(x)))
+            .filter_map(|path| {
+                let metadata = std::fs::metadata(&path).ok()?;
+                metadata
+                    .is_file()
+                    .then(|| (metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), path, metadata.len()))
+            })
+            .collect();
+
+        files.sort();
+        files.into_iter().map(|(_, path, size)| (path, size)).collect()
+    }
+
+    // the least free space on any of the disks we write to
+    fn free_space(&self) -> Option<u64> {
+        self.files
+            .iter()
+            .map(|x| x.parent().map(Path::to_path_buf).unwrap_or_default())
+            .chain(self.dirs.iter().cloned())
+            .filter_map(|dir| free_space(if dir.as_os_str().is_empty() { Path::new(".") } else { &dir }))
+            .min()
+    }
+}
+
+#[cfg(unix)]
+fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
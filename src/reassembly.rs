@@ -0,0 +1,197 @@
+// IPv4 fragments are held back until the whole datagram is in, then passed on as one packet, so e.g. a large DNS
+// answer over UDP shows up as one datagram of its real size, rather than a first fragment with a UDP header and some
+// more with garbage where their ports would be
+//
+// like the kernel, fragments are given up on if the rest of the datagram doesn't turn up within 30s, and a datagram
+// that's sent in too many pieces (or too many overlapping copies of them) is dropped, so a flood of tiny fragments
+// for one ID can't keep growing until then
+
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime},
+};
+
+use crate::{checksum, metrics::METRICS};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+// past this many datagrams being put back together, new ones are let through as they are
+const MAX_PENDING: usize = 1024;
+const MAX_DATAGRAM: usize = 65535;
+// a 64KB datagram is 45 fragments at Ethernet's MTU; overlaps and retransmissions can take it past that, but not far
+const MAX_PARTS: usize = 256;
+const MAX_HELD: usize = 2 * MAX_DATAGRAM;
+// copies of fragments turning up just after their datagram's been completed (loopback shows every packet twice) are
+// dropped, rather than starting it again
+const LATE_WINDOW: Duration = Duration::from_secs(2);
+
+pub enum Fragment {
+    Whole,                  // not a fragment
+    Held,                   // a fragment of a datagram that isn't complete yet
+    Complete(Vec<u8>, u64), // the last fragment of a datagram: the whole datagram, and how many fragments it came in
+}
+
+// (src, dst, identification, protocol)
+type DatagramKey = (Ipv4Addr, Ipv4Addr, u16, u8);
+
+struct Datagram {
+    header: Option<Vec<u8>>,      // from the first fragment
+    parts: Vec<(usize, Vec<u8>)>, // offset into the payload, and the data there
+    len: Option<usize>,           // of the payload, once the last fragment's in
+    started: SystemTime,
+}
+
+#[derive(Default)]
+pub struct Reassembler {
+    pending: HashMap<DatagramKey, Datagram>,
+    completed: HashMap<DatagramKey, SystemTime>,
+    checked: Option<SystemTime>,
+}
+
+impl Reassembler {
+    // take an IP packet, holding it back if it's part of a datagram that isn't complete
+    pub fn push(&mut self, packet: &[u8], timestamp: SystemTime) -> Fragment {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return Fragment::Whole;
+        }
+
+        let flags_offset = u16::from_be_bytes([packet[6], packet[7]]);
+        let more = flags_offset & 0x2000 != 0;
+        let offset = (flags_offset & 0x1fff) as usize * 8;
+        if !more && offset == 0 {
+            return Fragment::Whole;
+        }
+
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.expire(timestamp);
+        }
+
+        let header_len = (packet[0] & 0x0f) as usize * 4;
+        // short frames are padded out, and the padding isn't part of the fragment
+        let end = (u16::from_be_bytes([packet[2], packet[3]]) as usize).min(packet.len());
+        let Some(data) = packet.get(header_len..end) else {
+            return Fragment::Whole;
+        };
+        if header_len + offset + data.len() > MAX_DATAGRAM {
+            return Fragment::Whole;
+        }
+
+        let key = (
+            Ipv4Addr::from(<[u8; 4]>::try_from(&packet[12..16]).unwrap()),
+            Ipv4Addr::from(<[u8; 4]>::try_from(&packet[16..20]).unwrap()),
+            u16::from_be_bytes([packet[4], packet[5]]),
+            packet[9],
+        );
+
+        if self
+            .completed
+            .get(&key)
+            .is_some_and(|x| timestamp.duration_since(*x).unwrap_or_default() < LATE_WINDOW)
+        {
+            return Fragment::Held;
+        }
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING {
+            return Fragment::Whole;
+        }
+        let datagram = self.pending.entry(key).or_insert_with(|| Datagram {
+            header: None,
+            parts: Vec::new(),
+            len: None,
+            started: timestamp,
+        });
+
+        if offset == 0 {
+            datagram.header = Some(packet[..header_len].to_vec());
+        }
+        if !more {
+            datagram.len = Some(offset + data.len());
+        }
+        // a fragment seen again replaces the copy we have
+        datagram
+            .parts
+            .retain(|(x, part)| (*x, part.len()) != (offset, data.len()));
+        datagram.parts.push((offset, data.to_vec()));
+        let held: usize = datagram.parts.iter().map(|(_, part)| part.len()).sum();
+
+        if datagram.parts.len() > MAX_PARTS || held > MAX_HELD {
+            let dropped = self.pending.remove(&key).unwrap().parts.len() as u64;
+            METRICS.fragments_dropped.fetch_add(dropped, Ordering::Relaxed);
+            return Fragment::Held;
+        }
+
+        match datagram.assemble() {
+            Some(whole) => {
+                let fragments = self.pending.remove(&key).unwrap().parts.len() as u64;
+                self.completed.insert(key, timestamp);
+                METRICS.reassembled.fetch_add(1, Ordering::Relaxed);
+                Fragment::Complete(whole, fragments)
+            }
+            None => Fragment::Held,
+        }
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        self.pending.retain(|_, datagram| {
+            let waiting = now.duration_since(datagram.started).unwrap_or_default() < TIMEOUT;
+            if !waiting {
+                METRICS
+                    .fragments_expired
+                    .fetch_add(datagram.parts.len() as u64, Ordering::Relaxed);
+            }
+            waiting
+        });
+        self.completed
+            .retain(|_, x| now.duration_since(*x).unwrap_or_default() < LATE_WINDOW);
+    }
+}
+
+impl Datagram {
+    // the whole datagram, if every part of it is in
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let (header, len) = (self.header.as_ref()?, self.len?);
+
+        let mut parts: Vec<_> = self
+            .parts
+            .iter()
+            .map(|(offset, data)| (*offset, offset + data.len()))
+            .collect();
+        parts.sort();
+        let mut covered = 0;
+        for (start, end) in parts {
+            if start > covered {
+                return None;
+            }
+            covered = covered.max(end);
+        }
+        if covered < len {
+            return None;
+        }
+
+        let mut packet = header.clone();
+        packet.resize(header.len() + len, 0);
+        // where fragments overlap, the later one wins
+        for (offset, data) in self.parts.iter() {
+            let end = (offset + data.len()).min(len);
+            if *offset < end {
+                packet[header.len() + offset..header.len() + end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+
+        // it's one packet now: the whole length, no fragment flags or offset, and a checksum to match
+        let total = packet.len() as u16;
+        packet[2..4].copy_from_slice(&total.to_be_bytes());
+        packet[6] &= 0x40; // keeping don't fragment
+        packet[7] = 0;
+        packet[10..12].copy_from_slice(&[0, 0]);
+        let checksum = checksum::internet(&packet[..header.len()], 0);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        Some(packet)
+    }
+}
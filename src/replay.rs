@@ -0,0 +1,236 @@
+// re-transmit a saved log or pcap file on an interface, for load and lab testing
+
+use std::{
+    sync::atomic::Ordering,
+    time::{Instant, SystemTime},
+};
+
+use pnet::datalink::DataLinkSender;
+
+use crate::{checksum, conf::Config, ip, pcap, units, window, RequestStats, RUNNING};
+
+pub fn replay(path: &str, tx: &mut dyn DataLinkSender, config: &Config) {
+    let (start, mut frames) = match pcap::PcapReader::open(path) {
+        Ok(reader) if reader.linktype == pcap::LINKTYPE_ETHERNET => {
+            let frames: Vec<(SystemTime, Vec<u8>)> = reader.collect();
+            // a pcap doesn't say when its capture started, so it's taken to be its first frame
+            (frames.first().map_or(SystemTime::UNIX_EPOCH, |(timestamp, _)| *timestamp), frames)
+        }
+        Ok(reader) => {
+            eprintln!("Can only replay Ethernet captures, {} has link type {}", path, reader.linktype);
+            return;
+        }
+        // not a pcap, so it should be one of our own logs
+        Err(_) => frames_from_log(path),
+    };
+
+    let window = window::Window::new(config.from.as_ref(), config.to.as_ref(), start);
+    frames.retain(|(timestamp, _)| window.contains(*timestamp));
+
+    let Some(first) = frames.first().map(|(timestamp, _)| *timestamp) else {
+        println!("Nothing to replay");
+        return;
+    };
+
+    let started = Instant::now();
+    let mut sent = 0;
+    let mut bytes = 0;
+    let mut failed = 0;
+
+    for (timestamp, mut frame) in frames {
+        if !RUNNING.load(Ordering::SeqCst) {
+            break;
+        }
+
+        // keep the original spacing between packets, scaled by the speed multiplier
+        if config.replay_speed > 0.0 {
+            let due = timestamp.duration_since(first).unwrap_or_default().div_f64(config.replay_speed);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+
+        rewrite(&mut frame, config);
+
+        match tx.send_to(&frame, None) {
+            Some(Ok(())) => {
+                sent += 1;
+                bytes += frame.len() as u64;
+            }
+            Some(Err(e)) => {
+                if failed == 0 {
+                    eprintln!("Failed to send a {} byte frame: {}", frame.len(), e);
+                }
+                failed += 1;
+            }
+            None => failed += 1,
+        }
+    }
+
+    println!(
+        "Replayed {} frames ({}) in {:.2}s{}",
+        sent,
+        units::format_bytes(bytes, None, config.raw_bytes, None),
+        started.elapsed().as_secs_f32(),
+        if failed > 0 { format!(", {} failed", failed) } else { String::new() },
+    );
+}
+
+// rebuild Ethernet frames from a log, which only keeps the IP packets and the MAC addresses, returning them with when
+// the capture started
+pub fn frames_from_log(path: &str) -> (SystemTime, Vec<(SystemTime, Vec<u8>)>) {
+    let logs = crate::logfile::load(path).unwrap_or_else(|e| match e {
+        crate::error::Error::Io(e) => panic!("Failed to read {}: {}", path, e),
+        e @ crate::error::Error::NewerLog(..) => panic!("Cannot read {}: {}", path, e),
+        e => panic!("{} is not a pcap file or a sniff log: {}", path, e),
+    });
+
+    // every packet in a request shares its timestamp, so they go out back to back
+    let frames = logs
+        .packets
+        .iter()
+        .flat_map(|stats| frames(stats).into_iter().map(|frame| (stats.timestamp, frame)))
+        .collect();
+
+    (logs.start_time, frames)
+}
+
+// a request's packets as Ethernet frames
+pub fn frames(stats: &RequestStats) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+
+    for packet in ip::split_captured(&stats.raw, &stats.captured) {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&stats.dest_mac.octets());
+        frame.extend_from_slice(&stats.orig_mac.octets());
+
+        if let Some(vlan) = stats.vlan {
+            frame.extend_from_slice(&[0x81, 0x00]);
+            frame.extend_from_slice(&vlan.to_be_bytes());
+        }
+
+        frame.extend_from_slice(if packet[0] >> 4 == 6 { &[0x86, 0xdd] } else { &[0x08, 0x00] });
+        frame.extend_from_slice(packet);
+
+        frames.push(frame);
+    }
+
+    frames
+}
+
+fn rewrite(frame: &mut [u8], config: &Config) {
+    if frame.len() < 14 {
+        return;
+    }
+
+    if let Some(ref rewrites) = config.rewrite_macs {
+        for range in [0..6, 6..12] {
+            if let Some(rewrite) = rewrites.iter().find(|x| x.from.octets() == frame[range.clone()]) {
+                frame[range].copy_from_slice(&rewrite.to.octets());
+            }
+        }
+    }
+
+    let Some(ref rewrites) = config.rewrite_ips else {
+        return;
+    };
+
+    // skip any VLAN tags to find the IP header
+    let mut offset = 12;
+    while matches!(u16::from_be_bytes([frame[offset], frame[offset + 1]]), 0x8100 | 0x88a8 | 0x9100)
+        && frame.len() >= offset + 6
+    {
+        offset += 4;
+    }
+    let ethertype = u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+    let packet = &mut frame[offset + 2..];
+
+    let (src, dst) = match ethertype {
+        0x0800 if packet.len() >= 20 => (12..16, 16..20),
+        0x86dd if packet.len() >= 40 => (8..24, 24..40),
+        _ => return,
+    };
+
+    let mut changed = false;
+    for range in [src, dst] {
+        let address = match range.len() {
+            4 => std::net::IpAddr::from(<[u8; 4]>::try_from(&packet[range.clone()]).unwrap()),
+            _ => std::net::IpAddr::from(<[u8; 16]>::try_from(&packet[range.clone()]).unwrap()),
+        };
+
+        let replacement = match rewrites.iter().find(|x| x.from == address).map(|x| x.to) {
+            Some(std::net::IpAddr::V4(ip)) if range.len() == 4 => ip.octets().to_vec(),
+            Some(std::net::IpAddr::V6(ip)) if range.len() == 16 => ip.octets().to_vec(),
+            _ => continue,
+        };
+
+        packet[range].copy_from_slice(&replacement);
+        changed = true;
+    }
+
+    if changed {
+        fix_checksums(packet);
+    }
+}
+
+// recalculate the IPv4 header checksum, and the TCP/UDP/ICMPv6 checksum which covers the addresses too
+fn fix_checksums(packet: &mut [u8]) {
+    let (protocol, header_len, payload_len, pseudo_addresses) = if packet[0] >> 4 == 4 {
+        let header_len = (packet[0] & 0x0f) as usize * 4;
+        if packet.len() < header_len {
+            return;
+        }
+
+        packet[10..12].copy_from_slice(&[0, 0]);
+        let checksum = checksum::internet(&packet[..header_len], 0);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        // the transport checksum covers the whole datagram, which a fragment doesn't have
+        let fragmented = packet[6] & 0x20 != 0 || u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff != 0;
+        if fragmented {
+            return;
+        }
+
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        (packet[9], header_len, total_len.saturating_sub(header_len), packet[12..20].to_vec())
+    } else {
+        let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+        (packet[6], 40, payload_len, packet[8..40].to_vec())
+    };
+
+    let offset = match protocol {
+        6 => 16,  // TCP
+        17 => 6,  // UDP
+        58 => 2,  // ICMPv6
+        _ => return,
+    };
+
+    if packet.len() < header_len + payload_len || payload_len < offset + 2 {
+        return;
+    }
+
+    let segment = &mut packet[header_len..header_len + payload_len];
+
+    // a zero UDP checksum over IPv4 means "not computed", so leave it that way
+    if protocol == 17 && pseudo_addresses.len() == 8 && segment[6..8] == [0, 0] {
+        return;
+    }
+
+    segment[offset..offset + 2].copy_from_slice(&[0, 0]);
+
+    let mut pseudo = pseudo_addresses;
+    if pseudo.len() == 8 {
+        pseudo.extend_from_slice(&[0, protocol]);
+        pseudo.extend_from_slice(&(payload_len as u16).to_be_bytes());
+    } else {
+        pseudo.extend_from_slice(&(payload_len as u32).to_be_bytes());
+        pseudo.extend_from_slice(&[0, 0, 0, protocol]);
+    }
+    pseudo.extend_from_slice(segment);
+
+    let mut checksum = checksum::internet(&pseudo, 0);
+    if checksum == 0 && protocol == 17 {
+        checksum = 0xffff;
+    }
+    segment[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+}
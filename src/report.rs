@@ -0,0 +1,259 @@
+// `sniff report`: an offline summary of a saved log or pcap, instead of one-off scripts over the JSON
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    conf::{Config, Protocol},
+    convert, ip, pcap, roles, services, theme, timing, units, window,
+};
+
+// one request from a log, or one frame from a pcap
+pub struct Record {
+    pub timestamp: SystemTime,
+    pub orig: String,
+    pub dest: String,
+    pub protocol: Protocol,
+    pub ports: Option<(u16, u16)>,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Totals {
+    packets: u64,
+    bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, record: &Record) {
+        self.packets += record.packets;
+        self.bytes += record.bytes;
+    }
+}
+
+pub fn run(path: &str, top: usize, config: &Config) {
+    let mut roles = roles::RoleTracker::default();
+    let (start, mut records) = load(path, &mut roles);
+
+    let window = window::Window::new(config.from.as_ref(), config.to.as_ref(), start);
+    records.retain(|x| window.contains(x.timestamp));
+
+    let (Some(first), Some(last)) = (
+        records.iter().map(|x| x.timestamp).min(),
+        records.iter().map(|x| x.timestamp).max(),
+    ) else {
+        if window.is_everything() {
+            println!("{} is empty", path);
+        } else {
+            println!("Nothing in {} between --from and --to", path);
+        }
+        return;
+    };
+
+    let services = services::Services::load();
+    let bytes = |n: u64| units::format_bytes(n, None, config.raw_bytes, None);
+
+    let mut total = Totals::default();
+    let mut protocols: HashMap<String, Totals> = HashMap::new();
+    let mut sources: HashMap<&str, Totals> = HashMap::new();
+    let mut destinations: HashMap<&str, Totals> = HashMap::new();
+    let mut ports: HashMap<(u16, Protocol), Totals> = HashMap::new();
+    let mut minutes: HashMap<u64, Totals> = HashMap::new();
+
+    for record in records.iter() {
+        total.add(record);
+        protocols.entry(record.protocol.to_string()).or_default().add(record);
+        sources.entry(&record.orig).or_default().add(record);
+        destinations.entry(&record.dest).or_default().add(record);
+
+        // the lower port is (nearly always) the service
+        if let Some((src, dst)) = record.ports {
+            ports.entry((src.min(dst), record.protocol)).or_default().add(record);
+        }
+
+        let minute = record.timestamp.duration_since(first).unwrap_or_default().as_secs() / 60;
+        minutes.entry(minute).or_default().add(record);
+    }
+
+    let span = last.duration_since(first).unwrap_or_default();
+
+    println!("Report for {}:", path);
+    println!(
+        "    {} packets ({}) in {} records over {}",
+        total.packets,
+        bytes(total.bytes),
+        records.len(),
+        format_duration(span),
+    );
+    println!(
+        "    average {}/s",
+        units::human_bytes((total.bytes as f64 / span.as_secs_f64().max(1.0)) as u64, None)
+    );
+
+    if let Some((minute, busiest)) = minutes.iter().max_by_key(|(minute, x)| (x.bytes, std::cmp::Reverse(**minute))) {
+        println!(
+            "    busiest minute: {} to {} ({} packets, {})",
+            format_duration(Duration::from_secs(minute * 60)),
+            format_duration(Duration::from_secs((minute + 1) * 60)),
+            busiest.packets,
+            bytes(busiest.bytes),
+        );
+    }
+
+    let share = |x: u64| x as f64 / total.bytes.max(1) as f64 * 100.0;
+
+    println!("Protocols:");
+    for (protocol, totals) in sorted(protocols, usize::MAX) {
+        println!("    {:<24} {:>10} packets {:>12} ({:.1}%)", protocol, totals.packets, bytes(totals.bytes), share(totals.bytes));
+    }
+
+    // hosts with whatever roles they seem to have, e.g. "192.168.1.1 (gateway, DNS server)"
+    let host = |host: &str| match roles.roles_by_name(host) {
+        x if x.is_empty() => host.to_string(),
+        x => format!("{} ({})", host, roles::describe(&x)),
+    };
+
+    println!("Top sources:");
+    for (name, totals) in sorted(sources, top) {
+        println!("    {:<40} {:>10} packets {:>12} ({:.1}%)", host(name), totals.packets, bytes(totals.bytes), share(totals.bytes));
+    }
+
+    println!("Top destinations:");
+    for (name, totals) in sorted(destinations, top) {
+        println!("    {:<40} {:>10} packets {:>12} ({:.1}%)", host(name), totals.packets, bytes(totals.bytes), share(totals.bytes));
+    }
+
+    if !ports.is_empty() {
+        println!("Top ports:");
+        for ((port, protocol), totals) in sorted(ports, top) {
+            let name = match services.name(port, protocol) {
+                Some(name) if !config.no_service_names => format!("{}/{} ({})", port, protocol, name),
+                _ => format!("{}/{}", port, protocol),
+            };
+            println!("    {:<24} {:>10} packets {:>12} ({:.1}%)", name, totals.packets, bytes(totals.bytes), share(totals.bytes));
+        }
+    }
+
+    // a log only has requests, so handshakes and responses can't be timed from it
+    if let Ok(reader) = pcap::PcapReader::open(path) {
+        let mut latency = timing::ServiceLatency::default();
+        for (timestamp, frame) in reader.filter(|(timestamp, _)| window.contains(*timestamp)) {
+            if let Some(packet) = crate::parse_frame(&frame).ok().filter(|x| x.protocol == Protocol::Tcp) {
+                latency.observe(&packet.payload, timestamp);
+            }
+        }
+        latency.print_report(&services, !config.no_service_names, top);
+    }
+
+    roles.print_report(&theme::Theme::new(config.theme, config.color, config.highlight_color));
+}
+
+// every record in a saved log or pcap, and when its capture started
+pub fn load(path: &str, roles: &mut roles::RoleTracker) -> (SystemTime, Vec<Record>) {
+    match pcap::PcapReader::open(path) {
+        Ok(reader) if reader.linktype == pcap::LINKTYPE_ETHERNET => records_from_pcap(reader, roles),
+        Ok(reader) => panic!("Can only report on Ethernet captures, {} has link type {}", path, reader.linktype),
+        // not a pcap, so it should be one of our own logs
+        Err(_) => records_from_log(path, roles),
+    }
+}
+
+// biggest first, by bytes
+fn sorted<K: Ord>(map: HashMap<K, Totals>, top: usize) -> Vec<(K, Totals)> {
+    let mut entries: Vec<(K, Totals)> = map.into_iter().collect();
+    entries.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(top);
+    entries
+}
+
+// also used for --trace-pipeline's stages, which take micro- or milliseconds rather than minutes
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0 if duration.as_micros() < 1000 => format!("{}µs", duration.as_micros()),
+        0 => format!("{:.2}ms", duration.as_secs_f64() * 1000.0),
+        1..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m{:02}s", secs / 3600, secs % 3600 / 60, secs % 60),
+    }
+}
+
+fn records_from_log(path: &str, roles: &mut roles::RoleTracker) -> (SystemTime, Vec<Record>) {
+    let logs = crate::logfile::load(path).unwrap_or_else(|e| match e {
+        crate::error::Error::Io(e) => panic!("Failed to read {}: {}", path, e),
+        e @ crate::error::Error::NewerLog(..) => panic!("Cannot read {}: {}", path, e),
+        e => panic!("{} is not a pcap file or a sniff log: {}", path, e),
+    });
+
+    let records = logs
+        .packets
+        .into_iter()
+        .map(|stats| {
+            roles.observe(&stats);
+
+            // frames without IP addresses are counted against their MAC addresses instead, as in a pcap
+            let by_mac = matches!(stats.protocol, Protocol::Ether(_) | Protocol::Unknown);
+            let (mut orig, mut dest) = if by_mac {
+                (stats.orig_mac.to_string(), stats.dest_mac.to_string())
+            } else {
+                (stats.orig_ip.to_string(), stats.dest_ip.to_string())
+            };
+
+            // traffic through a proxy (logged with --unwrap-proxies) is counted against where it was really going
+            if let Some(ref tunnel) = stats.tunnel {
+                if tunnel.proxy.ip() == stats.dest_ip.to_std() {
+                    dest = tunnel.host.clone();
+                } else if tunnel.proxy.ip() == stats.orig_ip.to_std() {
+                    orig = tunnel.host.clone();
+                }
+            }
+
+            Record {
+                timestamp: stats.timestamp,
+                ports: match stats.protocol {
+                    Protocol::Tcp | Protocol::Udp => ip::transport(&stats.raw).map(|(src, dst, _)| (src, dst)),
+                    _ => None,
+                },
+                orig,
+                dest,
+                protocol: stats.protocol,
+                packets: stats.packets,
+                bytes: stats.bytes,
+            }
+        })
+        .collect();
+
+    (logs.start_time, records)
+}
+
+// a pcap doesn't say when its capture started, so it's taken to be its first frame
+fn records_from_pcap(reader: pcap::PcapReader, roles: &mut roles::RoleTracker) -> (SystemTime, Vec<Record>) {
+    let records: Vec<Record> = reader
+        .map(|(timestamp, frame)| {
+            let stats = convert::frame_stats(timestamp, &frame);
+            roles.observe(&stats);
+
+            // frames without IP addresses are counted against their MAC addresses instead
+            let by_mac = matches!(stats.protocol, Protocol::Ether(_) | Protocol::Unknown);
+
+            Record {
+                timestamp,
+                ports: match stats.protocol {
+                    Protocol::Tcp | Protocol::Udp => ip::transport(&stats.raw).map(|(src, dst, _)| (src, dst)),
+                    _ => None,
+                },
+                orig: if by_mac { stats.orig_mac.to_string() } else { stats.orig_ip.to_string() },
+                dest: if by_mac { stats.dest_mac.to_string() } else { stats.dest_ip.to_string() },
+                protocol: stats.protocol,
+                packets: 1,
+                bytes: stats.bytes,
+            }
+        })
+        .collect();
+
+    let start = records.iter().map(|x| x.timestamp).min().unwrap_or(SystemTime::UNIX_EPOCH);
+    (start, records)
+}
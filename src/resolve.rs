@@ -0,0 +1,127 @@
+// forward lookups for the hostnames given to -F, -X and --highlight-ips, so `-F example.com` matches traffic to and
+// from any of example.com's addresses (A and AAAA), not only requests whose reverse DNS happens to give that name.
+// Each name's looked up at startup, then again every minute in the background, so round-robin DNS and addresses
+// that change under us are followed. A lookup that fails keeps the addresses from the last one that worked
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{atomic::Ordering, Arc, Mutex, Once},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aliases::Aliases,
+    conf::{Config, IpAddrOrHostname},
+    RUNNING,
+};
+
+const RESOLVE_EVERY: Duration = Duration::from_secs(60);
+// how often the background thread looks for names that are due, or new (e.g. from the control socket)
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Entry {
+    addresses: Vec<IpAddr>,
+    resolved: Option<Instant>,
+}
+
+#[derive(Clone)]
+pub struct HostAddresses {
+    names: Arc<Mutex<HashMap<String, Entry>>>,
+    started: Arc<Once>, // the background thread, once there's a name to look up
+}
+
+impl HostAddresses {
+    pub fn new(config: &Config, aliases: Option<&Aliases>) -> HostAddresses {
+        let lookups = HostAddresses {
+            names: Arc::default(),
+            started: Arc::new(Once::new()),
+        };
+
+        let rules = [&config.filter_ips, &config.exclude_ips, &config.highlight_ips];
+        let names: Vec<&String> = rules
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|rule| match rule {
+                // IPv4 addresses are taken as names too, and matched as they're written
+                IpAddrOrHostname::Hostname(name) if name.parse::<IpAddr>().is_err() => Some(name),
+                _ => None,
+            })
+            // names from --aliases are only ever matched as they're shown
+            .filter(|name| !aliases.is_some_and(|x| x.contains(name)))
+            .collect();
+        if names.is_empty() {
+            return lookups;
+        }
+
+        // up front, so the first requests are matched too
+        for name in names {
+            let addresses = lookup(name);
+            if addresses.is_empty() {
+                eprintln!("Couldn't resolve {}, matching it by name until it does", name);
+            }
+            lookups.names.lock().unwrap().insert(
+                name.to_lowercase(),
+                Entry {
+                    addresses,
+                    resolved: Some(Instant::now()),
+                },
+            );
+        }
+
+        lookups.start();
+        lookups
+    }
+
+    // whether ip is one of name's addresses; a name we haven't seen before (e.g. one excluded over the control
+    // socket) is looked up in the background, and doesn't match until it's been resolved
+    pub fn matches(&self, name: &str, ip: IpAddr) -> bool {
+        let mut names = self.names.lock().unwrap();
+        if let Some(entry) = names.get(&name.to_lowercase()) {
+            return entry.addresses.contains(&ip);
+        }
+
+        names.insert(name.to_lowercase(), Entry::default());
+        self.start();
+        false
+    }
+
+    fn start(&self) {
+        let background = self.clone();
+        self.started.call_once(|| {
+            std::thread::spawn(move || background.run());
+        });
+    }
+
+    fn run(&self) {
+        while RUNNING.load(Ordering::SeqCst) {
+            let due: Vec<String> = self
+                .names
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, entry)| entry.resolved.is_none_or(|x| x.elapsed() >= RESOLVE_EVERY))
+                .map(|(name, _)| name.clone())
+                .collect();
+
+            // resolved without the lock held, since a lookup can take a while
+            for name in due {
+                let addresses = lookup(&name);
+                if let Some(entry) = self.names.lock().unwrap().get_mut(&name) {
+                    if !addresses.is_empty() {
+                        entry.addresses = addresses;
+                    }
+                    entry.resolved = Some(Instant::now());
+                }
+            }
+
+            std::thread::sleep(CHECK_EVERY);
+        }
+    }
+}
+
+fn lookup(name: &str) -> Vec<IpAddr> {
+    dns_lookup::lookup_host(name).unwrap_or_default()
+}
@@ -0,0 +1,237 @@
+// what each host on the network seems to be for, going by the traffic it sends: who answers DNS and DHCP, who
+// serves files and print jobs, which MAC everything off-net goes through, and who's just a client
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::{
+    conf::{IpAddr, MacAddr, Protocol},
+    ip,
+    theme::Theme,
+    RequestStats,
+};
+
+// a MAC carrying traffic for this many different internet addresses is routing for them
+const GATEWAY_ADDRESSES: usize = 4;
+// a host that's only ever a client, of this many others, is someone's computer
+const WORKSTATION_PEERS: usize = 3;
+// stop counting peers here, so a busy host doesn't keep growing its set
+const MAX_PEERS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    Gateway,
+    DhcpServer,
+    DnsServer,
+    Nas,
+    Printer,
+    Workstation,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Role::Gateway => write!(f, "gateway"),
+            Role::DhcpServer => write!(f, "DHCP server"),
+            Role::DnsServer => write!(f, "DNS server"),
+            Role::Nas => write!(f, "NAS"),
+            Role::Printer => write!(f, "printer"),
+            Role::Workstation => write!(f, "workstation"),
+        }
+    }
+}
+
+// the role of whoever answers from this port
+fn served_role(port: u16) -> Option<Role> {
+    match port {
+        53 => Some(Role::DnsServer),
+        139 | 445 | 548 | 2049 => Some(Role::Nas), // SMB, AFP, NFS
+        515 | 631 | 9100 => Some(Role::Printer),   // LPD, IPP, JetDirect
+        _ => None,
+    }
+}
+
+pub fn is_internet(ip: &IpAddr) -> bool {
+    match ip.to_std() {
+        std::net::IpAddr::V4(ip) => {
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_multicast() || ip.is_broadcast() || ip.is_unspecified())
+        }
+        std::net::IpAddr::V6(ip) => {
+            // global unicast is 2000::/3
+            ip.segments()[0] & 0xe000 == 0x2000
+        }
+    }
+}
+
+#[derive(Default)]
+struct Host {
+    mac: Option<MacAddr>,
+    roles: BTreeSet<Role>,
+    servers: HashSet<IpAddr>, // who it's been a client of
+}
+
+#[derive(Default)]
+pub struct RoleTracker {
+    hosts: HashMap<IpAddr, Host>,
+    routed: HashMap<MacAddr, HashSet<IpAddr>>, // internet addresses seen behind each MAC
+    routers: HashSet<MacAddr>,                 // MACs speaking a routing protocol
+}
+
+impl RoleTracker {
+    pub fn observe(&mut self, stats: &RequestStats) {
+        if matches!(stats.protocol, Protocol::Ether(_) | Protocol::Unknown) {
+            return;
+        }
+
+        for (ip, mac) in [(&stats.orig_ip, stats.orig_mac), (&stats.dest_ip, stats.dest_mac)] {
+            if is_internet(ip) {
+                let addresses = self.routed.entry(mac).or_default();
+                if addresses.len() < GATEWAY_ADDRESSES {
+                    addresses.insert(ip.clone());
+                }
+            }
+        }
+
+        if !stats.routing.is_empty() {
+            self.routers.insert(stats.orig_mac);
+        }
+
+        if stats.dhcp.as_ref().is_some_and(|x| matches!(x.message_type, 2 | 5 | 6)) {
+            self.host(stats).roles.insert(Role::DhcpServer);
+        }
+
+        let Some((src_port, dst_port, _)) = ip::transport(&stats.raw) else {
+            return;
+        };
+
+        // only an answer from a service port counts, so a scan doesn't make everything it touches a server
+        if let Some(role) = served_role(src_port) {
+            if src_port < dst_port {
+                self.host(stats).roles.insert(role);
+            }
+        }
+
+        // from an ephemeral port to a well-known one: a client
+        if src_port >= 1024 && dst_port < 1024 {
+            let servers = &mut self.host(stats).servers;
+            if servers.len() < MAX_PEERS {
+                servers.insert(stats.dest_ip.clone());
+            }
+        }
+    }
+
+    // the sender of this request
+    fn host(&mut self, stats: &RequestStats) -> &mut Host {
+        let host = self.hosts.entry(stats.orig_ip.clone()).or_default();
+        host.mac = Some(stats.orig_mac);
+        host
+    }
+
+    fn gateways(&self) -> HashSet<MacAddr> {
+        self.routed
+            .iter()
+            .filter(|(_, addresses)| addresses.len() >= GATEWAY_ADDRESSES)
+            .map(|(mac, _)| *mac)
+            .chain(self.routers.iter().copied())
+            .collect()
+    }
+
+    fn host_roles(&self, ip: &IpAddr, host: &Host, gateways: &HashSet<MacAddr>) -> Vec<Role> {
+        let mut roles = host.roles.clone();
+
+        // remote hosts are only known for what they serve: they share their gateway's MAC, and we only see
+        // the part of their traffic that comes our way
+        if is_internet(ip) {
+            return roles.into_iter().collect();
+        }
+
+        if host.mac.is_some_and(|x| gateways.contains(&x)) {
+            roles.insert(Role::Gateway);
+        }
+        if roles.is_empty() && host.servers.len() >= WORKSTATION_PEERS {
+            roles.insert(Role::Workstation);
+        }
+
+        roles.into_iter().collect()
+    }
+
+    // every host with a role, by address
+    fn all_roles(&self) -> BTreeMap<String, (Option<MacAddr>, Vec<Role>)> {
+        let gateways = self.gateways();
+        let mut all: BTreeMap<String, (Option<MacAddr>, Vec<Role>)> = self
+            .hosts
+            .iter()
+            .map(|(ip, host)| {
+                let mac = if is_internet(ip) { None } else { host.mac };
+                (ip.to_string(), (mac, self.host_roles(ip, host, &gateways)))
+            })
+            .filter(|(_, (_, roles))| !roles.is_empty())
+            .collect();
+
+        // a gateway we never saw with an address of its own
+        for mac in gateways.iter() {
+            if !all.values().any(|(x, _)| *x == Some(*mac)) {
+                all.insert(mac.to_string(), (Some(*mac), vec![Role::Gateway]));
+            }
+        }
+
+        all
+    }
+
+    // the roles of a device by its MAC and/or address, for other views to show
+    pub fn roles(&self, mac: Option<MacAddr>, ip: Option<&IpAddr>) -> Vec<Role> {
+        let gateways = self.gateways();
+        let mut roles: BTreeSet<Role> = BTreeSet::new();
+
+        if let Some((ip, host)) = ip.and_then(|x| self.hosts.get_key_value(x)) {
+            roles.extend(self.host_roles(ip, host, &gateways));
+        }
+        if mac.is_some_and(|x| gateways.contains(&x)) {
+            roles.insert(Role::Gateway);
+        }
+
+        roles.into_iter().collect()
+    }
+
+    pub fn roles_by_name(&self, name: &str) -> Vec<Role> {
+        match name.parse::<IpAddr>() {
+            Ok(ip) => self.roles(None, Some(&ip)),
+            Err(_) => self.roles(name.parse().ok(), None),
+        }
+    }
+
+    pub fn print_report(&self, theme: &Theme) {
+        let all = self.all_roles();
+        if all.is_empty() {
+            return;
+        }
+
+        println!("Host roles:");
+        for (name, (mac, roles)) in all.iter() {
+            match mac {
+                Some(mac) if mac.to_string() != *name => println!("    {} ({}): {}", name, mac, describe(roles)),
+                _ => println!("    {}: {}", name, describe(roles)),
+            }
+        }
+
+        // more than one of these on a segment is usually a rogue or misconfigured device
+        for role in [Role::DhcpServer, Role::Gateway] {
+            // a device with several addresses is still one device
+            let mut devices = HashSet::new();
+            let local: Vec<&str> = all
+                .iter()
+                .filter(|(name, (_, roles))| roles.contains(&role) && name.parse::<IpAddr>().map_or(true, |x| !is_internet(&x)))
+                .filter(|(name, (mac, _))| devices.insert(mac.map(|x| x.to_string()).unwrap_or_else(|| name.to_string())))
+                .map(|(name, _)| name.as_str())
+                .collect();
+
+            if local.len() > 1 {
+                let message = format!("*** {} devices acting as {}s: {} ***", local.len(), role, local.join(", "));
+                println!("{}", theme.paint(theme.warning, &message));
+            }
+        }
+    }
+}
+
+pub fn describe(roles: &[Role]) -> String {
+    roles.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")
+}
@@ -0,0 +1,204 @@
+// rolls the log over to a timestamped file once it gets too big or too old, e.g.
+// capture.json -> capture-20261017-190203.json(.gz), keeping at most --log-keep of them
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::conf::Config;
+
+pub struct LogRotator {
+    max_size: Option<u64>,
+    interval: Option<Duration>,
+    gzip: bool,
+    keep: Option<usize>,
+    opened: Instant, // when the current log file was started
+    upload: Option<Sender<PathBuf>>, // --upload, for each log once it's rotated (and compressed)
+}
+
+impl LogRotator {
+    pub fn new(config: &Config, upload: Option<Sender<PathBuf>>) -> Option<LogRotator> {
+        if config.log_rotate_size.is_none() && config.log_rotate_interval.is_none() {
+            return None;
+        }
+
+        Some(LogRotator {
+            max_size: config.log_rotate_size,
+            interval: config.log_rotate_interval,
+            // a compressed log is rotated as it is
+            gzip: config.log_rotate_gzip && config.log_compress.is_none(),
+            keep: config.log_keep,
+            opened: Instant::now(),
+            upload,
+        })
+    }
+
+    // called before each write to the log
+    pub fn maybe_rotate(&mut self, path: &str) {
+        let size = std::fs::metadata(path).map(|x| x.len()).unwrap_or(0);
+        if size == 0 {
+            return;
+        }
+
+        let too_big = self.max_size.is_some_and(|max| size >= max);
+        let too_old = self.interval.is_some_and(|interval| self.opened.elapsed() >= interval);
+        if !too_big && !too_old {
+            return;
+        }
+
+        self.opened = Instant::now();
+
+        let rotated = rotated_name(Path::new(path), SystemTime::now());
+        if let Err(e) = std::fs::rename(path, &rotated) {
+            eprintln!("Failed to rotate log {}: {}", path, e);
+            return;
+        }
+
+        // compressing a big log takes a while, so don't hold up the capture for it
+        let (gzip, keep, path, upload) = (self.gzip, self.keep, PathBuf::from(path), self.upload.clone());
+        std::thread::spawn(move || {
+            let mut finished = rotated.clone();
+            if gzip {
+                match compress(&rotated) {
+                    Ok(()) => finished = PathBuf::from(format!("{}.gz", rotated.display())),
+                    Err(e) => eprintln!("Failed to compress rotated log {}: {}", rotated.display(), e),
+                }
+            }
+            if let Some(upload) = upload {
+                let _ = upload.send(finished);
+            }
+            if let Some(keep) = keep {
+                prune(&path, keep);
+            }
+        });
+    }
+}
+
+// the log's rotated files, oldest first
+pub fn rotated_files(path: &Path) -> Vec<PathBuf> {
+    let (prefix, extension) = split_name(path);
+    let prefix = format!("{}-", prefix);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut files: Vec<(String, u32, PathBuf)> = std::fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let name = name.strip_suffix(".gz").unwrap_or(&name);
+
+            // YYYYMMDD-HHMMSS, then a counter if we rotated more than once that second
+            let stamp = name.strip_prefix(&prefix)?.strip_suffix(&extension)?;
+            let (time, counter) = (stamp.get(..15)?, stamp.get(15..)?);
+            if !time.chars().all(|c| c.is_ascii_digit() || c == '-') {
+                return None;
+            }
+            let counter = match counter.strip_prefix('.') {
+                Some(counter) => counter.parse().ok()?,
+                None if counter.is_empty() => 0,
+                None => return None,
+            };
+
+            Some((time.to_string(), counter, entry.path()))
+        })
+        .collect();
+
+    files.sort();
+    files.into_iter().map(|(_, _, path)| path).collect()
+}
+
+// "capture.json" -> ("capture", ".json")
+fn split_name(path: &Path) -> (String, String) {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = path
+        .extension()
+        .map(|x| format!(".{}", x.to_string_lossy()))
+        .unwrap_or_default();
+    (stem, extension)
+}
+
+fn rotated_name(path: &Path, time: SystemTime) -> PathBuf {
+    let (stem, extension) = split_name(path);
+    let stamp = timestamp(time);
+
+    let taken = |x: &PathBuf| x.exists() || PathBuf::from(format!("{}.gz", x.display())).exists();
+
+    let mut rotated = path.with_file_name(format!("{}-{}{}", stem, stamp, extension));
+    let mut counter = 1;
+    while taken(&rotated) {
+        rotated = path.with_file_name(format!("{}-{}.{}{}", stem, stamp, counter, extension));
+        counter += 1;
+    }
+    rotated
+}
+
+// YYYYMMDD-HHMMSS in UTC
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let ((year, month, day), rem, _) = utc(time);
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+// e.g. 2026-10-17T12:30:00.123456Z, for syslog
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let ((year, month, day), rem, micros) = utc(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        micros
+    )
+}
+
+// the civil date, seconds into the day and microseconds into the second
+fn utc(time: SystemTime) -> ((i64, i64, i64), i64, u32) {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    (civil_date(days), rem, since_epoch.subsec_micros())
+}
+
+// days since the epoch to a civil date (Howard Hinnant's algorithm)
+pub fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn compress(path: &Path) -> std::io::Result<()> {
+    let gz_path = PathBuf::from(format!("{}.gz", path.display()));
+
+    let mut encoder = flate2::write::GzEncoder::new(std::fs::File::create(&gz_path)?, flate2::Compression::default());
+    encoder.write_all(&std::fs::read(path)?)?;
+    encoder.finish()?;
+
+    std::fs::remove_file(path)
+}
+
+fn prune(path: &Path, keep: usize) {
+    let files = rotated_files(path);
+
+    for old in files.iter().take(files.len().saturating_sub(keep)) {
+        if let Err(e) = std::fs::remove_file(old) {
+            eprintln!("Failed to remove old log {}: {}", old.display(), e);
+        }
+    }
+}
@@ -0,0 +1,578 @@
+// OSPF (IP protocol 89) and BGP (TCP port 179) messages, so routing churn on the segment shows up,
+// plus per-peer totals and a warning when a BGP speaker we weren't told about turns up
+// also VRRP (IP protocol 112) and HSRP (UDP port 1985) adverts, to follow which router is master of each group
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::{IpAddr, Ipv4Addr},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ip, theme::Theme, RequestStats};
+
+const OSPF: u8 = 89;
+const VRRP: u8 = 112;
+const BGP_PORT: u16 = 179;
+const HSRP_PORT: u16 = 1985;
+
+// the HSRP state of the router forwarding for the group
+const HSRP_ACTIVE: u8 = 16;
+
+const OSPF_HEADER_LEN: usize = 24;
+const LSA_HEADER_LEN: usize = 20;
+const BGP_HEADER_LEN: usize = 19;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    OspfHello {
+        router_id: Ipv4Addr,
+        area: Ipv4Addr,
+        designated: Ipv4Addr,
+        neighbors: usize,
+    },
+    // database description, link state request/update/acknowledgement
+    OspfLinkState {
+        kind: u8,
+        router_id: Ipv4Addr,
+        area: Ipv4Addr,
+        lsas: usize,
+    },
+    BgpOpen {
+        asn: u32,
+        hold_time: u16,
+        router_id: Ipv4Addr,
+    },
+    BgpUpdate {
+        as_path: Vec<u32>,
+        announced: Vec<String>,
+        withdrawn: Vec<String>,
+    },
+    BgpNotification {
+        code: u8,
+        subcode: u8,
+    },
+    BgpKeepalive,
+    Vrrp {
+        version: u8,
+        group: u8,
+        priority: u8,
+        addresses: Vec<IpAddr>,
+    },
+    Hsrp {
+        version: u8,
+        group: u16,
+        state: u8,
+        priority: u32,
+        virtual_ip: Option<IpAddr>,
+    },
+}
+
+impl Message {
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Message::OspfHello { .. } | Message::OspfLinkState { .. } => "OSPF",
+            Message::Vrrp { .. } => "VRRP",
+            Message::Hsrp { .. } => "HSRP",
+            _ => "BGP",
+        }
+    }
+}
+
+// every routing message in a request's packets
+pub fn decode(stats: &RequestStats) -> Vec<Message> {
+    let mut messages = Vec::new();
+
+    for packet in ip::split_captured(&stats.raw, &stats.captured) {
+        let Some(header) = ip::parse(packet) else { continue };
+
+        if header.protocol == OSPF {
+            messages.extend(ospf(&packet[header.header_len..]));
+        } else if header.protocol == VRRP {
+            messages.extend(vrrp(&packet[header.header_len..], header.src.is_ipv6()));
+        } else if let Some((src_port, dst_port, payload)) = ip::transport(packet) {
+            if src_port == BGP_PORT || dst_port == BGP_PORT {
+                messages.extend(bgp(payload));
+            } else if header.protocol == 17 && dst_port == HSRP_PORT {
+                messages.extend(hsrp(payload));
+            }
+        }
+    }
+
+    messages
+}
+
+fn ipv4(bytes: &[u8]) -> Option<Ipv4Addr> {
+    Some(Ipv4Addr::from(<[u8; 4]>::try_from(bytes.get(..4)?).ok()?))
+}
+
+fn ospf(packet: &[u8]) -> Option<Message> {
+    let header = packet.get(..OSPF_HEADER_LEN)?;
+    let kind = header[1];
+    let len = (u16::from_be_bytes([header[2], header[3]]) as usize).min(packet.len());
+    let router_id = ipv4(&header[4..])?;
+    let area = ipv4(&header[8..])?;
+    let body = packet.get(OSPF_HEADER_LEN..len)?;
+
+    Some(match kind {
+        // mask, hello interval, options, priority, dead interval, DR, BDR, then the neighbours
+        1 => Message::OspfHello {
+            router_id,
+            area,
+            designated: ipv4(body.get(12..)?)?,
+            neighbors: body.len().saturating_sub(20) / 4,
+        },
+        // an update counts its LSAs; the others are just a list of LSA headers (or requests for them)
+        4 => Message::OspfLinkState {
+            kind,
+            router_id,
+            area,
+            lsas: u32::from_be_bytes(body.get(..4)?.try_into().ok()?) as usize,
+        },
+        2 => Message::OspfLinkState {
+            kind,
+            router_id,
+            area,
+            lsas: body.len().saturating_sub(8) / LSA_HEADER_LEN,
+        },
+        3 => Message::OspfLinkState {
+            kind,
+            router_id,
+            area,
+            lsas: body.len() / 12,
+        },
+        5 => Message::OspfLinkState {
+            kind,
+            router_id,
+            area,
+            lsas: body.len() / LSA_HEADER_LEN,
+        },
+        _ => return None,
+    })
+}
+
+// a TCP segment can carry several BGP messages, each starting with a marker of all ones
+fn bgp(mut data: &[u8]) -> Vec<Message> {
+    let mut messages = Vec::new();
+
+    while data.len() >= BGP_HEADER_LEN && data[..16].iter().all(|x| *x == 0xff) {
+        let len = u16::from_be_bytes([data[16], data[17]]) as usize;
+        if len < BGP_HEADER_LEN || len > data.len() {
+            break;
+        }
+
+        let body = &data[BGP_HEADER_LEN..len];
+        let message = match data[18] {
+            1 => bgp_open(body),
+            2 => bgp_update(body),
+            3 => body.get(..2).map(|x| Message::BgpNotification { code: x[0], subcode: x[1] }),
+            4 => Some(Message::BgpKeepalive),
+            _ => None,
+        };
+
+        messages.extend(message);
+        data = &data[len..];
+    }
+
+    messages
+}
+
+fn bgp_open(body: &[u8]) -> Option<Message> {
+    let mut asn = u16::from_be_bytes([*body.get(1)?, *body.get(2)?]) as u32;
+    let hold_time = u16::from_be_bytes([*body.get(3)?, *body.get(4)?]);
+    let router_id = ipv4(body.get(5..)?)?;
+
+    // a 2-byte AS of 23456 (AS_TRANS) means the real one is in the 4-byte AS capability
+    let params = body.get(10..10 + *body.get(9)? as usize)?;
+    let mut i = 0;
+    while i + 2 <= params.len() {
+        let (kind, len) = (params[i], params[i + 1] as usize);
+        let value = params.get(i + 2..i + 2 + len)?;
+
+        if kind == 2 {
+            let mut j = 0;
+            while j + 2 <= value.len() {
+                let (code, cap_len) = (value[j], value[j + 1] as usize);
+                if code == 65 && cap_len == 4 {
+                    asn = u32::from_be_bytes(value.get(j + 2..j + 6)?.try_into().ok()?);
+                }
+                j += 2 + cap_len;
+            }
+        }
+        i += 2 + len;
+    }
+
+    Some(Message::BgpOpen { asn, hold_time, router_id })
+}
+
+fn bgp_update(body: &[u8]) -> Option<Message> {
+    let withdrawn_len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let withdrawn = prefixes(body.get(2..2 + withdrawn_len)?);
+
+    let attrs_start = 2 + withdrawn_len + 2;
+    let attrs_len = u16::from_be_bytes([*body.get(attrs_start - 2)?, *body.get(attrs_start - 1)?]) as usize;
+    let attrs = body.get(attrs_start..attrs_start + attrs_len)?;
+    let announced = prefixes(body.get(attrs_start + attrs_len..)?);
+
+    let mut as_path = Vec::new();
+    let mut i = 0;
+    while i + 3 <= attrs.len() {
+        let (flags, kind) = (attrs[i], attrs[i + 1]);
+        // the extended length flag means a 2-byte length
+        let (len, start) = if flags & 0x10 != 0 {
+            (u16::from_be_bytes([attrs[i + 2], *attrs.get(i + 3)?]) as usize, i + 4)
+        } else {
+            (attrs[i + 2] as usize, i + 3)
+        };
+        let value = attrs.get(start..start + len)?;
+
+        if kind == 2 {
+            as_path = path_segments(value);
+        }
+        i = start + len;
+    }
+
+    Some(Message::BgpUpdate { as_path, announced, withdrawn })
+}
+
+// AS_PATH segments of 4-byte AS numbers (as sent between 4-byte capable speakers), falling back to 2-byte ones
+fn path_segments(value: &[u8]) -> Vec<u32> {
+    let parse = |width: usize| -> Option<Vec<u32>> {
+        let mut path = Vec::new();
+        let mut i = 0;
+        while i < value.len() {
+            let count = *value.get(i + 1)? as usize;
+            let segment = value.get(i + 2..i + 2 + count * width)?;
+            path.extend(segment.chunks(width).map(|x| match width {
+                4 => u32::from_be_bytes([x[0], x[1], x[2], x[3]]),
+                _ => u16::from_be_bytes([x[0], x[1]]) as u32,
+            }));
+            i += 2 + count * width;
+        }
+        Some(path)
+    };
+
+    parse(4).or_else(|| parse(2)).unwrap_or_default()
+}
+
+// IPv4 NLRI: a prefix length, then just enough bytes to hold it
+fn prefixes(mut data: &[u8]) -> Vec<String> {
+    let mut prefixes = Vec::new();
+
+    while let Some(&len) = data.first() {
+        let bytes = (len as usize).div_ceil(8);
+        let Some(addr) = data.get(1..1 + bytes) else { break };
+        if len > 32 {
+            break;
+        }
+
+        let mut octets = [0u8; 4];
+        octets[..bytes].copy_from_slice(addr);
+        prefixes.push(format!("{}/{}", Ipv4Addr::from(octets), len));
+        data = &data[1 + bytes..];
+    }
+
+    prefixes
+}
+
+// version/type, VRID, priority, address count, then (after 4 more bytes) the virtual addresses
+fn vrrp(packet: &[u8], v6: bool) -> Option<Message> {
+    let header = packet.get(..8)?;
+    let version = header[0] >> 4;
+    if header[0] & 0x0f != 1 {
+        return None; // only advertisements exist
+    }
+
+    let width = if v6 { 16 } else { 4 };
+    let addresses = packet
+        .get(8..8 + header[3] as usize * width)?
+        .chunks(width)
+        .map(|x| match <[u8; 16]>::try_from(x) {
+            Ok(v6) => IpAddr::from(v6),
+            Err(_) => IpAddr::from([x[0], x[1], x[2], x[3]]),
+        })
+        .collect();
+
+    Some(Message::Vrrp {
+        version,
+        group: header[1],
+        priority: header[2],
+        addresses,
+    })
+}
+
+fn hsrp(payload: &[u8]) -> Option<Message> {
+    match *payload.first()? {
+        // version 0 (HSRPv1): fixed layout, opcode 0 is a hello
+        0 => {
+            let packet = payload.get(..20)?;
+            (packet[1] == 0).then_some(Message::Hsrp {
+                version: 1,
+                group: packet[6] as u16,
+                state: packet[2],
+                priority: packet[5] as u32,
+                virtual_ip: Some(IpAddr::from([packet[16], packet[17], packet[18], packet[19]])),
+            })
+        }
+        // HSRPv2 is TLVs; the group state TLV (type 1) has everything we want
+        _ => {
+            let mut data = payload;
+            while data.len() >= 2 {
+                let (kind, len) = (data[0], data[1] as usize);
+                let value = data.get(2..2 + len)?;
+
+                if kind == 1 && value.len() >= 28 && value[1] == 0 {
+                    let v6 = value[3] == 6;
+                    return Some(Message::Hsrp {
+                        version: 2,
+                        group: u16::from_be_bytes([value[4], value[5]]),
+                        state: value[2],
+                        priority: u32::from_be_bytes(value[12..16].try_into().ok()?),
+                        virtual_ip: Some(if v6 {
+                            IpAddr::from(<[u8; 16]>::try_from(value.get(24..40)?).ok()?)
+                        } else {
+                            IpAddr::from([value[24], value[25], value[26], value[27]])
+                        }),
+                    });
+                }
+                data = &data[2 + len..];
+            }
+            None
+        }
+    }
+}
+
+fn hsrp_state(state: u8) -> &'static str {
+    match state {
+        0 | 1 => "initial",
+        2 => "learn",
+        4 => "listen",
+        8 => "speak",
+        16 => "active",
+        _ => "standby",
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Message::OspfHello { router_id, area, designated, neighbors } => write!(
+                f,
+                "OSPF hello from router {} in area {}, DR {}, {} neighbor{}",
+                router_id,
+                area,
+                designated,
+                neighbors,
+                if *neighbors == 1 { "" } else { "s" }
+            ),
+            Message::OspfLinkState { kind, router_id, area, lsas } => write!(
+                f,
+                "OSPF {} from router {} in area {}, {} LSA{}",
+                match kind {
+                    2 => "database description",
+                    3 => "link state request",
+                    4 => "link state update",
+                    _ => "link state ack",
+                },
+                router_id,
+                area,
+                lsas,
+                if *lsas == 1 { "" } else { "s" }
+            ),
+            Message::BgpOpen { asn, hold_time, router_id } => {
+                write!(f, "BGP OPEN from AS {} (router {}, hold time {}s)", asn, router_id, hold_time)
+            }
+            Message::BgpUpdate { as_path, announced, withdrawn } => {
+                write!(f, "BGP UPDATE")?;
+                if !announced.is_empty() {
+                    write!(f, " announced {}", announced.join(", "))?;
+                    if !as_path.is_empty() {
+                        let path: Vec<String> = as_path.iter().map(|x| x.to_string()).collect();
+                        write!(f, " via AS path {}", path.join(" "))?;
+                    }
+                }
+                if !withdrawn.is_empty() {
+                    write!(f, "{} withdrew {}", if announced.is_empty() { "" } else { ";" }, withdrawn.join(", "))?;
+                }
+                Ok(())
+            }
+            Message::BgpNotification { code, subcode } => {
+                write!(f, "BGP NOTIFICATION (error {}, subcode {}), session closing", code, subcode)
+            }
+            Message::BgpKeepalive => write!(f, "BGP KEEPALIVE"),
+            Message::Vrrp { version, group, priority, addresses } => {
+                let addresses: Vec<String> = addresses.iter().map(|x| x.to_string()).collect();
+                write!(
+                    f,
+                    "VRRPv{} advert for group {} ({}), priority {}{}",
+                    version,
+                    group,
+                    addresses.join(", "),
+                    priority,
+                    if *priority == 0 { ", master stepping down" } else { "" }
+                )
+            }
+            Message::Hsrp { version, group, state, priority, virtual_ip } => write!(
+                f,
+                "HSRPv{} hello for group {}{}, {} router, priority {}",
+                version,
+                group,
+                virtual_ip.map(|x| format!(" ({})", x)).unwrap_or_default(),
+                hsrp_state(*state),
+                priority
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PeerStats {
+    asn: Option<u32>,
+    updates: u64,
+    announced: u64,
+    withdrawn: u64,
+    notifications: u64,
+}
+
+fn warn(theme: &Theme, message: &str) {
+    outln!("{}", theme.paint(theme.warning, &format!("*** {} ***", message)));
+}
+
+// the current master of a VRRP/HSRP group
+struct Group {
+    master: IpAddr,
+    priority: u32,
+    failovers: u64,
+}
+
+// routing churn per speaker, and who's master of each redundancy group, for the exit report
+pub struct RoutingMonitor {
+    expected_peers: Option<Vec<IpAddr>>,
+    expected_routers: Option<Vec<IpAddr>>,
+    warned: HashSet<IpAddr>,
+    bgp: BTreeMap<IpAddr, PeerStats>,
+    ospf: BTreeMap<Ipv4Addr, (u64, u64)>, // router -> (hellos, LSAs)
+    groups: BTreeMap<(&'static str, u16), Group>, // (VRRP or HSRP, group) -> master
+    theme: Theme,
+}
+
+impl RoutingMonitor {
+    pub fn new(expected_peers: Option<Vec<IpAddr>>, expected_routers: Option<Vec<IpAddr>>, theme: Theme) -> RoutingMonitor {
+        RoutingMonitor {
+            expected_peers,
+            expected_routers,
+            warned: HashSet::new(),
+            bgp: BTreeMap::new(),
+            ospf: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            theme,
+        }
+    }
+
+    fn check_router(&mut self, protocol: &str, group: u16, router: IpAddr) {
+        if let Some(ref expected) = self.expected_routers {
+            if !expected.contains(&router) && self.warned.insert(router) {
+                warn(&self.theme, &format!("unexpected {} router {} for group {}", protocol, router, group));
+            }
+        }
+    }
+
+    // only the master sends VRRP adverts, and only the active router says it's active in HSRP
+    fn master(&mut self, protocol: &'static str, group: u16, router: IpAddr, priority: u32) {
+        match self.groups.get_mut(&(protocol, group)) {
+            Some(current) if current.master != router => {
+                warn(&self.theme, &format!(
+                    "{} group {} failover: {} (priority {}) -> {} (priority {})",
+                    protocol, group, current.master, current.priority, router, priority
+                ));
+                current.master = router;
+                current.priority = priority;
+                current.failovers += 1;
+            }
+            Some(current) => current.priority = priority,
+            None => {
+                self.groups.insert((protocol, group), Group { master: router, priority, failovers: 0 });
+            }
+        }
+    }
+
+    pub fn observe(&mut self, stats: &RequestStats) {
+        let origin = stats.orig_ip.to_std();
+
+        for message in stats.routing.iter() {
+            match message {
+                Message::OspfHello { router_id, .. } => self.ospf.entry(*router_id).or_default().0 += 1,
+                Message::OspfLinkState { router_id, kind: 4, lsas, .. } => {
+                    self.ospf.entry(*router_id).or_default().1 += *lsas as u64
+                }
+                Message::OspfLinkState { .. } => {}
+                Message::Vrrp { group, priority, .. } => {
+                    self.check_router("VRRP", *group as u16, origin);
+                    // priority 0 is the master giving up, so whoever adverts next is the new one
+                    if *priority > 0 {
+                        self.master("VRRP", *group as u16, origin, *priority as u32);
+                    }
+                }
+                Message::Hsrp { group, state, priority, .. } => {
+                    self.check_router("HSRP", *group, origin);
+                    if *state == HSRP_ACTIVE {
+                        self.master("HSRP", *group, origin, *priority);
+                    }
+                }
+                _ => {
+                    let peer = self.bgp.entry(origin).or_default();
+                    match message {
+                        Message::BgpOpen { asn, .. } => peer.asn = Some(*asn),
+                        Message::BgpUpdate { announced, withdrawn, .. } => {
+                            peer.updates += 1;
+                            peer.announced += announced.len() as u64;
+                            peer.withdrawn += withdrawn.len() as u64;
+                        }
+                        Message::BgpNotification { .. } => peer.notifications += 1,
+                        _ => {}
+                    }
+
+                    let asn = peer.asn.map(|x| format!(" (AS {})", x)).unwrap_or_default();
+                    if let Some(ref expected) = self.expected_peers {
+                        if !expected.contains(&origin) && self.warned.insert(origin) {
+                            warn(&self.theme, &format!("unexpected BGP peer {}{}", origin, asn));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn print_report(&self) {
+        if self.bgp.is_empty() && self.ospf.is_empty() && self.groups.is_empty() {
+            return;
+        }
+
+        println!("Routing:");
+        for (router, (hellos, lsas)) in self.ospf.iter() {
+            println!("    OSPF router {}: {} hellos, {} LSAs updated", router, hellos, lsas);
+        }
+        for (peer, stats) in self.bgp.iter() {
+            println!(
+                "    BGP peer {}{}: {} updates, {} prefixes announced, {} withdrawn{}",
+                peer,
+                stats.asn.map(|x| format!(" (AS {})", x)).unwrap_or_default(),
+                stats.updates,
+                stats.announced,
+                stats.withdrawn,
+                if stats.notifications > 0 { format!(", {} notifications", stats.notifications) } else { String::new() },
+            );
+        }
+        for ((protocol, group), state) in self.groups.iter() {
+            println!(
+                "    {} group {}: master {} (priority {}), {} failover{}",
+                protocol,
+                group,
+                state.master,
+                state.priority,
+                state.failovers,
+                if state.failovers == 1 { "" } else { "s" },
+            );
+        }
+    }
+}
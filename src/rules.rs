@@ -0,0 +1,155 @@
+use std::fmt::Display;
+
+use crate::{
+    conf::{Cast, Config},
+    locale::Locale,
+};
+
+// one configured rule and how many requests it has matched
+struct Rule {
+    kind: &'static str,
+    value: String,
+    hits: u64,
+}
+
+// hit counters for every filter/exclude/highlight rule, so dead or overly broad rules stand out at exit
+pub struct RuleStats {
+    rules: Vec<Rule>,
+    evaluated: u64,
+}
+
+impl RuleStats {
+    // register every rule up front, so rules that never match still show up in the report
+    // alert rules are registered by the alert engine once it has loaded them
+    pub fn new(config: &Config) -> RuleStats {
+        let mut stats = RuleStats {
+            rules: Vec::new(),
+            evaluated: 0,
+        };
+
+        if let Some(protocol) = config.protocol {
+            stats.register("protocol", &[protocol]);
+        }
+        if let Some(ref ip_proto) = config.ip_proto {
+            stats.register("ip proto", ip_proto);
+        }
+        if let Some(ref exclude_ips) = config.exclude_ips {
+            stats.register("exclude ip", exclude_ips);
+        }
+        if let Some(ref exclude_macs) = config.exclude_macs {
+            stats.register("exclude mac", exclude_macs);
+        }
+        stats.register("exclude cast", config.excluded_casts());
+        if config.multicast_only {
+            stats.register("cast", &[Cast::Multicast]);
+        }
+        if let Some(ref filter_ips) = config.filter_ips {
+            stats.register("filter ip", filter_ips);
+        }
+        if let Some(ref filter_macs) = config.filter_macs {
+            stats.register("filter mac", filter_macs);
+        }
+        if let Some(ref filter_vendors) = config.filter_vendors {
+            stats.register("filter vendor", filter_vendors);
+        }
+        if let Some(ref process) = config.process {
+            stats.register("process", process);
+        }
+        if let Some(direction) = config.direction {
+            stats.register("direction", &[direction]);
+        }
+        if let Some(ref dscp) = config.dscp {
+            stats.register("dscp", dscp);
+        }
+        if let Some(vlan) = config.vlan {
+            stats.register("vlan", &[vlan]);
+        }
+        if let Some(ref services) = config.services {
+            stats.register("service", services);
+        }
+        if let Some(ref filter) = config.filter {
+            stats.register("filter", &[filter]);
+        }
+        if let Some(ref patterns) = config.match_payload {
+            stats.register("match payload", patterns);
+        }
+        stats.register("output", &config.console_outputs());
+        if let Some(ref highlight_ips) = config.highlight_ips {
+            stats.register("highlight ip", highlight_ips);
+        }
+        if let Some(ref highlight_macs) = config.highlight_macs {
+            stats.register("highlight mac", highlight_macs);
+        }
+
+        stats
+    }
+
+    pub fn register<T: Display>(&mut self, kind: &'static str, rules: &[T]) {
+        for rule in rules {
+            self.rules.push(Rule {
+                kind,
+                value: rule.to_string(),
+                hits: 0,
+            });
+        }
+    }
+
+    // count a request reaching the rules, so hits can be shown as a share of everything seen
+    pub fn evaluate(&mut self) {
+        self.evaluated += 1;
+    }
+
+    // count a hit against every rule of this kind that matches, returning whether any did
+    pub fn check<T: Display>(&mut self, kind: &'static str, rules: &[T], matches: impl Fn(&T) -> bool) -> bool {
+        let mut any = false;
+
+        for rule in rules.iter().filter(|x| matches(x)) {
+            self.hit(kind, &rule.to_string());
+            any = true;
+        }
+
+        any
+    }
+
+    pub fn hit(&mut self, kind: &'static str, value: &str) {
+        if let Some(entry) = self.rules.iter_mut().find(|x| x.kind == kind && x.value == value) {
+            entry.hits += 1;
+        }
+    }
+
+    // every rule as (kind, value, hits), in the order they were registered
+    pub fn hits(&self) -> impl Iterator<Item = (&'static str, &str, u64)> {
+        self.rules.iter().map(|x| (x.kind, x.value.as_str(), x.hits))
+    }
+
+    pub fn evaluated(&self) -> u64 {
+        self.evaluated
+    }
+
+    pub fn print_report(&self, locale: Option<&Locale>) {
+        if self.rules.is_empty() {
+            return;
+        }
+
+        let number = |n: u64| match locale {
+            Some(locale) => locale.number(n),
+            None => n.to_string(),
+        };
+
+        println!("Rule hits ({} requests evaluated):", number(self.evaluated));
+
+        let width = self.rules.iter().map(|x| x.value.len()).max().unwrap_or(0);
+
+        for rule in self.rules.iter() {
+            println!(
+                "    {:<13} {:<width$} {:>10} ({:.1}%){}",
+                rule.kind,
+                rule.value,
+                number(rule.hits),
+                rule.hits as f64 / self.evaluated.max(1) as f64 * 100.0,
+                if rule.hits == 0 { " - never matched" } else { "" },
+                width = width,
+            );
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// --sample 1/N: sFlow-style packet sampling, for links too busy to look at everything. Each capture thread keeps one
+// frame in N on average, skipping a random number of frames (1 to 2N-1) between the ones it keeps, so what's kept
+// doesn't fall in step with any periodic pattern in the traffic; the rest are counted in the capture totals, then
+// dropped before they're copied onto the ring. Each frame kept stands for N, so requests' (and flows') byte and packet
+// counts are scaled up by N
+//
+// anything that needs every packet, like reassembling fragments or following TCP connections, only sees the sample
+
+use std::time::SystemTime;
+
+pub struct Sampler {
+    rate: u64,
+    skip: u64, // frames left to drop before the next one kept
+    state: u64,
+}
+
+impl Sampler {
+    pub fn new(rate: u32, seed: u64) -> Sampler {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        let mut sampler = Sampler {
+            rate: rate.max(1) as u64,
+            skip: 0,
+            // xorshift can't start from zero
+            state: (nanos ^ seed.wrapping_mul(0x9e3779b97f4a7c15)) | 1,
+        };
+        sampler.skip = sampler.next_skip() - 1;
+        sampler
+    }
+
+    // whether to keep this frame
+    pub fn keep(&mut self) -> bool {
+        if self.rate == 1 {
+            return true;
+        }
+        if self.skip > 0 {
+            self.skip -= 1;
+            return false;
+        }
+        self.skip = self.next_skip() - 1;
+        true
+    }
+
+    // uniform over 1..=2N-1, so N on average
+    fn next_skip(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        1 + self.state % (2 * self.rate - 1)
+    }
+}
+
+// 1/100, or just 100
+pub fn parse_rate(s: &str) -> Result<u32, std::io::Error> {
+    let s = s.trim();
+    let rate = s.strip_prefix("1/").unwrap_or(s);
+
+    match rate.trim().parse::<u32>() {
+        Ok(rate) if rate > 0 => Ok(rate),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Invalid sampling rate, expected e.g. 1/100",
+        )),
+    }
+}
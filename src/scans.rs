@@ -0,0 +1,368 @@
+// --detect-scans: sources probing the network, which otherwise show up as hundreds of one-packet requests. Within any
+// 10 seconds, a source is scanning if it
+//
+//     probes PORT_SCAN_PORTS or more ports on one host (a port scan)
+//     probes SWEEP_HOSTS or more hosts on the same port, or pings them, finishing few of the TCP handshakes (a sweep)
+//     sends HALF_OPEN_SYNS or more SYNs and finishes hardly any of the handshakes (a SYN scan, or a flood)
+//
+// a probe is a SYN, a UDP datagram from an unprivileged port (so servers answering aren't probing) or an echo request.
+// Each scan gets one line when it's found, and another once its source has been quiet for a while; in between, the
+// source's requests are hidden. At exit, every scan's totals are reported
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, theme::Theme};
+
+const WINDOW: Duration = Duration::from_secs(10);
+const PORT_SCAN_PORTS: usize = 20;
+const SWEEP_HOSTS: usize = 20;
+const HALF_OPEN_SYNS: usize = 20;
+// a sweep finishes fewer than half its handshakes, a SYN scan fewer than a fifth
+const SWEEP_COMPLETED: f64 = 0.5;
+const HALF_OPEN_COMPLETED: f64 = 0.2;
+// a scan's over once its source has sent no probes for this long
+const QUIET: Duration = Duration::from_secs(30);
+// traceroute's UDP probes go to a port each, but aren't a scan
+const TRACEROUTE_PORTS: std::ops::RangeInclusive<u16> = 33434..=33534;
+
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+const MAX_SOURCES: usize = 65536;
+const MAX_TARGETS: usize = 65536;
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMP: u8 = 1;
+const ICMPV6: u8 = 58;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    PortScan,
+    Sweep,
+    HalfOpen,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::PortScan => "port scan",
+            Kind::Sweep => "host sweep",
+            Kind::HalfOpen => "half-open SYNs",
+        }
+    }
+}
+
+struct Scan {
+    kinds: Vec<Kind>,
+    started: SystemTime,
+    last: SystemTime,
+    ports: HashSet<u16>,
+    hosts: HashSet<IpAddr>,
+    syns: u64,
+    completed: u64,
+    hidden: u64, // requests
+}
+
+impl Scan {
+    fn describe(&self) -> String {
+        // pings have no port
+        let ports = match self.ports.len() {
+            0 => String::new(),
+            1 => "1 port on ".to_string(),
+            ports => format!("{} ports on ", ports),
+        };
+        let mut description = format!(
+            "{}{} host{} over {:.1}s",
+            ports,
+            self.hosts.len(),
+            if self.hosts.len() == 1 { "" } else { "s" },
+            self.last.duration_since(self.started).unwrap_or_default().as_secs_f64()
+        );
+        if self.syns > 0 {
+            description.push_str(&format!(", {} of {} handshakes completed", self.completed, self.syns));
+        }
+        description
+    }
+
+    fn kinds(&self) -> String {
+        self.kinds.iter().map(|x| x.name()).collect::<Vec<_>>().join(" and ")
+    }
+}
+
+// what a source has probed within the last WINDOW, counted as it goes so nothing has to be added up per packet
+#[derive(Default)]
+struct Source {
+    targets: HashMap<(IpAddr, u16), SystemTime>, // host and port (0 for a ping) -> last probed
+    order: VecDeque<(SystemTime, IpAddr, u16)>,  // oldest first
+    ports_by_host: HashMap<IpAddr, usize>,
+    hosts_by_port: HashMap<u16, usize>,
+    handshakes: HashMap<(SocketAddr, SocketAddr), bool>, // started -> completed
+    syns: VecDeque<(SystemTime, SocketAddr, SocketAddr)>, // oldest first
+    completed: usize,
+    scan: Option<Scan>,
+}
+
+impl Source {
+    fn completed_ratio(&self) -> f64 {
+        match self.handshakes.len() {
+            0 => 0.0,
+            started => self.completed as f64 / started as f64,
+        }
+    }
+
+    // forget what's older than the window
+    fn expire(&mut self, now: SystemTime) {
+        let old = |at: SystemTime| now.duration_since(at).unwrap_or_default() > WINDOW;
+
+        while let Some((_, host, port)) = self.order.front().copied().filter(|x| old(x.0)) {
+            self.order.pop_front();
+            match self.targets.get(&(host, port)) {
+                // probed again since, so it goes round again
+                Some(last) if !old(*last) => self.order.push_back((*last, host, port)),
+                _ => {
+                    self.targets.remove(&(host, port));
+                    decrement(&mut self.ports_by_host, host);
+                    decrement(&mut self.hosts_by_port, port);
+                }
+            }
+        }
+
+        while let Some((_, src, dst)) = self.syns.front().copied().filter(|x| old(x.0)) {
+            self.syns.pop_front();
+            if self.handshakes.remove(&(src, dst)) == Some(true) {
+                self.completed -= 1;
+            }
+        }
+    }
+}
+
+fn decrement<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, usize>, key: K) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+pub struct ScanDetector {
+    theme: Theme,
+    sources: HashMap<IpAddr, Source>,
+    finished: Vec<(IpAddr, Scan)>,
+    checked: Option<SystemTime>,
+}
+
+impl ScanDetector {
+    pub fn new(theme: Theme) -> ScanDetector {
+        ScanDetector {
+            theme,
+            sources: HashMap::new(),
+            finished: Vec::new(),
+            checked: None,
+        }
+    }
+
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.check(timestamp);
+        }
+
+        let Some(header) = ip::parse(packet) else {
+            return;
+        };
+        if header.dst.is_multicast() || header.dst == IpAddr::from([255, 255, 255, 255]) {
+            return;
+        }
+        let segment = packet.get(header.header_len..).unwrap_or_default();
+
+        // what it's probing, and for TCP, the handshake it's starting
+        let (port, syn) = match (header.protocol, ip::transport(packet)) {
+            (TCP, Some((src_port, dst_port, _))) if segment.len() >= 14 => {
+                let flags = segment[13];
+                let (syn, rst, ack) = (flags & 0x02 != 0, flags & 0x04 != 0, flags & 0x10 != 0);
+                let handshake = (
+                    SocketAddr::new(header.src, src_port),
+                    SocketAddr::new(header.dst, dst_port),
+                );
+
+                // the rest of a handshake it started isn't a probe, but finishes it
+                if !syn {
+                    if let (true, false, Some(source)) = (ack, rst, self.sources.get_mut(&header.src)) {
+                        if source.handshakes.get(&handshake) == Some(&false) {
+                            source.handshakes.insert(handshake, true);
+                            source.completed += 1;
+                            if let Some(ref mut scan) = source.scan {
+                                scan.completed += 1;
+                            }
+                        }
+                    }
+                    return;
+                }
+                if ack {
+                    return;
+                }
+                (dst_port, Some(handshake))
+            }
+            (UDP, Some((src_port, dst_port, _))) if src_port >= 1024 && !TRACEROUTE_PORTS.contains(&dst_port) => {
+                (dst_port, None)
+            }
+            (ICMP, _) if segment.first() == Some(&8) => (0, None),
+            (ICMPV6, _) if segment.first() == Some(&128) => (0, None),
+            _ => return,
+        };
+
+        if self.sources.len() >= MAX_SOURCES && !self.sources.contains_key(&header.src) {
+            return;
+        }
+        let source = self.sources.entry(header.src).or_default();
+        source.expire(timestamp);
+
+        // a SYN sent again is the same handshake
+        let new_handshake = match syn {
+            Some(handshake) if !source.handshakes.contains_key(&handshake) => {
+                source.handshakes.insert(handshake, false);
+                source.syns.push_back((timestamp, handshake.0, handshake.1));
+                true
+            }
+            _ => false,
+        };
+        let new_target = source.targets.insert((header.dst, port), timestamp).is_none();
+        if new_target {
+            source.order.push_back((timestamp, header.dst, port));
+            *source.ports_by_host.entry(header.dst).or_default() += 1;
+            *source.hosts_by_port.entry(port).or_default() += 1;
+        }
+
+        if let Some(ref mut scan) = source.scan {
+            scan.last = timestamp;
+            scan.syns += new_handshake as u64;
+            if scan.ports.len() < MAX_TARGETS && port != 0 {
+                scan.ports.insert(port);
+            }
+            if scan.hosts.len() < MAX_TARGETS {
+                scan.hosts.insert(header.dst);
+            }
+            return;
+        }
+        if !new_target && !new_handshake {
+            return;
+        }
+
+        let ratio = source.completed_ratio();
+        let mut kinds = Vec::new();
+        if source
+            .ports_by_host
+            .get(&header.dst)
+            .is_some_and(|x| *x >= PORT_SCAN_PORTS)
+        {
+            kinds.push(Kind::PortScan);
+        }
+        if source.hosts_by_port.get(&port).is_some_and(|x| *x >= SWEEP_HOSTS) && ratio < SWEEP_COMPLETED {
+            kinds.push(Kind::Sweep);
+        }
+        if source.handshakes.len() >= HALF_OPEN_SYNS && ratio < HALF_OPEN_COMPLETED {
+            kinds.push(Kind::HalfOpen);
+        }
+        if kinds.is_empty() {
+            return;
+        }
+
+        // it started with what's in the window
+        let scan = Scan {
+            kinds,
+            started: source.order.iter().map(|x| x.0).min().unwrap_or(timestamp),
+            last: timestamp,
+            ports: source.targets.keys().map(|x| x.1).filter(|x| *x != 0).collect(),
+            hosts: source.targets.keys().map(|x| x.0).collect(),
+            syns: source.handshakes.len() as u64,
+            completed: source.completed as u64,
+            hidden: 0,
+        };
+        let message = format!(
+            "*** {} from {}: {}; hiding its requests until it stops ***",
+            scan.kinds(),
+            header.src,
+            scan.describe()
+        );
+        outln!("{}", self.theme.paint(self.theme.anomaly, &message));
+        source.scan = Some(scan);
+    }
+
+    // whether a request is from a source that's scanning, and so isn't worth showing
+    pub fn hide(&mut self, orig_ip: IpAddr) -> bool {
+        match self.sources.get_mut(&orig_ip).and_then(|x| x.scan.as_mut()) {
+            Some(scan) => {
+                scan.hidden += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // scans that have gone quiet are over, and sources with nothing recent are forgotten
+    fn check(&mut self, now: SystemTime) {
+        let mut over = Vec::new();
+        self.sources.retain(|ip, source| {
+            source.expire(now);
+
+            if source
+                .scan
+                .as_ref()
+                .is_some_and(|x| now.duration_since(x.last).unwrap_or_default() >= QUIET)
+            {
+                over.push((*ip, source.scan.take().unwrap()));
+            }
+            source.scan.is_some() || !source.targets.is_empty() || !source.handshakes.is_empty()
+        });
+
+        over.sort_by_key(|(_, scan)| scan.started);
+        for (ip, scan) in over {
+            let message = format!(
+                "*** {} from {} is over: {}, {} request{} hidden ***",
+                scan.kinds(),
+                ip,
+                scan.describe(),
+                scan.hidden,
+                if scan.hidden == 1 { "" } else { "s" }
+            );
+            outln!("{}", self.theme.paint(self.theme.anomaly, &message));
+            self.finished.push((ip, scan));
+        }
+    }
+
+    pub fn print_report(&self) {
+        let ongoing = self
+            .sources
+            .iter()
+            .filter_map(|(ip, x)| x.scan.as_ref().map(|scan| (*ip, scan)));
+        let mut by_source: BTreeMap<IpAddr, Vec<&Scan>> = BTreeMap::new();
+        for (ip, scan) in self.finished.iter().map(|(ip, scan)| (*ip, scan)).chain(ongoing) {
+            by_source.entry(ip).or_default().push(scan);
+        }
+        if by_source.is_empty() {
+            return;
+        }
+
+        println!("Scans:");
+        for (ip, mut scans) in by_source {
+            scans.sort_by_key(|x| x.started);
+            for scan in scans {
+                println!(
+                    "    {}: {}, {}, {} request{} hidden",
+                    ip,
+                    scan.kinds(),
+                    scan.describe(),
+                    scan.hidden,
+                    if scan.hidden == 1 { "" } else { "s" }
+                );
+            }
+        }
+    }
+}
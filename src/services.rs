@@ -0,0 +1,111 @@
+// port number -> service name, e.g. 443 -> "https", from /etc/services where there is one,
+// on top of a built-in table of the common ones for systems without it
+
+use std::collections::HashMap;
+
+use crate::conf::Protocol;
+
+const SERVICES_FILE: &str = "/etc/services";
+
+// (port, TCP, UDP, name)
+const BUILTIN: &[(u16, bool, bool, &str)] = &[
+    (20, true, false, "ftp-data"),
+    (21, true, false, "ftp"),
+    (22, true, false, "ssh"),
+    (23, true, false, "telnet"),
+    (25, true, false, "smtp"),
+    (53, true, true, "domain"),
+    (67, false, true, "bootps"),
+    (68, false, true, "bootpc"),
+    (69, false, true, "tftp"),
+    (80, true, false, "http"),
+    (88, true, true, "kerberos"),
+    (110, true, false, "pop3"),
+    (123, false, true, "ntp"),
+    (137, false, true, "netbios-ns"),
+    (138, false, true, "netbios-dgm"),
+    (139, true, false, "netbios-ssn"),
+    (143, true, false, "imap2"),
+    (161, false, true, "snmp"),
+    (162, false, true, "snmp-trap"),
+    (179, true, false, "bgp"),
+    (389, true, false, "ldap"),
+    (443, true, true, "https"),
+    (445, true, false, "microsoft-ds"),
+    (465, true, false, "submissions"),
+    (500, false, true, "isakmp"),
+    (514, false, true, "syslog"),
+    (546, false, true, "dhcpv6-client"),
+    (547, false, true, "dhcpv6-server"),
+    (587, true, false, "submission"),
+    (631, true, false, "ipp"),
+    (636, true, false, "ldaps"),
+    (853, true, false, "domain-s"),
+    (993, true, false, "imaps"),
+    (995, true, false, "pop3s"),
+    (1194, true, true, "openvpn"),
+    (1812, false, true, "radius"),
+    (1883, true, false, "mqtt"),
+    (1900, false, true, "ssdp"),
+    (3306, true, false, "mysql"),
+    (3389, true, false, "ms-wbt-server"),
+    (4500, false, true, "ipsec-nat-t"),
+    (5060, true, true, "sip"),
+    (5353, false, true, "mdns"),
+    (5432, true, false, "postgresql"),
+    (6379, true, false, "redis"),
+    (8080, true, false, "http-alt"),
+    (8883, true, false, "secure-mqtt"),
+    (51820, false, true, "wireguard"),
+];
+
+pub struct Services {
+    names: HashMap<(u16, bool), String>, // (port, is TCP) -> name
+}
+
+impl Services {
+    pub fn load() -> Services {
+        let mut names = HashMap::new();
+
+        for (port, tcp, udp, name) in BUILTIN.iter() {
+            if *tcp {
+                names.insert((*port, true), name.to_string());
+            }
+            if *udp {
+                names.insert((*port, false), name.to_string());
+            }
+        }
+
+        // "https  443/tcp  # comment", with the system's names taking precedence over ours
+        if let Ok(data) = std::fs::read_to_string(SERVICES_FILE) {
+            for line in data.lines() {
+                let mut fields = line.split('#').next().unwrap_or_default().split_whitespace();
+                let (Some(name), Some(port)) = (fields.next(), fields.next()) else {
+                    continue;
+                };
+                let Some((port, protocol)) = port.split_once('/') else {
+                    continue;
+                };
+                let (Ok(port), true) = (port.parse(), protocol == "tcp" || protocol == "udp") else {
+                    continue;
+                };
+
+                names.insert((port, protocol == "tcp"), name.to_string());
+            }
+        }
+
+        Services { names }
+    }
+
+    pub fn name(&self, port: u16, protocol: Protocol) -> Option<&str> {
+        self.names.get(&(port, protocol == Protocol::Tcp)).map(|x| x.as_str())
+    }
+
+    // a service given by name ("ssh") or number ("22") matches a port
+    pub fn matches(&self, service: &str, port: u16, protocol: Protocol) -> bool {
+        match service.parse::<u16>() {
+            Ok(number) => number == port,
+            Err(_) => self.name(port, protocol).is_some_and(|x| x.eq_ignore_ascii_case(service)),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+// --session NAME: a capture that can be stopped and picked up again later, kept in a directory of its own under
+// $XDG_DATA_HOME/sniff/sessions (or ~/.local/share/sniff/sessions):
+//
+//     session.json    when it was started, how many runs, what they've captured between them, and the filters
+//     capture.log     the -l log every run appends to, rotated alongside it as usual
+//
+// a run that doesn't give any filters of its own picks up the session's; one that does replaces them for later runs
+
+use std::{
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conf::{Config, Direction, IpAddrOrHostname, MacAddr, PayloadPattern, Protocol},
+    metrics::METRICS,
+    rotate, units,
+};
+
+const STATE: &str = "session.json";
+const LOG: &str = "capture.log";
+
+#[derive(Serialize, Deserialize)]
+struct State {
+    name: String,
+    started: SystemTime,
+    updated: SystemTime,
+    runs: u64,
+    interface: Option<String>,
+    packets: u64,
+    bytes: u64,
+    requests: u64,
+    dropped: u64,
+    filters: Filters,
+}
+
+// what decides which requests are shown and logged
+#[derive(Serialize, Deserialize)]
+struct Filters {
+    exclude_ips: Option<Vec<IpAddrOrHostname>>,
+    exclude_macs: Option<Vec<MacAddr>>,
+    exclude_broadcast: bool,
+    #[serde(default)]
+    no_broadcast: bool,
+    #[serde(default)]
+    multicast_only: bool,
+    #[serde(default)]
+    ignore_self: bool,
+    filter_ips: Option<Vec<IpAddrOrHostname>>,
+    filter_macs: Option<Vec<MacAddr>>,
+    filter_vendors: Option<Vec<String>>,
+    protocol: Option<Protocol>,
+    filter: Option<crate::filter::Expr>,
+    match_payload: Option<Vec<PayloadPattern>>,
+    vlan: Option<u16>,
+    #[serde(default)]
+    dscp: Option<Vec<crate::qos::Dscp>>,
+    direction: Option<Direction>,
+    ip_proto: Option<Vec<u8>>,
+    process: Option<Vec<String>>,
+    #[serde(default)]
+    services: Option<Vec<String>>,
+}
+
+impl Filters {
+    fn is_empty(&self) -> bool {
+        self.exclude_ips.is_none()
+            && self.exclude_macs.is_none()
+            && !self.exclude_broadcast
+            && !self.no_broadcast
+            && !self.multicast_only
+            && !self.ignore_self
+            && self.filter_ips.is_none()
+            && self.filter_macs.is_none()
+            && self.filter_vendors.is_none()
+            && self.protocol.is_none()
+            && self.filter.is_none()
+            && self.match_payload.is_none()
+            && self.vlan.is_none()
+            && self.dscp.is_none()
+            && self.direction.is_none()
+            && self.ip_proto.is_none()
+            && self.process.is_none()
+            && self.services.is_none()
+    }
+
+    fn from_config(config: &Config) -> Filters {
+        Filters {
+            exclude_ips: config.exclude_ips.clone(),
+            exclude_macs: config.exclude_macs.clone(),
+            exclude_broadcast: config.exclude_broadcast,
+            no_broadcast: config.no_broadcast,
+            multicast_only: config.multicast_only,
+            ignore_self: config.ignore_self,
+            filter_ips: config.filter_ips.clone(),
+            filter_macs: config.filter_macs.clone(),
+            filter_vendors: config.filter_vendors.clone(),
+            protocol: config.protocol,
+            filter: config.filter.clone(),
+            match_payload: config.match_payload.clone(),
+            vlan: config.vlan,
+            dscp: config.dscp.clone(),
+            direction: config.direction,
+            ip_proto: config.ip_proto.clone(),
+            process: config.process.clone(),
+            services: config.services.clone(),
+        }
+    }
+
+    fn apply(&self, config: &mut Config) {
+        config.exclude_ips = self.exclude_ips.clone();
+        config.exclude_macs = self.exclude_macs.clone();
+        config.exclude_broadcast = self.exclude_broadcast;
+        config.no_broadcast = self.no_broadcast;
+        config.multicast_only = self.multicast_only;
+        config.ignore_self = self.ignore_self;
+        config.filter_ips = self.filter_ips.clone();
+        config.filter_macs = self.filter_macs.clone();
+        config.filter_vendors = self.filter_vendors.clone();
+        config.protocol = self.protocol;
+        config.filter = self.filter.clone();
+        config.match_payload = self.match_payload.clone();
+        config.vlan = self.vlan;
+        config.dscp = self.dscp.clone();
+        config.direction = self.direction;
+        config.ip_proto = self.ip_proto.clone();
+        config.process = self.process.clone();
+        config.services = self.services.clone();
+    }
+}
+
+pub struct Session {
+    dir: PathBuf,
+    state: State,
+}
+
+impl Session {
+    // create the session, or resume it: the log goes in its directory, and its filters apply unless we were given some
+    pub fn open(name: &str, config: &mut Config) -> std::io::Result<Session> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid session name {:?}", name),
+            ));
+        }
+
+        let dir = sessions_dir()?.join(name);
+        std::fs::create_dir_all(&dir)?;
+
+        let now = SystemTime::now();
+        let given = Filters::from_config(config);
+
+        let state = match read_state(&dir) {
+            Ok(mut state) => {
+                if given.is_empty() {
+                    state.filters.apply(config);
+                } else {
+                    state.filters = given;
+                }
+                println!(
+                    "Resuming session {} (started {} UTC, run {})",
+                    name,
+                    readable(state.started),
+                    state.runs + 1
+                );
+                state
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => State {
+                name: name.to_string(),
+                started: now,
+                updated: now,
+                runs: 0,
+                interface: None,
+                packets: 0,
+                bytes: 0,
+                requests: 0,
+                dropped: 0,
+                filters: given,
+            },
+            Err(e) => return Err(e),
+        };
+
+        config.log_file = Some(dir.join(LOG).to_string_lossy().to_string());
+
+        let mut session = Session { dir, state };
+        session.state.runs += 1;
+        session.state.updated = now;
+        session.state.interface = config.interface.clone();
+        session.save()?;
+
+        Ok(session)
+    }
+
+    // add this run's counters to the session's, at the end of the capture
+    pub fn finish(mut self) {
+        self.state.updated = SystemTime::now();
+        self.state.packets += METRICS.packets.load(Ordering::Relaxed);
+        self.state.bytes += METRICS.bytes.load(Ordering::Relaxed);
+        self.state.requests += METRICS.events.load(Ordering::Relaxed);
+        self.state.dropped += METRICS.dropped.load(Ordering::Relaxed) + METRICS.kernel_dropped.load(Ordering::Relaxed);
+
+        match self.save() {
+            Ok(()) => println!("Saved session {} to {}", self.state.name, self.dir.display()),
+            Err(e) => eprintln!("Failed to save session {}: {}", self.state.name, e),
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        // written alongside and renamed over, so a capture killed mid-write doesn't lose the session
+        let path = self.dir.join(STATE);
+        let temp = self.dir.join(format!("{}.tmp", STATE));
+        std::fs::write(&temp, serde_json::to_vec_pretty(&self.state)?)?;
+        std::fs::rename(temp, path)
+    }
+}
+
+// `sniff sessions list`
+pub fn list() {
+    let dir = sessions_dir().unwrap_or_else(|e| panic!("Failed to find the sessions directory: {}", e));
+
+    let mut sessions: Vec<State> = std::fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| read_state(&entry.ok()?.path()).ok())
+        .collect();
+
+    if sessions.is_empty() {
+        println!("No sessions in {}", dir.display());
+        return;
+    }
+
+    sessions.sort_by_key(|x| x.started);
+
+    for session in sessions {
+        let log = dir.join(&session.name).join(LOG);
+        let mut logs = rotate::rotated_files(&log);
+        if log.exists() {
+            logs.push(log);
+        }
+        let log_bytes: u64 = logs
+            .iter()
+            .filter_map(|x| std::fs::metadata(x).ok())
+            .map(|x| x.len())
+            .sum();
+
+        println!(
+            "{} (started {} UTC, last run {} UTC, {} run{}{})",
+            session.name,
+            readable(session.started),
+            readable(session.updated),
+            session.runs,
+            if session.runs == 1 { "" } else { "s" },
+            session.interface.map(|x| format!(" on {}", x)).unwrap_or_default(),
+        );
+        println!(
+            "    {} packets ({}) in {} requests, {} dropped",
+            session.packets,
+            units::human_bytes(session.bytes, None),
+            session.requests,
+            session.dropped
+        );
+        println!(
+            "    {} log file{} ({})",
+            logs.len(),
+            if logs.len() == 1 { "" } else { "s" },
+            units::human_bytes(log_bytes, None)
+        );
+    }
+}
+
+fn read_state(dir: &Path) -> std::io::Result<State> {
+    let data = std::fs::read(dir.join(STATE))?;
+    serde_json::from_slice(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn sessions_dir() -> std::io::Result<PathBuf> {
+    let data = match std::env::var_os("XDG_DATA_HOME").filter(|x| !x.is_empty()) {
+        Some(data) => PathBuf::from(data),
+        None => match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+            Some(home) => PathBuf::from(home).join(".local").join("share"),
+            None => return Err(Error::new(ErrorKind::NotFound, "neither XDG_DATA_HOME nor HOME is set")),
+        },
+    };
+    Ok(data.join("sniff").join("sessions"))
+}
+
+// YYYY-MM-DD HH:MM:SS
+fn readable(time: SystemTime) -> String {
+    let stamp = rotate::timestamp(time);
+    format!(
+        "{}-{}-{} {}:{}:{}",
+        &stamp[0..4],
+        &stamp[4..6],
+        &stamp[6..8],
+        &stamp[9..11],
+        &stamp[11..13],
+        &stamp[13..15]
+    )
+}
@@ -0,0 +1,327 @@
+// --output: places requests go besides the console, any number at once, each with a filter of its own, e.g.
+// `--output json:all.jsonl --output "console where tcp"` logs everything but only prints TCP. Each is written as
+// KIND[:TARGET], optionally followed by `where` and a filter expression (the same syntax as --filter):
+//
+//     console                    the usual output; once any --output is given, the console only shows what a console
+//                                output's filter lets through (and nothing, if there isn't one)
+//     json:PATH                  a JSON line per request
+//     pcap:PATH                  the request's packets, as Ethernet frames
+//     syslog[:HOST[:PORT]]       a line per request to a syslog server over UDP (port 514), or the local /dev/log
+//     webhook:URL                batches of requests POSTed as JSON, as with --push-url
+//
+// the outputs see requests after the protocol filters but before the display filters, like the -l log
+
+use std::{
+    fs::File,
+    io::{BufWriter, Error, ErrorKind, Write},
+    net::{ToSocketAddrs, UdpSocket},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{conf::Config, filter::Expr, ip, pcap, pcapout::PcapOutput, push::Pusher, replay, rotate, RequestStats};
+
+const SYSLOG_PORT: u16 = 514;
+// user-level messages, informational
+const SYSLOG_PRIORITY: u8 = 8 + 6;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum OutputKind {
+    Console,
+    Json(String),
+    Pcap(String),
+    Syslog(Option<String>),
+    Webhook(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Output {
+    pub kind: OutputKind,
+    pub filter: Option<Expr>,
+}
+
+impl Output {
+    pub fn matches(&self, stats: &RequestStats, orig_name: &str, dest_name: &str) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|x| x.matches(stats, orig_name, dest_name))
+    }
+}
+
+impl FromStr for Output {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Output, Error> {
+        let (spec, filter) = match s.split_once(" where ") {
+            Some((spec, filter)) => (spec.trim(), Some(filter.parse()?)),
+            None => (s.trim(), None),
+        };
+        let (kind, target) = match spec.split_once(':') {
+            Some((kind, target)) => (kind, Some(target.to_string())),
+            None => (spec, None),
+        };
+
+        let needs_target = |target: Option<String>| {
+            target.filter(|x| !x.is_empty()).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("--output {} needs a target, e.g. {}:PATH", kind, kind),
+                )
+            })
+        };
+
+        let kind = match kind {
+            "console" if target.is_none() => OutputKind::Console,
+            "json" => OutputKind::Json(needs_target(target)?),
+            "pcap" => OutputKind::Pcap(needs_target(target)?),
+            "syslog" => OutputKind::Syslog(target.filter(|x| !x.is_empty())),
+            "webhook" => OutputKind::Webhook(needs_target(target)?),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Invalid output, expected console, json:PATH, pcap:PATH, syslog[:HOST[:PORT]] or webhook:URL",
+                ))
+            }
+        };
+
+        Ok(Output { kind, filter })
+    }
+}
+
+impl std::fmt::Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            OutputKind::Console => write!(f, "console")?,
+            OutputKind::Json(ref path) => write!(f, "json:{}", path)?,
+            OutputKind::Pcap(ref path) => write!(f, "pcap:{}", path)?,
+            OutputKind::Syslog(None) => write!(f, "syslog")?,
+            OutputKind::Syslog(Some(ref host)) => write!(f, "syslog:{}", host)?,
+            OutputKind::Webhook(ref url) => write!(f, "webhook:{}", url)?,
+        }
+        match self.filter {
+            Some(ref filter) => write!(f, " where {}", filter),
+            None => Ok(()),
+        }
+    }
+}
+
+// anywhere requests can be written to
+pub trait Sink {
+    fn write(&mut self, stats: &RequestStats);
+    // write out anything buffered, and say how it went
+    fn finish(self: Box<Self>);
+}
+
+// every output but the console, which is print_request's own
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<(Option<Expr>, Box<dyn Sink>)>,
+}
+
+impl Sinks {
+    pub fn new(config: &Config) -> Sinks {
+        let mut sinks = Sinks::default();
+
+        for output in config.outputs.iter() {
+            let sink: Box<dyn Sink> = match output.kind {
+                OutputKind::Console => continue,
+                OutputKind::Json(ref path) => Box::new(JsonSink::create(path)),
+                OutputKind::Pcap(ref path) => {
+                    Box::new(PcapSink(PcapOutput::new(path, pcap::LINKTYPE_ETHERNET, None, None)))
+                }
+                OutputKind::Syslog(ref host) => Box::new(SyslogSink::connect(host.as_deref())),
+                OutputKind::Webhook(ref url) => Box::new(Pusher::new(url, config.push_batch, config.flush_interval)),
+            };
+            sinks.sinks.push((output.filter.clone(), sink));
+        }
+
+        sinks
+    }
+
+    // orig_name/dest_name are the addresses as displayed, for the filters
+    pub fn write(&mut self, stats: &RequestStats, orig_name: &str, dest_name: &str) {
+        for (filter, sink) in self.sinks.iter_mut() {
+            if filter.as_ref().is_none_or(|x| x.matches(stats, orig_name, dest_name)) {
+                sink.write(stats);
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        for (_, sink) in self.sinks.drain(..) {
+            sink.finish();
+        }
+    }
+}
+
+struct JsonSink {
+    path: String,
+    writer: Option<BufWriter<File>>, // none once writing's failed
+    written: u64,
+}
+
+impl JsonSink {
+    fn create(path: &str) -> JsonSink {
+        let file = File::create(path).unwrap_or_else(|e| panic!("Failed to create {}: {}", path, e));
+
+        JsonSink {
+            path: path.to_string(),
+            writer: Some(BufWriter::new(file)),
+            written: 0,
+        }
+    }
+}
+
+impl Sink for JsonSink {
+    fn write(&mut self, stats: &RequestStats) {
+        let Some(ref mut writer) = self.writer else {
+            return;
+        };
+        if let Err(e) = writeln!(writer, "{}", serde_json::to_string(stats).unwrap()) {
+            eprintln!("Failed to write to {}, no longer writing to it: {}", self.path, e);
+            self.writer = None;
+            return;
+        }
+        self.written += 1;
+    }
+
+    fn finish(mut self: Box<Self>) {
+        if let Some(Err(e)) = self.writer.as_mut().map(|x| x.flush()) {
+            eprintln!("Failed to write to {}: {}", self.path, e);
+        }
+        println!(
+            "Wrote {} request{} to {}",
+            self.written,
+            if self.written == 1 { "" } else { "s" },
+            self.path
+        );
+    }
+}
+
+struct PcapSink(PcapOutput);
+
+impl Sink for PcapSink {
+    fn write(&mut self, stats: &RequestStats) {
+        for frame in replay::frames(stats) {
+            self.0.write(stats.timestamp, &frame);
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        self.0.finish();
+    }
+}
+
+impl Sink for Pusher {
+    fn write(&mut self, stats: &RequestStats) {
+        self.push(stats);
+    }
+
+    fn finish(self: Box<Self>) {
+        Pusher::finish(*self);
+    }
+}
+
+enum SyslogSocket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Local(std::os::unix::net::UnixDatagram),
+}
+
+// RFC 5424 messages, one per request
+struct SyslogSink {
+    socket: SyslogSocket,
+    sent: u64,
+    failed: u64,
+}
+
+impl SyslogSink {
+    fn connect(host: Option<&str>) -> SyslogSink {
+        let socket = match host {
+            Some(host) => {
+                // the port's optional
+                let address = host
+                    .to_socket_addrs()
+                    .or_else(|_| (host.trim_start_matches('[').trim_end_matches(']'), SYSLOG_PORT).to_socket_addrs())
+                    .ok()
+                    .and_then(|mut x| x.next())
+                    .unwrap_or_else(|| panic!("Invalid syslog server {}", host));
+
+                let bind = if address.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+                let socket = UdpSocket::bind(bind)
+                    .and_then(|x| x.connect(address).map(|_| x))
+                    .unwrap_or_else(|e| panic!("Failed to open a socket to {}: {}", address, e));
+                SyslogSocket::Udp(socket)
+            }
+            #[cfg(unix)]
+            None => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()
+                    .and_then(|x| x.connect("/dev/log").map(|_| x))
+                    .unwrap_or_else(|e| panic!("Failed to connect to /dev/log: {}", e));
+                SyslogSocket::Local(socket)
+            }
+            #[cfg(not(unix))]
+            None => panic!("There's no local syslog here, give a server, e.g. --output syslog:10.0.0.5"),
+        };
+
+        SyslogSink {
+            socket,
+            sent: 0,
+            failed: 0,
+        }
+    }
+}
+
+impl Sink for SyslogSink {
+    fn write(&mut self, stats: &RequestStats) {
+        let ports = ip::transport(&stats.raw).map(|(src, dst, _)| (src, dst));
+        let endpoint = |ip: &crate::conf::IpAddr, port: Option<u16>| match port {
+            Some(port) if ip.to_std().is_ipv6() => format!("[{}]:{}", ip, port),
+            Some(port) => format!("{}:{}", ip, port),
+            None => ip.to_string(),
+        };
+
+        let message = format!(
+            "<{}>1 {} - sniff {} - - {} {} -> {} {} byte{} {} packet{}",
+            SYSLOG_PRIORITY,
+            rotate::rfc3339(stats.timestamp),
+            std::process::id(),
+            stats.protocol,
+            endpoint(&stats.orig_ip, ports.map(|x| x.0)),
+            endpoint(&stats.dest_ip, ports.map(|x| x.1)),
+            stats.bytes,
+            if stats.bytes == 1 { "" } else { "s" },
+            stats.packets,
+            if stats.packets == 1 { "" } else { "s" },
+        );
+
+        let sent = match self.socket {
+            SyslogSocket::Udp(ref socket) => socket.send(message.as_bytes()),
+            #[cfg(unix)]
+            SyslogSocket::Local(ref socket) => socket.send(message.as_bytes()),
+        };
+        match sent {
+            Ok(_) => self.sent += 1,
+            Err(e) => {
+                if self.failed == 0 {
+                    eprintln!("Failed to send to syslog: {}", e);
+                }
+                self.failed += 1;
+            }
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        println!(
+            "Sent {} request{} to syslog{}",
+            self.sent,
+            if self.sent == 1 { "" } else { "s" },
+            if self.failed > 0 {
+                format!(", {} failed", self.failed)
+            } else {
+                String::new()
+            },
+        );
+    }
+}
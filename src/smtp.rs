@@ -0,0 +1,199 @@
+// a minimal SMTP client, for alert rules' email actions: one message per connection, to a submission server (or a
+// local relay), over implicit TLS (smtps, port 465), STARTTLS (port 587) or, for a relay on the same host or network
+// that doesn't do TLS, plain SMTP (port 25). Logging in is AUTH PLAIN, which is only ever done over TLS
+
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::{base64, rotate};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    Tls,
+    StartTls,
+    None,
+}
+
+impl Security {
+    pub fn default_port(self) -> u16 {
+        match self {
+            Security::Tls => 465,
+            Security::StartTls => 587,
+            Security::None => 25,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Mailer {
+    pub server: String,
+    pub port: u16,
+    pub security: Security,
+    pub login: Option<(String, String)>, // username and password
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+impl Mailer {
+    pub fn send(&self, subject: &str, body: &str) -> Result<(), Error> {
+        let tcp = TcpStream::connect((self.server.as_str(), self.port))?;
+        tcp.set_read_timeout(Some(TIMEOUT))?;
+        tcp.set_write_timeout(Some(TIMEOUT))?;
+        // without a name of our own to give, EHLO takes our address
+        let hello = match tcp.local_addr()?.ip() {
+            std::net::IpAddr::V4(ip) => format!("[{}]", ip),
+            std::net::IpAddr::V6(ip) => format!("[IPv6:{}]", ip),
+        };
+
+        let mut stream: Box<dyn Stream> = match self.security {
+            Security::Tls => Box::new(tls(&self.server, tcp)?),
+            _ => Box::new(tcp),
+        };
+
+        expect(&mut stream, 220)?;
+        command(&mut stream, &format!("EHLO {}", hello), 250)?;
+
+        if self.security == Security::StartTls {
+            command(&mut stream, "STARTTLS", 220)?;
+            // the TLS session goes over the connection we already have
+            stream = Box::new(tls(&self.server, stream)?);
+            command(&mut stream, &format!("EHLO {}", hello), 250)?;
+        }
+
+        if let Some((ref username, ref password)) = self.login {
+            let token = base64::encode(format!("\0{}\0{}", username, password).as_bytes());
+            command(&mut stream, &format!("AUTH PLAIN {}", token), 235)?;
+        }
+
+        command(&mut stream, &format!("MAIL FROM:<{}>", self.from), 250)?;
+        for to in self.to.iter() {
+            command(&mut stream, &format!("RCPT TO:<{}>", to), 250)?;
+        }
+        command(&mut stream, "DATA", 354)?;
+
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            subject,
+            date(SystemTime::now())
+        );
+        for line in body.lines() {
+            // a line starting with a dot gets another, so it can't end the message early
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push_str(".\r\n");
+        stream.write_all(message.as_bytes())?;
+        expect(&mut stream, 250)?;
+
+        // the message is accepted by now, whatever the server says to this
+        let _ = stream.write_all(b"QUIT\r\n");
+        Ok(())
+    }
+}
+
+fn command(stream: &mut Box<dyn Stream>, line: &str, code: u16) -> Result<(), Error> {
+    stream.write_all(format!("{}\r\n", line).as_bytes())?;
+    expect(stream, code).map_err(|e| {
+        // don't put the credentials in an error message
+        let sent = if line.starts_with("AUTH") { "AUTH PLAIN" } else { line };
+        Error::new(e.kind(), format!("{} (after {})", e, sent))
+    })
+}
+
+// a reply, which is a line or (with a "-" after the code) several, and whether it's the one we wanted
+fn expect(stream: &mut Box<dyn Stream>, code: u16) -> Result<(), Error> {
+    loop {
+        let line = read_line(stream)?;
+        let reply: u16 = line
+            .get(..3)
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unexpected reply: {}", line)))?;
+
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        // 251 is "not local, will forward", as good as 250 for a recipient
+        return match reply == code || (code == 250 && reply == 251) {
+            true => Ok(()),
+            false => Err(Error::other(format!("Server said {}", line))),
+        };
+    }
+}
+
+// a byte at a time, since there's so little of it and nothing must be left in a buffer when STARTTLS takes over
+fn read_line(stream: &mut Box<dyn Stream>) -> Result<String, Error> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while !line.ends_with(b"\r\n") {
+        if stream.read(&mut byte)? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Server closed the connection"));
+        }
+        line.push(byte[0]);
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+fn tls_config() -> Result<Arc<rustls::ClientConfig>, Error> {
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+fn tls<S: Read + Write>(server: &str, stream: S) -> Result<rustls::StreamOwned<rustls::ClientConnection, S>, Error> {
+    let name = rustls::pki_types::ServerName::try_from(server.to_string())
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let connection = rustls::ClientConnection::new(tls_config()?, name).map_err(Error::other)?;
+    Ok(rustls::StreamOwned::new(connection, stream))
+}
+
+// RFC 5322's date format, in UTC, e.g. "Tue, 14 Nov 2023 22:13:20 +0000"
+pub fn date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = rotate::civil_date(days);
+
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} +0000",
+        // the epoch was a Thursday
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
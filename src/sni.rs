@@ -0,0 +1,122 @@
+// the server names clients ask for in TLS ClientHellos (SNI), by server address, so hostname filters can go by the name
+// a connection was made to rather than the reverse DNS of the address, which for anything behind a CDN or cloud load
+// balancer says little
+//
+// an address serving several names (as CDNs do) is put down to the last one asked for. QUIC connections' names come
+// from quic.rs, which has to decrypt them first
+
+use std::{collections::HashMap, net::IpAddr};
+
+use crate::{conf::Protocol, ip, RequestStats};
+
+// forgotten all at once when there are this many, rather than keeping track of which are stale
+const MAX_SERVERS: usize = 65536;
+
+const HANDSHAKE: u8 = 0x16;
+const CLIENT_HELLO: u8 = 0x01;
+const SERVER_NAME: u16 = 0;
+const ALPN: u16 = 16;
+
+#[derive(Default)]
+pub struct ServerNames {
+    names: HashMap<IpAddr, String>,
+}
+
+impl ServerNames {
+    pub fn observe(&mut self, stats: &RequestStats) {
+        // QUIC's ClientHello is encrypted, and was picked out when the request was collated
+        if let Some((quic, name)) = stats.quic.as_ref().and_then(|x| Some((x, x.server_name.clone()?))) {
+            self.insert(quic.server.ip(), name);
+        }
+
+        if stats.protocol != Protocol::Tcp {
+            return;
+        }
+
+        for packet in ip::split_packets(&stats.raw) {
+            let (Some(header), Some((_, _, payload))) = (ip::parse(packet), ip::transport(packet)) else {
+                continue;
+            };
+            if let Some(name) = client_hello_server_name(payload) {
+                self.insert(header.dst, name);
+            }
+        }
+    }
+
+    fn insert(&mut self, server: IpAddr, name: String) {
+        if self.names.len() >= MAX_SERVERS {
+            self.names.clear();
+        }
+        self.names.insert(server, name);
+    }
+
+    pub fn get(&self, ip: IpAddr) -> Option<&str> {
+        self.names.get(&ip).map(|x| x.as_str())
+    }
+}
+
+// the host name in a ClientHello's server_name extension, if the segment starts with one
+pub fn client_hello_server_name(data: &[u8]) -> Option<String> {
+    if *data.first()? != HANDSHAKE {
+        return None;
+    }
+    parse_client_hello(data.get(5..)?)?.server_name
+}
+
+pub struct ClientHello {
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>, // the application protocols offered, e.g. h2 or h3
+}
+
+// a ClientHello handshake message, as carried in a TLS record or QUIC's CRYPTO frames
+pub fn parse_client_hello(data: &[u8]) -> Option<ClientHello> {
+    if *data.first()? != CLIENT_HELLO {
+        return None;
+    }
+
+    // handshake header (4), client version (2) and random (32)
+    let mut at = 38;
+    let u8_at = |at: usize| data.get(at).map(|x| *x as usize);
+    let u16_at = |at: usize| data.get(at..at + 2).map(|x| u16::from_be_bytes([x[0], x[1]]) as usize);
+
+    at += 1 + u8_at(at)?; // session id
+    at += 2 + u16_at(at)?; // cipher suites
+    at += 1 + u8_at(at)?; // compression methods
+
+    let end = (at + 2 + u16_at(at)?).min(data.len());
+    at += 2;
+
+    let mut hello = ClientHello {
+        server_name: None,
+        alpn: Vec::new(),
+    };
+
+    while at + 4 <= end {
+        let (kind, len) = (u16_at(at)? as u16, u16_at(at + 2)?);
+        at += 4;
+
+        match kind {
+            // a list of (type, name), of which only host names (type 0) are ever used
+            SERVER_NAME if u8_at(at + 2)? == 0 => {
+                let name_len = u16_at(at + 3)?;
+                let name = data.get(at + 5..at + 5 + name_len)?;
+                hello.server_name = std::str::from_utf8(name).ok().map(|x| x.to_ascii_lowercase());
+            }
+            // a list of length-prefixed protocol names
+            ALPN => {
+                let list_end = (at + 2 + u16_at(at)?).min(at + len);
+                let mut name_at = at + 2;
+                while name_at < list_end {
+                    let name_len = u8_at(name_at)?;
+                    let name = data.get(name_at + 1..name_at + 1 + name_len)?;
+                    hello.alpn.push(String::from_utf8_lossy(name).to_string());
+                    name_at += 1 + name_len;
+                }
+            }
+            _ => {}
+        }
+        at += len;
+    }
+
+    Some(hello)
+}
@@ -0,0 +1,346 @@
+// --tcp-stalls: TCP connections held up by the receiver's window, rather than the network, which packet and byte
+// counts never show: a zero window (the receiving application isn't reading) or a full one (everything the receiver
+// has room for is in flight, waiting to be acknowledged)
+//
+// windows are scaled by the factors both ends offer in their SYNs, so window-full stalls are only looked for on
+// connections we saw open; a zero window is zero whatever the scale
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, theme::Theme};
+
+// a full window is normal for a moment at a time in a bulk transfer, and a zero window that opens straight back up
+// isn't holding anything up for long; only longer ones are called out as they end (they're all in the report)
+const ZERO_WARNING: Duration = Duration::from_millis(100);
+const FULL_WARNING: Duration = Duration::from_secs(1);
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const PRUNE_EVERY: u64 = 4096;
+
+// what one end of a connection has sent
+#[derive(Default)]
+struct Side {
+    syn: bool,
+    scale: Option<u8>, // the window scale it offered in its SYN
+    window: u32,       // the window it last advertised, unscaled
+    seq_end: Option<u32>,
+    ack: Option<u32>,
+}
+
+// a stall in the data going from one end to the other, by kind: since when, if it's going on now
+#[derive(Default)]
+struct Stall {
+    zero: Option<SystemTime>,
+    full: Option<SystemTime>,
+}
+
+struct Connection {
+    ends: [SocketAddr; 2],
+    sides: [Side; 2],
+    stalls: [Stall; 2], // of the data each side sends
+    fin: [bool; 2],
+    last: SystemTime,
+}
+
+// stalls so far on the data going one way, from sender to receiver
+#[derive(Default)]
+struct Totals {
+    zero_periods: u64,
+    zero_time: Duration,
+    full_periods: u64,
+    full_time: Duration,
+}
+
+pub struct StallTracker {
+    theme: Theme,
+    connections: HashMap<(SocketAddr, SocketAddr), Connection>, // the ends, lower first
+    totals: BTreeMap<(SocketAddr, SocketAddr), Totals>,         // (sender, receiver)
+    observed: u64,
+}
+
+impl StallTracker {
+    pub fn new(theme: Theme) -> StallTracker {
+        StallTracker {
+            theme,
+            connections: HashMap::new(),
+            totals: BTreeMap::new(),
+            observed: 0,
+        }
+    }
+
+    // look at one IP packet, following the windows of the TCP connection it's part of
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet).filter(|x| x.protocol == 6) else {
+            return;
+        };
+        // short frames are padded out, and the padding isn't part of the segment
+        let end = ip::total_len(packet).unwrap_or(packet.len()).min(packet.len());
+        let Some(segment) = packet.get(header.header_len..end).filter(|x| x.len() >= 20) else {
+            return;
+        };
+
+        let src = SocketAddr::new(header.src, u16::from_be_bytes([segment[0], segment[1]]));
+        let dst = SocketAddr::new(header.dst, u16::from_be_bytes([segment[2], segment[3]]));
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let ack = u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]);
+        let header_len = (segment[12] >> 4) as usize * 4;
+        let flags = segment[13];
+        let (fin, syn, rst, has_ack) = (
+            flags & 0x01 != 0,
+            flags & 0x02 != 0,
+            flags & 0x04 != 0,
+            flags & 0x10 != 0,
+        );
+        let window = u16::from_be_bytes([segment[14], segment[15]]) as u32;
+        let payload_len = segment.len().saturating_sub(header_len) as u32;
+
+        self.observed += 1;
+        if self.observed.is_multiple_of(PRUNE_EVERY) {
+            self.prune(timestamp);
+        }
+
+        let key = if src < dst { (src, dst) } else { (dst, src) };
+        let this = if src < dst { 0 } else { 1 };
+
+        if rst {
+            if let Some(connection) = self.connections.remove(&key) {
+                self.close(connection, timestamp);
+            }
+            return;
+        }
+
+        let connection = self.connections.entry(key).or_insert_with(|| Connection {
+            ends: [key.0, key.1],
+            sides: Default::default(),
+            stalls: Default::default(),
+            fin: [false; 2],
+            last: timestamp,
+        });
+        connection.last = timestamp;
+
+        let side = &mut connection.sides[this];
+        if syn {
+            side.syn = true;
+            side.scale = window_scale(segment.get(20..header_len).unwrap_or_default());
+        }
+
+        // SYN and FIN each take up a sequence number
+        let seq_end = seq.wrapping_add(payload_len + syn as u32 + fin as u32);
+        if side.seq_end.is_none_or(|x| after(seq_end, x)) {
+            side.seq_end = Some(seq_end);
+        }
+        // the window in a SYN is never scaled, and means nothing for a stall
+        if has_ack && !syn {
+            side.ack = Some(ack);
+            side.window = window;
+        }
+
+        connection.fin[this] |= fin;
+        let stalls = self.update(key, timestamp);
+
+        for (sender, receiver, kind, duration) in stalls {
+            self.finish_stall(sender, receiver, kind, duration);
+        }
+
+        if self.connections.get(&key).is_some_and(|x| x.fin == [true; 2]) {
+            let connection = self.connections.remove(&key).unwrap();
+            self.close(connection, timestamp);
+        }
+    }
+
+    // start and end stalls on the data going each way, returning the ones that just ended
+    fn update(
+        &mut self,
+        key: (SocketAddr, SocketAddr),
+        now: SystemTime,
+    ) -> Vec<(SocketAddr, SocketAddr, Kind, Duration)> {
+        let connection = self.connections.get_mut(&key).unwrap();
+        let mut ended = Vec::new();
+
+        // scaling only happens if both ends offered it
+        let scales = match (&connection.sides[0], &connection.sides[1]) {
+            (a, b) if a.syn && b.syn => Some(match (a.scale, b.scale) {
+                (Some(x), Some(y)) => [x, y],
+                _ => [0, 0],
+            }),
+            _ => None,
+        };
+
+        for sender in 0..2 {
+            let receiver = 1 - sender;
+            let receiving = &connection.sides[receiver];
+            // nothing's been advertised yet
+            if receiving.ack.is_none() {
+                continue;
+            }
+
+            let zero = receiving.window == 0;
+            let full = match (scales, connection.sides[sender].seq_end, receiving.ack) {
+                (Some(scales), Some(seq_end), Some(ack)) if !zero => {
+                    let in_flight = seq_end.wrapping_sub(ack) as u64;
+                    in_flight < u32::MAX as u64 / 2
+                        && in_flight >= (receiving.window as u64) << scales[receiver].min(14)
+                }
+                _ => false,
+            };
+
+            let stall = &mut connection.stalls[sender];
+            let ends = (connection.ends[sender], connection.ends[receiver]);
+            let kinds = [(Kind::Zero, zero, &mut stall.zero), (Kind::Full, full, &mut stall.full)];
+            for (kind, now_stalled, since) in kinds {
+                match (*since, now_stalled) {
+                    (None, true) => *since = Some(now),
+                    (Some(started), false) => {
+                        *since = None;
+                        ended.push((ends.0, ends.1, kind, now.duration_since(started).unwrap_or_default()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        ended
+    }
+
+    fn finish_stall(&mut self, sender: SocketAddr, receiver: SocketAddr, kind: Kind, duration: Duration) {
+        let totals = self.totals.entry((sender, receiver)).or_default();
+        match kind {
+            Kind::Zero => {
+                totals.zero_periods += 1;
+                totals.zero_time += duration;
+            }
+            Kind::Full => {
+                totals.full_periods += 1;
+                totals.full_time += duration;
+            }
+        }
+
+        let threshold = match kind {
+            Kind::Zero => ZERO_WARNING,
+            Kind::Full => FULL_WARNING,
+        };
+        if duration >= threshold {
+            let message = format!(
+                "*** TCP {}: {} -> {} stalled for {:.2}s ***",
+                kind,
+                sender,
+                receiver,
+                duration.as_secs_f64()
+            );
+            outln!("{}", self.theme.paint(self.theme.warning, &message));
+        }
+    }
+
+    // a connection that's gone, with any stall it was in ending when we last heard from it
+    fn close(&mut self, connection: Connection, now: SystemTime) {
+        for sender in 0..2 {
+            let stall = &connection.stalls[sender];
+            for (kind, since) in [(Kind::Zero, stall.zero), (Kind::Full, stall.full)] {
+                if let Some(started) = since {
+                    let duration = now.duration_since(started).unwrap_or_default();
+                    self.finish_stall(connection.ends[sender], connection.ends[1 - sender], kind, duration);
+                }
+            }
+        }
+    }
+
+    fn prune(&mut self, now: SystemTime) {
+        let idle: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, x)| now.duration_since(x.last).unwrap_or_default() >= IDLE_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in idle {
+            let connection = self.connections.remove(&key).unwrap();
+            let last = connection.last;
+            self.close(connection, last);
+        }
+    }
+
+    pub fn print_report(mut self) {
+        // stalls still going on count up to the end of the capture
+        for (_, connection) in std::mem::take(&mut self.connections) {
+            let last = connection.last;
+            self.close(connection, last);
+        }
+
+        if self.totals.is_empty() {
+            return;
+        }
+
+        let mut flows: Vec<_> = self.totals.iter().collect();
+        flows.sort_by_key(|(_, x)| std::cmp::Reverse(x.zero_time + x.full_time));
+
+        println!("TCP stalls (sender -> receiver):");
+        for ((sender, receiver), totals) in flows {
+            let mut kinds = Vec::new();
+            if totals.zero_periods > 0 {
+                kinds.push(format!(
+                    "zero window {} time{}, {:.2}s",
+                    totals.zero_periods,
+                    if totals.zero_periods == 1 { "" } else { "s" },
+                    totals.zero_time.as_secs_f64()
+                ));
+            }
+            if totals.full_periods > 0 {
+                kinds.push(format!(
+                    "window full {} time{}, {:.2}s",
+                    totals.full_periods,
+                    if totals.full_periods == 1 { "" } else { "s" },
+                    totals.full_time.as_secs_f64()
+                ));
+            }
+            println!(
+                "    {} -> {}: stalled {:.2}s ({})",
+                sender,
+                receiver,
+                (totals.zero_time + totals.full_time).as_secs_f64(),
+                kinds.join("; ")
+            );
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Zero,
+    Full,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Kind::Zero => write!(f, "zero window"),
+            Kind::Full => write!(f, "window full"),
+        }
+    }
+}
+
+// whether sequence number a comes after b, allowing for wraparound
+fn after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+// the window scale option (kind 3) from a SYN's options
+fn window_scale(mut options: &[u8]) -> Option<u8> {
+    while let Some(&kind) = options.first() {
+        match kind {
+            0 => return None,
+            1 => options = &options[1..],
+            _ => {
+                let len = *options.get(1)? as usize;
+                if kind == 3 && len == 3 {
+                    return options.get(2).copied();
+                }
+                options = options.get(len.max(2)..)?;
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,185 @@
+// --format: each request's line laid out by the user, e.g. "{time} {proto} {src}:{sport} -> {dst}:{dport} {bytes}",
+// in place of the terse or verbose layouts; {{ and }} are literal braces, and fields a request doesn't have (e.g. the
+// ports of an ICMP message) come out as -
+
+use std::{
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Time,
+    Proto,
+    Src,
+    Sport,
+    Dst,
+    Dport,
+    Bytes,
+    Rate,
+    Packets,
+    Tx,
+    Rx,
+    Ipv,
+    Direction,
+    Vlan,
+    Ttl,
+    Dscp,
+    Ecn,
+    FlowLabel,
+    SrcMac,
+    DstMac,
+    SrcVendor,
+    DstVendor,
+    SrcGeo,
+    DstGeo,
+    Service,
+    Columns,
+}
+
+const FIELDS: [(&str, Field); 26] = [
+    ("time", Field::Time),
+    ("proto", Field::Proto),
+    ("src", Field::Src),
+    ("sport", Field::Sport),
+    ("dst", Field::Dst),
+    ("dport", Field::Dport),
+    ("bytes", Field::Bytes),
+    ("rate", Field::Rate),
+    ("packets", Field::Packets),
+    ("tx", Field::Tx),
+    ("rx", Field::Rx),
+    ("ipv", Field::Ipv),
+    ("direction", Field::Direction),
+    ("vlan", Field::Vlan),
+    ("ttl", Field::Ttl),
+    ("dscp", Field::Dscp),
+    ("ecn", Field::Ecn),
+    ("flow_label", Field::FlowLabel),
+    ("src_mac", Field::SrcMac),
+    ("dst_mac", Field::DstMac),
+    ("src_vendor", Field::SrcVendor),
+    ("dst_vendor", Field::DstVendor),
+    ("src_geo", Field::SrcGeo),
+    ("dst_geo", Field::DstGeo),
+    ("service", Field::Service),
+    ("columns", Field::Columns),
+];
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    // the line for one request, given each field's value, if it has one
+    pub fn render(&self, value: impl Fn(Field) -> Option<String>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => value(*field).unwrap_or_else(|| "-".to_string()),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "Unterminated { in format, use {{ for a literal one",
+                        ));
+                    }
+
+                    let field = FIELDS
+                        .iter()
+                        .find(|(x, _)| *x == name)
+                        .map(|(_, field)| *field)
+                        .ok_or_else(|| {
+                            let names: Vec<&str> = FIELDS.iter().map(|(x, _)| *x).collect();
+                            Error::new(
+                                ErrorKind::InvalidInput,
+                                format!(
+                                    "Unknown format field {{{}}}, expected one of {}",
+                                    name,
+                                    names.join(", ")
+                                ),
+                            )
+                        })?;
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "Unmatched } in format, use }} for a literal one",
+                    ))
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template {
+            source: s.to_string(),
+            parts,
+        })
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> String {
+        template.source
+    }
+}
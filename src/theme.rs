@@ -0,0 +1,100 @@
+use std::io::IsTerminal;
+
+use anstyle::{Ansi256Color, AnsiColor, Style};
+
+use crate::conf::{ColorName, ColorWhen, Protocol, ThemeName};
+
+// every color sniff prints with comes from here, so a theme applies everywhere at once
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub highlight: Style,
+    pub warning: Style,
+    pub anomaly: Style, // something going wrong on the network, rather than a warning from sniff
+    pub tcp: Style,
+    pub udp: Style,
+    pub icmp: Style,
+    pub unknown: Style,
+    enabled: bool, // false when piping to a file, so it doesn't fill up with escape codes
+}
+
+impl Theme {
+    pub fn new(name: ThemeName, color: ColorWhen, highlight: Option<ColorName>) -> Theme {
+        let mut theme = Theme::named(name);
+
+        theme.enabled = match color {
+            ColorWhen::Always => true,
+            ColorWhen::Never => false,
+            // https://no-color.org
+            ColorWhen::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|x| x.is_empty()),
+        };
+
+        if let Some(highlight) = highlight {
+            theme.highlight = highlight.ansi().on_default().bold();
+        }
+
+        theme
+    }
+
+    fn named(name: ThemeName) -> Theme {
+        match name {
+            ThemeName::Default => Theme {
+                highlight: AnsiColor::Red.on_default().bold(),
+                warning: AnsiColor::Yellow.on_default().bold(),
+                anomaly: AnsiColor::BrightRed.on_default().bold(),
+                tcp: AnsiColor::Cyan.on_default(),
+                udp: AnsiColor::Green.on_default(),
+                icmp: AnsiColor::Magenta.on_default(),
+                unknown: Style::new(),
+                enabled: true,
+            },
+            // blue/orange/purple from the Okabe-Ito palette, which stay distinct without red-green vision
+            ThemeName::Deuteranopia => Theme {
+                highlight: Ansi256Color(208).on_default().bold(), // orange
+                warning: Ansi256Color(220).on_default().bold(), // yellow
+                anomaly: Ansi256Color(166).on_default().bold(), // vermillion
+                tcp: Ansi256Color(33).on_default(), // blue
+                udp: Ansi256Color(117).on_default(), // sky blue
+                icmp: Ansi256Color(175).on_default(), // reddish purple
+                unknown: Style::new(),
+                enabled: true,
+            },
+            ThemeName::HighContrast => Theme {
+                highlight: AnsiColor::Black.on(AnsiColor::BrightYellow).bold(),
+                warning: AnsiColor::BrightWhite.on(AnsiColor::Red).bold(),
+                anomaly: AnsiColor::BrightWhite.on(AnsiColor::Magenta).bold(),
+                tcp: AnsiColor::BrightCyan.on_default().bold(),
+                udp: AnsiColor::BrightGreen.on_default().bold(),
+                icmp: AnsiColor::BrightMagenta.on_default().bold(),
+                unknown: AnsiColor::BrightWhite.on_default().bold(),
+                enabled: true,
+            },
+            // no colors at all, only weight and decoration
+            ThemeName::Monochrome => Theme {
+                highlight: Style::new().bold().underline(),
+                warning: Style::new().bold().invert(),
+                anomaly: Style::new().bold().italic().invert(),
+                tcp: Style::new().bold(),
+                udp: Style::new(),
+                icmp: Style::new().italic(),
+                unknown: Style::new().dimmed(),
+                enabled: true,
+            },
+        }
+    }
+
+    pub fn protocol(&self, protocol: Protocol) -> Style {
+        match protocol {
+            Protocol::Tcp => self.tcp,
+            Protocol::Udp => self.udp,
+            Protocol::Icmp => self.icmp,
+            Protocol::Unknown | Protocol::Ip(_) | Protocol::Ether(_) => self.unknown,
+        }
+    }
+
+    pub fn paint(&self, style: Style, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+        format!("{}{}{}", style.render(), text, style.render_reset())
+    }
+}
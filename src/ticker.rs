@@ -0,0 +1,88 @@
+// --interval: a line of totals every so often, between the requests, in the spirit of iftop's, so the trend is there
+// to see even when the filters leave hardly any requests showing
+//
+//     [interval] 10.00s to 20.00s: 1204.5 packets/s, 1.2 MiB/s, 37 active flows, 0 dropped
+//
+// dropped is by sniff and, on Linux, by the kernel
+
+use std::{
+    collections::HashSet,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::{flows::FlowKey, locale::Locale, metrics::METRICS, theme::Theme, units};
+
+pub struct IntervalTicker {
+    interval: Duration,
+    started: Instant,
+    window_start: Instant,
+    packets: u64, // the counters as they were when the window started
+    bytes: u64,
+    dropped: u64,
+    flows: HashSet<FlowKey>, // with a request in this window
+    theme: Theme,
+    locale: Option<Locale>,
+}
+
+impl IntervalTicker {
+    pub fn new(interval: Duration, started: Instant, theme: Theme, locale: Option<Locale>) -> IntervalTicker {
+        IntervalTicker {
+            interval,
+            started,
+            window_start: Instant::now(),
+            packets: METRICS.packets.load(Ordering::Relaxed),
+            bytes: METRICS.bytes.load(Ordering::Relaxed),
+            dropped: dropped(),
+            flows: HashSet::new(),
+            theme,
+            locale,
+        }
+    }
+
+    // count a flow as active, whether or not its requests make it through the filters
+    pub fn observe(&mut self, flow: &FlowKey) {
+        if !self.flows.contains(flow) {
+            self.flows.insert(flow.clone());
+        }
+    }
+
+    // print the window's line if it's over; called from the capture loop, so it keeps time when nothing's coming in
+    pub fn tick(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < self.interval {
+            return;
+        }
+
+        let (packets, bytes, dropped) = (
+            METRICS.packets.load(Ordering::Relaxed),
+            METRICS.bytes.load(Ordering::Relaxed),
+            dropped(),
+        );
+        let secs = elapsed.as_secs_f64();
+
+        let packet_rate = (packets - self.packets) as f64 / secs;
+        outln!(
+            "{} {:.2}s to {:.2}s: {} packets/s, {}, {} active flow{}, {} dropped",
+            self.theme.paint(self.theme.highlight, "[interval]"),
+            self.window_start.duration_since(self.started).as_secs_f64(),
+            self.started.elapsed().as_secs_f64(),
+            match self.locale {
+                Some(ref locale) => locale.decimal(packet_rate, 1),
+                None => format!("{:.1}", packet_rate),
+            },
+            units::human_rate((bytes - self.bytes) as f64 / secs, self.locale.as_ref()),
+            self.flows.len(),
+            if self.flows.len() == 1 { "" } else { "s" },
+            dropped - self.dropped,
+        );
+
+        self.window_start = Instant::now();
+        (self.packets, self.bytes, self.dropped) = (packets, bytes, dropped);
+        self.flows.clear();
+    }
+}
+
+fn dropped() -> u64 {
+    METRICS.dropped.load(Ordering::Relaxed) + METRICS.kernel_dropped.load(Ordering::Relaxed)
+}
@@ -0,0 +1,170 @@
+// per-service latency, from passive observation alone: for each TCP port something's listening on, how long the
+// server takes to answer a SYN (the handshake, which is mostly the network) and how long it takes to send the first
+// byte of its response once the client's asked (which is mostly the service itself), as p50/p95/p99 over the capture.
+// Shown by `sniff report` on a pcap, and at exit with --service-latency
+//
+// the first byte is timed from the client's first data, or for services that speak first (SSH, SMTP) from the
+// handshake finishing. Only connections seen opening are counted, since the SYN's what says which end's the server
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{conf::Protocol, ip, services::Services};
+
+// connections that never get as far as a response are forgotten after this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const PRUNE_EVERY: u64 = 4096;
+
+struct Connection {
+    syn: SystemTime,
+    syn_ack: Option<SystemTime>,
+    established: Option<SystemTime>, // the client's first segment after the SYN-ACK
+    request: Option<SystemTime>,     // the client's first data
+    last: SystemTime,
+}
+
+// in milliseconds
+#[derive(Default)]
+struct Samples {
+    handshakes: Vec<f64>,
+    first_bytes: Vec<f64>,
+}
+
+#[derive(Default)]
+pub struct ServiceLatency {
+    connections: HashMap<(SocketAddr, SocketAddr), Connection>, // (client, server)
+    ports: BTreeMap<u16, Samples>,                              // server port ->
+    observed: u64,
+}
+
+impl ServiceLatency {
+    // look at one IP packet, timing the TCP connection it's part of
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet).filter(|x| x.protocol == 6) else {
+            return;
+        };
+        // short frames are padded out, and the padding isn't part of the segment
+        let end = ip::total_len(packet).unwrap_or(packet.len()).min(packet.len());
+        let Some(segment) = packet.get(header.header_len..end).filter(|x| x.len() >= 20) else {
+            return;
+        };
+
+        let src = SocketAddr::new(header.src, u16::from_be_bytes([segment[0], segment[1]]));
+        let dst = SocketAddr::new(header.dst, u16::from_be_bytes([segment[2], segment[3]]));
+        let header_len = (segment[12] >> 4) as usize * 4;
+        let flags = segment[13];
+        let (fin, syn, rst, ack) = (
+            flags & 0x01 != 0,
+            flags & 0x02 != 0,
+            flags & 0x04 != 0,
+            flags & 0x10 != 0,
+        );
+        let has_data = segment.len() > header_len;
+
+        self.observed += 1;
+        if self.observed.is_multiple_of(PRUNE_EVERY) {
+            self.connections
+                .retain(|_, x| timestamp.duration_since(x.last).unwrap_or_default() < IDLE_TIMEOUT);
+        }
+
+        // a retransmitted SYN restarts the clock, otherwise we'd count the retransmission timeout as latency
+        if syn && !ack {
+            let connection = Connection {
+                syn: timestamp,
+                syn_ack: None,
+                established: None,
+                request: None,
+                last: timestamp,
+            };
+            self.connections.insert((src, dst), connection);
+            return;
+        }
+
+        let (key, from_client) = if self.connections.contains_key(&(src, dst)) {
+            ((src, dst), true)
+        } else if self.connections.contains_key(&(dst, src)) {
+            ((dst, src), false)
+        } else {
+            return;
+        };
+        if rst || fin {
+            self.connections.remove(&key);
+            return;
+        }
+
+        let connection = self.connections.get_mut(&key).unwrap();
+        connection.last = timestamp;
+        let samples = self.ports.entry(key.1.port()).or_default();
+
+        match (from_client, connection.syn_ack) {
+            // a retransmitted SYN-ACK is only the server answering again
+            (false, None) if syn => {
+                connection.syn_ack = Some(timestamp);
+                samples.handshakes.push(millis(timestamp, connection.syn));
+            }
+            (true, Some(_)) => {
+                connection.established.get_or_insert(timestamp);
+                if has_data {
+                    connection.request.get_or_insert(timestamp);
+                }
+            }
+            (false, Some(syn_ack)) if has_data => {
+                let asked = connection.request.or(connection.established).unwrap_or(syn_ack);
+                samples.first_bytes.push(millis(timestamp, asked));
+                // nothing more to time on this one
+                self.connections.remove(&key);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn print_report(&self, services: &Services, service_names: bool, top: usize) {
+        let mut ports: Vec<_> = self.ports.iter().filter(|(_, x)| !x.handshakes.is_empty()).collect();
+        if ports.is_empty() {
+            return;
+        }
+        // the busiest services first
+        ports.sort_by_key(|(port, x)| (std::cmp::Reverse(x.handshakes.len()), **port));
+        ports.truncate(top);
+
+        println!("Service latency (p50/p95/p99 ms):");
+        for (port, samples) in ports {
+            let name = match services.name(*port, Protocol::Tcp) {
+                Some(name) if service_names => format!("{}/TCP ({})", port, name),
+                _ => format!("{}/TCP", port),
+            };
+            let mut line = format!(
+                "    {:<24} {:>6} handshake{} {}",
+                name,
+                samples.handshakes.len(),
+                if samples.handshakes.len() == 1 { " " } else { "s" },
+                percentiles(&samples.handshakes)
+            );
+            if !samples.first_bytes.is_empty() {
+                line += &format!(
+                    "  {:>6} response{} first byte {}",
+                    samples.first_bytes.len(),
+                    if samples.first_bytes.len() == 1 { " " } else { "s" },
+                    percentiles(&samples.first_bytes)
+                );
+            }
+            println!("{}", line);
+        }
+    }
+}
+
+fn millis(end: SystemTime, start: SystemTime) -> f64 {
+    end.duration_since(start).unwrap_or_default().as_secs_f64() * 1000.0
+}
+
+// p50/p95/p99 by nearest rank, so each is a latency that was actually seen
+fn percentiles(values: &[f64]) -> String {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    let rank = |p: f64| sorted[((p / 100.0 * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+    format!("{:.2}/{:.2}/{:.2}", rank(50.0), rank(95.0), rank(99.0))
+}
@@ -0,0 +1,128 @@
+// --trace-pipeline: times a sample of packets through each stage of the pipeline, to show where delays
+// (and, behind them, ring drops) come from
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::report::format_duration;
+
+// in the order a packet goes through them
+pub const STAGES: [&str; 7] = [
+    "queue",   // captured -> taken off the ring
+    "dissect", // frame parsing and per-packet analysis
+    "collate", // waiting for the rest of its request
+    "enrich",  // request-level analysis: ICMP, routing, rates, GeoIP, plugins
+    "filter",  // filters and rules
+    "output",  // console, log and rotation
+    "total",
+];
+
+// keep memory bounded on long runs; the percentiles are of the first this many samples
+const MAX_SAMPLES: usize = 100_000;
+
+// one sampled packet's times through the stages so far, carried along with it
+#[derive(Clone)]
+pub struct Sample {
+    received: Instant,
+    mark: Instant, // when the last stage ended
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Sample {
+    pub fn new(received: Instant) -> Sample {
+        Sample {
+            received,
+            mark: received,
+            stages: Vec::new(),
+        }
+    }
+
+    // the stage that's just ended
+    pub fn lap(&mut self, stage: &'static str) {
+        self.lap_at(stage, Instant::now());
+    }
+
+    pub fn lap_at(&mut self, stage: &'static str, at: Instant) {
+        self.stages.push((stage, at.saturating_duration_since(self.mark)));
+        self.mark = at;
+    }
+}
+
+pub struct PipelineTrace {
+    every: u64,
+    seen: u64,
+    samples: HashMap<&'static str, Vec<Duration>>,
+    pub filtered: Option<Instant>, // when the request being traced made it through the filters
+}
+
+impl PipelineTrace {
+    pub fn new(every: u64) -> PipelineTrace {
+        PipelineTrace {
+            every: every.max(1),
+            seen: 0,
+            samples: HashMap::new(),
+            filtered: None,
+        }
+    }
+
+    // whether to trace the next packet
+    pub fn sample(&mut self) -> bool {
+        self.seen += 1;
+        self.seen.is_multiple_of(self.every)
+    }
+
+    // a sample that's been all the way through, and its timings under the request if it was shown
+    pub fn finish(&mut self, mut sample: Sample, shown: bool) {
+        sample.stages.push(("total", sample.received.elapsed()));
+
+        if shown {
+            let stages: Vec<String> = sample.stages.iter().map(|(stage, x)| format!("{} {}", stage, format_duration(*x))).collect();
+            outln!("    trace: {}", stages.join(", "));
+        }
+
+        for (stage, duration) in sample.stages {
+            self.record(stage, duration);
+        }
+    }
+
+    fn record(&mut self, stage: &'static str, duration: Duration) {
+        let samples = self.samples.entry(stage).or_default();
+        if samples.len() < MAX_SAMPLES {
+            samples.push(duration);
+        }
+    }
+
+    pub fn print_report(&self) {
+        let Some(traced) = self.samples.get("total").map(|x| x.len()) else {
+            return;
+        };
+
+        println!("Pipeline trace ({} sampled request{}, 1 in {} packets):", traced, if traced == 1 { "" } else { "s" }, self.every);
+        println!("    {:<8} {:>10} {:>10} {:>10} {:>10}", "stage", "p50", "p90", "p99", "max");
+
+        for stage in STAGES {
+            let Some(samples) = self.samples.get(stage) else {
+                continue;
+            };
+            let mut sorted = samples.clone();
+            sorted.sort();
+
+            println!(
+                "    {:<8} {:>10} {:>10} {:>10} {:>10}",
+                stage,
+                format_duration(percentile(&sorted, 50.0)),
+                format_duration(percentile(&sorted, 90.0)),
+                format_duration(percentile(&sorted, 99.0)),
+                format_duration(*sorted.last().unwrap()),
+            );
+        }
+    }
+}
+
+// nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
@@ -0,0 +1,83 @@
+// --trigger EXPR: a protocol analyser's capture trigger. Until a request matches the trigger (a filter expression, in
+// the same syntax as --filter), sniff only counts what it sees: nothing's shown, logged or pushed. From the first match
+// on it captures as usual, and with --trigger-stop, stops once that long has passed without another match
+
+use std::time::{Duration, SystemTime};
+
+use crate::{filter::Expr, theme::Theme, RequestStats};
+
+pub struct Trigger {
+    expr: Expr,
+    stop_after: Option<Duration>,
+    theme: Theme,
+    start_time: SystemTime,
+    fired: bool,
+    last_match: Option<SystemTime>, // by the capture's clock
+}
+
+impl Trigger {
+    pub fn new(expr: Expr, stop_after: Option<Duration>, theme: Theme, start_time: SystemTime) -> Trigger {
+        outln!(
+            "[trigger] waiting for a request matching \"{}\", only counting until then",
+            expr
+        );
+
+        Trigger {
+            expr,
+            stop_after,
+            theme,
+            start_time,
+            fired: false,
+            last_match: None,
+        }
+    }
+
+    // whether the request should be captured: the trigger's fired, with this request or before it
+    pub fn observe(&mut self, stats: &RequestStats) -> bool {
+        if !self
+            .expr
+            .matches(stats, &stats.orig_ip.to_string(), &stats.dest_ip.to_string())
+        {
+            return self.fired;
+        }
+        self.last_match = Some(stats.timestamp);
+
+        if !self.fired {
+            self.fired = true;
+            let message = format!(
+                "[trigger] fired at {:.2}s by {} -> {}, capturing from here{}",
+                stats
+                    .timestamp
+                    .duration_since(self.start_time)
+                    .unwrap_or_default()
+                    .as_secs_f32(),
+                stats.orig_ip,
+                stats.dest_ip,
+                match self.stop_after {
+                    Some(stop_after) => format!(" until {:.0}s after the last match", stop_after.as_secs_f64()),
+                    None => String::new(),
+                }
+            );
+            outln!("{}", self.theme.paint(self.theme.highlight, &message));
+        }
+
+        true
+    }
+
+    // whether it's time to stop, with nothing matching the trigger for --trigger-stop as of `now`
+    pub fn tick(&self, now: SystemTime) -> bool {
+        let (Some(stop_after), Some(last_match)) = (self.stop_after, self.last_match) else {
+            return false;
+        };
+        if now.duration_since(last_match).unwrap_or_default() < stop_after {
+            return false;
+        }
+
+        let message = format!(
+            "[trigger] nothing has matched for {:.0}s, stopping",
+            stop_after.as_secs_f64()
+        );
+        outln!("{}", self.theme.paint(self.theme.highlight, &message));
+        true
+    }
+}
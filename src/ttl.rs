@@ -0,0 +1,128 @@
+// --ttl-anomalies: TCP and UDP flows whose TTL (hop limit, for IPv6) changes mid-connection. A sender uses the same
+// initial TTL for every packet, and they all take the same number of hops to get here, so a packet arriving with a
+// different one was either sent by someone else (e.g. an injected reset) or came a different way (a routing change)
+//
+// each change is called out the first time a flow's packets arrive with that TTL, and totalled per flow at exit
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{ip, theme::Theme};
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+const REPORT_FLOWS: usize = 20;
+
+// one way of a flow
+type Key = (u8, SocketAddr, SocketAddr);
+
+struct Flow {
+    ttls: Vec<u8>, // every TTL seen, the first first
+    last: SystemTime,
+}
+
+pub struct TtlTracker {
+    theme: Theme,
+    flows: HashMap<Key, Flow>,
+    changes: BTreeMap<Key, (u64, Vec<u8>)>, // how many packets came with a TTL other than the first, and the TTLs
+    checked: Option<SystemTime>,
+}
+
+impl TtlTracker {
+    pub fn new(theme: Theme) -> TtlTracker {
+        TtlTracker {
+            theme,
+            flows: HashMap::new(),
+            changes: BTreeMap::new(),
+            checked: None,
+        }
+    }
+
+    pub fn observe(&mut self, packet: &[u8], timestamp: SystemTime) {
+        let Some(header) = ip::parse(packet).filter(|x| matches!(x.protocol, TCP | UDP)) else {
+            return;
+        };
+        let Some((src_port, dst_port, _)) = ip::transport(packet) else {
+            return;
+        };
+
+        if self
+            .checked
+            .is_none_or(|x| timestamp.duration_since(x).unwrap_or_default() >= CHECK_EVERY)
+        {
+            self.checked = Some(timestamp);
+            self.flows
+                .retain(|_, flow| timestamp.duration_since(flow.last).unwrap_or_default() < IDLE_TIMEOUT);
+        }
+
+        let key = (
+            header.protocol,
+            SocketAddr::new(header.src, src_port),
+            SocketAddr::new(header.dst, dst_port),
+        );
+        let flow = self.flows.entry(key).or_insert_with(|| Flow {
+            ttls: vec![header.ttl],
+            last: timestamp,
+        });
+        flow.last = timestamp;
+
+        let first = flow.ttls[0];
+        if header.ttl == first {
+            return;
+        }
+
+        let changes = self.changes.entry(key).or_insert_with(|| (0, vec![first]));
+        changes.0 += 1;
+        if !changes.1.contains(&header.ttl) {
+            changes.1.push(header.ttl);
+        }
+
+        if flow.ttls.contains(&header.ttl) {
+            return;
+        }
+        flow.ttls.push(header.ttl);
+
+        let message = format!(
+            "*** {} {} changed mid-connection from {} to {}: {} -> {} (spoofed, or a different route) ***",
+            if key.0 == TCP { "TCP" } else { "UDP" },
+            if key.1.is_ipv6() { "hop limit" } else { "TTL" },
+            first,
+            header.ttl,
+            key.1,
+            key.2
+        );
+        outln!("{}", self.theme.paint(self.theme.anomaly, &message));
+    }
+
+    pub fn print_report(self) {
+        if self.changes.is_empty() {
+            return;
+        }
+
+        let mut flows: Vec<_> = self.changes.iter().collect();
+        flows.sort_by_key(|(_, (packets, _))| std::cmp::Reverse(*packets));
+
+        println!("TTL changes:");
+        for ((protocol, src, dst), (packets, ttls)) in flows.iter().take(REPORT_FLOWS) {
+            let ttls: Vec<String> = ttls.iter().map(|x| x.to_string()).collect();
+            println!(
+                "    {} {} -> {}: TTLs {}, {} packet{} with a changed one",
+                if *protocol == TCP { "TCP" } else { "UDP" },
+                src,
+                dst,
+                ttls.join(", "),
+                packets,
+                if *packets == 1 { "" } else { "s" }
+            );
+        }
+        if flows.len() > REPORT_FLOWS {
+            println!("    ... and {} more", flows.len() - REPORT_FLOWS);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::locale::Locale;
+
+const BINARY_UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+// 1536 -> "1.5 KiB", 512 -> "512 B"
+pub fn human_bytes(bytes: u64, locale: Option<&Locale>) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < BINARY_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        return format!("{} {}", bytes, BINARY_UNITS[0]);
+    }
+
+    match locale {
+        Some(locale) => format!("{} {}", locale.decimal(value, 1), BINARY_UNITS[unit]),
+        None => format!("{:.1} {}", value, BINARY_UNITS[unit]),
+    }
+}
+
+// bytes per second -> "320.0 KiB/s"
+pub fn human_rate(bytes_per_sec: f64, locale: Option<&Locale>) -> String {
+    format!("{}/s", human_bytes(bytes_per_sec.round() as u64, locale))
+}
+
+// a byte count (and rate, if known) as it should appear in the output
+pub fn format_bytes(bytes: u64, rate: Option<f64>, raw: bool, locale: Option<&Locale>) -> String {
+    if raw {
+        return match locale {
+            Some(locale) => format!("{} bytes", locale.number(bytes)),
+            None => format!("{} bytes", bytes),
+        };
+    }
+
+    match rate {
+        Some(rate) => format!("{} @ {}", human_bytes(bytes, locale), human_rate(rate, locale)),
+        None => human_bytes(bytes, locale),
+    }
+}
+
+// "10G", "512MiB", "1.5T" or a plain number of bytes -> bytes, with binary multiples
+pub fn parse_size(s: &str) -> Result<u64, std::io::Error> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid size, expected e.g. 500M or 10G",
+            ))
+        }
+    };
+
+    let number: f64 = number.parse().map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid size, expected e.g. 500M or 10G")
+    })?;
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+// "5MBps", "5MB/s", "40Mbps" (bits) or a plain size per second -> bytes per second
+pub fn parse_rate(s: &str) -> Result<f64, std::io::Error> {
+    let s = s.trim();
+    let size = s.strip_suffix("ps").or_else(|| s.strip_suffix("/s")).unwrap_or(s);
+
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid rate, expected e.g. 5MBps or 40Mbps");
+
+    match size.strip_suffix('b') {
+        Some(bits) => Ok(parse_size(bits).map_err(|_| invalid())? as f64 / 8.0),
+        None => Ok(parse_size(size).map_err(|_| invalid())? as f64),
+    }
+}
+
+// "1h", "30m", "90s", "250ms", "1d" or a plain number of seconds -> a duration
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, std::io::Error> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid duration, expected e.g. 250ms, 90s, 30m or 1h");
+
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(invalid()),
+    };
+
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+
+    Ok(std::time::Duration::from_secs_f64(number * multiplier))
+}
@@ -0,0 +1,298 @@
+// uploads finished logs (rotated, or at the end of the capture) to S3-compatible storage or over SFTP, from a
+// background thread, so a fleet of sniffs can send everything somewhere central
+//
+// s3://bucket/prefix signs with AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY (and AWS_SESSION_TOKEN) for AWS_REGION
+// (default us-east-1), against AWS_ENDPOINT_URL (path-style, e.g. for MinIO) if it's set
+// sftp://[user@]host[:port]/path runs the system's sftp, so it uses the usual SSH keys and config
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+enum Target {
+    S3 { bucket: String, prefix: String },
+    Sftp { destination: String, port: Option<u16>, dir: String },
+}
+
+impl Target {
+    fn parse(url: &str) -> Target {
+        if let Some(rest) = url.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                panic!("Invalid upload URL {}: no bucket", url);
+            }
+            return Target::S3 {
+                bucket: bucket.to_string(),
+                prefix: prefix.trim_matches('/').to_string(),
+            };
+        }
+
+        if let Some(rest) = url.strip_prefix("sftp://") {
+            let (host, dir) = rest.split_once('/').unwrap_or((rest, ""));
+            let (destination, port) = match host.rsplit_once(':') {
+                Some((destination, port)) => (
+                    destination,
+                    Some(port.parse().unwrap_or_else(|_| panic!("Invalid upload URL {}: bad port", url))),
+                ),
+                None => (host, None),
+            };
+            if destination.is_empty() {
+                panic!("Invalid upload URL {}: no host", url);
+            }
+            return Target::Sftp {
+                destination: destination.to_string(),
+                port,
+                dir: dir.trim_end_matches('/').to_string(),
+            };
+        }
+
+        panic!("Invalid upload URL {}, expected s3://bucket/prefix or sftp://[user@]host[:port]/path", url);
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Target::S3 { bucket, prefix } if prefix.is_empty() => write!(f, "s3://{}", bucket),
+            Target::S3 { bucket, prefix } => write!(f, "s3://{}/{}", bucket, prefix),
+            Target::Sftp { destination, dir, .. } => write!(f, "sftp://{}/{}", destination, dir),
+        }
+    }
+}
+
+pub struct Uploader {
+    tx: Option<Sender<PathBuf>>,
+    worker: Option<JoinHandle<(u64, u64)>>, // (uploaded, failed)
+}
+
+impl Uploader {
+    pub fn new(url: &str) -> Uploader {
+        let target = Target::parse(url);
+        let (tx, rx) = channel();
+
+        Uploader {
+            tx: Some(tx),
+            worker: Some(std::thread::spawn(move || run(target, rx))),
+        }
+    }
+
+    // for whatever else finishes files, e.g. log rotation
+    pub fn queue(&self) -> Sender<PathBuf> {
+        self.tx.clone().unwrap()
+    }
+
+    pub fn upload(&self, path: &Path) {
+        if let Some(ref tx) = self.tx {
+            let _ = tx.send(path.to_path_buf());
+        }
+    }
+
+    // wait for everything queued to go, including files still being finished elsewhere
+    pub fn finish(mut self) {
+        drop(self.tx.take());
+        let (uploaded, failed) = self.worker.take().unwrap().join().unwrap_or_default();
+
+        println!(
+            "Uploaded {} file{}{}",
+            uploaded,
+            if uploaded == 1 { "" } else { "s" },
+            if failed > 0 { format!(", {} failed", failed) } else { String::new() },
+        );
+    }
+}
+
+fn run(target: Target, rx: Receiver<PathBuf>) -> (u64, u64) {
+    let (mut uploaded, mut failed) = (0, 0);
+
+    for path in rx {
+        let mut backoff = FIRST_BACKOFF;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let result = match target {
+                Target::S3 { ref bucket, ref prefix } => upload_s3(bucket, prefix, &path),
+                Target::Sftp { ref destination, port, ref dir } => upload_sftp(destination, port, dir, &path),
+            };
+
+            match result {
+                Ok(()) => {
+                    println!("Uploaded {} to {}", path.display(), target);
+                    uploaded += 1;
+                    break;
+                }
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    eprintln!(
+                        "Failed to upload {} (attempt {}/{}), retrying in {}s: {}",
+                        path.display(),
+                        attempt,
+                        MAX_ATTEMPTS,
+                        backoff.as_secs(),
+                        e
+                    );
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(e) => {
+                    eprintln!("Failed to upload {} to {}, giving up: {}", path.display(), target, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    (uploaded, failed)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+// a PUT signed with AWS Signature Version 4, carrying the file's SHA-256 so the store rejects a corrupted upload
+fn upload_s3(bucket: &str, prefix: &str, path: &Path) -> Result<(), String> {
+    let env = |name: &str| std::env::var(name).ok().filter(|x| !x.is_empty());
+    let access_key = env("AWS_ACCESS_KEY_ID").ok_or("AWS_ACCESS_KEY_ID is not set")?;
+    let secret_key = env("AWS_SECRET_ACCESS_KEY").ok_or("AWS_SECRET_ACCESS_KEY is not set")?;
+    let region = env("AWS_REGION").unwrap_or_else(|| "us-east-1".to_string());
+
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let digest = Sha256::digest(&data);
+
+    let key = if prefix.is_empty() { file_name(path) } else { format!("{}/{}", prefix, file_name(path)) };
+
+    let (url, host, uri) = match env("AWS_ENDPOINT_URL") {
+        Some(endpoint) => {
+            let endpoint = endpoint.trim_end_matches('/');
+            let host = endpoint.split_once("://").map(|x| x.1).unwrap_or(endpoint).to_string();
+            let uri = format!("/{}/{}", bucket, uri_encode(&key));
+            (format!("{}{}", endpoint, uri), host, uri)
+        }
+        None => {
+            let host = format!("{}.s3.{}.amazonaws.com", bucket, region);
+            let uri = format!("/{}", uri_encode(&key));
+            (format!("https://{}{}", host, uri), host, uri)
+        }
+    };
+
+    let now = crate::rotate::timestamp(SystemTime::now()).replace('-', "T") + "Z";
+    let date = &now[..8];
+
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-checksum-sha256", crate::base64::encode(&digest)),
+        ("x-amz-content-sha256", hex(&digest)),
+        ("x-amz-date", now.clone()),
+    ];
+    if let Some(token) = env("AWS_SESSION_TOKEN") {
+        headers.push(("x-amz-security-token", token));
+    }
+
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(";");
+    let canonical_request = format!("PUT\n{}\n\n{}\n{}\n{}", uri, canonical_headers, signed_headers, hex(&digest));
+
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        now,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let mut signing_key = format!("AWS4{}", secret_key).into_bytes();
+    for part in [date, &region, "s3", "aws4_request"] {
+        signing_key = hmac(&signing_key, part.as_bytes());
+    }
+    let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes()));
+
+    let mut request = ureq::put(&url).timeout(Duration::from_secs(300)).set(
+        "Authorization",
+        &format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            access_key, scope, signed_headers, signature
+        ),
+    );
+    for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+        request = request.set(name, value);
+    }
+
+    match request.send_bytes(&data) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, response)) => {
+            let body = response.into_string().unwrap_or_default();
+            Err(format!("HTTP {}: {}", code, body.trim()))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// put the file, then check the size on the other end matches
+fn upload_sftp(destination: &str, port: Option<u16>, dir: &str, path: &Path) -> Result<(), String> {
+    let size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    let remote = if dir.is_empty() { file_name(path) } else { format!("{}/{}", dir, file_name(path)) };
+
+    let mut command = Command::new("sftp");
+    command.arg("-b").arg("-").arg("-o").arg("BatchMode=yes");
+    if let Some(port) = port {
+        command.arg("-P").arg(port.to_string());
+    }
+
+    let mut child = command
+        .arg(destination)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run sftp: {}", e))?;
+
+    let batch = format!("put \"{}\" \"{}\"\nls -ln \"{}\"\n", path.display(), remote, remote);
+    child.stdin.take().unwrap().write_all(batch.as_bytes()).map_err(|e| e.to_string())?;
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    // "-rw-r--r--    1 1000     1000         1234 Oct 17 19:02 capture.json": the size is the fifth field
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let remote_size = listing
+        .lines()
+        .filter(|x| !x.starts_with("sftp>"))
+        .find_map(|x| x.split_whitespace().nth(4)?.parse::<u64>().ok());
+
+    match remote_size {
+        Some(remote_size) if remote_size == size => Ok(()),
+        Some(remote_size) => Err(format!("{} is {} bytes on the server, but {} here", remote, remote_size, size)),
+        None => Err(format!("couldn't check the size of {} on the server", remote)),
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+// S3's flavour of percent-encoding: everything but unreserved characters, and '/' between key segments
+fn uri_encode(key: &str) -> String {
+    key.bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (x as char).to_string(),
+            x => format!("%{:02X}", x),
+        })
+        .collect()
+}
@@ -0,0 +1,240 @@
+// the flows a locked-down network is expected to carry, loaded from a TOML file, so that only traffic outside them
+// is shown, e.g.
+//
+// [[flow]]
+// name = "thermostat cloud"
+// from = "10.20.0.0/24"
+// to = "52.14.7.20"
+// port = 443
+// protocol = "tcp"
+//
+// [[flow]]
+// name = "ntp"
+// port = 123
+// protocol = "udp"
+//
+// [[flow]]
+// name = "printer discovery"
+// filter = "ether src 00:11:22:33:44:55 and udp portrange 5353-5355"
+//
+// a flow matches in both directions, so replies to an expected flow are expected too
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    aliases::Aliases,
+    conf::{MacAddr, Protocol},
+    filter,
+    theme::Theme,
+    RequestStats,
+};
+
+// how many unexpected flows to list at exit
+const REPORT_FLOWS: usize = 20;
+
+#[derive(Deserialize)]
+struct WhitelistFile {
+    #[serde(rename = "flow", default)]
+    flows: Vec<FlowConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FlowConfig {
+    name: String,
+    from: Option<String>, // an address, network (CIDR), MAC address or hostname (with -H)
+    to: Option<String>,
+    port: Option<Port>, // the port on the "to" end
+    protocol: Option<String>,
+    filter: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Port {
+    Single(u16),
+    Range(String), // "8000-8100"
+}
+
+struct Flow {
+    name: String,
+    filter: filter::Expr,
+}
+
+// one end of a flow, in filter syntax
+fn endpoint(direction: &str, spec: &str) -> String {
+    if spec.parse::<MacAddr>().is_ok() {
+        format!("ether {} {}", direction, spec)
+    } else if spec.contains('/') {
+        format!("{} net {}", direction, spec)
+    } else {
+        format!("{} host {}", direction, spec)
+    }
+}
+
+// the flow's conditions as a filter: from -> to:port, or the reply coming back the other way
+fn flow_filter(flow: &FlowConfig) -> String {
+    let port = |direction: &str| match flow.port {
+        Some(Port::Single(port)) => Some(format!("{} port {}", direction, port)),
+        Some(Port::Range(ref range)) => Some(format!("{} portrange {}", direction, range)),
+        None => None,
+    };
+
+    let forward: Vec<String> = [
+        flow.from.as_ref().map(|x| endpoint("src", x)),
+        flow.to.as_ref().map(|x| endpoint("dst", x)),
+        port("dst"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let reverse: Vec<String> = [
+        flow.to.as_ref().map(|x| endpoint("src", x)),
+        flow.from.as_ref().map(|x| endpoint("dst", x)),
+        port("src"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let mut conditions = Vec::new();
+    if !forward.is_empty() {
+        conditions.push(format!("(({}) or ({}))", forward.join(" and "), reverse.join(" and ")));
+    }
+    if let Some(ref protocol) = flow.protocol {
+        conditions.push(protocol.to_ascii_lowercase());
+    }
+    if let Some(ref filter) = flow.filter {
+        conditions.push(format!("({})", filter));
+    }
+
+    conditions.join(" and ")
+}
+
+// (origin, destination, protocol, port), with the ends as displayed and the way round they were first seen
+type FlowKey = (String, String, Protocol, Option<u16>);
+
+pub struct Whitelist {
+    flows: Vec<Flow>,
+    theme: Theme,
+    expected: u64,
+    unexpected: HashMap<FlowKey, u64>, // -> requests
+}
+
+impl Whitelist {
+    pub fn load(path: &str, theme: Theme) -> Whitelist {
+        let data = std::fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read whitelist {}: {}", path, e));
+        let file: WhitelistFile = toml::from_str(&data).unwrap_or_else(|e| panic!("Failed to parse whitelist {}: {}", path, e));
+
+        let flows = file
+            .flows
+            .into_iter()
+            .map(|flow| {
+                let filter = flow_filter(&flow);
+                if filter.is_empty() {
+                    panic!("Whitelist flow \"{}\" needs at least one of from, to, port, protocol or filter", flow.name);
+                }
+
+                Flow {
+                    filter: filter.parse().unwrap_or_else(|e| panic!("Whitelist flow \"{}\": {}", flow.name, e)),
+                    name: flow.name,
+                }
+            })
+            .collect();
+
+        Whitelist {
+            flows,
+            theme,
+            expected: 0,
+            unexpected: HashMap::new(),
+        }
+    }
+
+    // flows' ends see addresses, not the names they're shown as
+    pub fn expand_aliases(&mut self, aliases: &Aliases) {
+        for flow in self.flows.iter_mut() {
+            flow.filter = aliases.expand(flow.filter.clone());
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.flows.iter().map(|x| x.name.clone()).collect()
+    }
+
+    // the names of the flows this request is part of; none means it's unexpected, and the first request of each
+    // unexpected flow is called out
+    pub fn check(&mut self, stats: &RequestStats, orig_name: &str, dest_name: &str) -> Vec<&str> {
+        let matched: Vec<&str> = self
+            .flows
+            .iter()
+            .filter(|x| x.filter.matches(stats, orig_name, dest_name))
+            .map(|x| x.name.as_str())
+            .collect();
+
+        if !matched.is_empty() {
+            self.expected += 1;
+            return matched;
+        }
+
+        // the service is usually on the lower port
+        let port = match stats.protocol {
+            Protocol::Tcp | Protocol::Udp => crate::ip::transport(&stats.raw).map(|(src, dst, _)| src.min(dst)),
+            _ => None,
+        };
+
+        // replies count towards the flow they're replying to
+        let reverse = (dest_name.to_string(), orig_name.to_string(), stats.protocol, port);
+        let key = if self.unexpected.contains_key(&reverse) {
+            reverse
+        } else {
+            (orig_name.to_string(), dest_name.to_string(), stats.protocol, port)
+        };
+
+        let count = self.unexpected.entry(key).or_default();
+        *count += 1;
+
+        if *count == 1 {
+            let message = format!(
+                "*** unexpected flow: {} {} -> {}{} ***",
+                stats.protocol,
+                orig_name,
+                dest_name,
+                port.map(|x| format!(" port {}", x)).unwrap_or_default(),
+            );
+            outln!("{}", self.theme.paint(self.theme.warning, &message));
+        }
+
+        matched
+    }
+
+    pub fn print_report(&self) {
+        let requests: u64 = self.unexpected.values().sum();
+        println!(
+            "Whitelist: {} expected request{}, {} unexpected in {} flow{}",
+            self.expected,
+            if self.expected == 1 { "" } else { "s" },
+            requests,
+            self.unexpected.len(),
+            if self.unexpected.len() == 1 { "" } else { "s" },
+        );
+
+        let mut flows: Vec<_> = self.unexpected.iter().collect();
+        flows.sort_by(|a, b| b.1.cmp(a.1));
+        for ((orig, dest, protocol, port), count) in flows.iter().take(REPORT_FLOWS) {
+            println!(
+                "    {} {} -> {}{}: {} request{}",
+                protocol,
+                orig,
+                dest,
+                port.map(|x| format!(" port {}", x)).unwrap_or_default(),
+                count,
+                if **count == 1 { "" } else { "s" },
+            );
+        }
+        if flows.len() > REPORT_FLOWS {
+            println!("    ... and {} more", flows.len() - REPORT_FLOWS);
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::SystemTime,
+};
+
+use crate::conf::MacAddr;
+
+// radiotap header, as prepended to every frame by a monitor mode interface
+// we only care about its length (so we can skip it) and the antenna signal, if present
+pub struct Radiotap {
+    pub len: usize,
+    pub signal: Option<i8>,
+}
+
+// (alignment, size) of the radiotap fields that come before the antenna signal, indexed by their presence bit
+const RADIOTAP_FIELDS: [(usize, usize); 5] = [
+    (8, 8), // TSFT
+    (1, 1), // flags
+    (1, 1), // rate
+    (2, 4), // channel
+    (2, 2), // FHSS
+];
+
+const RADIOTAP_ANTENNA_SIGNAL: u32 = 5;
+
+pub fn parse_radiotap(data: &[u8]) -> Option<Radiotap> {
+    if data.len() < 8 || data[0] != 0 {
+        return None;
+    }
+
+    let len = u16::from_le_bytes([data[2], data[3]]) as usize;
+
+    if len > data.len() {
+        return None;
+    }
+
+    let present = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+
+    // skip over any extended presence bitmaps (bit 31 set means another word follows)
+    let mut offset = 8;
+    let mut word = present;
+    while word & (1 << 31) != 0 {
+        if offset + 4 > len {
+            return None;
+        }
+        word = u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]);
+        offset += 4;
+    }
+
+    let mut signal = None;
+
+    if present & (1 << RADIOTAP_ANTENNA_SIGNAL) != 0 {
+        for (bit, (align, size)) in RADIOTAP_FIELDS.iter().enumerate() {
+            if present & (1 << bit) != 0 {
+                offset = offset.next_multiple_of(*align) + size;
+            }
+        }
+
+        if offset < len {
+            signal = Some(data[offset] as i8);
+        }
+    }
+
+    Some(Radiotap { len, signal })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameType {
+    Management,
+    Control,
+    Data,
+    Extension,
+}
+
+// the parts of an 802.11 MAC header we use
+pub struct Dot11Frame<'a> {
+    pub frame_type: FrameType,
+    pub subtype: u8,
+    pub to_ds: bool,
+    pub from_ds: bool,
+    pub protected: bool,
+    pub addr1: MacAddr,
+    pub addr2: Option<MacAddr>,
+    pub addr3: Option<MacAddr>,
+    pub body: &'a [u8],
+}
+
+const DOT11_HEADER_LEN: usize = 24;
+
+// data frame subtypes with this bit set carry a 2 byte QoS control field after the addresses
+const QOS_DATA: u8 = 0b1000;
+
+pub fn parse_dot11(data: &[u8]) -> Option<Dot11Frame<'_>> {
+    if data.len() < 10 {
+        return None;
+    }
+
+    let frame_type = match (data[0] >> 2) & 0b11 {
+        0 => FrameType::Management,
+        1 => FrameType::Control,
+        2 => FrameType::Data,
+        _ => FrameType::Extension,
+    };
+
+    let mac_at = |offset: usize| -> Option<MacAddr> {
+        data.get(offset..offset + 6).map(|x| MacAddr::from(x.to_vec()))
+    };
+
+    let subtype = data[0] >> 4;
+    let to_ds = data[1] & 0b01 != 0;
+    let from_ds = data[1] & 0b10 != 0;
+
+    // data frames can have a fourth address, a QoS control field and an HT control field
+    let mut header_len = DOT11_HEADER_LEN;
+    if frame_type == FrameType::Data {
+        if to_ds && from_ds {
+            header_len += 6;
+        }
+        if subtype & QOS_DATA != 0 {
+            header_len += 2;
+            if data[1] & 0b1000_0000 != 0 {
+                header_len += 4;
+            }
+        }
+    }
+
+    Some(Dot11Frame {
+        frame_type,
+        subtype,
+        to_ds,
+        from_ds,
+        protected: data[1] & 0b0100_0000 != 0,
+        addr1: mac_at(4)?,
+        addr2: mac_at(10),
+        addr3: mac_at(16),
+        body: data.get(header_len..).unwrap_or(&[]),
+    })
+}
+
+// management frame subtypes that advertise an SSID, or ask for one
+const PROBE_REQUEST: u8 = 4;
+const PROBE_RESPONSE: u8 = 5;
+const BEACON: u8 = 8;
+
+impl Dot11Frame<'_> {
+    // the SSID advertised by a beacon or probe response
+    pub fn ssid(&self) -> Option<String> {
+        if self.frame_type != FrameType::Management
+            || (self.subtype != BEACON && self.subtype != PROBE_RESPONSE)
+        {
+            return None;
+        }
+
+        // skip the timestamp, beacon interval and capability info
+        self.tagged_ssid(12)
+    }
+
+    // the SSID a probe request is looking for; empty if it's asking every network in range
+    pub fn probed_ssid(&self) -> Option<String> {
+        if self.frame_type != FrameType::Management || self.subtype != PROBE_REQUEST {
+            return None;
+        }
+
+        // a probe request is nothing but tagged parameters
+        self.tagged_ssid(0)
+    }
+
+    // walk the tagged parameters starting at `offset` into the body, for the SSID
+    fn tagged_ssid(&self, offset: usize) -> Option<String> {
+        let mut tags = self.body.get(offset..)?;
+
+        while tags.len() >= 2 {
+            let (id, len) = (tags[0], tags[1] as usize);
+            let value = tags.get(2..2 + len)?;
+
+            if id == 0 {
+                return Some(String::from_utf8_lossy(value).to_string());
+            }
+
+            tags = &tags[2 + len..];
+        }
+
+        None
+    }
+}
+
+// management frame subtypes
+const ASSOC_RESPONSE: u8 = 1;
+const REASSOC_RESPONSE: u8 = 3;
+const DISASSOC: u8 = 10;
+const DEAUTH: u8 = 12;
+
+// the common deauthentication and disassociation reason codes (802.11-2020 table 9-49)
+fn reason(code: u16) -> &'static str {
+    match code {
+        1 => "unspecified",
+        2 => "previous authentication no longer valid",
+        3 => "station is leaving",
+        4 => "inactivity",
+        5 => "AP is full",
+        6 => "class 2 frame from unauthenticated station",
+        7 => "class 3 frame from unassociated station",
+        8 => "station is leaving the BSS",
+        9 => "not authenticated",
+        14 => "message integrity failure",
+        15 => "4-way handshake timeout",
+        16 => "group key handshake timeout",
+        23 => "802.1X authentication failed",
+        34 => "too many unacknowledged frames",
+        _ => "other",
+    }
+}
+
+// management frames, in a line each: a network's beacons the first time it's heard (they come ten times a second),
+// and every probe request and deauthentication or disassociation
+#[derive(Default)]
+pub struct ManagementLog {
+    networks: HashSet<(MacAddr, String)>, // BSSID and SSID
+}
+
+impl ManagementLog {
+    pub fn describe(&mut self, frame: &Dot11Frame, signal: Option<i8>) -> Option<String> {
+        if frame.frame_type != FrameType::Management {
+            return None;
+        }
+        let transmitter = frame.addr2?;
+
+        let description = match frame.subtype {
+            BEACON | PROBE_RESPONSE => {
+                let (ssid, bssid) = (frame.ssid()?, frame.addr3?);
+                if !self.networks.insert((bssid, ssid.clone())) {
+                    return None;
+                }
+                if ssid.is_empty() {
+                    format!("hidden network on {}", bssid)
+                } else {
+                    format!("network {:?} on {}", ssid, bssid)
+                }
+            }
+            PROBE_REQUEST => match frame.probed_ssid()? {
+                ssid if ssid.is_empty() => format!("{} probing for any network", transmitter),
+                ssid => format!("{} probing for {:?}", transmitter, ssid),
+            },
+            DEAUTH | DISASSOC => {
+                let code = u16::from_le_bytes(frame.body.get(0..2)?.try_into().ok()?);
+                format!(
+                    "{} {} {} (reason {}: {})",
+                    transmitter,
+                    if frame.subtype == DEAUTH { "deauthenticated" } else { "disassociated" },
+                    if frame.addr1.is_broadcast() { "everyone".to_string() } else { frame.addr1.to_string() },
+                    code,
+                    reason(code),
+                )
+            }
+            _ => return None,
+        };
+
+        Some(match signal {
+            Some(signal) => format!("{} ({} dBm)", description, signal),
+            None => description,
+        })
+    }
+}
+
+// whether an interface hands us radiotap + 802.11 frames (i.e. it's in monitor mode) rather than ethernet
+pub fn is_monitor(name: &str) -> bool {
+    // ARPHRD_IEEE80211_RADIOTAP
+    std::fs::read_to_string(format!("/sys/class/net/{}/type", name)).is_ok_and(|x| x.trim() == "803")
+}
+
+#[derive(Clone, Debug)]
+pub enum RoamEventKind {
+    Associated,
+    Roamed(MacAddr), // previous BSSID
+    Disassociated,
+    Deauthenticated,
+    Seen, // first time we saw the client talking to an AP, without catching the association
+}
+
+#[derive(Clone, Debug)]
+pub struct RoamEvent {
+    pub client: MacAddr,
+    pub bssid: MacAddr,
+    pub kind: RoamEventKind,
+    pub signal: Option<i8>,
+    pub timestamp: SystemTime,
+}
+
+impl RoamEvent {
+    pub fn describe(&self) -> String {
+        let action = match &self.kind {
+            RoamEventKind::Associated => format!("associated with {}", self.bssid),
+            RoamEventKind::Roamed(from) => format!("roamed {} -> {}", from, self.bssid),
+            RoamEventKind::Disassociated => format!("disassociated from {}", self.bssid),
+            RoamEventKind::Deauthenticated => format!("deauthenticated from {}", self.bssid),
+            RoamEventKind::Seen => format!("seen on {}", self.bssid),
+        };
+
+        match self.signal {
+            Some(signal) => format!("{} ({} dBm)", action, signal),
+            None => action,
+        }
+    }
+}
+
+// signal statistics over one association period
+#[derive(Clone, Debug, Default)]
+struct SignalStats {
+    min: i8,
+    max: i8,
+    sum: i64,
+    count: u64,
+}
+
+impl SignalStats {
+    fn add(&mut self, signal: i8) {
+        if self.count == 0 {
+            self.min = signal;
+            self.max = signal;
+        } else {
+            self.min = self.min.min(signal);
+            self.max = self.max.max(signal);
+        }
+        self.sum += signal as i64;
+        self.count += 1;
+    }
+}
+
+impl std::fmt::Display for SignalStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no signal data");
+        }
+        write!(
+            f,
+            "RSSI min/avg/max {}/{}/{} dBm over {} frames",
+            self.min,
+            self.sum / self.count as i64,
+            self.max,
+            self.count
+        )
+    }
+}
+
+#[derive(Default)]
+struct ClientHistory {
+    bssid: Option<MacAddr>,
+    events: Vec<(RoamEvent, SignalStats)>, // each event, along with the signal seen until the next one
+}
+
+#[derive(Default)]
+pub struct RoamingTracker {
+    clients: HashMap<MacAddr, ClientHistory>,
+}
+
+impl RoamingTracker {
+    // feed a frame into the tracker, returning an event if the client's association changed
+    pub fn observe(
+        &mut self,
+        frame: &Dot11Frame,
+        signal: Option<i8>,
+        timestamp: SystemTime,
+    ) -> Option<RoamEvent> {
+        let transmitter = frame.addr2?;
+
+        let event = self.classify(frame, transmitter, timestamp);
+
+        match event {
+            Some(ref event) => {
+                let history = self.clients.entry(event.client).or_default();
+
+                history.bssid = match event.kind {
+                    RoamEventKind::Disassociated | RoamEventKind::Deauthenticated => None,
+                    _ => Some(event.bssid),
+                };
+
+                let mut event = event.clone();
+                let mut stats = SignalStats::default();
+
+                // the signal only tells us about the client if the client sent the frame
+                if transmitter == event.client {
+                    event.signal = signal;
+                    if let Some(signal) = signal {
+                        stats.add(signal);
+                    }
+                }
+
+                history.events.push((event.clone(), stats));
+
+                Some(event)
+            }
+            None => {
+                // record the signal for any other frame a known client transmitted
+                if let (Some(signal), Some(history)) =
+                    (signal, self.clients.get_mut(&transmitter))
+                {
+                    if let Some((_, stats)) = history.events.last_mut() {
+                        stats.add(signal);
+                    }
+                }
+
+                None
+            }
+        }
+    }
+
+    // work out which (client, bssid) pair this frame tells us about, and whether that's news
+    fn classify(
+        &self,
+        frame: &Dot11Frame,
+        transmitter: MacAddr,
+        timestamp: SystemTime,
+    ) -> Option<RoamEvent> {
+        let (client, bssid, kind) = match frame.frame_type {
+            FrameType::Management => {
+                let bssid = frame.addr3?;
+                // if the AP sent it, the client is the receiver, otherwise it's the transmitter
+                let client = if transmitter == bssid {
+                    frame.addr1
+                } else {
+                    transmitter
+                };
+
+                match frame.subtype {
+                    ASSOC_RESPONSE | REASSOC_RESPONSE => {
+                        // status code comes after the 2 byte capability info, 0 is success
+                        let status = frame.body.get(2..4)?;
+                        if status != [0, 0] {
+                            return None;
+                        }
+                        (client, bssid, RoamEventKind::Associated)
+                    }
+                    DISASSOC => (client, bssid, RoamEventKind::Disassociated),
+                    DEAUTH => (client, bssid, RoamEventKind::Deauthenticated),
+                    _ => return None,
+                }
+            }
+            // a station sending data to the distribution system is associated with addr1
+            FrameType::Data if frame.to_ds && !frame.from_ds => {
+                (transmitter, frame.addr1, RoamEventKind::Seen)
+            }
+            _ => return None,
+        };
+
+        if client.is_broadcast() {
+            return None;
+        }
+
+        let previous = self.clients.get(&client).and_then(|x| x.bssid);
+
+        let kind = match (kind, previous) {
+            // repeated association with the same AP (e.g. a retransmitted response), or a data
+            // frame to the AP we already know about, tells us nothing new
+            (RoamEventKind::Associated | RoamEventKind::Seen, Some(previous))
+                if previous == bssid =>
+            {
+                return None
+            }
+            (RoamEventKind::Associated | RoamEventKind::Seen, Some(previous)) => {
+                RoamEventKind::Roamed(previous)
+            }
+            (kind, _) => kind,
+        };
+
+        Some(RoamEvent {
+            client,
+            bssid,
+            kind,
+            signal: None,
+            timestamp,
+        })
+    }
+
+    // print the roaming timeline for every client seen
+    pub fn print_report(&self, start_time: SystemTime) {
+        if self.clients.is_empty() {
+            println!("No Wi-Fi clients seen");
+            return;
+        }
+
+        println!("Wi-Fi roaming report:");
+
+        let mut clients: Vec<_> = self.clients.iter().collect();
+        clients.sort_by_key(|(client, _)| client.to_string());
+
+        for (client, history) in clients {
+            let roams = history
+                .events
+                .iter()
+                .filter(|(event, _)| matches!(event.kind, RoamEventKind::Roamed(_)))
+                .count();
+
+            println!(
+                "{} ({} event{}, {} roam{})",
+                client,
+                history.events.len(),
+                if history.events.len() == 1 { "" } else { "s" },
+                roams,
+                if roams == 1 { "" } else { "s" },
+            );
+
+            for (event, stats) in history.events.iter() {
+                println!(
+                    "    {:>8.2}s  {} - {}",
+                    event
+                        .timestamp
+                        .duration_since(start_time)
+                        .unwrap_or_default()
+                        .as_secs_f32(),
+                    event.describe(),
+                    stats,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// --from/--to: only part of a saved capture, for playback (-L), `sniff replay` and `sniff report`. Either end is a
+// time of day, e.g. 12:30:00 (or 12:30), in local time on the day the capture started, or how far into the capture,
+// e.g. 90s or 5m; both ends are inclusive, a time of day to the end of its second
+
+use std::{
+    io::{Error, ErrorKind},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{locale, units};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimeBound {
+    Offset(Duration),
+    TimeOfDay(u32), // seconds since midnight
+}
+
+impl TimeBound {
+    fn resolve(&self, start: SystemTime) -> SystemTime {
+        match self {
+            TimeBound::Offset(offset) => start + *offset,
+            TimeBound::TimeOfDay(secs) => {
+                let since_epoch = start.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+                let (hour, minute, second) = locale::local_time_of_day(since_epoch.as_secs() as i64);
+                let midnight = since_epoch.as_secs() - (hour * 3600 + minute * 60 + second) as u64;
+                SystemTime::UNIX_EPOCH + Duration::from_secs(midnight + *secs as u64)
+            }
+        }
+    }
+}
+
+impl FromStr for TimeBound {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TimeBound, Error> {
+        let s = s.trim();
+        if !s.contains(':') {
+            return Ok(TimeBound::Offset(units::parse_duration(s.trim_start_matches('+'))?));
+        }
+
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid time, expected e.g. 12:30:00, 12:30 or 90s",
+            )
+        };
+        let parts: Vec<u32> = s
+            .split(':')
+            .map(|x| x.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+
+        match parts[..] {
+            [hour, minute] if hour < 24 && minute < 60 => Ok(TimeBound::TimeOfDay(hour * 3600 + minute * 60)),
+            [hour, minute, second] if hour < 24 && minute < 60 && second < 60 => {
+                Ok(TimeBound::TimeOfDay(hour * 3600 + minute * 60 + second))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for TimeBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TimeBound::Offset(offset) => write!(f, "{}s", offset.as_secs_f64()),
+            TimeBound::TimeOfDay(secs) => write!(f, "{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60),
+        }
+    }
+}
+
+impl TryFrom<String> for TimeBound {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<TimeBound, Error> {
+        s.parse()
+    }
+}
+
+impl From<TimeBound> for String {
+    fn from(bound: TimeBound) -> String {
+        bound.to_string()
+    }
+}
+
+// the part of a capture that started at `start` between --from and --to
+#[derive(Default)]
+pub struct Window {
+    pub from: Option<SystemTime>,
+    to: Option<SystemTime>,
+}
+
+impl Window {
+    pub fn new(from: Option<&TimeBound>, to: Option<&TimeBound>, start: SystemTime) -> Window {
+        Window {
+            from: from.map(|x| x.resolve(start)),
+            to: to.map(|x| match x {
+                TimeBound::TimeOfDay(_) => x.resolve(start) + Duration::from_secs(1) - Duration::from_nanos(1),
+                TimeBound::Offset(_) => x.resolve(start),
+            }),
+        }
+    }
+
+    pub fn contains(&self, timestamp: SystemTime) -> bool {
+        self.from.is_none_or(|from| timestamp >= from) && self.to.is_none_or(|to| timestamp <= to)
+    }
+
+    pub fn is_everything(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
+}
@@ -0,0 +1,485 @@
+// what the integration tests are built from: FrameBuilder crafts Ethernet frames carrying TCP, UDP or ICMP over IPv4
+// or IPv6, Capture lays them out in a pcap file with timestamps of its own choosing, and sniff() runs the binary over
+// that file with --read, so every test sees the same packets at the same times whatever the machine's doing
+
+#![allow(dead_code)]
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+#[derive(Clone)]
+enum Transport {
+    Tcp { flags: u8, seq: u32, ack: u32 },
+    Udp,
+    IcmpEcho { reply: bool, id: u16, seq: u16 },
+}
+
+// a frame between two addresses, e.g. FrameBuilder::tcp("10.0.0.1:40000", "10.0.0.2:80").payload(b"GET /").build();
+// each end's MAC is made up from its address, so different hosts get different ones unless told otherwise
+#[derive(Clone)]
+pub struct FrameBuilder {
+    src: SocketAddr,
+    dst: SocketAddr,
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    vlan: Option<u16>,
+    ttl: u8,
+    traffic_class: u8,
+    flow_label: u32,
+    transport: Transport,
+    payload: Vec<u8>,
+    bad_checksum: bool,
+}
+
+impl FrameBuilder {
+    fn new(src: &str, dst: &str, transport: Transport) -> FrameBuilder {
+        let src: SocketAddr = src.parse().expect("Invalid source address");
+        let dst: SocketAddr = dst.parse().expect("Invalid destination address");
+        assert_eq!(src.is_ipv4(), dst.is_ipv4(), "Both ends must be IPv4 or IPv6");
+
+        FrameBuilder {
+            src_mac: mac_for(src.ip()),
+            dst_mac: mac_for(dst.ip()),
+            src,
+            dst,
+            vlan: None,
+            ttl: 64,
+            traffic_class: 0,
+            flow_label: 0,
+            transport,
+            payload: Vec::new(),
+            bad_checksum: false,
+        }
+    }
+
+    pub fn tcp(src: &str, dst: &str) -> FrameBuilder {
+        FrameBuilder::new(
+            src,
+            dst,
+            Transport::Tcp {
+                flags: TCP_ACK | TCP_PSH,
+                seq: 1,
+                ack: 1,
+            },
+        )
+    }
+
+    pub fn udp(src: &str, dst: &str) -> FrameBuilder {
+        FrameBuilder::new(src, dst, Transport::Udp)
+    }
+
+    // an echo request; addresses without ports
+    pub fn ping(src: &str, dst: &str) -> FrameBuilder {
+        let (src, dst) = (format!("{}:0", bracketed(src)), format!("{}:0", bracketed(dst)));
+        FrameBuilder::new(
+            &src,
+            &dst,
+            Transport::IcmpEcho {
+                reply: false,
+                id: 1,
+                seq: 1,
+            },
+        )
+    }
+
+    // the same packet sent back the other way, e.g. a reply to a request
+    pub fn reversed(&self) -> FrameBuilder {
+        let mut frame = self.clone();
+        std::mem::swap(&mut frame.src, &mut frame.dst);
+        std::mem::swap(&mut frame.src_mac, &mut frame.dst_mac);
+        if let Transport::IcmpEcho { ref mut reply, .. } = frame.transport {
+            *reply = !*reply;
+        }
+        frame
+    }
+
+    pub fn macs(mut self, src: [u8; 6], dst: [u8; 6]) -> FrameBuilder {
+        self.src_mac = src;
+        self.dst_mac = dst;
+        self
+    }
+
+    pub fn vlan(mut self, id: u16) -> FrameBuilder {
+        self.vlan = Some(id);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> FrameBuilder {
+        self.ttl = ttl;
+        self
+    }
+
+    // the top 6 bits of IPv4's TOS byte or IPv6's traffic class
+    pub fn dscp(mut self, dscp: u8) -> FrameBuilder {
+        self.traffic_class = dscp << 2 | (self.traffic_class & 0x03);
+        self
+    }
+
+    // the bottom 2: 1 and 2 are ECN-capable, 3 is congestion experienced
+    pub fn ecn(mut self, ecn: u8) -> FrameBuilder {
+        self.traffic_class = (self.traffic_class & 0xfc) | (ecn & 0x03);
+        self
+    }
+
+    // IPv6 only
+    pub fn flow_label(mut self, label: u32) -> FrameBuilder {
+        self.flow_label = label & 0x000f_ffff;
+        self
+    }
+
+    // TCP only
+    pub fn flags(mut self, flags: u8) -> FrameBuilder {
+        if let Transport::Tcp { flags: ref mut x, .. } = self.transport {
+            *x = flags;
+        }
+        self
+    }
+
+    // TCP's sequence and acknowledgement numbers, or an echo's
+    pub fn seq(mut self, seq: u32, ack: u32) -> FrameBuilder {
+        match self.transport {
+            Transport::Tcp {
+                seq: ref mut x,
+                ack: ref mut y,
+                ..
+            } => {
+                *x = seq;
+                *y = ack;
+            }
+            Transport::IcmpEcho { seq: ref mut x, .. } => *x = seq as u16,
+            Transport::Udp => {}
+        }
+        self
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> FrameBuilder {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    // the transport checksum comes out wrong, as if the packet were corrupted on the way
+    pub fn bad_checksum(mut self) -> FrameBuilder {
+        self.bad_checksum = true;
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let v6 = self.src.is_ipv6();
+
+        let mut transport = match self.transport {
+            Transport::Tcp { flags, seq, ack } => {
+                let mut tcp = Vec::with_capacity(20);
+                tcp.extend(self.src.port().to_be_bytes());
+                tcp.extend(self.dst.port().to_be_bytes());
+                tcp.extend(seq.to_be_bytes());
+                tcp.extend(ack.to_be_bytes());
+                tcp.extend([0x50, flags]); // a 20-byte header
+                tcp.extend(65535u16.to_be_bytes()); // window
+                tcp.extend([0; 4]); // checksum and urgent pointer
+                tcp
+            }
+            Transport::Udp => {
+                let mut udp = Vec::with_capacity(8);
+                udp.extend(self.src.port().to_be_bytes());
+                udp.extend(self.dst.port().to_be_bytes());
+                udp.extend((8 + self.payload.len() as u16).to_be_bytes());
+                udp.extend([0; 2]);
+                udp
+            }
+            Transport::IcmpEcho { reply, id, seq } => {
+                let kind = match (v6, reply) {
+                    (false, false) => 8,
+                    (false, true) => 0,
+                    (true, false) => 128,
+                    (true, true) => 129,
+                };
+                let mut icmp = vec![kind, 0, 0, 0];
+                icmp.extend(id.to_be_bytes());
+                icmp.extend(seq.to_be_bytes());
+                icmp
+            }
+        };
+        transport.extend(&self.payload);
+
+        let protocol = match (&self.transport, v6) {
+            (Transport::Tcp { .. }, _) => 6,
+            (Transport::Udp, _) => 17,
+            (Transport::IcmpEcho { .. }, false) => 1,
+            (Transport::IcmpEcho { .. }, true) => 58,
+        };
+
+        // ICMPv4's checksum doesn't cover a pseudo-header; everything else's does
+        let mut sum = match (protocol, self.src.ip(), self.dst.ip()) {
+            (1, _, _) => 0,
+            (_, IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let mut pseudo = Vec::new();
+                pseudo.extend(src.octets());
+                pseudo.extend(dst.octets());
+                pseudo.extend([0, protocol]);
+                pseudo.extend((transport.len() as u16).to_be_bytes());
+                add(0, &pseudo)
+            }
+            (_, IpAddr::V6(src), IpAddr::V6(dst)) => {
+                let mut pseudo = Vec::new();
+                pseudo.extend(src.octets());
+                pseudo.extend(dst.octets());
+                pseudo.extend((transport.len() as u32).to_be_bytes());
+                pseudo.extend([0, 0, 0, protocol]);
+                add(0, &pseudo)
+            }
+            _ => unreachable!(),
+        };
+        sum = add(sum, &transport);
+        let mut checksum = fold(sum);
+        if self.bad_checksum {
+            checksum ^= 0x0101;
+        }
+        let at = match protocol {
+            6 => 16,
+            17 => 6,
+            _ => 2,
+        };
+        transport[at..at + 2].copy_from_slice(&checksum.to_be_bytes());
+
+        let ip = match (self.src.ip(), self.dst.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let mut ip = vec![0x45, self.traffic_class];
+                ip.extend((20 + transport.len() as u16).to_be_bytes());
+                ip.extend([0, 1, 0x40, 0]); // identification, and DF
+                ip.extend([self.ttl, protocol, 0, 0]);
+                ip.extend(src.octets());
+                ip.extend(dst.octets());
+                let checksum = fold(add(0, &ip));
+                ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+                ip
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                let mut ip = (6 << 28 | (self.traffic_class as u32) << 20 | self.flow_label)
+                    .to_be_bytes()
+                    .to_vec();
+                ip.extend((transport.len() as u16).to_be_bytes());
+                ip.extend([protocol, self.ttl]);
+                ip.extend(src.octets());
+                ip.extend(dst.octets());
+                ip
+            }
+            _ => unreachable!(),
+        };
+
+        let mut frame = Vec::with_capacity(18 + ip.len() + transport.len());
+        frame.extend(self.dst_mac);
+        frame.extend(self.src_mac);
+        if let Some(vlan) = self.vlan {
+            frame.extend(0x8100u16.to_be_bytes());
+            frame.extend(vlan.to_be_bytes());
+        }
+        frame.extend(if v6 { 0x86ddu16 } else { 0x0800 }.to_be_bytes());
+        frame.extend(ip);
+        frame.extend(transport);
+        frame
+    }
+}
+
+// 02:00 (locally administered) then the last four bytes of the address
+pub fn mac_for(ip: IpAddr) -> [u8; 6] {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let end = &octets[octets.len() - 4..];
+    [0x02, 0x00, end[0], end[1], end[2], end[3]]
+}
+
+// an IPv6 address needs brackets before a port goes on the end
+fn bracketed(ip: &str) -> String {
+    if ip.contains(':') {
+        format!("[{}]", ip)
+    } else {
+        ip.to_string()
+    }
+}
+
+fn add(mut sum: u32, data: &[u8]) -> u32 {
+    for pair in data.chunks(2) {
+        sum += u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32;
+    }
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// frames at times of our choosing, in seconds from an arbitrary (but fixed) start
+#[derive(Default)]
+pub struct Capture {
+    frames: Vec<(f64, Vec<u8>)>,
+}
+
+// 2023-11-14 22:13:20 UTC
+pub const EPOCH: u64 = 1_700_000_000;
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture::default()
+    }
+
+    pub fn at(mut self, seconds: f64, frame: &FrameBuilder) -> Capture {
+        self.frames.push((seconds, frame.build()));
+        self
+    }
+
+    pub fn raw(mut self, seconds: f64, frame: Vec<u8>) -> Capture {
+        self.frames.push((seconds, frame));
+        self
+    }
+
+    // a classic pcap file, microsecond timestamps and Ethernet frames
+    pub fn to_pcap(&self) -> Vec<u8> {
+        let mut pcap = Vec::new();
+        pcap.extend(0xa1b2c3d4u32.to_le_bytes());
+        pcap.extend(2u16.to_le_bytes());
+        pcap.extend(4u16.to_le_bytes());
+        pcap.extend([0; 8]); // timezone and accuracy
+        pcap.extend(65535u32.to_le_bytes());
+        pcap.extend(1u32.to_le_bytes());
+
+        for (seconds, frame) in self.frames.iter() {
+            let micros = (seconds * 1_000_000.0).round() as u64;
+            pcap.extend((EPOCH as u32 + (micros / 1_000_000) as u32).to_le_bytes());
+            pcap.extend(((micros % 1_000_000) as u32).to_le_bytes());
+            pcap.extend((frame.len() as u32).to_le_bytes());
+            pcap.extend((frame.len() as u32).to_le_bytes());
+            pcap.extend(frame);
+        }
+        pcap
+    }
+}
+
+// a directory of its own for each run, removed afterwards
+pub struct Run {
+    pub dir: PathBuf,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Run {
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    // the lines printed for each request, before the summary
+    pub fn requests(&self) -> Vec<&str> {
+        self.stdout.lines().take_while(|x| *x != "Summary:").collect()
+    }
+
+    // a JSON log written with -l
+    pub fn log(&self, name: &str) -> serde_json::Value {
+        let log = std::fs::read_to_string(self.path(name)).expect("Failed to read the log");
+        serde_json::from_str(&log).expect("Failed to parse the log")
+    }
+
+    // when each frame in a pcap written with --pcap was captured, in seconds from the capture's start as in Capture
+    pub fn pcap_times(&self, name: &str) -> Vec<f64> {
+        let pcap = std::fs::read(self.path(name)).expect("Failed to read the pcap");
+        let field = |at: usize| u32::from_le_bytes(pcap[at..at + 4].try_into().unwrap()) as f64;
+
+        let mut times = Vec::new();
+        let mut at = 24;
+        while at < pcap.len() {
+            times.push(field(at) - EPOCH as f64 + field(at + 4) / 1_000_000.0);
+            at += 16 + field(at + 8) as usize;
+        }
+        times
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+// sniff --read over the capture, with the given arguments; relative paths in them are inside the run's directory
+pub fn sniff(capture: &Capture, args: &[&str]) -> Run {
+    sniff_with_files(capture, &[], args)
+}
+
+// the same, with files (alert rules, say) written into the run's directory first
+pub fn sniff_with_files(capture: &Capture, files: &[(&str, &str)], args: &[&str]) -> Run {
+    let (run, success) = run(capture, files, &[], args);
+    assert!(success, "sniff failed:\n{}\n{}", run.stdout, run.stderr);
+    run
+}
+
+// the requests a run shows, for tests trying several sets of arguments on top of some they all share
+pub fn requests_shown(capture: &Capture, files: &[(&str, &str)], shared: &[&str], args: &[&str]) -> Vec<String> {
+    let args: Vec<&str> = shared.iter().chain(args).copied().collect();
+    sniff_with_files(capture, files, &args)
+        .requests()
+        .iter()
+        .map(|x| x.to_string())
+        .collect()
+}
+
+// sniff --read with arguments it should refuse, and what it said about them
+pub fn sniff_error(capture: &Capture, args: &[&str]) -> String {
+    sniff_failing_with_files(capture, &[], args).stderr.clone()
+}
+
+pub fn sniff_failing_with_files(capture: &Capture, files: &[(&str, &str)], args: &[&str]) -> Run {
+    let (run, success) = run(capture, files, &[], args);
+    assert!(!success, "sniff should have failed:\n{}", run.stdout);
+    run
+}
+
+// sniff --read with some environment variables set, e.g. XDG_DATA_HOME for somewhere to keep sessions
+pub fn sniff_with_env(capture: &Capture, env: &[(&str, &str)], args: &[&str]) -> Run {
+    let (run, success) = run(capture, &[], env, args);
+    assert!(success, "sniff failed:\n{}\n{}", run.stdout, run.stderr);
+    run
+}
+
+fn run(capture: &Capture, files: &[(&str, &str)], env: &[(&str, &str)], args: &[&str]) -> (Run, bool) {
+    let dir = std::env::temp_dir().join(format!(
+        "sniff-test-{}-{}",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::create_dir_all(&dir).expect("Failed to create the test directory");
+    std::fs::write(dir.join("capture.pcap"), capture.to_pcap()).expect("Failed to write the capture");
+    for (name, contents) in files.iter() {
+        std::fs::write(dir.join(name), contents).expect("Failed to write a file for the test");
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sniff"))
+        .current_dir(&dir)
+        .args(["--read", "capture.pcap"])
+        .args(args)
+        .env("NO_COLOR", "1")
+        .envs(env.iter().copied())
+        .output()
+        .expect("Failed to run sniff");
+
+    let run = Run {
+        dir,
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    };
+    (run, output.status.success())
+}
@@ -0,0 +1,581 @@
+// the capture pipeline end to end: synthetic frames read in with --read, collated into requests, filtered, and
+// printed or logged
+
+mod common;
+
+use common::{
+    requests_shown, sniff, sniff_error, sniff_failing_with_files, sniff_with_env, sniff_with_files, Capture,
+    FrameBuilder, EPOCH, TCP_ACK, TCP_SYN,
+};
+
+const LINE: &str = "{time} {proto} {src}:{sport} -> {dst}:{dport} {packets} {tx} {rx}";
+
+fn http() -> FrameBuilder {
+    FrameBuilder::tcp("10.0.0.1:40000", "10.0.0.2:80").payload(b"GET / HTTP/1.1\r\n\r\n")
+}
+
+fn dns() -> FrameBuilder {
+    FrameBuilder::udp("10.0.0.3:5353", "10.0.0.4:53").payload(b"query")
+}
+
+// an IPv4 frame between the same hosts as dns(), carrying whatever bytes it's given as the protocol's
+fn ipv4_carrying(protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = dns().build();
+    frame.truncate(34);
+    frame[16..18].copy_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+    frame[23] = protocol;
+    frame.extend(payload);
+    frame
+}
+
+#[test]
+fn collates_a_conversation_into_one_request() {
+    let capture = Capture::new()
+        .at(0.0, &http().flags(TCP_SYN))
+        .at(0.1, &http().reversed().flags(TCP_SYN | TCP_ACK))
+        .at(0.2, &http())
+        .at(0.3, &http().reversed().payload(b"HTTP/1.1 200 OK\r\n\r\n"))
+        .at(1.0, &dns());
+
+    let run = sniff(&capture, &["--format", LINE, "--raw-bytes"]);
+    assert_eq!(
+        run.requests(),
+        [
+            "1.00s TCP 10.0.0.1:40000 -> 10.0.0.2:80 4 116 bytes 117 bytes",
+            "1.00s UDP 10.0.0.3:5353 -> 10.0.0.4:53 1 33 bytes 0 bytes",
+        ]
+    );
+}
+
+#[test]
+fn writes_out_the_last_request_at_the_end_of_the_capture() {
+    let capture = Capture::new().at(0.0, &dns()).at(0.5, &dns());
+
+    let run = sniff(&capture, &["--format", LINE, "--raw-bytes"]);
+    assert_eq!(
+        run.requests(),
+        ["0.50s UDP 10.0.0.3:5353 -> 10.0.0.4:53 2 66 bytes 0 bytes"]
+    );
+    assert!(
+        run.stdout.contains("2 packets (94 bytes) in 1 requests"),
+        "{}",
+        run.stdout
+    );
+}
+
+#[test]
+fn keeps_directions_apart_without_merging() {
+    let capture = Capture::new()
+        .at(0.0, &http())
+        .at(0.1, &http().reversed())
+        .at(0.2, &http());
+
+    let run = sniff(
+        &capture,
+        &["--format", LINE, "--raw-bytes", "--merge-bidirectional", "false"],
+    );
+    assert_eq!(
+        run.requests(),
+        [
+            "0.10s TCP 10.0.0.1:40000 -> 10.0.0.2:80 1 58 bytes 0 bytes",
+            "0.20s TCP 10.0.0.2:80 -> 10.0.0.1:40000 1 58 bytes 0 bytes",
+            "0.20s TCP 10.0.0.1:40000 -> 10.0.0.2:80 1 58 bytes 0 bytes",
+        ]
+    );
+}
+
+#[test]
+fn shows_every_packet_without_collating() {
+    let capture = Capture::new().at(0.0, &dns()).at(0.1, &dns()).at(0.2, &dns());
+
+    let run = sniff(&capture, &["--format", "{proto} {packets}", "--dont-collate"]);
+    assert_eq!(run.requests(), ["UDP 1", "UDP 1", "UDP 1"]);
+}
+
+#[test]
+fn keeps_vlans_apart() {
+    let capture = Capture::new()
+        .at(0.0, &dns().vlan(10))
+        .at(0.1, &dns().vlan(20))
+        .at(0.2, &dns().vlan(20));
+
+    let run = sniff(&capture, &["--format", "{vlan} {packets}"]);
+    assert_eq!(run.requests(), ["10 1", "20 2"]);
+}
+
+#[test]
+fn filters_requests() {
+    let capture = Capture::new()
+        .at(0.0, &http())
+        .at(0.1, &dns())
+        .at(0.2, &FrameBuilder::ping("10.0.0.5", "10.0.0.6"))
+        .at(0.3, &FrameBuilder::udp("10.0.0.7:123", "10.0.0.8:123"));
+
+    let shown = |args: &[&str]| requests_shown(&capture, &[], &["--format", "{proto} {src}"], args);
+
+    assert_eq!(shown(&["udp"]), ["UDP 10.0.0.3", "UDP 10.0.0.7"]);
+    assert_eq!(shown(&["--filter", "udp port 53"]), ["UDP 10.0.0.3"]);
+    assert_eq!(
+        shown(&["--filter", "tcp or icmp"]),
+        ["TCP 10.0.0.1", "ICMP echo request 10.0.0.5"]
+    );
+    assert_eq!(shown(&["-F", "10.0.0.4"]), ["UDP 10.0.0.3"]);
+    assert_eq!(
+        shown(&["-X", "10.0.0.1,10.0.0.8"]),
+        ["UDP 10.0.0.3", "ICMP echo request 10.0.0.5"]
+    );
+}
+
+#[test]
+fn prints_requests_as_they_were_captured() {
+    let capture = Capture::new()
+        .at(0.0, &http())
+        .at(0.25, &FrameBuilder::ping("fd00::1", "fd00::2").ttl(32))
+        .at(0.5, &FrameBuilder::ping("fd00::1", "fd00::2").reversed())
+        .at(0.75, &dns().bad_checksum());
+
+    let run = sniff(&capture, &["--verify-checksums"]);
+    assert_eq!(
+        run.requests(),
+        [
+            "TCP at 0.25s: 10.0.0.1:40000 -> 10.0.0.2:80 (http): 58 B",
+            "ICMPv6 echo request at 0.75s: fd00::1 <-> fd00::2: 96 B (tx 48 B, rx 48 B)",
+            "UDP at 0.75s [bad UDP checksum]: 10.0.0.3:5353 -> 10.0.0.4:53 (domain): 33 B",
+        ]
+    );
+    assert!(run.stdout.contains("1 packets with bad checksums"), "{}", run.stdout);
+
+    let run = sniff(&capture, &["-v"]);
+    assert_eq!(
+        run.requests()[1],
+        "ICMPv6 echo request (IPv6, hop limit 32) (2 packets) at 0.75s: fd00::1 (2:0:0:0:0:1) <-> fd00::2 (2:0:0:0:0:2) \
+         96 B (tx 48 B, rx 48 B)"
+    );
+}
+
+#[test]
+fn logs_requests_with_the_capture_timestamps() {
+    let capture = Capture::new()
+        .at(10.0, &http())
+        .at(10.5, &http().reversed().payload(b"HTTP/1.1 200 OK\r\n\r\n"))
+        .at(12.0, &dns());
+
+    let run = sniff(&capture, &["-l", "capture.json"]);
+    let log = run.log("capture.json");
+
+    // the capture starts at its first frame
+    assert_eq!(log["start_time"]["secs_since_epoch"], EPOCH + 10);
+
+    let requests = log["packets"].as_array().unwrap();
+    assert_eq!(requests.len(), 2);
+
+    assert_eq!(requests[0]["protocol"], "Tcp");
+    assert_eq!(requests[0]["orig_ip"]["V4"]["octets"], serde_json::json!([10, 0, 0, 1]));
+    assert_eq!(
+        requests[0]["orig_mac"]["octets"],
+        serde_json::json!([2, 0, 10, 0, 0, 1])
+    );
+    assert_eq!(requests[0]["packets"], 2);
+    assert_eq!(requests[0]["bytes"], 58 + 59);
+    assert_eq!(requests[0]["reply_packets"], 1);
+    assert_eq!(requests[0]["reply_bytes"], 59);
+    assert_eq!(requests[0]["ttl"], 64);
+    assert_eq!(requests[0]["timestamp"]["secs_since_epoch"], EPOCH + 12);
+
+    assert_eq!(requests[1]["protocol"], "Udp");
+    assert_eq!(requests[1]["packets"], 1);
+    assert_eq!(requests[1]["timestamp"]["secs_since_epoch"], EPOCH + 12);
+}
+
+#[test]
+fn shows_and_filters_by_qos_markings() {
+    let voice = FrameBuilder::udp("10.0.0.10:16384", "10.0.0.11:16384").payload(b"rtp");
+    let capture = Capture::new()
+        .at(0.0, &voice.clone().dscp(46).ecn(2))
+        .at(0.1, &voice.clone().macs([2, 0, 0, 0, 0, 0x10], [2, 0, 0, 0, 0, 0x12]))
+        .at(
+            0.2,
+            &FrameBuilder::udp("[fd00::1]:5004", "[fd00::2]:5004")
+                .dscp(34)
+                .flow_label(0x5f3a1),
+        )
+        .at(0.3, &dns());
+
+    let run = sniff(&capture, &["-v"]);
+    let lines = run.requests();
+    assert!(
+        lines[0].starts_with("UDP (IPv4, TTL 64, DSCP EF, ECT(0)) (1 packet)"),
+        "{}",
+        lines[0]
+    );
+    assert!(lines[1].starts_with("UDP (IPv4, TTL 64) (1 packet)"), "{}", lines[1]);
+    assert!(
+        lines[2].starts_with("UDP (IPv6, hop limit 64, DSCP AF41, flow label 0x5f3a1)"),
+        "{}",
+        lines[2]
+    );
+
+    let run = sniff(&capture, &["--format", "{src} {dscp} {ecn} {flow_label}"]);
+    assert_eq!(
+        run.requests(),
+        [
+            "10.0.0.10 EF ECT(0) -",
+            "10.0.0.10 BE Not-ECT -",
+            "fd00::1 AF41 Not-ECT 0x5f3a1",
+            "10.0.0.3 BE Not-ECT -"
+        ]
+    );
+
+    let shown = |args: &[&str]| requests_shown(&capture, &[], &["--format", "{src} {dscp}"], args);
+    assert_eq!(shown(&["--dscp", "ef"]), ["10.0.0.10 EF"]);
+    assert_eq!(shown(&["--dscp", "ef,34"]), ["10.0.0.10 EF", "fd00::1 AF41"]);
+    assert_eq!(shown(&["--filter", "udp port 16384 and not dscp ef"]), ["10.0.0.10 BE"]);
+}
+
+#[test]
+fn runs_exec_alert_actions_with_their_arguments_filled_in() {
+    let capture = Capture::new().at(0.0, &http()).at(0.1, &dns());
+    let rules = r#"
+[[rule]]
+name = "dns"
+filter = "udp port 53"
+actions = [{ type = "exec", program = "sh", args = ["-c", "echo \"$1\" > alerted", "sh", "{rule}: {protocol} {orig_ip} -> {dest_ip} ({bytes})"] }]
+"#;
+
+    let run = sniff_with_files(&capture, &[("rules.toml", rules)], &["--alerts", "rules.toml"]);
+
+    // the action isn't waited for
+    let mut alerted = String::new();
+    for _ in 0..50 {
+        alerted = std::fs::read_to_string(run.path("alerted")).unwrap_or_default();
+        if alerted.ends_with('\n') {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(alerted, "dns: UDP 10.0.0.3 -> 10.0.0.4 (33)\n");
+}
+
+#[test]
+fn refuses_alert_rules_with_a_bad_webhook_url() {
+    let rules = r#"
+[[rule]]
+name = "dns"
+actions = [{ type = "webhook", url = "ftp://127.0.0.1/alerts" }]
+"#;
+
+    let run = sniff_failing_with_files(&Capture::new(), &[("rules.toml", rules)], &["--alerts", "rules.toml"]);
+    assert!(
+        run.stderr.contains("Alert rule \"dns\": invalid webhook URL ftp://127.0.0.1/alerts"),
+        "{}",
+        run.stderr
+    );
+    assert!(!run.stderr.contains("panicked"), "{}", run.stderr);
+}
+
+#[test]
+fn writes_the_pre_roll_once_the_trigger_fires() {
+    let ping = FrameBuilder::ping("10.0.0.5", "10.0.0.6");
+    let capture = Capture::new()
+        .at(0.0, &http())
+        .at(5.0, &ping)
+        .at(5.5, &dns())
+        .at(6.0, &http())
+        .at(8.0, &ping);
+
+    let run = sniff(
+        &capture,
+        &["--pcap", "out.pcap", "--pre-roll", "1s", "--trigger", "udp port 53"],
+    );
+    assert_eq!(run.pcap_times("out.pcap"), [5.0, 5.5, 6.0, 8.0]);
+
+    // an alert only keeps it going for as long again
+    let rules = r#"
+[[rule]]
+name = "dns"
+filter = "udp port 53"
+actions = [{ type = "console" }]
+"#;
+    let run = sniff_with_files(
+        &capture,
+        &[("rules.toml", rules)],
+        &["--pcap", "out.pcap", "--pre-roll", "1s", "--alerts", "rules.toml"],
+    );
+    assert_eq!(run.pcap_times("out.pcap"), [5.0, 5.5, 6.0]);
+}
+
+#[test]
+fn names_hosts_from_aliases_in_output_and_filters() {
+    let laptop = [2, 0, 10, 0, 0, 3];
+    let router = [2, 0, 0, 0, 0, 1];
+    let capture = Capture::new().at(0.0, &http()).at(0.1, &dns()).at(
+        0.2,
+        &FrameBuilder::udp("10.0.0.3:5353", "1.1.1.1:53").macs(laptop, router),
+    );
+    let aliases = r#"
+nas = "10.0.0.2"
+laptop = ["02:00:0a:00:00:03", "fd00::3"]
+router = "02:00:00:00:00:01"
+"#;
+    let flows = r#"
+[[flow]]
+name = "file sharing"
+to = "nas"
+"#;
+
+    let files = [("hosts.toml", aliases), ("flows.toml", flows)];
+    let shared = ["--aliases", "hosts.toml", "--format", "{src} {dst}"];
+    let shown = |args: &[&str]| requests_shown(&capture, &files, &shared, args);
+
+    // the router's MAC doesn't make everything past it the router
+    assert_eq!(shown(&[]), ["10.0.0.1 nas", "laptop 10.0.0.4", "laptop 1.1.1.1"]);
+    assert_eq!(shown(&["--filter", "dst host nas"]), ["10.0.0.1 nas"]);
+    // a name in a filter stands for all its addresses, whether or not it's what's shown
+    assert_eq!(shown(&["--filter", "host router"]), ["laptop 1.1.1.1"]);
+    assert_eq!(shown(&["-F", "laptop"]), ["laptop 10.0.0.4", "laptop 1.1.1.1"]);
+    assert_eq!(shown(&["-X", "nas"]), ["laptop 10.0.0.4", "laptop 1.1.1.1"]);
+    // and for its MACs, where a MAC's wanted
+    assert_eq!(shown(&["-f", "router"]), ["laptop 1.1.1.1"]);
+    assert_eq!(shown(&["-x", "laptop"]), ["10.0.0.1 nas"]);
+    assert_eq!(
+        shown(&["--whitelist", "flows.toml"]),
+        [
+            "*** unexpected flow: UDP laptop -> 10.0.0.4 port 53 ***",
+            "laptop 10.0.0.4",
+            "*** unexpected flow: UDP laptop -> 1.1.1.1 port 53 ***",
+            "laptop 1.1.1.1",
+        ]
+    );
+}
+
+#[test]
+fn summarises_scans_instead_of_showing_every_probe() {
+    let mut capture = Capture::new();
+    for port in 1..=30u16 {
+        let probe = FrameBuilder::tcp(&format!("10.0.0.9:{}", 50000 + port), &format!("10.0.0.2:{}", port));
+        capture = capture.at(port as f64 * 0.01, &probe.flags(TCP_SYN));
+    }
+    for host in 1..=25 {
+        capture = capture.at(
+            1.0 + host as f64 * 0.01,
+            &FrameBuilder::ping("10.0.0.8", &format!("10.0.1.{}", host)),
+        );
+    }
+    // a browser's connections to lots of servers all get somewhere
+    for host in 1..=25 {
+        let connection = FrameBuilder::tcp(&format!("10.0.0.7:{}", 40000 + host), &format!("10.0.2.{}:443", host));
+        capture = capture
+            .at(2.0 + host as f64 * 0.01, &connection.clone().flags(TCP_SYN))
+            .at(2.005 + host as f64 * 0.01, &connection.flags(TCP_ACK));
+    }
+    capture = capture.at(40.0, &dns());
+
+    let run = sniff(&capture, &["--detect-scans", "--dont-collate", "--format", "{src}"]);
+    let lines = run.requests();
+    let shown = |src: &str| lines.iter().filter(|x| **x == src).count();
+
+    // each is found at its 20th probe, and nothing of it's shown after that
+    assert_eq!(shown("10.0.0.9"), 18);
+    assert_eq!(shown("10.0.0.8"), 18);
+    assert_eq!(shown("10.0.0.7"), 50);
+    assert_eq!(
+        lines.iter().copied().filter(|x| x.starts_with("***")).collect::<Vec<_>>(),
+        [
+            "*** port scan and half-open SYNs from 10.0.0.9: 20 ports on 1 host over 0.2s, 0 of 20 handshakes \
+             completed; hiding its requests until it stops ***",
+            "*** host sweep from 10.0.0.8: 20 hosts over 0.2s; hiding its requests until it stops ***",
+            "*** port scan and half-open SYNs from 10.0.0.9 is over: 30 ports on 1 host over 0.3s, 0 of 30 handshakes \
+             completed, 12 requests hidden ***",
+            "*** host sweep from 10.0.0.8 is over: 25 hosts over 0.2s, 7 requests hidden ***",
+        ]
+    );
+    assert!(
+        run.stdout.contains(
+            "Scans:\n    10.0.0.8: host sweep, 25 hosts over 0.2s, 7 requests hidden\n    10.0.0.9: port scan and \
+             half-open SYNs, 30 ports on 1 host over 0.3s, 0 of 30 handshakes completed, 12 requests hidden\n"
+        ),
+        "{}",
+        run.stdout
+    );
+}
+
+#[test]
+fn reports_handshake_and_first_byte_latency_per_service() {
+    let mut capture = Capture::new();
+    let timings = [(0.001, 0.02), (0.002, 0.03), (0.003, 0.04), (0.01, 0.1)];
+    for (i, (handshake, first_byte)) in timings.into_iter().enumerate() {
+        let start = i as f64;
+        let request = FrameBuilder::tcp(&format!("10.0.0.1:{}", 40000 + i), "10.0.0.2:80");
+        let response = request.reversed();
+        capture = capture
+            .at(start, &request.clone().flags(TCP_SYN))
+            .at(start + handshake, &response.clone().flags(TCP_SYN | TCP_ACK))
+            .at(start + handshake + 0.0005, &request.clone().flags(TCP_ACK))
+            .at(start + 0.015, &request.payload(b"GET / HTTP/1.1\r\n\r\n"))
+            .at(
+                start + 0.015 + first_byte,
+                &response.payload(b"HTTP/1.1 200 OK\r\n\r\n"),
+            );
+    }
+    // SSH speaks first, so its banner's timed from the handshake
+    let ssh = FrameBuilder::tcp("10.0.0.1:50000", "10.0.0.3:22");
+    capture = capture
+        .at(10.0, &ssh.clone().flags(TCP_SYN))
+        .at(10.004, &ssh.reversed().flags(TCP_SYN | TCP_ACK))
+        .at(10.005, &ssh.clone().flags(TCP_ACK))
+        .at(10.05, &ssh.reversed().payload(b"SSH-2.0-OpenSSH_9.6\r\n"));
+
+    let run = sniff(&capture, &["--service-latency"]);
+    assert!(
+        run.stdout.contains(
+            "Service latency (p50/p95/p99 ms):\n    80/TCP (http)                 4 handshakes 2.00/10.00/10.00       4 \
+             responses first byte 30.00/100.00/100.00\n    22/TCP (ssh)                  1 handshake  4.00/4.00/4.00       \
+             1 response  first byte 45.00/45.00/45.00\n"
+        ),
+        "{}",
+        run.stdout
+    );
+}
+
+#[test]
+fn survives_a_truncated_gre_header() {
+    // the K and S flags say a key and a sequence number follow, but the packet ends halfway through them
+    let gre = ipv4_carrying(47, &[0x30, 0x00, 0x08, 0x00, 0, 0, 0, 1, 0, 0]);
+    let capture = Capture::new().raw(0.0, gre).at(0.1, &dns());
+
+    for args in [&[][..], &["--decapsulate"]] {
+        let run = sniff(&capture, &[&["--dont-collate", "--format", "{src} -> {dst}"], args].concat());
+        assert_eq!(run.requests(), ["10.0.0.3 -> 10.0.0.4", "10.0.0.3 -> 10.0.0.4"]);
+    }
+}
+
+#[test]
+fn records_a_fixture_from_a_packet_shorter_than_its_own_header() {
+    // an IPv4 total length of 10, less than the 20-byte header it's in
+    let mut frame = dns().build();
+    frame[16..18].copy_from_slice(&10u16.to_be_bytes());
+    let capture = Capture::new().raw(0.0, frame).at(0.1, &dns());
+
+    let run = sniff(&capture, &["--record-fixture", "fixtures"]);
+    let fixtures: Vec<_> = std::fs::read_dir(run.path("fixtures")).unwrap().collect();
+    assert!(!fixtures.is_empty(), "{}", run.stdout);
+}
+
+#[test]
+fn leaves_a_log_from_a_newer_sniff_alone() {
+    let newer = r#"{"version": 999, "packets": [], "start_time": {"secs_since_epoch": 0, "nanos_since_epoch": 0}}"#;
+    let run = sniff_with_files(&Capture::new().at(0.0, &dns()), &[("log.json", newer)], &["-l", "log.json"]);
+
+    assert_eq!(std::fs::read_to_string(run.path("log.json")).unwrap(), newer);
+    assert!(run.stderr.contains("Failed to write to the log: log is from a newer sniff"), "{}", run.stderr);
+}
+
+#[test]
+fn reads_a_capture_whose_timestamps_go_backwards() {
+    let capture = Capture::new().at(1.0, &dns()).at(0.5, &http()).at(1.5, &dns());
+
+    let run = sniff(&capture, &["--dont-collate", "--format", "{time} {proto}"]);
+    // a request's time is that of the packet ending it, and one from before the first frame shows as the start
+    assert_eq!(run.requests(), ["0.00s UDP", "0.50s TCP", "0.50s UDP"]);
+}
+
+#[test]
+fn only_writes_the_pcap_from_the_trigger_until_it_stops() {
+    let capture = Capture::new()
+        .at(0.0, &http())
+        .at(1.0, &http())
+        .at(5.0, &dns())
+        .at(5.5, &http())
+        .at(6.0, &dns())
+        .at(9.0, &http())
+        .at(20.0, &dns());
+
+    // the stop's measured on the capture's clock, however fast it's read
+    let run = sniff(
+        &capture,
+        &["--pcap", "out.pcap", "--trigger", "udp port 53", "--trigger-stop", "2s", "--format", "{time} {proto}"],
+    );
+    assert_eq!(run.pcap_times("out.pcap"), [5.0, 5.5, 6.0]);
+    assert!(run.stdout.contains("[trigger] nothing has matched for 2s, stopping"), "{}", run.stdout);
+    // the request still going when it stopped is written out too
+    let requests: Vec<_> = run.requests().into_iter().filter(|x| !x.starts_with('[')).collect();
+    assert_eq!(requests, ["5.50s UDP", "6.00s TCP", "6.00s UDP"]);
+}
+
+#[test]
+fn refuses_filters_with_unknown_protocols_or_vlan_ids() {
+    let capture = Capture::new().at(0.0, &dns());
+
+    let error = sniff_error(&capture, &["--filter", "ip proto tpc"]);
+    assert!(error.contains("unknown protocol 'tpc' in filter"), "{}", error);
+    let error = sniff_error(&capture, &["--filter", "vlan 4096"]);
+    assert!(error.contains("invalid VLAN ID 4096"), "{}", error);
+
+    assert_eq!(sniff(&capture, &["--filter", "ip proto udp", "--format", "{proto}"]).requests(), ["UDP"]);
+}
+
+#[test]
+fn drops_a_datagram_sent_in_too_many_fragments() {
+    // 8-byte fragments of one datagram, none of them the last
+    let mut capture = Capture::new();
+    for i in 0..300u16 {
+        let mut fragment = ipv4_carrying(17, &[0; 8]);
+        fragment[20..22].copy_from_slice(&(0x2000 | i).to_be_bytes());
+        capture = capture.raw(i as f64 * 0.001, fragment);
+    }
+
+    let run = sniff(&capture, &[]);
+    assert!(run.stdout.contains("257 fragments dropped, in datagrams with too many of them"), "{}", run.stdout);
+}
+
+#[test]
+fn wont_remove_a_file_in_the_way_of_the_control_socket() {
+    let run = sniff_failing_with_files(
+        &Capture::new().at(0.0, &dns()),
+        &[("notes.txt", "keep me")],
+        &["--control", "notes.txt"],
+    );
+
+    assert!(run.stderr.contains("Failed to listen on the control socket notes.txt"), "{}", run.stderr);
+    assert_eq!(std::fs::read_to_string(run.path("notes.txt")).unwrap(), "keep me");
+}
+
+#[test]
+fn times_netflow_records_by_the_capture() {
+    let collector = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+    let capture = Capture::new().at(0.0, &dns()).at(2.0, &dns());
+    sniff(&capture, &["--netflow", &collector.local_addr().unwrap().to_string()]);
+
+    let mut packet = [0; 1500];
+    let len = collector.recv(&mut packet).unwrap();
+    let u32_at = |i: usize| u32::from_be_bytes(packet[i..i + 4].try_into().unwrap());
+
+    // the NetFlow v9 header's system uptime, then the flowsets, one of them the IPv4 records
+    assert_eq!(u32_at(4), 2000);
+    let mut set = 20;
+    while u16::from_be_bytes([packet[set], packet[set + 1]]) != 256 {
+        set += u16::from_be_bytes([packet[set + 2], packet[set + 3]]) as usize;
+        assert!(set < len, "no IPv4 records");
+    }
+    // FIRST_SWITCHED and LAST_SWITCHED end the record
+    assert_eq!((u32_at(set + 4 + 30), u32_at(set + 4 + 34)), (0, 2000));
+}
+
+#[test]
+fn keeps_service_filters_with_a_session() {
+    let data = std::env::temp_dir().join(format!("sniff-test-sessions-{}", std::process::id()));
+    let env = [("XDG_DATA_HOME", data.to_str().unwrap())];
+    let capture = Capture::new().at(0.0, &http()).at(0.1, &dns());
+    let shown = |args: &[&str]| -> Vec<String> {
+        let args = [&["--session", "web", "--format", "{proto}"], args].concat();
+        let run = sniff_with_env(&capture, &env, &args);
+        let requests = run.requests().into_iter().filter(|x| !x.starts_with("Resuming"));
+        requests.map(|x| x.to_string()).collect()
+    };
+
+    assert_eq!(shown(&["--service", "http"]), ["TCP"]);
+    // a run without filters of its own picks up the session's
+    assert_eq!(shown(&[]), ["TCP"]);
+    std::fs::remove_dir_all(&data).unwrap();
+}